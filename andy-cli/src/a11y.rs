@@ -1,4 +1,4 @@
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{HashMap, HashSet};
 
 fn empty_as_none<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
@@ -13,10 +13,63 @@ pub struct A11yTree {
 
 #[derive(Deserialize)]
 pub struct A11yWindow {
+    /// Z-order; the device lists windows highest first.
+    #[serde(default)]
+    pub layer: i32,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub title: Option<String>,
     pub nodes: Vec<A11yNode>,
 }
 
-#[derive(Deserialize)]
+/// A window from `GET /screens/{name}/windows`, without its nodes.
+#[derive(Serialize, Deserialize)]
+pub struct WindowInfo {
+    #[serde(rename = "type")]
+    pub window_type: i32,
+    pub layer: i32,
+    #[serde(default)]
+    pub focused: bool,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub title: Option<String>,
+    pub bounds: Bounds,
+}
+
+impl WindowInfo {
+    /// `AccessibilityWindowInfo.TYPE_*` by name.
+    fn type_name(&self) -> &'static str {
+        match self.window_type {
+            1 => "application",
+            2 => "input_method",
+            3 => "system",
+            4 => "accessibility_overlay",
+            5 => "split_screen_divider",
+            6 => "magnification_overlay",
+            _ => "unknown",
+        }
+    }
+}
+
+/// One line per window, topmost first: layer, type, title, bounds.
+pub fn render_windows(windows: &[WindowInfo]) -> String {
+    windows
+        .iter()
+        .map(|w| {
+            let b = &w.bounds;
+            let mut line = format!("layer={} {}", w.layer, w.type_name());
+            if let Some(title) = &w.title {
+                line.push_str(&format!(" \"{title}\""));
+            }
+            line.push_str(&format!(" ({},{},{},{})", b.left, b.top, b.right, b.bottom));
+            if w.focused {
+                line.push_str(" [focused]");
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Bounds {
     pub left: i32,
     pub top: i32,
@@ -24,13 +77,38 @@ pub struct Bounds {
     pub bottom: i32,
 }
 
-#[derive(Deserialize)]
+impl Bounds {
+    pub fn center(&self) -> (f32, f32) {
+        (
+            (self.left + self.right) as f32 / 2.0,
+            (self.top + self.bottom) as f32 / 2.0,
+        )
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        (self.left as f32..self.right as f32).contains(&x)
+            && (self.top as f32..self.bottom as f32).contains(&y)
+    }
+}
+
+/// A row in the notification shade; bounds are on the default display.
+#[derive(Serialize, Deserialize)]
+pub struct Notification {
+    pub app: Option<String>,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub bounds: Bounds,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct A11yNode {
     pub id: i32,
     pub parent_id: Option<i32>,
     #[serde(rename = "class")]
     pub class_name: Option<String>,
     #[serde(deserialize_with = "empty_as_none")]
+    pub resource_id: Option<String>,
+    #[serde(deserialize_with = "empty_as_none")]
     pub text: Option<String>,
     #[serde(deserialize_with = "empty_as_none")]
     pub content_desc: Option<String>,
@@ -55,23 +133,230 @@ pub struct A11yNode {
     pub bounds: Bounds,
 }
 
-pub fn find_node<'a>(tree: &'a A11yTree, query: &str) -> Option<&'a A11yNode> {
-    for window in &tree.windows {
-        for node in &window.nodes {
-            if node.text.as_deref() == Some(query) || node.content_desc.as_deref() == Some(query) {
-                return Some(node);
+/// Which attribute a label query compares against.
+#[derive(Clone, Copy, Default)]
+pub enum LabelMatch {
+    /// Text or content description.
+    #[default]
+    Any,
+    Text,
+    Desc,
+}
+
+pub fn matches_label(node: &A11yNode, query: &str, by: LabelMatch) -> bool {
+    let text = || node.text.as_deref() == Some(query);
+    let desc = || node.content_desc.as_deref() == Some(query);
+    match by {
+        LabelMatch::Any => text() || desc(),
+        LabelMatch::Text => text(),
+        LabelMatch::Desc => desc(),
+    }
+}
+
+pub fn is_webview(node: &A11yNode) -> bool {
+    node.class_name
+        .as_deref()
+        .is_some_and(|cls| cls.ends_with("WebView"))
+}
+
+/// Whether some WebView exposes no page content to accessibility, so only coordinate
+/// taps can reach inside it.
+pub fn has_opaque_webview(tree: &A11yTree) -> bool {
+    tree.windows.iter().any(|window| {
+        let parents: HashSet<i32> = window.nodes.iter().filter_map(|n| n.parent_id).collect();
+        window
+            .nodes
+            .iter()
+            .any(|n| is_webview(n) && !parents.contains(&n.id))
+    })
+}
+
+/// All nodes matching `query`, in tree order (windows, then depth-first node order).
+pub fn find_nodes<'a>(tree: &'a A11yTree, query: &str, by: LabelMatch) -> Vec<&'a A11yNode> {
+    tree.windows
+        .iter()
+        .flat_map(|w| &w.nodes)
+        .filter(|node| matches_label(node, query, by))
+        .collect()
+}
+
+/// `app | title | text (l,t,r,b)`, skipping whatever the row doesn't show.
+pub fn describe_notification(notification: &Notification) -> String {
+    let fields = [&notification.app, &notification.title, &notification.text];
+    let mut line = fields
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let b = &notification.bounds;
+    line.push_str(&format!(" ({},{},{},{})", b.left, b.top, b.right, b.bottom));
+    line
+}
+
+/// One-line summary of a node, to tell apart candidates that share a label.
+pub fn describe_node(node: &A11yNode) -> String {
+    let mut line = short_class(&node.class_name, true)
+        .unwrap_or("node")
+        .to_string();
+    if let Some(id) = &node.resource_id {
+        line.push_str(&format!(" id={id}"));
+    }
+    if let Some(text) = &node.text {
+        line.push_str(&format!(" text=\"{text}\""));
+    }
+    if let Some(desc) = &node.content_desc {
+        line.push_str(&format!(" desc=\"{desc}\""));
+    }
+    if !node.clickable {
+        line.push_str(" NOT-clickable");
+    }
+    let b = &node.bounds;
+    line.push_str(&format!(" ({},{},{},{})", b.left, b.top, b.right, b.bottom));
+    line
+}
+
+/// Among nodes accepted by `predicate`, the one whose center is closest to (x, y).
+pub fn nearest_node(
+    tree: &A11yTree,
+    x: f32,
+    y: f32,
+    predicate: impl Fn(&A11yNode) -> bool,
+) -> Option<&A11yNode> {
+    tree.windows
+        .iter()
+        .flat_map(|w| &w.nodes)
+        .filter(|node| predicate(node))
+        .min_by(|a, b| {
+            let dist = |n: &A11yNode| {
+                let (cx, cy) = n.bounds.center();
+                (cx - x).powi(2) + (cy - y).powi(2)
+            };
+            dist(a).total_cmp(&dist(b))
+        })
+}
+
+/// Where a node sits relative to an anchor node.
+#[derive(Clone, Copy)]
+pub enum Direction {
+    RightOf,
+    LeftOf,
+    Below,
+    Above,
+}
+
+impl Direction {
+    pub fn flag(self) -> &'static str {
+        match self {
+            Direction::RightOf => "--right-of",
+            Direction::LeftOf => "--left-of",
+            Direction::Below => "--below",
+            Direction::Above => "--above",
+        }
+    }
+}
+
+/// The clickable node closest to `anchor` on its `dir` side, for controls without a label
+/// of their own (the checkbox beside "Remember me"). Candidates must lie past the anchor's
+/// edge and overlap it on the other axis; the smallest gap wins, then the best alignment.
+pub fn nearest_in_direction<'a>(
+    tree: &'a A11yTree,
+    anchor: &A11yNode,
+    dir: Direction,
+) -> Option<&'a A11yNode> {
+    let a = &anchor.bounds;
+    let (acx, acy) = a.center();
+    tree.windows
+        .iter()
+        .flat_map(|w| &w.nodes)
+        .filter(|n| n.clickable && n.id != anchor.id)
+        .filter_map(|n| {
+            let b = &n.bounds;
+            let (cx, cy) = b.center();
+            let (gap, past, offset) = match dir {
+                Direction::RightOf => (b.left - a.right, cx > a.right as f32, cy - acy),
+                Direction::LeftOf => (a.left - b.right, cx < a.left as f32, cy - acy),
+                Direction::Below => (b.top - a.bottom, cy > a.bottom as f32, cx - acx),
+                Direction::Above => (a.top - b.bottom, cy < a.top as f32, cx - acx),
+            };
+            // A clickable row around both label and control is not beside the anchor
+            let contains =
+                b.left <= a.left && b.top <= a.top && b.right >= a.right && b.bottom >= a.bottom;
+            let aligned = match dir {
+                Direction::RightOf | Direction::LeftOf => b.top < a.bottom && b.bottom > a.top,
+                Direction::Below | Direction::Above => b.left < a.right && b.right > a.left,
+            };
+            (past && aligned && !contains).then(|| (gap.max(0), offset.abs(), n))
+        })
+        .min_by(|(gap_a, off_a, _), (gap_b, off_b, _)| {
+            gap_a.cmp(gap_b).then(off_a.total_cmp(off_b))
+        })
+        .map(|(_, _, n)| n)
+}
+
+/// What `render_text` leaves out; the default is the compact view agents read.
+#[derive(Default, Clone, Copy)]
+pub struct RenderOptions {
+    /// Name layout classes (FrameLayout, LinearLayout, ...) instead of showing `View`.
+    pub all_classes: bool,
+    /// Render leaf nodes even when nothing about them is interesting.
+    pub plain_leaves: bool,
+    /// Render every node with its id and parent id, without deduplicating text.
+    pub raw: bool,
+    /// Render every item of long lists instead of collapsing look-alike siblings.
+    pub no_collapse: bool,
+    /// Print bounds as fractions of this screen width and height instead of pixels.
+    pub normalized_to: Option<(i32, i32)>,
+}
+
+impl RenderOptions {
+    /// `-v` levels: 1 = all classes, 2 = plus plain leaves, 3 = raw.
+    pub fn verbosity(level: u8) -> Self {
+        Self {
+            all_classes: level >= 1,
+            plain_leaves: level >= 2,
+            raw: level >= 3,
+            no_collapse: level >= 3,
+            normalized_to: None,
+        }
+    }
+
+    /// `(l,t,r,b)` in pixels, or as fractions of the screen with `normalized_to`.
+    fn bounds(&self, b: &Bounds) -> String {
+        match self.normalized_to {
+            Some((width, height)) => {
+                let (w, h) = (width as f32, height as f32);
+                format!(
+                    "({:.3},{:.3},{:.3},{:.3})",
+                    b.left as f32 / w,
+                    b.top as f32 / h,
+                    b.right as f32 / w,
+                    b.bottom as f32 / h
+                )
             }
+            None => format!("({},{},{},{})", b.left, b.top, b.right, b.bottom),
         }
     }
-    None
 }
 
-pub fn render_text(tree: &A11yTree) -> String {
+/// Windows topmost first; each gets a `== window ... ==` header when more than one has nodes.
+pub fn render_text(tree: &A11yTree, opts: &RenderOptions) -> String {
     let mut lines = Vec::new();
 
-    for window in &tree.windows {
-        if window.nodes.is_empty() {
-            continue;
+    let mut windows: Vec<&A11yWindow> = tree
+        .windows
+        .iter()
+        .filter(|w| !w.nodes.is_empty())
+        .collect();
+    windows.sort_by_key(|w| std::cmp::Reverse(w.layer));
+    let headers = windows.len() > 1;
+
+    for window in windows {
+        if headers {
+            lines.push(match &window.title {
+                Some(title) => format!("== window layer={} \"{title}\" ==", window.layer),
+                None => format!("== window layer={} ==", window.layer),
+            });
         }
 
         let mut children_map: HashMap<i32, Vec<usize>> = HashMap::new();
@@ -86,13 +371,50 @@ pub fn render_text(tree: &A11yTree) -> String {
         }
 
         if let Some(ri) = root_idx {
-            render_node(&window.nodes, ri, 0, None, &children_map, &mut lines);
+            render_node(&window.nodes, ri, 0, None, &children_map, opts, &mut lines);
         }
     }
 
     lines.join("\n")
 }
 
+/// One row per interesting node, for grepping and spreadsheets.
+pub fn render_csv(tree: &A11yTree) -> String {
+    let mut lines =
+        vec!["class,text,desc,resource_id,center_x,center_y,clickable,checkable".to_string()];
+    for node in tree.windows.iter().flat_map(|w| &w.nodes) {
+        if !is_interesting(node) {
+            continue;
+        }
+        let class = node
+            .class_name
+            .as_deref()
+            .map(|cls| cls.rsplit('.').next().unwrap_or(cls));
+        let (cx, cy) = node.bounds.center();
+        lines.push(format!(
+            "{},{},{},{},{},{},{},{}",
+            csv_field(class),
+            csv_field(node.text.as_deref()),
+            csv_field(node.content_desc.as_deref()),
+            csv_field(node.resource_id.as_deref()),
+            cx as i32,
+            cy as i32,
+            node.clickable,
+            node.checkable,
+        ));
+    }
+    lines.join("\n")
+}
+
+fn csv_field(value: Option<&str>) -> String {
+    let value = value.unwrap_or_default();
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 fn is_interesting(node: &A11yNode) -> bool {
     node.text.is_some()
         || node.content_desc.is_some()
@@ -105,11 +427,13 @@ fn is_interesting(node: &A11yNode) -> bool {
         || node.selected
 }
 
-fn short_class(class: &Option<String>) -> Option<&str> {
+fn short_class(class: &Option<String>, all_classes: bool) -> Option<&str> {
     let cls = class.as_deref()?;
     let name = cls.rsplit('.').next().unwrap_or(cls);
     match name {
-        "ViewGroup" | "FrameLayout" | "LinearLayout" | "RelativeLayout" | "ConstraintLayout" => {
+        "ViewGroup" | "FrameLayout" | "LinearLayout" | "RelativeLayout" | "ConstraintLayout"
+            if !all_classes =>
+        {
             None
         }
         _ => Some(name),
@@ -122,12 +446,14 @@ fn render_node(
     depth: usize,
     parent_texts: Option<&HashSet<&str>>,
     children_map: &HashMap<i32, Vec<usize>>,
+    opts: &RenderOptions,
     lines: &mut Vec<String>,
 ) {
     let node = &nodes[idx];
     let children = children_map.get(&node.id);
 
-    let only_text = node.text.is_some()
+    let only_text = !opts.raw
+        && node.text.is_some()
         && node.content_desc.is_none()
         && node.hint.is_none()
         && !node.clickable
@@ -137,22 +463,23 @@ fn render_node(
         && !node.focused
         && !node.selected;
 
-    if only_text {
-        if let (Some(text), Some(pt)) = (&node.text, parent_texts) {
-            if pt.contains(text.as_str()) {
-                if let Some(child_indices) = children {
-                    for &ci in child_indices {
-                        render_node(nodes, ci, depth, None, children_map, lines);
-                    }
-                }
-                return;
-            }
+    if only_text
+        && let (Some(text), Some(pt)) = (&node.text, parent_texts)
+        && pt.contains(text.as_str())
+    {
+        if let Some(child_indices) = children {
+            render_children(nodes, child_indices, depth, None, children_map, opts, lines);
         }
+        return;
     }
 
-    if is_interesting(node) {
+    let shown = is_interesting(node)
+        || is_webview(node)
+        || opts.raw
+        || (opts.plain_leaves && children.is_none());
+    if shown {
         let indent = "  ".repeat(depth);
-        let cls = short_class(&node.class_name).unwrap_or("View");
+        let cls = short_class(&node.class_name, opts.all_classes).unwrap_or("View");
         let b = &node.bounds;
         let mut line = format!("{indent}{cls}");
 
@@ -195,10 +522,19 @@ fn render_node(
         if node.password {
             flags.push("password");
         }
+        if is_webview(node) && children.is_none() {
+            flags.push("no-a11y-content");
+        }
         if !flags.is_empty() {
             line.push_str(&format!(" {}", flags.join(" ")));
         }
-        line.push_str(&format!(" ({},{},{},{})", b.left, b.top, b.right, b.bottom));
+        if opts.raw {
+            line.push_str(&format!(" id={}", node.id));
+            if let Some(pid) = node.parent_id {
+                line.push_str(&format!(" parent={pid}"));
+            }
+        }
+        line.push_str(&format!(" {}", opts.bounds(b)));
         lines.push(line);
 
         let mut new_parent_texts = HashSet::new();
@@ -209,20 +545,277 @@ fn render_node(
             new_parent_texts.insert(desc.as_str());
         }
         if let Some(child_indices) = children {
-            for &ci in child_indices {
-                render_node(
-                    nodes,
-                    ci,
-                    depth + 1,
-                    Some(&new_parent_texts),
-                    children_map,
-                    lines,
-                );
-            }
+            render_children(
+                nodes,
+                child_indices,
+                depth + 1,
+                Some(&new_parent_texts),
+                children_map,
+                opts,
+                lines,
+            );
         }
     } else if let Some(child_indices) = children {
-        for &ci in child_indices {
-            render_node(nodes, ci, depth, None, children_map, lines);
+        render_children(nodes, child_indices, depth, None, children_map, opts, lines);
+    }
+}
+
+/// Items shown from a run of look-alike siblings before the rest collapse into one line.
+const SIMILAR_SHOWN: usize = 3;
+
+/// Class and interestingness of a node and its first few levels of descendants; list
+/// rows built from the same layout share a shape even when their text differs.
+fn shape(
+    nodes: &[A11yNode],
+    idx: usize,
+    children_map: &HashMap<i32, Vec<usize>>,
+    depth: usize,
+) -> String {
+    let node = &nodes[idx];
+    let mut shape = node.class_name.clone().unwrap_or_default();
+    if is_interesting(node) {
+        shape.push('*');
+    }
+    if depth < 3
+        && let Some(children) = children_map.get(&node.id)
+    {
+        shape.push('(');
+        for &ci in children {
+            shape.push_str(&self::shape(nodes, ci, children_map, depth + 1));
+            shape.push(',');
         }
+        shape.push(')');
+    }
+    shape
+}
+
+fn render_children(
+    nodes: &[A11yNode],
+    child_indices: &[usize],
+    depth: usize,
+    parent_texts: Option<&HashSet<&str>>,
+    children_map: &HashMap<i32, Vec<usize>>,
+    opts: &RenderOptions,
+    lines: &mut Vec<String>,
+) {
+    let shapes: Vec<String> = if opts.no_collapse {
+        Vec::new()
+    } else {
+        child_indices
+            .iter()
+            .map(|&ci| shape(nodes, ci, children_map, 0))
+            .collect()
+    };
+    let mut start = 0;
+    while start < child_indices.len() {
+        let mut end = start + 1;
+        while end < shapes.len() && shapes[end] == shapes[start] {
+            end += 1;
+        }
+        let run = &child_indices[start..end];
+        // Collapsing a single item would not save a line
+        let shown = if run.len() > SIMILAR_SHOWN + 1 {
+            SIMILAR_SHOWN
+        } else {
+            run.len()
+        };
+        let before = lines.len();
+        for &ci in &run[..shown] {
+            render_node(nodes, ci, depth, parent_texts, children_map, opts, lines);
+        }
+        let hidden = &run[shown..];
+        if let (Some(&first), Some(&last)) = (hidden.first(), hidden.last())
+            && lines.len() > before
+        {
+            lines.push(format!(
+                "{}... {} similar items ... {}..{}",
+                "  ".repeat(depth),
+                hidden.len(),
+                opts.bounds(&nodes[first].bounds),
+                opts.bounds(&nodes[last].bounds),
+            ));
+        }
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_repeated_list_rows() {
+        let node = |id: i32, parent: Option<i32>, class: &str, text: Option<String>, top: i32| {
+            serde_json::json!({
+                "id": id, "parent_id": parent, "class": class, "resource_id": null,
+                "text": text, "content_desc": null, "hint": null, "scrollable": parent.is_none(),
+                "bounds": {"left": 0, "top": top, "right": 1080, "bottom": top + 100},
+            })
+        };
+        let mut nodes = vec![node(
+            1,
+            None,
+            "androidx.recyclerview.widget.RecyclerView",
+            None,
+            0,
+        )];
+        for row in 0..10 {
+            let text = Some(format!("Item {row}"));
+            nodes.push(node(
+                2 + row,
+                Some(1),
+                "android.widget.TextView",
+                text,
+                row * 100,
+            ));
+        }
+        let json = serde_json::json!({"windows": [{"nodes": nodes}]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+
+        let text = render_text(&tree, &RenderOptions::default());
+        assert!(text.contains("\"Item 2\""));
+        assert!(!text.contains("\"Item 3\""));
+        assert!(text.ends_with("  ... 7 similar items ... (0,300,1080,400)..(0,900,1080,1000)"));
+
+        let opts = RenderOptions {
+            no_collapse: true,
+            ..Default::default()
+        };
+        assert!(render_text(&tree, &opts).contains("\"Item 9\""));
+    }
+
+    #[test]
+    fn renders_bounds_as_fractions_of_the_screen() {
+        let json = serde_json::json!({"windows": [{"nodes": [
+            {"id": 1, "parent_id": null, "class": "android.widget.Button",
+             "resource_id": null, "text": "OK", "content_desc": null, "hint": null,
+             "bounds": {"left": 270, "top": 200, "right": 540, "bottom": 400}},
+        ]}]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+        let opts = RenderOptions {
+            normalized_to: Some((1080, 2000)),
+            ..Default::default()
+        };
+        let text = render_text(&tree, &opts);
+        assert!(text.contains("(0.250,0.100,0.500,0.200)"), "{text}");
+        assert!(!text.contains("(270,200,540,400)"));
+    }
+
+    #[test]
+    fn renders_windows_topmost_first() {
+        let window = |layer: i32, title: &str, text: &str| {
+            serde_json::json!({"layer": layer, "title": title, "nodes": [
+                {"id": layer, "parent_id": null, "class": "android.widget.TextView",
+                 "resource_id": null, "text": text, "content_desc": null, "hint": null,
+                 "bounds": {"left": 0, "top": 0, "right": 100, "bottom": 50}},
+            ]})
+        };
+        let json = serde_json::json!({"windows": [
+            window(1, "Main", "Home"),
+            window(5, "Dialog", "OK"),
+        ]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+        let text = render_text(&tree, &RenderOptions::default());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "== window layer=5 \"Dialog\" ==");
+        assert!(lines[1].contains("\"OK\""));
+        assert_eq!(lines[2], "== window layer=1 \"Main\" ==");
+        assert!(lines[3].contains("\"Home\""));
+    }
+
+    #[test]
+    fn csv_quotes_commas_and_quotes() {
+        let json = serde_json::json!({"windows": [{"nodes": [
+            {"id": 1, "parent_id": null, "class": "android.widget.FrameLayout",
+             "resource_id": null, "text": null, "content_desc": null, "hint": null,
+             "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 2000}},
+            {"id": 2, "parent_id": 1, "class": "android.widget.Button",
+             "resource_id": "app:id/save", "text": "Save, \"draft\"", "content_desc": null,
+             "hint": null, "clickable": true,
+             "bounds": {"left": 100, "top": 200, "right": 300, "bottom": 300}},
+        ]}]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+        let csv = render_csv(&tree);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "class,text,desc,resource_id,center_x,center_y,clickable,checkable",
+                "Button,\"Save, \"\"draft\"\"\",,app:id/save,200,250,true,false",
+            ]
+        );
+    }
+
+    #[test]
+    fn label_match_restricts_attribute() {
+        let json = serde_json::json!({"windows": [{"nodes": [
+            {"id": 1, "parent_id": null, "class": "android.widget.TextView", "resource_id": null,
+             "text": "Search", "content_desc": null, "hint": null,
+             "bounds": {"left": 0, "top": 0, "right": 100, "bottom": 50}},
+            {"id": 2, "parent_id": null, "class": "android.widget.ImageButton", "resource_id": null,
+             "text": null, "content_desc": "Search", "hint": null,
+             "bounds": {"left": 0, "top": 50, "right": 100, "bottom": 100}},
+        ]}]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+        let ids = |by| -> Vec<i32> {
+            find_nodes(&tree, "Search", by)
+                .iter()
+                .map(|n| n.id)
+                .collect()
+        };
+        assert_eq!(ids(LabelMatch::Any), [1, 2]);
+        assert_eq!(ids(LabelMatch::Text), [1]);
+        assert_eq!(ids(LabelMatch::Desc), [2]);
+    }
+
+    #[test]
+    fn finds_control_beside_label() {
+        let node = |id: i32, text: Option<&str>, clickable: bool, bounds: [i32; 4]| {
+            serde_json::json!({
+                "id": id, "parent_id": null, "class": "android.view.View", "resource_id": null,
+                "text": text, "content_desc": null, "hint": null, "clickable": clickable,
+                "bounds": {"left": bounds[0], "top": bounds[1], "right": bounds[2], "bottom": bounds[3]},
+            })
+        };
+        let json = serde_json::json!({"windows": [{"nodes": [
+            node(1, None, true, [0, 0, 1080, 400]),
+            node(2, Some("Remember me"), false, [100, 100, 400, 150]),
+            node(3, None, true, [20, 100, 80, 150]),
+            node(4, None, true, [900, 90, 960, 160]),
+            node(5, None, true, [500, 160, 600, 210]),
+            node(6, Some("Sign in"), true, [100, 250, 980, 320]),
+        ]}]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+        let anchor = find_nodes(&tree, "Remember me", LabelMatch::Any)[0];
+        let id = |dir| nearest_in_direction(&tree, anchor, dir).map(|n| n.id);
+        assert_eq!(id(Direction::RightOf), Some(4));
+        assert_eq!(id(Direction::LeftOf), Some(3));
+        assert_eq!(id(Direction::Below), Some(6));
+        assert_eq!(id(Direction::Above), None);
+    }
+
+    #[test]
+    fn nearest_node_picks_the_closest_repeated_label() {
+        let node = |id: i32, text: &str, top: i32| {
+            serde_json::json!({
+                "id": id, "parent_id": null, "class": "android.widget.Button", "resource_id": null,
+                "text": text, "content_desc": null, "hint": null, "clickable": true,
+                "bounds": {"left": 0, "top": top, "right": 1080, "bottom": top + 100},
+            })
+        };
+        let json = serde_json::json!({"windows": [{"nodes": [
+            node(1, "Delete", 200),
+            node(2, "Delete", 1200),
+            node(3, "Archive", 1100),
+        ]}]});
+        let tree: A11yTree = serde_json::from_value(json).unwrap();
+        let delete = |n: &A11yNode| n.text.as_deref() == Some("Delete");
+        let id = |x, y| nearest_node(&tree, x, y, delete).map(|n| n.id);
+        assert_eq!(id(540.0, 300.0), Some(1));
+        assert_eq!(id(540.0, 1150.0), Some(2));
+        assert_eq!(
+            nearest_node(&tree, 540.0, 300.0, |n| !n.clickable).map(|n| n.id),
+            None
+        );
     }
 }
@@ -1,114 +1,607 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use bytes::Bytes;
 use reqwest::Client as ReqwestClient;
 
-use crate::a11y::A11yTree;
+use crate::a11y::{A11yNode, A11yTree, Notification, WindowInfo};
 use crate::types::*;
 
+/// What a screen is created with, and refreshed to on every command.
+pub struct ScreenSettings {
+    pub package: String,
+    /// Seconds without a command before the screen is reaped; 0 keeps it until deleted.
+    /// Unset leaves an existing screen's timeout alone (the server default for a new one).
+    pub timeout_secs: Option<u64>,
+    /// Let the server tap through known system dialogs after interactions. Off leaves
+    /// an existing screen's setting alone.
+    pub auto_dismiss: bool,
+    /// Replaces the server's default dialog table.
+    pub dismiss_rules: Option<Vec<DismissRule>>,
+}
+
+impl ScreenSettings {
+    fn request(&self, name: &str) -> CreateScreenRequest {
+        CreateScreenRequest {
+            name: name.to_string(),
+            width: 1080,
+            height: 1920,
+            dpi: 240,
+            timeout_secs: self.timeout_secs,
+            package: self.package.clone(),
+            auto_dismiss: self.auto_dismiss.then_some(true),
+            dismiss_rules: self.dismiss_rules.clone(),
+        }
+    }
+}
+
+/// How the device should render a screenshot; the default is the full screen in color.
+#[derive(Default)]
+pub struct ScreenshotOptions<'a> {
+    pub scale: Option<f32>,
+    pub max_dim: Option<u32>,
+    pub debug: bool,
+    pub crop_to: Option<&'a str>,
+    pub grayscale: bool,
+    /// JPEG chroma subsampling, `444` or `420`; the device default (420) when unset.
+    pub chroma: Option<&'a str>,
+    /// Scroll through a scrollable and stitch it into one tall image.
+    pub full: bool,
+    /// Text of the scrollable (or of a node inside it) to page through with `full`.
+    pub scroll_node: Option<&'a str>,
+    /// Frames for the device to discard before the one returned.
+    pub settle_frames: u32,
+    /// Unencoded RGBA instead of JPEG.
+    pub raw: bool,
+    /// AVIF instead of JPEG.
+    pub avif: bool,
+    /// Encoder quality 1-100; the device picks per format when unset.
+    pub quality: Option<u8>,
+}
+
+/// The device pages through up to 30 viewports for a full screenshot.
+const FULL_SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(90);
+
 pub struct Client {
     http: ReqwestClient,
+    /// Scheme and authority every path is appended to.
+    base: String,
+    wait: Option<WaitStrategy>,
+    timeout: Duration,
+}
+
+/// Request deadline unless `ANDY_REQUEST_TIMEOUT_SECS` overrides it.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl Interaction {
+    fn from_headers(resp: &reqwest::Response) -> Self {
+        Self {
+            wait_ms: header_value(resp, "X-Wait-Ms"),
+            app_crashed: header_text(resp, "X-App-Crashed"),
+            ui_changed: header_value(resp, "X-Ui-Changed"),
+            auto_dismissed: header_text(resp, "X-Auto-Dismissed"),
+            cold_start: header_value(resp, "X-Cold-Start"),
+        }
+    }
+}
+
+fn push_query(url: &mut String, key: &str, value: impl std::fmt::Display) {
+    url.push(if url.contains('?') { '&' } else { '?' });
+    url.push_str(key);
+    url.push('=');
+    // Percent-encode everything but RFC 3986 unreserved characters
+    for byte in value.to_string().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{byte:02X}")),
+        }
+    }
+}
+
+/// Error for a non-success response, calling out device-side deadlines and stalls.
+async fn status_error(what: &str, resp: reqwest::Response) -> anyhow::Error {
+    let status = resp.status();
+    let text = resp.text().await.unwrap_or_default();
+    if status == reqwest::StatusCode::GATEWAY_TIMEOUT {
+        return anyhow::anyhow!("{what}: device operation timed out: {text}");
+    }
+    if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+        return anyhow::anyhow!("{what}: device busy with another request: {text}");
+    }
+    anyhow::anyhow!("{what}: {status} {text}")
+}
+
+/// Extra attempts for a POST whose response was lost in transport.
+const POST_RETRIES: u64 = 2;
+
+/// Unique per mutating request, shared by its retries.
+fn idempotency_key() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{nanos}-{seq}", std::process::id())
+}
+
+/// Error for a request that never got a response, telling a dead server from a stuck one.
+fn transport_error(e: reqwest::Error, timeout: Duration) -> anyhow::Error {
+    if e.is_timeout() {
+        return anyhow::anyhow!(
+            "andy server hung: no response within {}s (raise ANDY_REQUEST_TIMEOUT_SECS for slow operations)",
+            timeout.as_secs()
+        );
+    }
+    if e.is_connect() {
+        return anyhow::anyhow!("andy server not running: {e}");
+    }
+    e.into()
+}
+
+fn header_value<T: std::str::FromStr>(resp: &reqwest::Response, name: &str) -> Option<T> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<T>().ok())
+}
+
+/// A header the device percent-encodes because it carries on-screen text.
+fn header_text(resp: &reqwest::Response, name: &str) -> Option<String> {
+    let value = resp.headers().get(name)?.as_bytes();
+    Some(percent_decode(value))
+}
+
+fn percent_decode(value: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value;
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Full frame from `base` with the `x,y,w,h` region of `update` copied over it.
+fn patch_region(base: &RawFrame, update: RawFrame, region: &str) -> Result<RawFrame> {
+    let parts: Vec<usize> = region
+        .split(',')
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid X-Region header: {region}"))?;
+    let [x, y, w, h] = parts[..] else {
+        bail!("invalid X-Region header: {region}");
+    };
+    let stride = base.stride as usize;
+    if x + w > base.width as usize || y + h > base.height as usize || update.data.len() != w * h * 4
+    {
+        bail!("X-Region {region} does not fit the frame");
+    }
+    let mut data = base.data.to_vec();
+    // An empty region means nothing changed
+    if w > 0 {
+        for (row, src) in update.data.chunks_exact(w * 4).enumerate() {
+            let start = (y + row) * stride + x * 4;
+            data[start..start + w * 4].copy_from_slice(src);
+        }
+    }
+    Ok(RawFrame {
+        stride: base.stride,
+        data: data.into(),
+        ..update
+    })
 }
 
 impl Client {
-    pub fn new(socket_path: PathBuf) -> Self {
-        let http = ReqwestClient::builder()
-            .unix_socket(socket_path)
+    /// Client over the adb-forwarded unix socket.
+    pub fn new(socket_path: PathBuf, timeout: Duration) -> Result<Self> {
+        Self::build(
+            ReqwestClient::builder().unix_socket(socket_path),
+            "http://localhost".to_string(),
+            timeout,
+        )
+    }
+
+    /// Client over TCP, e.g. `http://127.0.0.1:21632` from `andy start --tcp-port`.
+    pub fn with_base_url(base_url: &str, timeout: Duration) -> Result<Self> {
+        Self::build(
+            ReqwestClient::builder(),
+            base_url.trim_end_matches('/').to_string(),
+            timeout,
+        )
+    }
+
+    /// Sends `ANDY_AUTH_TOKEN` as a bearer token when set, and puts every path under
+    /// `ANDY_PATH_PREFIX` unless `base` already ends with it, matching the server.
+    fn build(builder: reqwest::ClientBuilder, mut base: String, timeout: Duration) -> Result<Self> {
+        if let Ok(prefix) = std::env::var("ANDY_PATH_PREFIX") {
+            let prefix = prefix.trim().trim_matches('/');
+            if !prefix.is_empty() && !base.ends_with(&format!("/{prefix}")) {
+                base = format!("{base}/{prefix}");
+            }
+        }
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Ok(token) = std::env::var("ANDY_AUTH_TOKEN") {
+            // Not echoed: the value is a secret
+            let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "ANDY_AUTH_TOKEN must be visible ASCII (no newlines or control characters)"
+                    )
+                })?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        let http = builder
+            .default_headers(headers)
             .build()
-            .expect("build reqwest client");
-        Self { http }
+            .context("build reqwest client")?;
+        Ok(Self {
+            http,
+            base,
+            wait: None,
+            timeout,
+        })
+    }
+
+    /// Auto-wait strategy sent with every read unless the command passes `--no-wait`.
+    pub fn with_wait_strategy(mut self, wait: Option<WaitStrategy>) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    fn push_wait(&self, url: &mut String, no_wait: bool) {
+        if no_wait {
+            push_query(url, "no_wait", true);
+            return;
+        }
+        match self.wait {
+            Some(WaitStrategy::Idle) => push_query(url, "wait", "idle"),
+            Some(WaitStrategy::Fixed(ms)) => {
+                push_query(url, "wait", "fixed");
+                push_query(url, "wait_ms", ms);
+            }
+            Some(WaitStrategy::None) => push_query(url, "wait", "none"),
+            None => {}
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        self.send_within(req, self.timeout).await
+    }
+
+    async fn send_within(
+        &self,
+        req: reqwest::RequestBuilder,
+        timeout: Duration,
+    ) -> Result<reqwest::Response> {
+        let req = req.timeout(timeout);
+        let is_post = req
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .is_some_and(|r| r.method() == reqwest::Method::POST);
+        if !is_post {
+            return req.send().await.map_err(|e| transport_error(e, timeout));
+        }
+
+        // The adb forward can drop a response after the device acted; the key makes a
+        // retry replay that result instead of tapping or launching again
+        let req = req.header("Idempotency-Key", idempotency_key());
+        let mut attempt = 0;
+        loop {
+            let Some(try_req) = req.try_clone() else {
+                return req.send().await.map_err(|e| transport_error(e, timeout));
+            };
+            match try_req.send().await {
+                Ok(resp) => return Ok(resp),
+                // A timed-out operation may still be running, and a refused connection
+                // means no server to retry against
+                Err(e) if attempt < POST_RETRIES && !e.is_timeout() && !e.is_connect() => {
+                    attempt += 1;
+                    eprintln!("debug: retrying request after transport error: {e}");
+                    tokio::time::sleep(Duration::from_millis(200 * attempt)).await;
+                }
+                Err(e) => return Err(transport_error(e, timeout)),
+            }
+        }
+    }
+
+    /// Deadline for launches and raw frames, which legitimately run long.
+    fn long_timeout(&self) -> Duration {
+        self.timeout * 2
     }
 
     async fn get(&self, path: &str) -> Result<Bytes> {
-        let resp = self
-            .http
-            .get(format!("http://localhost{path}"))
-            .send()
-            .await?;
+        let resp = self.send(self.http.get(self.url(path))).await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{path}: {status} {text}");
+            return Err(status_error(path, resp).await);
         }
         Ok(resp.bytes().await?)
     }
 
+    async fn post(&self, path: &str) -> Result<()> {
+        let resp = self.send(self.http.post(self.url(path))).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(status_error(path, resp).await);
+        }
+        Ok(())
+    }
+
+    async fn post_for<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let resp = self.send(self.http.post(self.url(path))).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(status_error(path, resp).await);
+        }
+        Ok(resp.json().await?)
+    }
+
     async fn post_json(&self, path: &str, json: &impl serde::Serialize) -> Result<()> {
+        self.post_json_within(path, json, self.timeout).await
+    }
+
+    async fn post_json_within(
+        &self,
+        path: &str,
+        json: &impl serde::Serialize,
+        timeout: Duration,
+    ) -> Result<()> {
         let resp = self
-            .http
-            .post(format!("http://localhost{path}"))
-            .json(json)
-            .send()
+            .send_within(self.http.post(self.url(path)).json(json), timeout)
             .await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{path}: {status} {text}");
+            return Err(status_error(path, resp).await);
         }
         Ok(())
     }
 
-    pub async fn ensure_screen(&self, name: &str, package: &str) -> Result<()> {
-        self.post_json(
-            "/screens",
-            &CreateScreenRequest {
-                name: name.to_string(),
-                width: 1080,
-                height: 1920,
-                dpi: 240,
-                timeout_secs: 300,
-                package: package.to_string(),
+    async fn post_json_for<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        json: &impl serde::Serialize,
+    ) -> Result<T> {
+        self.post_json_for_within(path, json, self.timeout).await
+    }
+
+    async fn post_json_for_within<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        json: &impl serde::Serialize,
+        timeout: Duration,
+    ) -> Result<T> {
+        let resp = self
+            .send_within(self.http.post(self.url(path)).json(json), timeout)
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(status_error(path, resp).await);
+        }
+        Ok(serde_json::from_slice(&resp.bytes().await?)?)
+    }
+
+    /// Get-or-create the screen; returns its package when this call bound it (a new screen,
+    /// or one whose package was released).
+    pub async fn ensure_screen(
+        &self,
+        name: &str,
+        settings: &ScreenSettings,
+    ) -> Result<Option<String>> {
+        let path = "/screens";
+        let resp = self
+            .send(self.http.post(self.url(path)).json(&settings.request(name)))
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(status_error(path, resp).await);
+        }
+        if status != reqwest::StatusCode::CREATED {
+            return Ok(None);
+        }
+        let info: ScreenInfo = resp.json().await?;
+        Ok(Some(info.assigned_package))
+    }
+
+    /// Get-or-create several screens at once; fields an entry leaves out come from `settings`.
+    pub async fn create_screens(
+        &self,
+        mut entries: Vec<serde_json::Map<String, serde_json::Value>>,
+        settings: &ScreenSettings,
+    ) -> Result<Vec<BatchScreenResult>> {
+        let serde_json::Value::Object(defaults) = serde_json::to_value(settings.request(""))?
+        else {
+            unreachable!("screen requests serialize to objects");
+        };
+        for entry in &mut entries {
+            if !entry.contains_key("name") {
+                bail!("every screen needs a name");
+            }
+            for (key, value) in &defaults {
+                entry.entry(key.as_str()).or_insert_with(|| value.clone());
+            }
+        }
+        self.post_json_for("/screens/batch", &entries).await
+    }
+
+    pub async fn list_screens(&self) -> Result<Vec<ScreenInfo>> {
+        let body = self.get("/debug/screens").await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub async fn ime(&self, screen: &str) -> Result<ImeInfo> {
+        let body = self.get(&format!("/screens/{screen}/ime")).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Close the soft keyboard (BACK) if it is shown; returns its state afterwards.
+    pub async fn hide_ime(&self, screen: &str) -> Result<ImeInfo> {
+        self.post_for(&format!("/screens/{screen}/hide-ime")).await
+    }
+
+    pub async fn app_state(&self, screen: &str) -> Result<AppProcessState> {
+        let body = self.get(&format!("/screens/{screen}/app-state")).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub async fn appops(&self, screen: &str, op: Option<&str>) -> Result<Vec<AppOpMode>> {
+        let mut url = format!("/screens/{screen}/appops");
+        if let Some(op) = op {
+            push_query(&mut url, "op", op);
+        }
+        let body = self.get(&url).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub async fn set_appop(&self, screen: &str, op: &str, mode: &str) -> Result<Vec<AppOpMode>> {
+        self.post_json_for(
+            &format!("/screens/{screen}/appops"),
+            &AppOpsRequest {
+                op: op.to_string(),
+                mode: mode.to_string(),
             },
         )
         .await
     }
 
+    pub async fn installed_packages(&self, spec: &str) -> Result<InstalledPackages> {
+        let mut url = String::from("/packages/installed");
+        push_query(&mut url, "package", spec);
+        let body = self.get(&url).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
     pub async fn info(&self, screen: &str) -> Result<ScreenInfo> {
         let body = self.get(&format!("/screens/{screen}/info")).await?;
         Ok(serde_json::from_slice(&body)?)
     }
 
-    pub async fn screenshot(&self, screen: &str, no_wait: bool) -> Result<(Bytes, Option<u64>)> {
+    pub async fn history(&self, screen: &str, limit: Option<usize>) -> Result<Vec<HistoryEntry>> {
+        let mut url = format!("/screens/{screen}/history");
+        if let Some(limit) = limit {
+            push_query(&mut url, "limit", limit);
+        }
+        let body = self.get(&url).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub async fn foreground(&self, screen: &str) -> Result<ForegroundInfo> {
+        let body = self.get(&format!("/screens/{screen}/foreground")).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Move the bound app's running task onto this screen; returns the new foreground.
+    pub async fn adopt(&self, screen: &str) -> Result<ForegroundInfo> {
+        self.post_for(&format!("/screens/{screen}/adopt")).await
+    }
+
+    pub async fn screenshot(
+        &self,
+        screen: &str,
+        no_wait: bool,
+        opts: &ScreenshotOptions<'_>,
+    ) -> Result<Screenshot> {
         let mut url = format!("/screens/{screen}/screenshot");
-        if no_wait {
-            url.push_str("?no_wait=true");
+        self.push_wait(&mut url, no_wait);
+        if let Some(scale) = opts.scale {
+            push_query(&mut url, "scale", scale);
+        }
+        if let Some(max_dim) = opts.max_dim {
+            push_query(&mut url, "max_dim", max_dim);
+        }
+        if opts.debug {
+            push_query(&mut url, "debug", true);
+        }
+        if let Some(text) = opts.crop_to {
+            push_query(&mut url, "node", text);
+        }
+        if opts.grayscale {
+            push_query(&mut url, "grayscale", true);
+        }
+        if let Some(chroma) = opts.chroma {
+            push_query(&mut url, "chroma", chroma);
+        }
+        let mut timeout = self.timeout;
+        if opts.full {
+            push_query(&mut url, "full", true);
+            timeout = timeout.max(FULL_SCREENSHOT_TIMEOUT);
+        }
+        if let Some(text) = opts.scroll_node {
+            push_query(&mut url, "scroll_node", text);
+        }
+        if opts.settle_frames > 0 {
+            push_query(&mut url, "settle_frames", opts.settle_frames);
+        }
+        if opts.raw {
+            push_query(&mut url, "format", "raw");
+        }
+        if opts.avif {
+            push_query(&mut url, "format", "avif");
+        }
+        if let Some(quality) = opts.quality {
+            push_query(&mut url, "quality", quality);
         }
         let resp = self
-            .http
-            .get(format!("http://localhost{url}"))
-            .send()
+            .send_within(self.http.get(self.url(&url)), timeout)
             .await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{url}: {status} {text}");
+            return Err(status_error(&url, resp).await);
         }
-        let wait_ms = resp
-            .headers()
-            .get("X-Wait-Ms")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<u64>().ok());
-        Ok((resp.bytes().await?, wait_ms))
+        Ok(Screenshot {
+            wait_ms: header_value(&resp, "X-Wait-Ms"),
+            scale: header_value(&resp, "X-Scale").unwrap_or(1.0),
+            frozen: header_value(&resp, "X-Frame-Frozen").unwrap_or(false),
+            age_ms: header_value(&resp, "X-Frame-Age-Ms"),
+            interaction_age_ms: header_value(&resp, "X-Interaction-Age-Ms"),
+            width: header_value(&resp, "X-Width"),
+            height: header_value(&resp, "X-Height"),
+            pages: header_value(&resp, "X-Pages"),
+            pixel_format: header_value(&resp, "X-Pixel-Format"),
+            data: resp.bytes().await?,
+        })
     }
 
-    pub async fn raw_frame(&self, screen: &str, no_wait: bool) -> Result<Option<RawFrame>> {
+    /// Next raw frame. With `base`, the frame held from the previous call, the server may
+    /// send only the region that changed; it is patched into a copy of `base` here.
+    pub async fn raw_frame(
+        &self,
+        screen: &str,
+        no_wait: bool,
+        base: Option<&RawFrame>,
+    ) -> Result<Option<RawFrame>> {
         let mut url = format!("/screens/{screen}/frame/raw");
-        if no_wait {
-            url.push_str("?no_wait=true");
+        self.push_wait(&mut url, no_wait);
+        if let Some(base) = base {
+            push_query(&mut url, "base", base.seq);
         }
         let resp = self
-            .http
-            .get(format!("http://localhost{url}"))
-            .send()
+            .send_within(self.http.get(self.url(&url)), self.long_timeout())
             .await?;
         let status = resp.status();
         if status == reqwest::StatusCode::NO_CONTENT {
             return Ok(None);
         }
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{url}: {status} {text}");
+            return Err(status_error(&url, resp).await);
         }
 
         let headers = resp.headers().clone();
@@ -127,7 +620,8 @@ impl Client {
                 .ok_or_else(|| anyhow::anyhow!("missing or invalid {name} header"))
         };
 
-        Ok(Some(RawFrame {
+        let region = header_value::<String>(&resp, "X-Region");
+        let mut frame = RawFrame {
             width: parse_i32("X-Frame-Width")?,
             height: parse_i32("X-Frame-Height")?,
             stride: parse_i32("X-Frame-Stride")?,
@@ -135,23 +629,26 @@ impl Client {
             seq: parse_u64("X-Frame-Seq")?,
             timestamp_ms: parse_u64("X-Frame-Timestamp-Ms")?,
             data: resp.bytes().await?,
-        }))
+        };
+        if let Some(region) = region {
+            let base = base.ok_or_else(|| anyhow::anyhow!("region frame without a base"))?;
+            frame = patch_region(base, frame, &region)?;
+        }
+        Ok(Some(frame))
     }
 
-    pub async fn a11y(&self, screen: &str, no_wait: bool) -> Result<(A11yTree, Option<u64>)> {
-        let mut url = format!("/screens/{screen}/a11y");
-        if no_wait {
-            url.push_str("?no_wait=true");
-        }
-        let resp = self
-            .http
-            .get(format!("http://localhost{url}"))
-            .send()
-            .await?;
+    /// `peek` reads the tree without counting as activity on the screen (and without waiting).
+    pub async fn a11y(
+        &self,
+        screen: &str,
+        no_wait: bool,
+        peek: bool,
+    ) -> Result<(A11yTree, Option<u64>)> {
+        let url = self.a11y_url(screen, no_wait, peek);
+        let resp = self.send(self.http.get(self.url(&url))).await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{url}: {status} {text}");
+            return Err(status_error(&url, resp).await);
         }
         let wait_ms = resp
             .headers()
@@ -162,57 +659,105 @@ impl Client {
         Ok((serde_json::from_slice(&body)?, wait_ms))
     }
 
-    pub async fn tap(&self, screen: &str, x: f32, y: f32, no_wait: bool) -> Result<Option<u64>> {
+    fn tap_url(&self, screen: &str, no_wait: bool, coord_space: CoordSpace) -> String {
         let mut url = format!("/screens/{screen}/tap");
-        if no_wait {
-            url.push_str("?no_wait=true");
+        self.push_wait(&mut url, no_wait);
+        if coord_space != CoordSpace::Device {
+            push_query(&mut url, "coord_space", coord_space.as_str());
         }
+        url
+    }
+
+    fn a11y_url(&self, screen: &str, no_wait: bool, peek: bool) -> String {
+        let mut url = format!("/screens/{screen}/a11y");
+        if peek {
+            push_query(&mut url, "peek", true);
+        } else {
+            self.push_wait(&mut url, no_wait);
+        }
+        url
+    }
+
+    /// Windows on the screen, topmost first, without their nodes.
+    pub async fn windows(&self, screen: &str) -> Result<Vec<WindowInfo>> {
+        let body = self.get(&format!("/screens/{screen}/windows")).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub async fn a11y_xml(&self, screen: &str, no_wait: bool, peek: bool) -> Result<String> {
+        let mut url = self.a11y_url(screen, no_wait, peek);
+        push_query(&mut url, "format", "xml");
+        let body = self.get(&url).await?;
+        Ok(String::from_utf8(body.to_vec())?)
+    }
+
+    pub async fn tap(
+        &self,
+        screen: &str,
+        x: f32,
+        y: f32,
+        no_wait: bool,
+        coord_space: CoordSpace,
+    ) -> Result<Interaction> {
+        let url = self.tap_url(screen, no_wait, coord_space);
         let resp = self
-            .http
-            .post(format!("http://localhost{url}"))
-            .json(&TapRequest { x, y })
-            .send()
+            .send(self.http.post(self.url(&url)).json(&TapRequest { x, y }))
             .await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{url}: {status} {text}");
+            return Err(status_error(&url, resp).await);
         }
-        let wait_ms = resp
-            .headers()
-            .get("X-Wait-Ms")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<u64>().ok());
-        Ok(wait_ms)
+        Ok(Interaction::from_headers(&resp))
     }
 
-    pub async fn swipe(
+    /// Touch every point at the same instant, one finger each (device pixels).
+    pub async fn multi_tap(
         &self,
         screen: &str,
-        x1: f32,
-        y1: f32,
-        x2: f32,
-        y2: f32,
-        duration_ms: i64,
-    ) -> Result<()> {
-        self.post_json(
-            &format!("/screens/{screen}/swipe"),
-            &SwipeRequest {
-                x1,
-                y1,
-                x2,
-                y2,
-                duration_ms,
-            },
-        )
-        .await
+        points: &[(f32, f32)],
+        no_wait: bool,
+    ) -> Result<Interaction> {
+        let points = points.iter().map(|&(x, y)| TapRequest { x, y }).collect();
+        let mut url = format!("/screens/{screen}/multi-tap");
+        self.push_wait(&mut url, no_wait);
+        let resp = self
+            .send(
+                self.http
+                    .post(self.url(&url))
+                    .json(&MultiTapRequest { points }),
+            )
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(status_error(&url, resp).await);
+        }
+        Ok(Interaction::from_headers(&resp))
+    }
+
+    pub async fn tap_snapshot(
+        &self,
+        screen: &str,
+        x: f32,
+        y: f32,
+        no_wait: bool,
+        coord_space: CoordSpace,
+    ) -> Result<Snapshot> {
+        let mut url = self.tap_url(screen, no_wait, coord_space);
+        push_query(&mut url, "snapshot", true);
+        self.post_json_for(&url, &TapRequest { x, y }).await
     }
 
-    pub async fn type_text(&self, screen: &str, text: &str) -> Result<()> {
+    pub async fn swipe(&self, screen: &str, swipe: &SwipeRequest) -> Result<()> {
+        self.post_json(&format!("/screens/{screen}/swipe"), swipe)
+            .await
+    }
+
+    pub async fn type_text(&self, screen: &str, text: &str, paste: bool) -> Result<()> {
         self.post_json(
             &format!("/screens/{screen}/type"),
             &TypeRequest {
                 text: text.to_string(),
+                paste,
             },
         )
         .await
@@ -223,52 +768,169 @@ impl Client {
             .await
     }
 
-    pub async fn launch(&self, screen: &str, no_wait: bool) -> Result<Option<u64>> {
-        let mut url = format!("/screens/{screen}/launch");
-        if no_wait {
-            url.push_str("?no_wait=true");
+    pub async fn key_combo(&self, screen: &str, keycodes: &[i32]) -> Result<()> {
+        self.post_json(
+            &format!("/screens/{screen}/key-combo"),
+            &KeyComboRequest {
+                keycodes: keycodes.to_vec(),
+            },
+        )
+        .await
+    }
+
+    pub async fn select(&self, screen: &str, text: &str, start: i32, end: i32) -> Result<()> {
+        self.post_json(
+            &format!("/screens/{screen}/select"),
+            &SelectRequest {
+                text: text.to_string(),
+                start,
+                end,
+            },
+        )
+        .await
+    }
+
+    /// Tap the field and type into it server-side; returns the node that was tapped.
+    pub async fn fill(&self, screen: &str, req: &FillRequest) -> Result<A11yNode> {
+        self.post_json_for(&format!("/screens/{screen}/fill"), req)
+            .await
+    }
+
+    pub async fn focus(&self, screen: &str, direction: &str) -> Result<A11yNode> {
+        self.post_json_for(
+            &format!("/screens/{screen}/focus"),
+            &FocusRequest {
+                direction: direction.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// Launch the bound package, through `intent` when given instead of its launcher activity.
+    pub async fn launch(
+        &self,
+        screen: &str,
+        no_wait: bool,
+        intent: Option<&LaunchIntentRequest>,
+        idle_timeout_ms: Option<i64>,
+        global_timeout_ms: Option<i64>,
+        prefetch: bool,
+    ) -> Result<Interaction> {
+        let mut url = match intent {
+            Some(_) => format!("/screens/{screen}/launch-intent"),
+            None => format!("/screens/{screen}/launch"),
+        };
+        self.push_wait(&mut url, no_wait);
+        if let Some(ms) = idle_timeout_ms {
+            push_query(&mut url, "idle_timeout_ms", ms);
         }
-        let resp = self
-            .http
-            .post(format!("http://localhost{url}"))
-            .send()
-            .await?;
+        let mut timeout = self.long_timeout();
+        if let Some(ms) = global_timeout_ms {
+            push_query(&mut url, "global_timeout_ms", ms);
+            timeout = timeout.max(self.timeout + Duration::from_millis(ms.max(0) as u64));
+        }
+        if prefetch {
+            push_query(&mut url, "prefetch", true);
+        }
+        let mut req = self.http.post(self.url(&url));
+        if let Some(intent) = intent {
+            req = req.json(intent);
+        }
+        let resp = self.send_within(req, timeout).await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            bail!("{url}: {status} {text}");
+            return Err(status_error(&url, resp).await);
         }
-        let wait_ms = resp
-            .headers()
-            .get("X-Wait-Ms")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<u64>().ok());
-        Ok(wait_ms)
+        Ok(Interaction::from_headers(&resp))
     }
 
-    pub async fn stop(&self, screen: &str) -> Result<()> {
-        let _ = self
-            .http
-            .post(format!("http://localhost/screens/{screen}/stop"))
-            .send()
+    /// Switch a device-wide toggle (`airplane-mode`, `wifi`, `mobile-data`); returns the
+    /// state the device reports afterwards.
+    pub async fn set_device_toggle(&self, toggle: &str, on: bool) -> Result<bool> {
+        let state: ToggleState = self
+            .post_json_for(&format!("/device/{toggle}"), &ToggleState { on })
             .await?;
-        Ok(())
+        Ok(state.on)
     }
 
-    pub async fn reset(&self, screen: &str) -> Result<()> {
-        let _ = self
-            .http
-            .post(format!("http://localhost/screens/{screen}/reset"))
-            .send()
+    pub async fn set_device_time(&self, iso8601: &str, disable_auto_time: bool) -> Result<i64> {
+        let time: DeviceTime = self
+            .post_json_for(
+                "/device/time",
+                &SetTimeRequest {
+                    iso8601: iso8601.to_string(),
+                    disable_auto_time,
+                },
+            )
             .await?;
-        Ok(())
+        Ok(time.epoch_ms)
+    }
+
+    pub async fn input_methods(&self) -> Result<InputMethods> {
+        let body = self.get("/device/ime").await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Make `id` the device's keyboard; returns the input methods afterwards.
+    pub async fn set_input_method(&self, id: &str) -> Result<InputMethods> {
+        self.post_json_for("/device/ime", &SetInputMethodRequest { id: id.to_string() })
+            .await
+    }
+
+    pub async fn notifications(&self, screen: &str) -> Result<Vec<Notification>> {
+        let body = self
+            .get(&format!("/screens/{screen}/notifications"))
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Expand the notification shade and list what it shows.
+    pub async fn open_notifications(&self, screen: &str) -> Result<Vec<Notification>> {
+        self.post_for(&format!("/screens/{screen}/notifications/open"))
+            .await
+    }
+
+    pub async fn stop(&self, screen: &str) -> Result<()> {
+        self.post(&format!("/screens/{screen}/stop")).await
+    }
+
+    /// End waits the server is running for this screen (wait-for-idle, wait-for, auto-wait).
+    pub async fn cancel(&self, screen: &str) -> Result<()> {
+        self.post(&format!("/screens/{screen}/cancel")).await
+    }
+
+    /// Keep the screen from being reaped without doing anything on it.
+    pub async fn heartbeat(&self, screen: &str) -> Result<()> {
+        self.post(&format!("/screens/{screen}/heartbeat")).await
+    }
+
+    pub async fn reset(&self, screen: &str) -> Result<()> {
+        self.post(&format!("/screens/{screen}/reset")).await
+    }
+
+    pub async fn release_package(&self, screen: &str, package: Option<&str>) -> Result<ScreenInfo> {
+        self.post_json_for(
+            &format!("/screens/{screen}/release-package"),
+            &ReleasePackageRequest {
+                package: package.map(str::to_string),
+            },
+        )
+        .await
     }
 
-    pub async fn open_url(&self, screen: &str, url: &str) -> Result<()> {
+    pub async fn open_url(
+        &self,
+        screen: &str,
+        url: &str,
+        activity: Option<&str>,
+        extras: Vec<IntentExtra>,
+    ) -> Result<()> {
         self.post_json(
             &format!("/screens/{screen}/open-url"),
             &OpenUrlRequest {
                 url: url.to_string(),
+                activity: activity.map(str::to_string),
+                extras,
             },
         )
         .await
@@ -280,13 +942,47 @@ impl Client {
         idle_timeout_ms: i64,
         global_timeout_ms: i64,
     ) -> Result<()> {
-        self.post_json(
+        self.post_json_within(
             &format!("/screens/{screen}/wait-for-idle"),
             &WaitForIdleRequest {
                 idle_timeout_ms,
                 global_timeout_ms,
             },
+            self.timeout + Duration::from_millis(global_timeout_ms.max(0) as u64),
+        )
+        .await
+    }
+
+    pub async fn wait_for_node(
+        &self,
+        screen: &str,
+        text: &str,
+        timeout_ms: u64,
+        gone: bool,
+    ) -> Result<WaitForNodeResponse> {
+        self.post_json_for_within(
+            &format!("/screens/{screen}/wait-for-node"),
+            &WaitForNodeRequest {
+                selector: Selector {
+                    text: text.to_string(),
+                },
+                timeout_ms,
+                gone,
+            },
+            self.timeout + Duration::from_millis(timeout_ms),
         )
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_restores_dialog_text() {
+        let header = b"%C2%ABKarten%C2%BB wurde beendet 100%25";
+        assert_eq!(percent_decode(header), "«Karten» wurde beendet 100%");
+        assert_eq!(percent_decode(b"50% off"), "50% off");
+    }
+}
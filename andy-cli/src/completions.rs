@@ -0,0 +1,224 @@
+//! Shell completion scripts generated from the argh command tree.
+
+use argh::{CommandInfoWithArgs, FlagInfoKind};
+
+#[derive(Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+pub fn parse_shell(value: &str) -> Result<Shell, String> {
+    match value {
+        "bash" => Ok(Shell::Bash),
+        "zsh" => Ok(Shell::Zsh),
+        "fish" => Ok(Shell::Fish),
+        _ => Err(format!("expected bash, zsh or fish, got {value:?}")),
+    }
+}
+
+/// A command reachable from the root, with the subcommand names leading to it.
+struct Node<'a> {
+    path: Vec<&'a str>,
+    info: &'a CommandInfoWithArgs,
+}
+
+impl Node<'_> {
+    fn flags(&self) -> impl Iterator<Item = &str> {
+        self.info
+            .flags
+            .iter()
+            .filter(|flag| !flag.hidden)
+            .map(|flag| flag.long)
+    }
+
+    fn subcommands(&self) -> impl Iterator<Item = &str> {
+        visible(self.info).map(|sub| sub.name)
+    }
+}
+
+fn visible(info: &CommandInfoWithArgs) -> impl Iterator<Item = &argh::SubCommandInfo> {
+    // `_log-daemon` and the like are internal
+    info.commands
+        .iter()
+        .filter(|sub| !sub.name.starts_with('_'))
+}
+
+fn walk<'a>(path: Vec<&'a str>, info: &'a CommandInfoWithArgs, out: &mut Vec<Node<'a>>) {
+    for sub in visible(info) {
+        let mut child = path.clone();
+        child.push(sub.name);
+        walk(child, &sub.command, out);
+    }
+    out.push(Node { path, info });
+}
+
+pub fn script(shell: Shell, bin: &str, root: &CommandInfoWithArgs) -> String {
+    let mut nodes = Vec::new();
+    walk(vec![bin], root, &mut nodes);
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    match shell {
+        Shell::Bash => bash(bin, &nodes),
+        Shell::Zsh => zsh(bin, &nodes),
+        Shell::Fish => fish(bin, &nodes),
+    }
+}
+
+/// `case` arms that advance `cmdpath` past each subcommand word, shared by bash and zsh.
+/// An option's value is skipped, so `--screen tap` doesn't read as the `tap` command.
+fn path_arms(nodes: &[Node]) -> String {
+    let mut arms = String::new();
+    for node in nodes {
+        let options = node
+            .info
+            .flags
+            .iter()
+            .filter(|flag| matches!(flag.kind, FlagInfoKind::Option { .. }));
+        for flag in options {
+            let short = flag
+                .short
+                .map(|c| format!("|\"{}:-{c}\"", node.path.join(" ")));
+            arms.push_str(&format!(
+                "            \"{}:{}\"{}) ((i++)) ;;\n",
+                node.path.join(" "),
+                flag.long,
+                short.unwrap_or_default()
+            ));
+        }
+        for sub in node.subcommands() {
+            arms.push_str(&format!(
+                "            \"{}:{sub}\") cmdpath=\"$cmdpath {sub}\" ;;\n",
+                node.path.join(" ")
+            ));
+        }
+    }
+    arms
+}
+
+fn words(node: &Node) -> String {
+    node.subcommands()
+        .chain(node.flags())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bash(bin: &str, nodes: &[Node]) -> String {
+    let mut out = format!(
+        "_{bin}() {{\n    \
+             local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" cmdpath={bin} opts i\n    \
+             for ((i = 1; i < COMP_CWORD; i++)); do\n        \
+                 case \"$cmdpath:${{COMP_WORDS[i]}}\" in\n"
+    );
+    out.push_str(&path_arms(nodes));
+    out.push_str("        esac\n    done\n    case \"$cmdpath\" in\n");
+    for node in nodes {
+        out.push_str(&format!(
+            "        \"{}\") opts=\"{}\" ;;\n",
+            node.path.join(" "),
+            words(node)
+        ));
+    }
+    out.push_str("    esac\n    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n}\n");
+    // Positionals are mostly paths and free text: fall back to file names
+    out.push_str(&format!("complete -o default -F _{bin} {bin}\n"));
+    out
+}
+
+fn zsh(bin: &str, nodes: &[Node]) -> String {
+    let mut out = format!(
+        "#compdef {bin}\n\n\
+         _{bin}() {{\n    \
+             local cmdpath={bin} i\n    \
+             local -a opts\n    \
+             for ((i = 2; i < CURRENT; i++)); do\n        \
+                 case \"$cmdpath:${{words[i]}}\" in\n"
+    );
+    out.push_str(&path_arms(nodes));
+    out.push_str("        esac\n    done\n    case \"$cmdpath\" in\n");
+    for node in nodes {
+        out.push_str(&format!(
+            "        \"{}\") opts=({}) ;;\n",
+            node.path.join(" "),
+            words(node)
+        ));
+    }
+    out.push_str("    esac\n    compadd -a opts\n    _files\n}\n\n");
+    out.push_str(&format!("compdef _{bin} {bin}\n"));
+    out
+}
+
+fn fish(bin: &str, nodes: &[Node]) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut out = String::new();
+    for node in nodes {
+        let mut conditions: Vec<String> = node.path[1..]
+            .iter()
+            .map(|name| format!("__fish_seen_subcommand_from {name}"))
+            .collect();
+        // Subcommands and flags are offered until a subcommand is typed
+        let subs: Vec<&str> = node.subcommands().collect();
+        if !subs.is_empty() {
+            conditions.push(format!(
+                "not __fish_seen_subcommand_from {}",
+                subs.join(" ")
+            ));
+        }
+        let choosing = conditions.join("; and ");
+        for sub in visible(node.info) {
+            out.push_str(&format!(
+                "complete -c {bin} -n {} -a {} -d {}\n",
+                quote(&choosing),
+                sub.name,
+                quote(sub.command.description)
+            ));
+        }
+        for flag in node.info.flags.iter().filter(|flag| !flag.hidden) {
+            let value = match flag.kind {
+                FlagInfoKind::Switch => "",
+                FlagInfoKind::Option { .. } => " -r",
+            };
+            out.push_str(&format!(
+                "complete -c {bin} -n {} -l {}{value} -d {}\n",
+                quote(&choosing),
+                flag.long.trim_start_matches("--"),
+                quote(flag.description)
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argh::ArgsInfo;
+
+    fn script_for(shell: Shell) -> String {
+        script(shell, "andy", &crate::Cli::get_args_info())
+    }
+
+    #[test]
+    fn bash_skips_option_values() {
+        let script = script_for(Shell::Bash);
+        assert!(script.contains("\"andy:--screen\") ((i++)) ;;"));
+        assert!(script.contains("\"andy tap:--coord-space\") ((i++)) ;;"));
+        assert!(script.contains("\"andy:tap\") cmdpath=\"$cmdpath tap\" ;;"));
+        assert!(!script.contains("--no-wait\") ((i++))"));
+    }
+
+    #[test]
+    fn zsh_skips_option_values() {
+        let script = script_for(Shell::Zsh);
+        assert!(script.starts_with("#compdef andy"));
+        assert!(script.contains("\"andy:--screen\") ((i++)) ;;"));
+        assert!(!script.contains("--no-wait\") ((i++))"));
+    }
+
+    #[test]
+    fn fish_marks_options_as_taking_a_value() {
+        let script = script_for(Shell::Fish);
+        assert!(script.contains("-l screen -r -d 'screen name'"));
+        assert!(script.contains("-l no-wait -d"));
+    }
+}
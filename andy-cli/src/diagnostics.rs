@@ -0,0 +1,197 @@
+//! Self-test for the coordinate and pixel mix-ups some devices hit: a display whose
+//! size disagrees with `/info`, frames whose bytes aren't laid out as RGBA, and taps
+//! that land somewhere other than where the a11y tree says.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::a11y::{A11yNode, A11yTree};
+use crate::client::{Client, ScreenshotOptions};
+use crate::types::{CoordSpace, ScreenInfo};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    /// Nothing on screen to test with, or nothing to check against.
+    Skip,
+}
+
+impl Status {
+    pub fn label(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "FAIL",
+            Status::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, ok: bool, detail: String) -> Self {
+        let status = if ok { Status::Pass } else { Status::Fail };
+        Self {
+            name,
+            status,
+            detail,
+        }
+    }
+}
+
+pub async fn run(client: &Client, screen: &str) -> Result<Vec<Check>> {
+    let info = client.info(screen).await?;
+    let raw = client
+        .screenshot(
+            screen,
+            false,
+            &ScreenshotOptions {
+                raw: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+    let jpeg = client
+        .screenshot(
+            screen,
+            true,
+            &ScreenshotOptions {
+                scale: Some(1.0),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let expected = (info.width as u32, info.height as u32);
+    let raw_size = (raw.width.unwrap_or(0), raw.height.unwrap_or(0));
+    let jpeg_size = (jpeg.width.unwrap_or(0), jpeg.height.unwrap_or(0));
+    let mut checks = vec![
+        Check::new(
+            "geometry",
+            raw_size == expected && jpeg_size == expected,
+            format!(
+                "/info {}x{}, raw frame {}x{}, unscaled jpeg {}x{}",
+                expected.0, expected.1, raw_size.0, raw_size.1, jpeg_size.0, jpeg_size.1
+            ),
+        ),
+        pixel_layout(&raw.data, raw.pixel_format.as_deref()),
+    ];
+    let (tree, _) = client.a11y(screen, true, false).await?;
+    checks.push(tap_landing(client, screen, &info, &tree).await?);
+    Ok(checks)
+}
+
+/// The display is opaque, so in RGBA every fourth byte is 0xFF. Frames read as ARGB or
+/// with the wrong stride break that pattern and fail. The server converts BGRA surfaces
+/// itself; the layout the surface reported is passed on, since a surface that mislabels
+/// its frames shows up as red and blue swapped and needs `ANDY_PIXEL_FORMAT`.
+fn pixel_layout(rgba: &[u8], surface: Option<&str>) -> Check {
+    let pixels = rgba.len() / 4;
+    let step = (pixels / 1000).max(1);
+    let sampled: Vec<&[u8]> = rgba.chunks_exact(4).step_by(step).collect();
+    let opaque = sampled.iter().filter(|px| px[3] == 0xFF).count();
+    let mut detail = format!(
+        "alpha 0xFF in the 4th byte of {opaque}/{} sampled pixels",
+        sampled.len()
+    );
+    if let Some(surface) = surface {
+        let other = if surface == "bgra" { "rgba" } else { "bgra" };
+        detail.push_str(&format!(
+            "; the surface reports {surface}, converted to RGBA (if red and blue look \
+             swapped, restart with ANDY_PIXEL_FORMAT={other})"
+        ));
+    }
+    Check::new(
+        "pixel layout",
+        !sampled.is_empty() && opaque == sampled.len(),
+        detail,
+    )
+}
+
+/// Tap the middle of a text field and check that it is the one that took focus.
+async fn tap_landing(
+    client: &Client,
+    screen: &str,
+    info: &ScreenInfo,
+    tree: &A11yTree,
+) -> Result<Check> {
+    let is_field = |node: &A11yNode| {
+        node.class_name
+            .as_deref()
+            .is_some_and(|class| class.ends_with("EditText"))
+            && node.bounds.right > node.bounds.left
+            && node.bounds.bottom > node.bounds.top
+            && node.bounds.right <= info.width
+            && node.bounds.bottom <= info.height
+    };
+    let Some(field) = tree
+        .windows
+        .iter()
+        .flat_map(|w| &w.nodes)
+        .find(|node| is_field(node) && !node.focused)
+    else {
+        return Ok(Check {
+            name: "tap landing",
+            status: Status::Skip,
+            detail: "no unfocused text field on screen; open a screen with one and rerun".into(),
+        });
+    };
+    let (x, y) = field.bounds.center();
+    let b = &field.bounds;
+    let at = (b.left, b.top, b.right, b.bottom);
+    client.tap(screen, x, y, false, CoordSpace::Device).await?;
+    let (after, _) = client.a11y(screen, true, false).await?;
+    let focused = after
+        .windows
+        .iter()
+        .flat_map(|w| &w.nodes)
+        .find(|node| node.focused && is_field(node));
+    let landed = focused.is_some_and(|node| {
+        let b = &node.bounds;
+        (b.left, b.top, b.right, b.bottom) == at
+    });
+    let detail = match focused {
+        _ if landed => format!("tapped {x},{y}; the text field at {at:?} took focus"),
+        Some(node) => {
+            let b = &node.bounds;
+            format!(
+                "tapped {x},{y} on the text field at {at:?}, but the one at {:?} took focus",
+                (b.left, b.top, b.right, b.bottom)
+            )
+        }
+        None => format!("tapped {x},{y} on the text field at {at:?}, but no text field took focus"),
+    };
+    Ok(Check::new("tap landing", landed, detail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_layout_checks_the_alpha_byte() {
+        let argb = [0xFF, 10, 20, 30].repeat(64);
+        assert_eq!(pixel_layout(&argb, None).status, Status::Fail);
+        assert_eq!(pixel_layout(&[], None).status, Status::Fail);
+        let rgba = [10, 20, 30, 0xFF].repeat(64);
+        assert_eq!(pixel_layout(&rgba, None).status, Status::Pass);
+    }
+
+    #[test]
+    fn pixel_layout_names_the_override_for_the_other_layout() {
+        let rgba = [10, 20, 30, 0xFF].repeat(64);
+        let check = pixel_layout(&rgba, Some("bgra"));
+        assert!(check.detail.contains("reports bgra"), "{}", check.detail);
+        assert!(
+            check.detail.contains("ANDY_PIXEL_FORMAT=rgba"),
+            "{}",
+            check.detail
+        );
+    }
+}
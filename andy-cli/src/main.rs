@@ -1,28 +1,47 @@
 use anyhow::{Context, Result, bail};
-use argh::FromArgs;
-use std::io::Write;
+use argh::{ArgsInfo, FromArgs, SubCommand};
+use base64::Engine;
+use std::io::{IsTerminal, Write};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::{Command as ProcessCommand, Stdio};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::client::Client;
+use crate::client::{Client, ScreenSettings, ScreenshotOptions};
+use crate::output::Output;
+use crate::types::{
+    CoordSpace, DismissRule, FillRequest, IntentExtra, Interaction, LaunchIntentRequest,
+    ScreenInfo, Screenshot, Snapshot, SwipeRequest, WaitStrategy,
+};
 
 mod a11y;
 mod assets;
 mod client;
+mod completions;
+mod diagnostics;
+mod output;
+mod repl;
 mod runner;
 mod types;
 
 /// Android coordinator CLI
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 struct Cli {
     /// screen name
     #[argh(option, default = "default_screen_from_env()")]
     screen: String,
-    /// bind package or prefix at screen creation, e.g. com.fedi.dev or com.fedi.dev17
+    /// bind package, prefix, or comma-separated pool at screen creation, e.g. com.fedi.dev, com.fedi.dev17, or com.a,com.b
     #[argh(option, default = "default_package_from_env()")]
     package: String,
+    /// never reap the screen for missing heartbeats (interactive debugging)
+    #[argh(switch)]
+    no_timeout: bool,
+    /// tap through permission prompts, ANR and similar system dialogs after interactions
+    #[argh(switch)]
+    auto_dismiss: bool,
+    /// print the result as one JSON object on stdout ({command, ok, ...}) instead of prose
+    #[argh(switch)]
+    json: bool,
 
     #[argh(subcommand)]
     command: Command,
@@ -33,38 +52,129 @@ fn socket_path() -> PathBuf {
     PathBuf::from(format!("{home}/.local/state/andy.sock"))
 }
 
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand)]
 enum Command {
     Info(InfoCmd),
+    Foreground(ForegroundCmd),
+    History(HistoryCmd),
+    AppState(AppStateCmd),
+    Ime(ImeCmd),
+    HideIme(HideImeCmd),
+    Appops(AppopsCmd),
     Screenshot(ScreenshotCmd),
     RawFrame(RawFrameCmd),
     StartRecord(StartRecordCmd),
     StartStream(StartStreamCmd),
     A11y(A11yCmd),
+    Windows(WindowsCmd),
     Tap(TapCmd),
+    MultiTap(MultiTapCmd),
     Swipe(SwipeCmd),
+    Scroll(ScrollCmd),
+    Fling(FlingCmd),
     Type(TypeCmd),
     Key(KeyCmd),
+    KeyCombo(KeyComboCmd),
+    Select(SelectCmd),
+    Fill(FillCmd),
+    Focus(FocusCmd),
     Launch(LaunchCmd),
+    Adopt(AdoptCmd),
     Stop(StopCmd),
     Reset(ResetCmd),
+    ReleasePackage(ReleasePackageCmd),
     OpenUrl(OpenUrlCmd),
     WaitForIdle(WaitForIdleCmd),
+    Cancel(CancelCmd),
+    WaitFor(WaitForCmd),
+    Device(DeviceCmd),
+    Notifications(NotificationsCmd),
+    Diagnostics(DiagnosticsCmd),
+    Screens(ScreensCmd),
+    Repl(ReplCmd),
     Start(StartCmd),
+    Status(StatusCmd),
+    Kill(KillCmd),
     Install(InstallCmd),
     Version(VersionCmd),
+    Completions(CompletionsCmd),
     SyncLogsToFile(SyncLogsToFileCmd),
     LogDaemon(LogDaemonCmd),
 }
 
 /// show screen info
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "info")]
 struct InfoCmd {}
 
+/// show the foreground package and activity on this screen
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "foreground")]
+struct ForegroundCmd {}
+
+/// list the actions the server performed on this screen, oldest first (the last 200 are kept)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "history")]
+struct HistoryCmd {
+    /// only the most recent N actions
+    #[argh(option)]
+    limit: Option<usize>,
+}
+
+/// read or set special access for the bound app through appops (overlay, battery, ...)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "appops")]
+struct AppopsCmd {
+    #[argh(subcommand)]
+    command: AppopsSubcommand,
+}
+
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand)]
+enum AppopsSubcommand {
+    Get(AppopsGetCmd),
+    Set(AppopsSetCmd),
+}
+
+/// show the bound app's appops modes, or just one op's
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "get")]
+struct AppopsGetCmd {
+    /// op name, e.g. SYSTEM_ALERT_WINDOW
+    #[argh(positional)]
+    op: Option<String>,
+}
+
+/// set an appops mode for the bound app, e.g. SYSTEM_ALERT_WINDOW allow
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "set")]
+struct AppopsSetCmd {
+    /// op name, e.g. SYSTEM_ALERT_WINDOW or RUN_ANY_IN_BACKGROUND
+    #[argh(positional)]
+    op: String,
+    /// allow, ignore, deny, default or foreground
+    #[argh(positional)]
+    mode: String,
+}
+
+/// show whether the soft keyboard is up and how much of the screen it covers (JSON)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "ime")]
+struct ImeCmd {}
+
+/// close the soft keyboard (presses BACK only if it is shown)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "hide-ime")]
+struct HideImeCmd {}
+
+/// show whether the bound app is installed, running and in the foreground (JSON)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "app-state")]
+struct AppStateCmd {}
+
 /// take a screenshot and save to path
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "screenshot")]
 struct ScreenshotCmd {
     #[argh(positional)]
@@ -72,10 +182,50 @@ struct ScreenshotCmd {
     /// skip waiting for idle before screenshot
     #[argh(switch)]
     no_wait: bool,
+    /// downscale factor in (0, 1]; use `tap --coord-space screenshot` to tap in image pixels
+    #[argh(option)]
+    scale: Option<f32>,
+    /// downscale further so the longer side is at most this many pixels (X-Scale reports the result)
+    #[argh(option)]
+    max_dim: Option<u32>,
+    /// draw a crosshair where the last tap or swipe landed
+    #[argh(switch)]
+    debug: bool,
+    /// save only the node matching this a11y text (clamped to the screen)
+    #[argh(option)]
+    crop_to: Option<String>,
+    /// save a single-channel grayscale image (smaller, OCR-friendly)
+    #[argh(switch)]
+    grayscale: bool,
+    /// JPEG chroma subsampling: 444 keeps small colored text crisp, 420 (default) is smaller
+    #[argh(option)]
+    chroma: Option<String>,
+    /// scroll through the largest scrollable and stitch its whole content into one tall image
+    #[argh(switch)]
+    full: bool,
+    /// with --full, page through the scrollable with (or containing) this a11y text
+    #[argh(option)]
+    scroll_node: Option<String>,
+    /// discard this many frames (50ms apart, at most 30) first, for entry animations idle misses
+    #[argh(option, default = "0")]
+    settle_frames: u32,
+    /// save unencoded RGBA (width*height*4 bytes, rows top to bottom) instead of JPEG
+    #[argh(switch)]
+    raw: bool,
+    /// save AVIF: smaller than JPEG but seconds to encode (servers built with the avif feature)
+    #[argh(switch)]
+    avif: bool,
+    /// encoder quality 1-100 (default 85 for JPEG, 50 for AVIF)
+    #[argh(option)]
+    quality: Option<u8>,
 }
 
+/// A screenshot older than this is reported when it is also older than the last
+/// interaction: the display sent nothing after the app was poked.
+const STALE_FRAME_MS: u64 = 2000;
+
 /// fetch a changed raw RGBA frame and save it to path
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "raw-frame")]
 struct RawFrameCmd {
     #[argh(positional)]
@@ -86,7 +236,7 @@ struct RawFrameCmd {
 }
 
 /// start recording to a video file until Ctrl-C
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "start-record")]
 struct StartRecordCmd {
     #[argh(positional)]
@@ -97,7 +247,7 @@ struct StartRecordCmd {
 }
 
 /// stream H.264/MPEG-TS to stdout until Ctrl-C
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "start-stream")]
 struct StartStreamCmd {
     /// output fps for the encoded stream
@@ -106,90 +256,567 @@ struct StartStreamCmd {
 }
 
 /// print human-readable accessibility tree
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "a11y")]
 struct A11yCmd {
     /// skip waiting for idle before fetching tree
     #[argh(switch)]
     no_wait: bool,
+    /// print the tree as uiautomator dump XML
+    #[argh(switch)]
+    xml: bool,
+    /// print interesting nodes as CSV rows
+    #[argh(switch)]
+    csv: bool,
+    /// show more nodes; repeat for more (1: all classes, 2: plain leaves, 3: raw with ids)
+    #[argh(switch, short = 'v')]
+    verbose: u8,
+    /// list every item of long lists instead of collapsing look-alike rows
+    #[argh(switch)]
+    no_collapse: bool,
+    /// print bounds as fractions of the screen size (0.000-1.000), for `tap --normalized`
+    #[argh(switch)]
+    percent: bool,
+    /// read the tree without keeping the screen alive (no heartbeat, no wait), for monitoring
+    #[argh(switch)]
+    peek: bool,
 }
 
+/// list the windows on screen, topmost first (layer, type, title, bounds), without their nodes
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "windows")]
+struct WindowsCmd {}
+
 /// tap at coordinates (x,y) or by accessibility text
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "tap")]
 struct TapCmd {
     #[argh(positional)]
-    target: String,
+    target: Option<String>,
     /// skip waiting for idle after tap
     #[argh(switch)]
     no_wait: bool,
     /// retry finding the node up to N times (waiting for idle between attempts)
     #[argh(option, default = "3")]
     tries: u32,
+    /// coordinate space of x,y: device (default) or screenshot (pixels of the last screenshot)
+    #[argh(option, default = "CoordSpace::Device")]
+    coord_space: CoordSpace,
+    /// x,y: tap the matching node (or any clickable node without a label) closest to this point
+    #[argh(option)]
+    near: Option<String>,
+    /// x,y (and --near) are fractions of the screen size, as printed by `a11y --percent`
+    #[argh(switch)]
+    normalized: bool,
+    /// x,y (and --near) are pixels of a WxH image of the whole screen, e.g. a downscaled screenshot
+    #[argh(option)]
+    image_size: Option<String>,
+    /// tap the Nth node (1-based) among those matching the text
+    #[argh(option)]
+    index: Option<usize>,
+    /// when several nodes match the text, tap the first instead of failing
+    #[argh(switch)]
+    first: bool,
+    /// match the target against node text only
+    #[argh(switch)]
+    by_text: bool,
+    /// match the target against content description only (icon buttons)
+    #[argh(switch)]
+    by_desc: bool,
+    /// save screenshot.jpg and a11y.json taken after the tap into this dir, and print the tree
+    #[argh(option)]
+    snapshot: Option<String>,
+    /// tap the clickable node nearest to the right of the node with this text
+    #[argh(option)]
+    right_of: Option<String>,
+    /// tap the clickable node nearest to the left of the node with this text
+    #[argh(option)]
+    left_of: Option<String>,
+    /// tap the clickable node nearest below the node with this text
+    #[argh(option)]
+    below: Option<String>,
+    /// tap the clickable node nearest above the node with this text
+    #[argh(option)]
+    above: Option<String>,
 }
 
-/// swipe gesture
-#[derive(FromArgs)]
+impl TapCmd {
+    /// The anchor text of `--right-of` and friends, with its direction.
+    fn relative(&self) -> Result<Option<(a11y::Direction, &str)>> {
+        let given: Vec<(a11y::Direction, &str)> = [
+            (a11y::Direction::RightOf, &self.right_of),
+            (a11y::Direction::LeftOf, &self.left_of),
+            (a11y::Direction::Below, &self.below),
+            (a11y::Direction::Above, &self.above),
+        ]
+        .into_iter()
+        .filter_map(|(dir, anchor)| Some((dir, anchor.as_deref()?)))
+        .collect();
+        match given.as_slice() {
+            [] => Ok(None),
+            [relative] => {
+                if self.target.is_some() || self.near.is_some() {
+                    bail!(
+                        "{} takes the place of a target and cannot be combined with --near",
+                        relative.0.flag()
+                    );
+                }
+                Ok(Some(*relative))
+            }
+            _ => bail!("only one of --right-of, --left-of, --below and --above can be given"),
+        }
+    }
+}
+
+/// tap 2 to 10 points at once, one finger each (e.g. 100,200 800,200), for controls that need simultaneous touches
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "multi-tap")]
+struct MultiTapCmd {
+    #[argh(positional)]
+    points: Vec<String>,
+    /// skip waiting for idle after the tap
+    #[argh(switch)]
+    no_wait: bool,
+}
+
+/// swipe gesture: `up|down|left|right` (the way the finger moves), or x1 y1 x2 y2 [duration_ms]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "swipe")]
 struct SwipeCmd {
     #[argh(positional)]
-    x1: f32,
-    #[argh(positional)]
-    y1: f32,
+    args: Vec<String>,
+    /// fraction of the screen a direction swipe covers, centered (default 0.6)
+    #[argh(option, default = "0.6")]
+    distance: f32,
+    /// swipe duration in milliseconds (default 300)
+    #[argh(option)]
+    duration_ms: Option<i64>,
+    /// ACTION_MOVE events between touch down and up (default one per 10ms)
+    #[argh(option)]
+    steps: Option<u32>,
+    /// how the finger speeds up or slows down: linear (default), decelerate or accelerate
+    #[argh(option)]
+    easing: Option<String>,
+    /// x1 y1 x2 y2 are fractions of the screen size, as printed by `a11y --percent`
+    #[argh(switch)]
+    normalized: bool,
+}
+
+const DEFAULT_SWIPE_MS: i64 = 300;
+
+/// slow, short drag that moves content in a direction without momentum: `down` reveals what is below
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "scroll")]
+struct ScrollCmd {
     #[argh(positional)]
-    x2: f32,
+    direction: String,
+    /// fraction of the screen to drag across (default 0.4)
+    #[argh(option, default = "0.4")]
+    distance: f32,
+    /// drag duration in milliseconds (default 800)
+    #[argh(option, default = "800")]
+    duration_ms: i64,
+    /// milliseconds the finger rests on the end point before lifting, so the list stops there (default 150)
+    #[argh(option, default = "150")]
+    hold_ms: i64,
+    /// ACTION_MOVE events between touch down and up (default one per 10ms)
+    #[argh(option)]
+    steps: Option<u32>,
+    /// how the finger speeds up or slows down: linear (default), decelerate or accelerate
+    #[argh(option)]
+    easing: Option<String>,
+}
+
+/// fast swipe that flings content with momentum: `down` travels far toward what is below
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "fling")]
+struct FlingCmd {
     #[argh(positional)]
-    y2: f32,
-    /// swipe duration in milliseconds
-    #[argh(positional, default = "300")]
+    direction: String,
+    /// fraction of the screen to swipe across (default 0.6)
+    #[argh(option, default = "0.6")]
+    distance: f32,
+    /// swipe duration in milliseconds (default 80)
+    #[argh(option, default = "80")]
     duration_ms: i64,
+    /// ACTION_MOVE events between touch down and up (default one per 10ms)
+    #[argh(option)]
+    steps: Option<u32>,
+    /// how the finger speeds up or slows down: linear (default), decelerate or accelerate
+    #[argh(option)]
+    easing: Option<String>,
+}
+
+fn swipe_request(
+    [x1, y1, x2, y2]: [f32; 4],
+    duration_ms: i64,
+    steps: Option<u32>,
+    easing: Option<String>,
+) -> SwipeRequest {
+    SwipeRequest {
+        x1,
+        y1,
+        x2,
+        y2,
+        duration_ms,
+        steps,
+        easing,
+        hold_ms: None,
+    }
+}
+
+/// Start and end points, in device pixels, for a swipe whose finger moves toward `dir`.
+fn swipe_points(dir: &str, distance: f32, info: &ScreenInfo) -> Result<[f32; 4]> {
+    if !(distance > 0.0 && distance <= 1.0) {
+        bail!("--distance must be in (0, 1], got {distance}");
+    }
+    let (cx, cy) = (info.width as f32 / 2.0, info.height as f32 / 2.0);
+    let dx = info.width as f32 * distance / 2.0;
+    let dy = info.height as f32 * distance / 2.0;
+    Ok(match dir {
+        "up" => [cx, cy + dy, cx, cy - dy],
+        "down" => [cx, cy - dy, cx, cy + dy],
+        "left" => [cx + dx, cy, cx - dx, cy],
+        "right" => [cx - dx, cy, cx + dx, cy],
+        _ => bail!("unknown direction {dir:?}: expected up, down, left or right"),
+    })
+}
+
+/// Device pixels of a point given as fractions of the screen size.
+fn denormalize((x, y): (f32, f32), info: &ScreenInfo) -> Result<(f32, f32)> {
+    if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+        bail!("normalized coordinates must be in [0, 1], got {x},{y}");
+    }
+    Ok((x * info.width as f32, y * info.height as f32))
+}
+
+/// Device pixels of a point given in pixels of a `WxH` image of the whole screen.
+fn from_image_space((x, y): (f32, f32), size: &str, info: &ScreenInfo) -> Result<(f32, f32)> {
+    let (w, h) = size
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.trim().parse::<f32>().ok()?, h.trim().parse::<f32>().ok()?)))
+        .filter(|&(w, h)| w > 0.0 && h > 0.0)
+        .ok_or_else(|| anyhow::anyhow!("expected --image-size WxH but got \"{size}\""))?;
+    // Downscaling rounds each side to whole pixels, so allow a little slack
+    let (dw, dh) = (info.width as f32, info.height as f32);
+    if ((w / h) / (dw / dh) - 1.0).abs() > 0.02 {
+        bail!(
+            "image size {w}x{h} does not have the screen's aspect ratio ({}x{})",
+            info.width,
+            info.height
+        );
+    }
+    if !(0.0..=w).contains(&x) || !(0.0..=h).contains(&y) {
+        bail!("{x},{y} is outside the {w}x{h} image");
+    }
+    Ok((x * dw / w, y * dh / h))
+}
+
+/// Content scrolls opposite to the finger: scrolling down means swiping up.
+fn content_swipe_points(dir: &str, distance: f32, info: &ScreenInfo) -> Result<[f32; 4]> {
+    let finger = match dir {
+        "up" => "down",
+        "down" => "up",
+        "left" => "right",
+        "right" => "left",
+        other => other,
+    };
+    swipe_points(finger, distance, info)
 }
 
 /// type text
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "type")]
 struct TypeCmd {
     #[argh(positional)]
     text: String,
+    /// insert through the clipboard and a PASTE key, replacing the device clipboard (automatic for emoji and non-ASCII text)
+    #[argh(switch)]
+    paste: bool,
 }
 
-/// send keycode
-#[derive(FromArgs)]
+/// send a key by keycode or name (ENTER, BACK, HOME, TAB, DEL, DPAD_UP, VOLUME_UP, ...)
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "key")]
 struct KeyCmd {
     #[argh(positional)]
-    keycode: i32,
+    key: String,
+}
+
+/// Android `KeyEvent.KEYCODE_*` values for the keys agents press most.
+const KEY_NAMES: &[(&str, i32)] = &[
+    ("HOME", 3),
+    ("BACK", 4),
+    ("DPAD_UP", 19),
+    ("DPAD_DOWN", 20),
+    ("DPAD_LEFT", 21),
+    ("DPAD_RIGHT", 22),
+    ("DPAD_CENTER", 23),
+    ("VOLUME_UP", 24),
+    ("VOLUME_DOWN", 25),
+    ("POWER", 26),
+    ("ALT_LEFT", 57),
+    ("SHIFT_LEFT", 59),
+    ("TAB", 61),
+    ("SPACE", 62),
+    ("ENTER", 66),
+    ("DEL", 67),
+    ("MENU", 82),
+    ("SEARCH", 84),
+    ("PAGE_UP", 92),
+    ("PAGE_DOWN", 93),
+    ("ESCAPE", 111),
+    ("FORWARD_DEL", 112),
+    ("CTRL_LEFT", 113),
+    ("MOVE_HOME", 122),
+    ("MOVE_END", 123),
+    ("VOLUME_MUTE", 164),
+    ("APP_SWITCH", 187),
+];
+
+/// A numeric keycode, a letter, or a name from `KEY_NAMES` (any case, `KEYCODE_` prefix
+/// optional).
+fn parse_keycode(key: &str) -> Result<i32> {
+    if let Ok(code) = key.parse() {
+        return Ok(code);
+    }
+    let upper = key.to_ascii_uppercase();
+    let name = upper.strip_prefix("KEYCODE_").unwrap_or(&upper);
+    // KEYCODE_A..KEYCODE_Z are contiguous
+    if let [letter @ b'A'..=b'Z'] = name.as_bytes() {
+        return Ok(29 + (letter - b'A') as i32);
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, code)| *code)
+        .with_context(|| format!("unknown key {key:?}: use a keycode or a name like ENTER"))
+}
+
+/// press keys together, modifiers first (e.g. CTRL_LEFT A or 113 29 = Ctrl+A), or an alias: select-all, copy, cut, paste, undo
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "key-combo")]
+struct KeyComboCmd {
+    #[argh(positional)]
+    keys: Vec<String>,
+}
+
+/// Ctrl (113) + A/C/X/V/Z.
+const KEY_COMBO_ALIASES: &[(&str, &[i32])] = &[
+    ("select-all", &[113, 29]),
+    ("copy", &[113, 31]),
+    ("cut", &[113, 52]),
+    ("paste", &[113, 50]),
+    ("undo", &[113, 54]),
+];
+
+fn parse_key_combo(keys: &[String]) -> Result<Vec<i32>> {
+    if let [alias] = keys
+        && let Some((_, keycodes)) = KEY_COMBO_ALIASES.iter().find(|(name, _)| name == alias)
+    {
+        return Ok(keycodes.to_vec());
+    }
+    if keys.is_empty() {
+        bail!("key-combo needs keycodes or an alias");
+    }
+    keys.iter().map(|key| parse_keycode(key)).collect()
+}
+
+/// move input focus up|down|left|right like a D-pad (TV layouts) and print the focused node
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "focus")]
+struct FocusCmd {
+    #[argh(positional)]
+    direction: String,
+}
+
+/// select text start..end in the field matching a11y text (start == end moves the cursor)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "select")]
+struct SelectCmd {
+    #[argh(positional)]
+    text: String,
+    #[argh(positional)]
+    start: i32,
+    #[argh(positional)]
+    end: i32,
 }
 
-/// launch the bound package
-#[derive(FromArgs)]
+/// tap the text field labelled by text, content description or hint, then type into it in one step
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "fill")]
+struct FillCmd {
+    #[argh(positional)]
+    target: String,
+    #[argh(positional)]
+    text: String,
+    /// delete what the field held first (select all + DEL)
+    #[argh(switch)]
+    clear: bool,
+    /// press ENTER after typing
+    #[argh(switch)]
+    submit: bool,
+    /// insert through the clipboard, as `type --paste`
+    #[argh(switch)]
+    paste: bool,
+}
+
+/// launch the bound package, optionally with an explicit intent
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "launch")]
 struct LaunchCmd {
     /// skip waiting for idle after launch
     #[argh(switch)]
     no_wait: bool,
+    /// how long the app must stay idle before launch returns, in milliseconds (default 5000)
+    #[argh(option)]
+    idle_timeout_ms: Option<i64>,
+    /// longest launch waits for idle, in milliseconds (default 30000)
+    #[argh(option)]
+    global_timeout_ms: Option<i64>,
+    /// once idle, have the server cache the a11y tree and a screenshot so the next a11y or screenshot returns faster
+    #[argh(switch)]
+    prefetch: bool,
+    /// activity in the bound package to start (.DeepLinkActivity, com.example.Main or
+    /// com.example/.Main) instead of the launcher one
+    #[argh(option)]
+    activity: Option<String>,
+    /// intent action (e.g. android.intent.action.VIEW)
+    #[argh(option)]
+    action: Option<String>,
+    /// intent data URI
+    #[argh(option)]
+    data: Option<String>,
+    /// string extra key=value (repeatable)
+    #[argh(option)]
+    es: Vec<String>,
+    /// int extra key=value (repeatable)
+    #[argh(option)]
+    ei: Vec<String>,
+    /// boolean extra key=true|false (repeatable)
+    #[argh(option)]
+    ez: Vec<String>,
+    /// long extra key=value (repeatable)
+    #[argh(option)]
+    el: Vec<String>,
 }
 
+impl LaunchCmd {
+    /// The explicit intent, if any intent option was given.
+    fn intent(&self) -> Result<Option<LaunchIntentRequest>> {
+        let extras = parse_extras(&self.es, &self.ei, &self.ez, &self.el)?;
+        if self.activity.is_none()
+            && self.action.is_none()
+            && self.data.is_none()
+            && extras.is_empty()
+        {
+            return Ok(None);
+        }
+        Ok(Some(LaunchIntentRequest {
+            activity: self.activity.clone(),
+            action: self.action.clone(),
+            data: self.data.clone(),
+            extras,
+        }))
+    }
+}
+
+/// Typed intent extras from `key=value` options, as `am start --es/--ei/--ez/--el` take them.
+fn parse_extras(
+    es: &[String],
+    ei: &[String],
+    ez: &[String],
+    el: &[String],
+) -> Result<Vec<IntentExtra>> {
+    fn split<'a>(flag: &str, extra: &'a str) -> Result<(String, &'a str)> {
+        match extra.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value)),
+            _ => bail!("malformed --{flag} {extra:?}: expected key=value"),
+        }
+    }
+    fn typed<T: std::str::FromStr>(flag: &str, key: &str, value: &str) -> Result<T> {
+        let kind = match flag {
+            "ez" => "boolean",
+            _ => "integer",
+        };
+        value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("malformed --{flag} {key}={value}: not a {kind}"))
+    }
+
+    let mut extras = Vec::new();
+    for extra in es {
+        let (key, value) = split("es", extra)?;
+        let value = value.to_string();
+        extras.push(IntentExtra::String { key, value });
+    }
+    for extra in ei {
+        let (key, value) = split("ei", extra)?;
+        let value = typed("ei", &key, value)?;
+        extras.push(IntentExtra::Int { key, value });
+    }
+    for extra in ez {
+        let (key, value) = split("ez", extra)?;
+        let value = typed("ez", &key, value)?;
+        extras.push(IntentExtra::Bool { key, value });
+    }
+    for extra in el {
+        let (key, value) = split("el", extra)?;
+        let value = typed("el", &key, value)?;
+        extras.push(IntentExtra::Long { key, value });
+    }
+    Ok(extras)
+}
+
+/// move the bound app, already running elsewhere (e.g. started on the default display), onto this screen
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "adopt")]
+struct AdoptCmd {}
+
 /// stop package
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "stop")]
 struct StopCmd {}
 
 /// clear app data (pm clear)
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "reset")]
 struct ResetCmd {}
 
+/// force-stop and clear the bound app, freeing it for other screens, optionally binding another
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "release-package")]
+struct ReleasePackageCmd {
+    /// package, prefix, or pool to bind next (default: rebind from --package on next command)
+    #[argh(positional)]
+    package: Option<String>,
+}
+
 /// open URL in package
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "open-url")]
 struct OpenUrlCmd {
     #[argh(positional)]
     url: String,
+    /// activity in the bound package to open the URL with (default: whichever handles it)
+    #[argh(option)]
+    activity: Option<String>,
+    /// string extra key=value (repeatable)
+    #[argh(option)]
+    es: Vec<String>,
+    /// int extra key=value (repeatable)
+    #[argh(option)]
+    ei: Vec<String>,
+    /// boolean extra key=true|false (repeatable)
+    #[argh(option)]
+    ez: Vec<String>,
+    /// long extra key=value (repeatable)
+    #[argh(option)]
+    el: Vec<String>,
 }
 
 /// wait for UI to become idle
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "wait-for-idle")]
 struct WaitForIdleCmd {
     /// idle timeout in milliseconds
@@ -200,28 +827,230 @@ struct WaitForIdleCmd {
     global_timeout_ms: i64,
 }
 
+/// end waits the server is running on this screen (wait-for-idle, wait-for, auto-waits)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "cancel")]
+struct CancelCmd {}
+
+/// list all screens (debug), or create several at once
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "screens")]
+struct ScreensCmd {
+    #[argh(subcommand)]
+    command: Option<ScreensSubcommand>,
+}
+
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand)]
+enum ScreensSubcommand {
+    CreateMany(CreateManyCmd),
+}
+
+/// get-or-create every screen in a JSON array of screen requests; omitted fields use the
+/// usual defaults (--package, timeout, 1080x1920 at 240 dpi)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "create-many")]
+struct CreateManyCmd {
+    /// JSON file, e.g. [{"name": "a", "package": "com.example.a"}, {"name": "b"}]
+    #[argh(positional)]
+    file: String,
+}
+
+/// open the notification shade or list its notifications (system UI on the default display)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "notifications")]
+struct NotificationsCmd {
+    #[argh(subcommand)]
+    command: NotificationsSubcommand,
+}
+
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand)]
+enum NotificationsSubcommand {
+    Open(NotificationsOpenCmd),
+    List(NotificationsListCmd),
+}
+
+/// expand the notification shade and list notifications
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "open")]
+struct NotificationsOpenCmd {}
+
+/// list notifications in the shade (open it first)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "list")]
+struct NotificationsListCmd {}
+
+/// device-wide settings that affect every screen
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "device")]
+struct DeviceCmd {
+    #[argh(subcommand)]
+    command: DeviceSubcommand,
+}
+
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand)]
+enum DeviceSubcommand {
+    AirplaneMode(AirplaneModeCmd),
+    Wifi(WifiCmd),
+    MobileData(MobileDataCmd),
+    SetTime(SetTimeCmd),
+    Ime(DeviceImeCmd),
+}
+
+/// turn airplane mode on or off
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "airplane-mode")]
+struct AirplaneModeCmd {
+    /// on or off
+    #[argh(positional, from_str_fn(parse_on_off))]
+    on: bool,
+}
+
+/// turn wifi on or off
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "wifi")]
+struct WifiCmd {
+    /// on or off
+    #[argh(positional, from_str_fn(parse_on_off))]
+    on: bool,
+}
+
+/// turn mobile data on or off
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "mobile-data")]
+struct MobileDataCmd {
+    /// on or off
+    #[argh(positional, from_str_fn(parse_on_off))]
+    on: bool,
+}
+
+/// set the device clock, e.g. 2030-01-31T09:00:00Z
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "set-time")]
+struct SetTimeCmd {
+    /// ISO 8601 time with a Z or ±HH:MM offset
+    #[argh(positional)]
+    time: String,
+    /// leave automatic network time on (it may undo the change)
+    #[argh(switch)]
+    keep_auto_time: bool,
+}
+
+/// list or switch the device's input methods (keyboards)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "ime")]
+struct DeviceImeCmd {
+    #[argh(subcommand)]
+    command: DeviceImeSubcommand,
+}
+
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand)]
+enum DeviceImeSubcommand {
+    List(ImeListCmd),
+    Set(ImeSetCmd),
+}
+
+/// list enabled input methods, marking the current one with *
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "list")]
+struct ImeListCmd {}
+
+/// switch to an input method, enabling it first if it is installed but off
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "set")]
+struct ImeSetCmd {
+    /// input method id as printed by `device ime list`, e.g. com.android.inputmethod.latin/.LatinIME
+    #[argh(positional)]
+    id: String,
+}
+
+fn parse_on_off(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got {value:?}")),
+    }
+}
+
+/// wait until a node with the given text appears (or disappears with --gone)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "wait-for")]
+struct WaitForCmd {
+    #[argh(positional)]
+    text: String,
+    /// give up after this many milliseconds
+    #[argh(option, default = "10000")]
+    timeout_ms: u64,
+    /// wait for the node to disappear instead
+    #[argh(switch)]
+    gone: bool,
+}
+
+/// check screen geometry, frame channel order and tap accuracy; taps a text field on screen if there is one
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "diagnostics")]
+struct DiagnosticsCmd {}
+
+/// run commands read from stdin, one per line (`tap "OK"`), over one connection that keeps the screen alive; `exit` or EOF ends it
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "repl")]
+struct ReplCmd {}
+
 /// deploy and start the coordinator on device
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "start")]
-struct StartCmd {}
+struct StartCmd {
+    /// also forward the HTTP API to 127.0.0.1:<port> on this host (use with ANDY_BASE_URL)
+    #[argh(option)]
+    tcp_port: Option<u16>,
+    /// print the adb commands that would change the device, without running them
+    #[argh(switch)]
+    dry_run: bool,
+}
 
 /// install agent skill file into $PWD/.agents/skills/android-emulator/
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "install")]
 struct InstallCmd {}
 
 /// print version
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "version")]
 struct VersionCmd {}
 
+/// show whether a coordinator runs on the device (pid, user, uptime) and which local ports and sockets forward to it
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "status")]
+struct StatusCmd {}
+
+/// kill coordinator processes on the device, listing what was killed (`start` does this implicitly)
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "kill")]
+struct KillCmd {
+    /// print the kill command instead of running it
+    #[argh(switch)]
+    dry_run: bool,
+}
+
+/// print a shell completion script, e.g. `andy completions bash > /etc/bash_completion.d/andy`
+#[derive(FromArgs, ArgsInfo)]
+#[argh(subcommand, name = "completions")]
+struct CompletionsCmd {
+    /// bash, zsh or fish
+    #[argh(positional, from_str_fn(completions::parse_shell))]
+    shell: completions::Shell,
+}
+
 /// start syncing JS console logs (ReactNativeJS) to a file in the background
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "sync-logs-to-file")]
 struct SyncLogsToFileCmd {}
 
 /// internal: log daemon process
-#[derive(FromArgs)]
+#[derive(FromArgs, ArgsInfo)]
 #[argh(subcommand, name = "_log-daemon")]
 struct LogDaemonCmd {
     #[argh(positional)]
@@ -230,12 +1059,53 @@ struct LogDaemonCmd {
     log_file: PathBuf,
 }
 
+/// Whether the command only observes the screen, without creating it or sending a heartbeat.
+fn peeks(command: &Command) -> bool {
+    matches!(command, Command::A11y(cmd) if cmd.peek)
+}
+
+impl Command {
+    /// The subcommand's name as typed, for the `--json` object.
+    fn name(&self) -> &'static str {
+        fn name_of<T: SubCommand>(_: &T) -> &'static str {
+            T::COMMAND.name
+        }
+        macro_rules! names {
+            ($($variant:ident),* $(,)?) => {
+                match self {
+                    $(Command::$variant(cmd) => name_of(cmd),)*
+                }
+            };
+        }
+        names! {
+            Info, Foreground, History, AppState, Ime, HideIme, Appops, Screenshot, RawFrame,
+            StartRecord, StartStream, A11y, Windows, Tap, MultiTap, Swipe, Scroll, Fling, Type,
+            Key, KeyCombo, Select, Fill, Focus, Launch, Adopt, Stop, Reset, ReleasePackage,
+            OpenUrl, WaitForIdle, Cancel, WaitFor, Device, Notifications, Diagnostics, Screens,
+            Repl, Start, Status, Kill, Install, Version, Completions, SyncLogsToFile, LogDaemon,
+        }
+    }
+}
+
 /// Check if the server is reachable; if not, auto-start it.
-/// Also ensures the screen exists (saving a round-trip).
-async fn ensure_server(socket: &Path, screen: &str, package: &str) -> Result<Client> {
+/// Also ensures the screen exists (saving a round-trip), unless `settings` is `None`.
+async fn ensure_server(
+    socket: &Path,
+    screen: &str,
+    settings: Option<&ScreenSettings>,
+    request_timeout: Duration,
+) -> Result<Client> {
+    // A TCP endpoint may be remote, so there is nothing to auto-start
+    if let Ok(base_url) = std::env::var("ANDY_BASE_URL") {
+        let client = Client::with_base_url(&base_url, request_timeout)?;
+        if !screen_ready(&client, screen, settings).await? {
+            bail!("server at ANDY_BASE_URL={base_url} not reachable");
+        }
+        return Ok(client);
+    }
     if socket.exists() {
-        let client = Client::new(socket.to_path_buf());
-        if client.ensure_screen(screen, package).await.is_ok() {
+        let client = Client::new(socket.to_path_buf(), request_timeout)?;
+        if screen_ready(&client, screen, settings).await? {
             return Ok(client);
         }
         eprintln!("debug: socket exists but server is not responding, restarting...");
@@ -243,19 +1113,22 @@ async fn ensure_server(socket: &Path, screen: &str, package: &str) -> Result<Cli
         eprintln!("debug: socket not found, starting server...");
     }
 
-    runner::start(socket)?;
+    runner::start(socket, None, false)?;
 
     // Daemon was spawned on device — poll until it's ready
-    let client = Client::new(socket.to_path_buf());
+    let client = Client::new(socket.to_path_buf(), request_timeout)?;
     let mut delay_ms = 1u64;
     let mut total_ms = 0u64;
     loop {
-        if client.ensure_screen(screen, package).await.is_ok() {
+        if screen_ready(&client, screen, settings).await? {
             eprintln!("debug: server ready after {total_ms}ms");
             return Ok(client);
         }
         if total_ms >= 30000 {
-            bail!("server did not become ready after 30s");
+            bail!(
+                "server did not become ready after 30s; device logs:\n{}",
+                runner::startup_logs()
+            );
         }
         delay_ms = (delay_ms * 2).min(1000);
         tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
@@ -263,6 +1136,42 @@ async fn ensure_server(socket: &Path, screen: &str, package: &str) -> Result<Cli
     }
 }
 
+/// Whether the screen exists now. A server that answers but can't create it is an error,
+/// since restarting or polling won't help; the usual cause is a package spec matching no
+/// installed app. Without `settings` only the server is checked.
+async fn screen_ready(
+    client: &Client,
+    screen: &str,
+    settings: Option<&ScreenSettings>,
+) -> Result<bool> {
+    let Some(settings) = settings else {
+        return Ok(client.list_screens().await.is_ok());
+    };
+    let err = match client.ensure_screen(screen, settings).await {
+        Ok(bound) => {
+            if let Some(package) = bound.filter(|p| !p.is_empty()) {
+                eprintln!("screen {screen} bound to {package}");
+            }
+            return Ok(true);
+        }
+        Err(err) => err,
+    };
+    let Ok(packages) = client.installed_packages(&settings.package).await else {
+        return Ok(false);
+    };
+    if !packages.matches.is_empty() {
+        return Err(err);
+    }
+    let mut message = format!("no installed package matches '{}'", settings.package);
+    if !packages.nearby.is_empty() {
+        message.push_str(&format!(
+            "; installed nearby: {}",
+            packages.nearby.join(", ")
+        ));
+    }
+    bail!("{message}")
+}
+
 fn default_screen_from_env() -> String {
     std::env::var("ANDY_SCREEN").unwrap_or_else(|_| String::from("default"))
 }
@@ -271,27 +1180,233 @@ fn default_package_from_env() -> String {
     std::env::var("ANDY_PACKAGE").unwrap_or_default()
 }
 
+/// `ANDY_WAIT_STRATEGY=idle|fixed|none`; `fixed` settles for `ANDY_FIXED_WAIT_MS` (default 500).
+fn wait_strategy_from_env() -> Result<Option<WaitStrategy>> {
+    let Ok(strategy) = std::env::var("ANDY_WAIT_STRATEGY") else {
+        return Ok(None);
+    };
+    Ok(Some(match strategy.as_str() {
+        "idle" => WaitStrategy::Idle,
+        "fixed" => {
+            let ms = match std::env::var("ANDY_FIXED_WAIT_MS") {
+                Ok(ms) => ms
+                    .parse()
+                    .with_context(|| format!("invalid ANDY_FIXED_WAIT_MS: {ms}"))?,
+                Err(_) => 500,
+            };
+            WaitStrategy::Fixed(ms)
+        }
+        "none" => WaitStrategy::None,
+        other => bail!("invalid ANDY_WAIT_STRATEGY: {other} (idle|fixed|none)"),
+    }))
+}
+
+/// `ANDY_DISMISS_RULES`: JSON `[{"when": "...", "tap": "..."}]` replacing the defaults.
+fn dismiss_rules_from_env() -> Result<Option<Vec<DismissRule>>> {
+    let Ok(rules) = std::env::var("ANDY_DISMISS_RULES") else {
+        return Ok(None);
+    };
+    let rules = serde_json::from_str(&rules)
+        .with_context(|| format!("invalid ANDY_DISMISS_RULES: {rules}"))?;
+    Ok(Some(rules))
+}
+
+fn request_timeout_from_env() -> Result<Duration> {
+    let Ok(secs) = std::env::var("ANDY_REQUEST_TIMEOUT_SECS") else {
+        return Ok(client::DEFAULT_REQUEST_TIMEOUT);
+    };
+    let secs = secs
+        .parse()
+        .with_context(|| format!("invalid ANDY_REQUEST_TIMEOUT_SECS: {secs}"))?;
+    Ok(Duration::from_secs(secs))
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     let cli: Cli = argh::from_env();
+    if let Command::LogDaemon(cmd) = &cli.command {
+        return run_log_daemon(cmd.uid, &cmd.log_file);
+    }
+    if !cli.json {
+        return run(cli).await?.print();
+    }
+    if let Command::StartStream(_) = &cli.command {
+        bail!("start-stream writes video to stdout; --json doesn't apply");
+    }
+
+    let command = cli.command.name();
+    if !print_json(command, run(cli).await)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print a command's result as its `--json` object; returns whether it succeeded.
+fn print_json(command: &str, result: Result<Output>) -> Result<bool> {
+    let (object, ok) = match result.and_then(|output| output.into_json(command)) {
+        Ok(object) => {
+            let ok = object["ok"] == true;
+            (object, ok)
+        }
+        Err(err) => (output::error_json(command, &err), false),
+    };
+    println!("{}", serde_json::to_string_pretty(&object)?);
+    Ok(ok)
+}
 
+async fn run(cli: Cli) -> Result<Output> {
     let socket = socket_path();
 
     // Handle commands that don't need a client
-    if let Command::Start(_) = &cli.command {
-        return runner::start(&socket);
+    if let Command::Start(cmd) = &cli.command {
+        let planned = runner::start(&socket, cmd.tcp_port, cmd.dry_run)?;
+        if cmd.dry_run {
+            return Ok(dry_run_output(planned));
+        }
+        return Ok(Output::Done);
     }
-    if let Command::Version(_) = &cli.command {
-        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-        return Ok(());
+    if let Command::Kill(cmd) = &cli.command {
+        return kill_coordinators(cmd.dry_run);
     }
-    if let Command::LogDaemon(cmd) = cli.command {
-        return run_log_daemon(cmd.uid, &cmd.log_file);
+    if let Some(output) = run_local(&cli.command)? {
+        return Ok(output);
+    }
+
+    let screen = &cli.screen;
+    // A peek reads a screen that already exists; creating it would count as activity
+    let creates_screen = !peeks(&cli.command);
+    let package = cli.package.clone();
+    if creates_screen && package.is_empty() {
+        bail!(
+            "--package or ANDY_PACKAGE required to bind at screen creation (full, prefix, or comma-separated pool)"
+        );
+    }
+    let settings = ScreenSettings {
+        package,
+        timeout_secs: cli.no_timeout.then_some(0),
+        auto_dismiss: cli.auto_dismiss,
+        dismiss_rules: dismiss_rules_from_env()?,
+    };
+    let request_timeout = request_timeout_from_env()?;
+    let ready_settings = creates_screen.then_some(&settings);
+    let client = ensure_server(&socket, screen, ready_settings, request_timeout)
+        .await?
+        .with_wait_strategy(wait_strategy_from_env()?);
+
+    if let Command::Repl(_) = cli.command {
+        repl(&client, screen, &settings, cli.json).await?;
+        return Ok(Output::Done);
+    }
+    dispatch(&client, screen, &settings, cli.command).await
+}
+
+/// Kill coordinator processes and report which ones went away.
+fn kill_coordinators(dry_run: bool) -> Result<Output> {
+    let before = runner::processes()?;
+    let planned = runner::kill(dry_run);
+    if dry_run {
+        return Ok(dry_run_output(planned));
+    }
+    let after = runner::processes()?;
+    let killed: Vec<_> = before
+        .into_iter()
+        .filter(|p| after.iter().all(|a| a.pid != p.pid))
+        .collect();
+    let mut lines: Vec<String> = killed
+        .iter()
+        .map(|p| format!("killed {}", describe_process(p)))
+        .collect();
+    if lines.is_empty() && after.is_empty() {
+        lines.push("no coordinator was running".into());
+    }
+    let output = Output::Report {
+        stdout: Some(lines.join("\n")),
+        notes: vec![],
+        data: serde_json::json!({ "killed": killed, "still_running": after }),
+    };
+    if !after.is_empty() {
+        let error = format!(
+            "{} still running; a daemon started as another user needs ANDY_RUN_AS",
+            after
+                .iter()
+                .map(describe_process)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return Ok(Output::Failed(Box::new(output), error));
+    }
+    Ok(output)
+}
+
+/// The commands a `--dry-run` skipped: one per line, or `{"commands": [...]}` with `--json`.
+fn dry_run_output(commands: Vec<String>) -> Output {
+    Output::Report {
+        stdout: Some(commands.join("\n")),
+        notes: vec![],
+        data: serde_json::json!({ "commands": commands }),
+    }
+}
+
+fn describe_process(p: &runner::DeviceProcess) -> String {
+    format!("pid {} ({}, up {})", p.pid, p.user, p.elapsed)
+}
+
+/// Commands that run on this machine only, without a server.
+fn run_local(command: &Command) -> Result<Option<Output>> {
+    if let Command::Status(_) = command {
+        let processes = runner::processes()?;
+        let forwards = runner::forwards()?;
+        let mut lines: Vec<String> = if processes.is_empty() {
+            vec!["no coordinator running".into()]
+        } else {
+            processes
+                .iter()
+                .map(|p| {
+                    format!(
+                        "coordinator {} on device port {}",
+                        describe_process(p),
+                        runner::DEVICE_PORT
+                    )
+                })
+                .collect()
+        };
+        lines.extend(
+            forwards
+                .iter()
+                .map(|local| format!("forwarded from {local}")),
+        );
+        return Ok(Some(Output::Report {
+            stdout: Some(lines.join("\n")),
+            notes: vec![],
+            data: serde_json::json!({
+                "running": !processes.is_empty(),
+                "processes": processes,
+                "device_port": runner::DEVICE_PORT,
+                "forwards": forwards,
+            }),
+        }));
+    }
+    if let Command::Version(_) = command {
+        let (name, version) = (env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        return Ok(Some(Output::Report {
+            stdout: Some(format!("{name} {version}")),
+            notes: vec![],
+            data: serde_json::json!({ "name": name, "version": version }),
+        }));
+    }
+    if let Command::Completions(cmd) = command {
+        let script = completions::script(cmd.shell, "andy", &Cli::get_args_info());
+        return Ok(Some(Output::Report {
+            stdout: Some(script.trim_end().to_string()),
+            notes: vec![],
+            data: serde_json::json!({ "script": script }),
+        }));
     }
-    if let Command::Install(_) = cli.command {
+    if let Command::Install(_) = command {
         let skill_dir = PathBuf::from(".agents/skills/android-emulator");
         fs::create_dir_all(&skill_dir)?;
-        fs::write(skill_dir.join("SKILL.md"), assets::SKILL_MD)?;
+        let skill = skill_dir.join("SKILL.md");
+        fs::write(&skill, assets::SKILL_MD)?;
 
         let claude_skills = PathBuf::from(".claude/skills");
         fs::create_dir_all(&claude_skills)?;
@@ -300,136 +1415,640 @@ async fn main() -> Result<()> {
             std::os::unix::fs::symlink("../../.agents/skills/android-emulator", &link)?;
         }
 
-        eprintln!("installed .agents/skills/android-emulator/SKILL.md");
-        return Ok(());
+        return Ok(Some(Output::Report {
+            stdout: None,
+            notes: vec![format!("installed {}", skill.display())],
+            data: serde_json::json!({ "path": skill }),
+        }));
     }
+    Ok(None)
+}
 
-    let screen = &cli.screen;
-    let package = cli.package.clone();
-    if package.is_empty() {
-        bail!("--package or ANDY_PACKAGE required to bind at screen creation (full or prefix)");
+/// Read commands from stdin, one per line, and run each against one client, keeping
+/// the screen alive while waiting for input.
+async fn repl(client: &Client, screen: &str, settings: &ScreenSettings, json: bool) -> Result<()> {
+    let prompt = std::io::stdin().is_terminal();
+    let (lines_tx, mut lines) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    // Well inside the 300s reap timeout, so an idle session never loses its screen
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(60));
+    heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        if prompt {
+            eprint!("andy> ");
+            std::io::stderr().flush()?;
+        }
+        let line = loop {
+            tokio::select! {
+                line = lines.recv() => break line,
+                // Once a wait has listened for Ctrl-C, tokio keeps SIGINT from killing the
+                // process, so the prompt has to handle it to stay interruptible
+                ctrl_c = tokio::signal::ctrl_c() => {
+                    ctrl_c.context("failed to listen for Ctrl-C")?;
+                    if prompt {
+                        eprintln!();
+                    }
+                    return Ok(());
+                }
+                _ = heartbeat.tick() => {
+                    if let Err(err) = client.heartbeat(screen).await {
+                        eprintln!("warning: heartbeat failed: {err:#}");
+                    }
+                }
+            }
+        };
+        let Some(line) = line.transpose()? else {
+            return Ok(());
+        };
+        let words = repl::split_words(&line)?;
+        let Some((name, args)) = words.split_first() else {
+            continue;
+        };
+        if name == "exit" || name == "quit" {
+            return Ok(());
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let command = match Command::from_args(&[name.as_str()], &args) {
+            Ok(command) => command,
+            Err(exit) => {
+                match exit.status {
+                    Ok(()) => println!("{}", exit.output.trim_end()),
+                    Err(()) => eprintln!("{}", exit.output.trim_end()),
+                }
+                continue;
+            }
+        };
+        let name = command.name();
+        let result = match command {
+            Command::Start(_) | Command::Kill(_) | Command::Repl(_) | Command::LogDaemon(_) => {
+                Err(anyhow::anyhow!("{name} is not available in the repl"))
+            }
+            command => match run_local(&command) {
+                Ok(Some(output)) => Ok(output),
+                Ok(None) => dispatch(client, screen, settings, command).await,
+                Err(err) => Err(err),
+            },
+        };
+        if json {
+            print_json(name, result)?;
+        } else if let Err(err) = result.and_then(Output::print) {
+            eprintln!("Error: {err:#}");
+        }
     }
-    let client = ensure_server(&socket, screen, &package).await?;
+}
 
-    match cli.command {
-        Command::Info(_) => {
-            let info = client.info(screen).await?;
-            println!("{}", serde_json::to_string_pretty(&info)?);
+/// Run a command that talks to the server for `screen`.
+async fn dispatch(
+    client: &Client,
+    screen: &str,
+    settings: &ScreenSettings,
+    command: Command,
+) -> Result<Output> {
+    let output = match command {
+        Command::Info(_) => Output::Json(serde_json::to_value(client.info(screen).await?)?),
+        Command::Foreground(_) => {
+            Output::Json(serde_json::to_value(client.foreground(screen).await?)?)
+        }
+        Command::History(cmd) => {
+            let entries = client.history(screen, cmd.limit).await?;
+            let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+            let lines: Vec<String> = entries
+                .iter()
+                .map(|entry| {
+                    let ago = now_ms.saturating_sub(entry.timestamp_ms) as f64 / 1000.0;
+                    format!("{ago:>8.1}s ago  {:<12} {}", entry.action, entry.params)
+                })
+                .collect();
+            Output::Report {
+                stdout: (!lines.is_empty()).then(|| lines.join("\n")),
+                notes: vec![],
+                data: serde_json::to_value(&entries)?,
+            }
+        }
+        Command::Appops(cmd) => {
+            let modes = match cmd.command {
+                AppopsSubcommand::Get(c) => client.appops(screen, c.op.as_deref()).await?,
+                AppopsSubcommand::Set(c) => client.set_appop(screen, &c.op, &c.mode).await?,
+            };
+            let lines: Vec<String> = modes
+                .iter()
+                .map(|mode| format!("{}: {}", mode.op, mode.mode))
+                .collect();
+            Output::Report {
+                stdout: (!lines.is_empty()).then(|| lines.join("\n")),
+                notes: vec![],
+                data: serde_json::to_value(&modes)?,
+            }
+        }
+        Command::Ime(_) => Output::Json(serde_json::to_value(client.ime(screen).await?)?),
+        Command::HideIme(_) => {
+            let ime = client.hide_ime(screen).await?;
+            if ime.shown {
+                bail!("the soft keyboard is still shown after BACK");
+            }
+            Output::Report {
+                stdout: None,
+                notes: vec![],
+                data: serde_json::to_value(&ime)?,
+            }
+        }
+        Command::AppState(_) => {
+            Output::Json(serde_json::to_value(client.app_state(screen).await?)?)
         }
         Command::Screenshot(cmd) => {
-            let (data, wait_ms) = client.screenshot(screen, cmd.no_wait).await?;
-            fs::write(&cmd.path, &data)?;
-            if let Some(ms) = wait_ms {
-                if ms > 0 {
-                    eprintln!("note: waited {ms}ms for idle");
+            if cmd.raw && cmd.avif {
+                bail!("--raw and --avif are different formats; pick one");
+            }
+            let format = match (cmd.raw, cmd.avif) {
+                (true, _) => "raw",
+                (_, true) => "avif",
+                _ => "jpeg",
+            };
+            let shot = client
+                .screenshot(
+                    screen,
+                    cmd.no_wait,
+                    &ScreenshotOptions {
+                        scale: cmd.scale,
+                        max_dim: cmd.max_dim,
+                        debug: cmd.debug,
+                        crop_to: cmd.crop_to.as_deref(),
+                        grayscale: cmd.grayscale,
+                        chroma: cmd.chroma.as_deref(),
+                        full: cmd.full,
+                        scroll_node: cmd.scroll_node.as_deref(),
+                        settle_frames: cmd.settle_frames,
+                        raw: cmd.raw,
+                        avif: cmd.avif,
+                        quality: cmd.quality,
+                    },
+                )
+                .await?;
+            fs::write(&cmd.path, &shot.data)?;
+            let mut notes = Vec::new();
+            if let Some(ms) = shot.wait_ms
+                && ms > 0
+            {
+                notes.push(format!("note: waited {ms}ms for idle"));
+            }
+            if shot.scale != 1.0 {
+                notes.push(format!(
+                    "note: image scaled by {}; a11y bounds stay in device pixels",
+                    shot.scale
+                ));
+            }
+            if shot.frozen {
+                notes.push(
+                    "warning: every frame since the last interaction shows the same \
+                     picture; the app may have stopped rendering"
+                        .into(),
+                );
+            } else if let Some(ms) = stale_frame_ms(&shot) {
+                notes.push(format!(
+                    "warning: no new frame for {:.1}s, since before the last interaction; \
+                     this is the last one captured (expected if nothing on screen changed)",
+                    ms as f64 / 1000.0
+                ));
+            }
+            if let Some(pages) = shot.pages {
+                notes.push(format!("note: stitched {pages} scrolled viewports"));
+            }
+            notes.push(match (shot.width, shot.height) {
+                (Some(w), Some(h)) if cmd.raw => {
+                    format!("saved {w}x{h} raw RGBA screenshot to {}", cmd.path)
+                }
+                (Some(w), Some(h)) if cmd.avif => {
+                    format!("saved {w}x{h} AVIF screenshot to {}", cmd.path)
                 }
+                (Some(w), Some(h)) => format!("saved {w}x{h} screenshot to {}", cmd.path),
+                _ => format!("saved screenshot to {}", cmd.path),
+            });
+            Output::Report {
+                stdout: None,
+                notes,
+                data: serde_json::json!({
+                    "path": cmd.path,
+                    "format": format,
+                    "width": shot.width,
+                    "height": shot.height,
+                    "scale": shot.scale,
+                    "wait_ms": shot.wait_ms,
+                    "age_ms": shot.age_ms,
+                    "frozen": shot.frozen,
+                    "pages": shot.pages,
+                }),
             }
-            eprintln!("saved screenshot to {}", cmd.path);
         }
         Command::RawFrame(cmd) => {
-            let frame = client.raw_frame(screen, cmd.no_wait).await?;
+            let frame = client.raw_frame(screen, cmd.no_wait, None).await?;
             if let Some(frame) = frame {
                 fs::write(&cmd.path, &frame.data)?;
-                println!(
-                    "{}",
-                    serde_json::to_string_pretty(&serde_json::json!({
-                        "path": cmd.path,
-                        "width": frame.width,
-                        "height": frame.height,
-                        "stride": frame.stride,
-                        "bytes_per_pixel": frame.bytes_per_pixel,
-                        "pixel_format": "rgba8888",
-                        "seq": frame.seq,
-                        "timestamp_ms": frame.timestamp_ms,
-                        "size_bytes": frame.data.len(),
-                    }))?
-                );
+                Output::Json(serde_json::json!({
+                    "path": cmd.path,
+                    "width": frame.width,
+                    "height": frame.height,
+                    "stride": frame.stride,
+                    "bytes_per_pixel": frame.bytes_per_pixel,
+                    "pixel_format": "rgba8888",
+                    "seq": frame.seq,
+                    "timestamp_ms": frame.timestamp_ms,
+                    "size_bytes": frame.data.len(),
+                }))
             } else {
-                eprintln!("no new frame available");
+                Output::Report {
+                    stdout: None,
+                    notes: vec!["no new frame available".into()],
+                    data: serde_json::json!({ "path": null }),
+                }
             }
         }
         Command::StartRecord(cmd) => {
-            start_record(&client, screen, &cmd).await?;
+            start_record(client, screen, &cmd).await?;
+            Output::Done
         }
         Command::StartStream(cmd) => {
-            start_stream(&client, screen, &cmd).await?;
+            start_stream(client, screen, &cmd).await?;
+            Output::Done
+        }
+        Command::A11y(cmd) if cmd.xml => {
+            let xml = client.a11y_xml(screen, cmd.no_wait, cmd.peek).await?;
+            Output::Report {
+                data: serde_json::json!({ "xml": xml }),
+                stdout: Some(xml),
+                notes: vec![],
+            }
         }
         Command::A11y(cmd) => {
-            let (tree, wait_ms) = client.a11y(screen, cmd.no_wait).await?;
-            if let Some(ms) = wait_ms {
-                if ms > 0 {
-                    eprintln!("note: waited {ms}ms for idle");
+            let (tree, wait_ms) = client.a11y(screen, cmd.no_wait, cmd.peek).await?;
+            let mut notes = Vec::new();
+            if let Some(ms) = wait_ms
+                && ms > 0
+            {
+                notes.push(format!("note: waited {ms}ms for idle"));
+            }
+            let text = if cmd.csv {
+                a11y::render_csv(&tree)
+            } else {
+                let mut opts = a11y::RenderOptions::verbosity(cmd.verbose);
+                opts.no_collapse |= cmd.no_collapse;
+                if cmd.percent {
+                    if cmd.peek {
+                        bail!(
+                            "--percent reads the screen size, which counts as activity; drop --peek"
+                        );
+                    }
+                    let info = client.info(screen).await?;
+                    opts.normalized_to = Some((info.width, info.height));
                 }
+                a11y::render_text(&tree, &opts)
+            };
+            Output::Report {
+                data: serde_json::json!({ "text": text, "wait_ms": wait_ms }),
+                stdout: Some(text),
+                notes,
+            }
+        }
+        Command::Windows(_) => {
+            let windows = client.windows(screen).await?;
+            Output::Report {
+                stdout: Some(a11y::render_windows(&windows)),
+                notes: vec![],
+                data: serde_json::to_value(&windows)?,
             }
-            println!("{}", a11y::render_text(&tree));
         }
         Command::Tap(cmd) => {
-            let wait_ms = if let Some((x_str, y_str)) = cmd.target.split_once(',') {
-                let x: f32 = x_str.parse()?;
-                let y: f32 = y_str.parse()?;
-                client.tap(screen, x, y, cmd.no_wait).await?
-            } else {
-                let tries = cmd.tries.max(1);
-                let mut coords = None;
-                for attempt in 1..=tries {
-                    let (tree, _) = client.a11y(screen, true).await?;
-                    if let Some(node) = a11y::find_node(&tree, &cmd.target) {
-                        let x = (node.bounds.left + node.bounds.right) as f32 / 2.0;
-                        let y = (node.bounds.top + node.bounds.bottom) as f32 / 2.0;
-                        coords = Some((x, y));
-                        break;
+            let point = match (&cmd.target, &cmd.near) {
+                (Some(target), None) => target.split_once(',').map(|_| parse_point(target)),
+                (None, None) if cmd.relative()?.is_none() => {
+                    bail!("tap needs a target: x,y or accessibility text")
+                }
+                _ => None,
+            };
+            let spaces = [
+                cmd.normalized,
+                cmd.image_size.is_some(),
+                cmd.coord_space != CoordSpace::Device,
+            ];
+            if spaces.iter().filter(|&&set| set).count() > 1 {
+                bail!(
+                    "--normalized, --image-size and --coord-space screenshot are mutually exclusive"
+                );
+            }
+            let (x, y, coord_space) = if let Some(point) = point {
+                let (x, y) = match (point?, &cmd.image_size) {
+                    (point, _) if cmd.normalized => {
+                        denormalize(point, &client.info(screen).await?)?
                     }
-                    if attempt < tries {
-                        eprintln!(
-                            "note: node \"{}\" not found, retrying ({}/{})",
-                            cmd.target, attempt, tries
-                        );
-                        client.wait_for_idle(screen, 500, 5000).await?;
+                    (point, Some(size)) => {
+                        from_image_space(point, size, &client.info(screen).await?)?
                     }
-                }
-                let (x, y) =
-                    coords.ok_or_else(|| anyhow::anyhow!("node not found: \"{}\"", cmd.target))?;
-                client.tap(screen, x, y, cmd.no_wait).await?
+                    (point, None) => point,
+                };
+                (x, y, cmd.coord_space)
+            } else {
+                let (x, y) = find_tap_target(client, screen, &cmd).await?;
+                warn_if_behind_ime(client, screen, x, y).await;
+                (x, y, CoordSpace::Device)
             };
-            if let Some(ms) = wait_ms {
-                if ms > 0 {
-                    eprintln!("note: waited {ms}ms for idle");
+            if let Some(dir) = &cmd.snapshot {
+                let snapshot = client
+                    .tap_snapshot(screen, x, y, cmd.no_wait, coord_space)
+                    .await?;
+                let result = Interaction {
+                    wait_ms: Some(snapshot.wait_ms),
+                    app_crashed: snapshot.app_crashed.clone(),
+                    ui_changed: snapshot.ui_changed,
+                    auto_dismissed: snapshot.auto_dismissed.clone(),
+                    cold_start: None,
+                };
+                let text = save_snapshot(dir, &snapshot)?;
+                let mut notes = output::interaction_notes(&result);
+                notes.push(format!("saved snapshot to {dir}"));
+                let mut data = serde_json::to_value(&result)?;
+                data["snapshot"] = serde_json::json!(dir);
+                Output::Report {
+                    stdout: Some(text),
+                    notes,
+                    data,
                 }
+            } else {
+                Output::Interaction(client.tap(screen, x, y, cmd.no_wait, coord_space).await?)
             }
         }
+        Command::MultiTap(cmd) => {
+            let points = cmd
+                .points
+                .iter()
+                .map(|point| parse_point(point))
+                .collect::<Result<Vec<_>>>()?;
+            Output::Interaction(client.multi_tap(screen, &points, cmd.no_wait).await?)
+        }
         Command::Swipe(cmd) => {
-            client
-                .swipe(screen, cmd.x1, cmd.y1, cmd.x2, cmd.y2, cmd.duration_ms)
-                .await?;
+            let (points, duration_ms) = match cmd.args.as_slice() {
+                [_, _, _, _] | [_, _, _, _, _] => {
+                    let nums = cmd
+                        .args
+                        .iter()
+                        .map(|arg| {
+                            arg.parse::<f32>()
+                                .with_context(|| format!("invalid swipe argument: {arg}"))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let duration_ms = match cmd.args.get(4) {
+                        Some(ms) => Some(
+                            ms.parse()
+                                .with_context(|| format!("invalid duration_ms: {ms}"))?,
+                        ),
+                        None => cmd.duration_ms,
+                    };
+                    let mut points = [nums[0], nums[1], nums[2], nums[3]];
+                    if cmd.normalized {
+                        let info = client.info(screen).await?;
+                        let (x1, y1) = denormalize((points[0], points[1]), &info)?;
+                        let (x2, y2) = denormalize((points[2], points[3]), &info)?;
+                        points = [x1, y1, x2, y2];
+                    }
+                    (points, duration_ms)
+                }
+                [_] if cmd.normalized => bail!("--normalized applies to x1 y1 x2 y2 swipes"),
+                [dir] => {
+                    let info = client.info(screen).await?;
+                    (swipe_points(dir, cmd.distance, &info)?, cmd.duration_ms)
+                }
+                _ => bail!("swipe takes a direction or x1 y1 x2 y2 [duration_ms]"),
+            };
+            let duration_ms = duration_ms.unwrap_or(DEFAULT_SWIPE_MS);
+            let swipe = swipe_request(points, duration_ms, cmd.steps, cmd.easing);
+            client.swipe(screen, &swipe).await?;
+            Output::Done
+        }
+        Command::Scroll(cmd) => {
+            let info = client.info(screen).await?;
+            let points = content_swipe_points(&cmd.direction, cmd.distance, &info)?;
+            let swipe = SwipeRequest {
+                hold_ms: Some(cmd.hold_ms),
+                ..swipe_request(points, cmd.duration_ms, cmd.steps, cmd.easing)
+            };
+            client.swipe(screen, &swipe).await?;
+            Output::Done
+        }
+        Command::Fling(cmd) => {
+            let info = client.info(screen).await?;
+            let points = content_swipe_points(&cmd.direction, cmd.distance, &info)?;
+            let swipe = swipe_request(points, cmd.duration_ms, cmd.steps, cmd.easing);
+            client.swipe(screen, &swipe).await?;
+            Output::Done
         }
         Command::Type(cmd) => {
-            client.type_text(screen, &cmd.text).await?;
+            client.type_text(screen, &cmd.text, cmd.paste).await?;
+            Output::Done
         }
         Command::Key(cmd) => {
-            client.key(screen, cmd.keycode).await?;
+            client.key(screen, parse_keycode(&cmd.key)?).await?;
+            Output::Done
         }
-        Command::Launch(cmd) => {
-            let wait_ms = client.launch(screen, cmd.no_wait).await?;
-            if let Some(ms) = wait_ms {
-                if ms > 0 {
-                    eprintln!("note: waited {ms}ms for idle");
-                }
+        Command::KeyCombo(cmd) => {
+            let keycodes = parse_key_combo(&cmd.keys)?;
+            client.key_combo(screen, &keycodes).await?;
+            Output::Done
+        }
+        Command::Select(cmd) => {
+            client.select(screen, &cmd.text, cmd.start, cmd.end).await?;
+            Output::Done
+        }
+        Command::Fill(cmd) => {
+            let node = client
+                .fill(
+                    screen,
+                    &FillRequest {
+                        target: cmd.target,
+                        text: cmd.text,
+                        clear: cmd.clear,
+                        submit: cmd.submit,
+                        paste: cmd.paste,
+                    },
+                )
+                .await?;
+            Output::Report {
+                stdout: None,
+                notes: vec![format!("filled {}", a11y::describe_node(&node))],
+                data: serde_json::json!({ "node": node }),
             }
         }
+        Command::Focus(cmd) => {
+            if !["up", "down", "left", "right"].contains(&cmd.direction.as_str()) {
+                bail!(
+                    "unknown direction {:?}: expected up, down, left or right",
+                    cmd.direction
+                );
+            }
+            let node = client.focus(screen, &cmd.direction).await?;
+            Output::Report {
+                stdout: Some(a11y::describe_node(&node)),
+                notes: vec![],
+                data: serde_json::json!({ "node": node }),
+            }
+        }
+        Command::Launch(cmd) => {
+            let intent = cmd.intent()?;
+            let result = client
+                .launch(
+                    screen,
+                    cmd.no_wait,
+                    intent.as_ref(),
+                    cmd.idle_timeout_ms,
+                    cmd.global_timeout_ms,
+                    cmd.prefetch,
+                )
+                .await?;
+            Output::Interaction(result)
+        }
+        Command::Adopt(_) => Output::Json(serde_json::to_value(client.adopt(screen).await?)?),
         Command::Stop(_) => {
             client.stop(screen).await?;
+            Output::Done
         }
         Command::Reset(_) => {
             client.reset(screen).await?;
+            Output::Done
         }
-        Command::OpenUrl(cmd) => {
-            client.open_url(screen, &cmd.url).await?;
+        Command::ReleasePackage(cmd) => {
+            let info = client
+                .release_package(screen, cmd.package.as_deref())
+                .await?;
+            Output::Json(serde_json::to_value(&info)?)
         }
-        Command::WaitForIdle(cmd) => {
+        Command::OpenUrl(cmd) => {
+            let extras = parse_extras(&cmd.es, &cmd.ei, &cmd.ez, &cmd.el)?;
             client
-                .wait_for_idle(screen, cmd.idle_timeout_ms, cmd.global_timeout_ms)
+                .open_url(screen, &cmd.url, cmd.activity.as_deref(), extras)
                 .await?;
+            Output::Done
+        }
+        Command::WaitForIdle(cmd) => {
+            let wait = client.wait_for_idle(screen, cmd.idle_timeout_ms, cmd.global_timeout_ms);
+            cancel_on_ctrl_c(client, screen, wait).await?;
+            Output::Done
+        }
+        Command::WaitFor(cmd) => {
+            cancel_on_ctrl_c(client, screen, wait_for_node(client, screen, &cmd)).await?
+        }
+        Command::Cancel(_) => {
+            client.cancel(screen).await?;
+            Output::Done
+        }
+        Command::Screens(ScreensCmd { command: None }) => {
+            Output::Json(serde_json::to_value(client.list_screens().await?)?)
+        }
+        Command::Screens(ScreensCmd {
+            command: Some(ScreensSubcommand::CreateMany(cmd)),
+        }) => {
+            let json = fs::read_to_string(&cmd.file)
+                .with_context(|| format!("failed to read {}", cmd.file))?;
+            let entries = serde_json::from_str(&json)
+                .with_context(|| format!("{} is not a JSON array of objects", cmd.file))?;
+            let results = client.create_screens(entries, settings).await?;
+            let failed = results.iter().filter(|r| r.error.is_some()).count();
+            let output = Output::Json(serde_json::to_value(&results)?);
+            if failed > 0 {
+                let error = format!("{failed} of {} screens failed", results.len());
+                Output::Failed(Box::new(output), error)
+            } else {
+                output
+            }
         }
+        Command::Notifications(cmd) => {
+            let notifications = match cmd.command {
+                NotificationsSubcommand::Open(_) => client.open_notifications(screen).await?,
+                NotificationsSubcommand::List(_) => client.notifications(screen).await?,
+            };
+            let text = if notifications.is_empty() {
+                "no notifications".to_string()
+            } else {
+                notifications
+                    .iter()
+                    .map(a11y::describe_notification)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            Output::Report {
+                stdout: Some(text),
+                notes: vec![],
+                data: serde_json::to_value(&notifications)?,
+            }
+        }
+        Command::Diagnostics(_) => {
+            let checks = diagnostics::run(client, screen).await?;
+            let lines: Vec<String> = checks
+                .iter()
+                .map(|c| format!("{:<4}  {}: {}", c.status.label(), c.name, c.detail))
+                .collect();
+            let failed = checks
+                .iter()
+                .filter(|c| c.status == diagnostics::Status::Fail)
+                .count();
+            let output = Output::Report {
+                stdout: Some(lines.join("\n")),
+                notes: vec![],
+                data: serde_json::json!({ "checks": checks }),
+            };
+            if failed > 0 {
+                let error = format!("{failed} of {} checks failed", checks.len());
+                Output::Failed(Box::new(output), error)
+            } else {
+                output
+            }
+        }
+        Command::Device(cmd) => match cmd.command {
+            DeviceSubcommand::AirplaneMode(c) => {
+                device_toggle(client, "airplane mode", "airplane-mode", c.on).await?
+            }
+            DeviceSubcommand::Wifi(c) => device_toggle(client, "wifi", "wifi", c.on).await?,
+            DeviceSubcommand::MobileData(c) => {
+                device_toggle(client, "mobile data", "mobile-data", c.on).await?
+            }
+            DeviceSubcommand::SetTime(cmd) => {
+                let epoch_ms = client
+                    .set_device_time(&cmd.time, !cmd.keep_auto_time)
+                    .await?;
+                Output::Report {
+                    stdout: Some(format!(
+                        "device time set to {} ({epoch_ms} ms since epoch)",
+                        cmd.time
+                    )),
+                    notes: vec![],
+                    data: serde_json::json!({ "time": cmd.time, "epoch_ms": epoch_ms }),
+                }
+            }
+            DeviceSubcommand::Ime(cmd) => {
+                let methods = match cmd.command {
+                    DeviceImeSubcommand::List(_) => client.input_methods().await?,
+                    DeviceImeSubcommand::Set(c) => client.set_input_method(&c.id).await?,
+                };
+                let lines: Vec<String> = methods
+                    .enabled
+                    .iter()
+                    .map(|id| {
+                        if methods.current.as_ref() == Some(id) {
+                            format!("* {id}")
+                        } else {
+                            format!("  {id}")
+                        }
+                    })
+                    .collect();
+                Output::Report {
+                    stdout: Some(lines.join("\n")),
+                    notes: vec![],
+                    data: serde_json::to_value(&methods)?,
+                }
+            }
+        },
         Command::SyncLogsToFile(_) => {
             let info = client.info(screen).await?;
             let uid = resolve_package_uid(&info.assigned_package)?;
@@ -451,14 +2070,241 @@ async fn main() -> Result<()> {
                     .spawn()
                     .context("failed to spawn log daemon")?;
             }
-            println!("Logging to {}", log_file.display());
+            Output::Report {
+                stdout: Some(format!("Logging to {}", log_file.display())),
+                notes: vec![],
+                data: serde_json::json!({ "log_file": log_file }),
+            }
         }
-        Command::Start(_) | Command::Install(_) | Command::Version(_) | Command::LogDaemon(_) => {
+        Command::Start(_)
+        | Command::Status(_)
+        | Command::Kill(_)
+        | Command::Install(_)
+        | Command::Version(_)
+        | Command::Completions(_)
+        | Command::Repl(_)
+        | Command::LogDaemon(_) => {
             unreachable!()
         }
+    };
+
+    Ok(output)
+}
+
+/// Save the snapshot's screenshot and tree under `dir`; returns the tree as text.
+fn save_snapshot(dir: &str, snapshot: &Snapshot) -> Result<String> {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+    let jpeg = base64::engine::general_purpose::STANDARD
+        .decode(&snapshot.screenshot)
+        .context("invalid snapshot screenshot")?;
+    fs::write(dir.join("screenshot.jpg"), jpeg)?;
+    fs::write(
+        dir.join("a11y.json"),
+        serde_json::to_string_pretty(&snapshot.a11y)?,
+    )?;
+    let tree: a11y::A11yTree = serde_json::from_value(snapshot.a11y.clone())?;
+    Ok(a11y::render_text(&tree, &a11y::RenderOptions::default()))
+}
+
+fn parse_point(s: &str) -> Result<(f32, f32)> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow::anyhow!("expected x,y but got \"{s}\""))?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
+/// Resolve a tap by label and/or `--near` to the center of a node, retrying
+/// with idle waits while the node is missing.
+async fn find_tap_target(client: &Client, screen: &str, cmd: &TapCmd) -> Result<(f32, f32)> {
+    let near = match (
+        cmd.near.as_deref().map(parse_point).transpose()?,
+        &cmd.image_size,
+    ) {
+        (Some(point), _) if cmd.normalized => {
+            Some(denormalize(point, &client.info(screen).await?)?)
+        }
+        (Some(point), Some(size)) => {
+            Some(from_image_space(point, size, &client.info(screen).await?)?)
+        }
+        (near, _) => near,
+    };
+    let relative = cmd.relative()?;
+    // With --right-of and friends the label picks the anchor, not the node to tap
+    let label = cmd.target.as_deref().or(relative.map(|(_, anchor)| anchor));
+    let describe = match label {
+        Some(label) => format!("\"{label}\""),
+        None => String::from("clickable node"),
+    };
+
+    if cmd.index == Some(0) {
+        bail!("--index is 1-based");
+    }
+    if cmd.index.is_some() && (near.is_some() || label.is_none()) {
+        bail!("--index needs a text target and cannot be combined with --near");
     }
 
-    Ok(())
+    let by = match (cmd.by_text, cmd.by_desc) {
+        (true, true) => bail!("--by-text and --by-desc are mutually exclusive"),
+        (true, false) => a11y::LabelMatch::Text,
+        (false, true) => a11y::LabelMatch::Desc,
+        (false, false) => a11y::LabelMatch::Any,
+    };
+    if (cmd.by_text || cmd.by_desc) && label.is_none() {
+        bail!("--by-text and --by-desc need a text target");
+    }
+
+    let tries = cmd.tries.max(1);
+    let mut match_count = 0;
+    let mut anchor_found = false;
+    let mut opaque_webview = false;
+    for attempt in 1..=tries {
+        let (tree, _) = client.a11y(screen, true, false).await?;
+        opaque_webview = a11y::has_opaque_webview(&tree);
+        let node = match (near, label) {
+            (Some((x, y)), Some(label)) => {
+                a11y::nearest_node(&tree, x, y, |n| a11y::matches_label(n, label, by))
+            }
+            (Some((x, y)), None) => a11y::nearest_node(&tree, x, y, |n| n.clickable),
+            (None, Some(label)) => {
+                let matches = a11y::find_nodes(&tree, label, by);
+                match_count = matches.len();
+                match cmd.index {
+                    Some(index) => matches.get(index - 1).copied(),
+                    None if matches.len() > 1 && !cmd.first => {
+                        let candidates: Vec<String> = matches
+                            .iter()
+                            .enumerate()
+                            .map(|(i, n)| {
+                                format!("  --index {}: {}", i + 1, a11y::describe_node(n))
+                            })
+                            .collect();
+                        bail!(
+                            "{} nodes match {describe}; pick one with --index N or --near x,y \
+                             (or --first):\n{}",
+                            matches.len(),
+                            candidates.join("\n")
+                        );
+                    }
+                    None => matches.first().copied(),
+                }
+            }
+            (None, None) => unreachable!(),
+        };
+        anchor_found = node.is_some();
+        let node = match (node, relative) {
+            (Some(anchor), Some((dir, _))) => a11y::nearest_in_direction(&tree, anchor, dir),
+            (node, _) => node,
+        };
+        if let Some(node) = node {
+            return Ok(node.bounds.center());
+        }
+        if attempt < tries {
+            match relative {
+                Some((dir, _)) if anchor_found => eprintln!(
+                    "note: no clickable node {} {describe}, retrying ({attempt}/{tries})",
+                    dir.flag()
+                ),
+                _ => eprintln!("note: {describe} not found, retrying ({attempt}/{tries})"),
+            }
+            client.wait_for_idle(screen, 500, 5000).await?;
+        }
+    }
+    if let Some((dir, _)) = relative
+        && anchor_found
+    {
+        bail!("no clickable node {} {describe}", dir.flag());
+    }
+    if let Some(index) = cmd.index
+        && match_count > 0
+    {
+        bail!("--index {index} out of range: found {match_count} nodes matching {describe}");
+    }
+    if opaque_webview {
+        bail!(
+            "node not found: {describe}; a WebView on screen exposes no a11y content, \
+             so tap inside it by coordinates from a screenshot"
+        );
+    }
+    bail!("node not found: {describe}")
+}
+
+/// The frame's age when it is old and predates the last interaction. An old frame on a
+/// screen nobody touched since is just a static screen.
+fn stale_frame_ms(shot: &Screenshot) -> Option<u64> {
+    let age_ms = shot.age_ms.filter(|&ms| ms > STALE_FRAME_MS)?;
+    shot.interaction_age_ms
+        .is_some_and(|since| age_ms > since)
+        .then_some(age_ms)
+}
+
+/// The a11y tree still lists nodes the soft keyboard covers; a tap there hits the keyboard.
+/// Only a hint, so a failed keyboard lookup doesn't stop the tap.
+async fn warn_if_behind_ime(client: &Client, screen: &str, x: f32, y: f32) {
+    match client.ime(screen).await {
+        Ok(ime) if ime.bounds.as_ref().is_some_and(|b| b.contains(x, y)) => eprintln!(
+            "warning: the target is behind the soft keyboard, which gets the tap; \
+             run `andy hide-ime` or scroll it into view first"
+        ),
+        Ok(_) => {}
+        Err(e) => eprintln!("note: couldn't check the soft keyboard: {e:#}"),
+    }
+}
+
+/// Run a server-side wait; on Ctrl-C, cancel it on the device so it stops holding the screen.
+async fn cancel_on_ctrl_c<T>(
+    client: &Client,
+    screen: &str,
+    wait: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        result = wait => result,
+        ctrl_c = tokio::signal::ctrl_c() => {
+            ctrl_c.with_context(|| "failed to listen for Ctrl-C")?;
+            client.cancel(screen).await?;
+            bail!("interrupted; cancelled the wait on the device");
+        }
+    }
+}
+
+/// Switch a device-wide setting and check that it took.
+async fn device_toggle(client: &Client, setting: &str, path: &str, on: bool) -> Result<Output> {
+    let state = client.set_device_toggle(path, on).await?;
+    let output = Output::Report {
+        stdout: Some(format!("{setting}: {}", if state { "on" } else { "off" })),
+        notes: vec![],
+        data: serde_json::json!({ "setting": path, "on": state }),
+    };
+    if state != on {
+        let error = format!("{setting} did not switch {}", if on { "on" } else { "off" });
+        return Ok(Output::Failed(Box::new(output), error));
+    }
+    Ok(output)
+}
+
+/// Polls on the device so only the match result crosses the socket.
+async fn wait_for_node(client: &Client, screen: &str, cmd: &WaitForCmd) -> Result<Output> {
+    let what = if cmd.gone { "disappear" } else { "appear" };
+    let start = std::time::Instant::now();
+    let resp = client
+        .wait_for_node(screen, &cmd.text, cmd.timeout_ms, cmd.gone)
+        .await?;
+    if resp.found == cmd.gone {
+        bail!(
+            "timed out after {}ms waiting for \"{}\" to {what}",
+            cmd.timeout_ms,
+            cmd.text
+        );
+    }
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    Ok(Output::Report {
+        stdout: None,
+        notes: vec![format!(
+            "note: \"{}\" did {what} after {elapsed_ms}ms",
+            cmd.text
+        )],
+        data: serde_json::json!({ "text": cmd.text, "gone": cmd.gone, "elapsed_ms": elapsed_ms }),
+    })
 }
 
 async fn start_record(client: &Client, screen: &str, cmd: &StartRecordCmd) -> Result<()> {
@@ -468,7 +2314,7 @@ async fn start_record(client: &Client, screen: &str, cmd: &StartRecordCmd) -> Re
 
     eprintln!("waiting for first frame...");
     let mut last_frame = loop {
-        if let Some(frame) = client.raw_frame(screen, true).await? {
+        if let Some(frame) = client.raw_frame(screen, true, None).await? {
             break frame;
         }
         tokio::time::sleep(Duration::from_millis(100)).await;
@@ -526,7 +2372,7 @@ async fn start_record(client: &Client, screen: &str, cmd: &StartRecordCmd) -> Re
             _ = tokio::time::sleep(frame_period) => {}
         }
 
-        if let Some(frame) = client.raw_frame(screen, true).await? {
+        if let Some(frame) = client.raw_frame(screen, true, Some(&last_frame)).await? {
             if frame.width != last_frame.width || frame.height != last_frame.height {
                 bail!(
                     "frame size changed from {}x{} to {}x{} during recording",
@@ -556,7 +2402,7 @@ async fn start_stream(client: &Client, screen: &str, cmd: &StartStreamCmd) -> Re
 
     eprintln!("waiting for first frame...");
     let mut last_frame = loop {
-        if let Some(frame) = client.raw_frame(screen, true).await? {
+        if let Some(frame) = client.raw_frame(screen, true, None).await? {
             break frame;
         }
         tokio::time::sleep(Duration::from_millis(100)).await;
@@ -621,7 +2467,7 @@ async fn start_stream(client: &Client, screen: &str, cmd: &StartStreamCmd) -> Re
             _ = tokio::time::sleep(frame_period) => {}
         }
 
-        if let Some(frame) = client.raw_frame(screen, true).await? {
+        if let Some(frame) = client.raw_frame(screen, true, Some(&last_frame)).await? {
             if frame.width != last_frame.width || frame.height != last_frame.height {
                 bail!(
                     "frame size changed from {}x{} to {}x{} during stream",
@@ -702,12 +2548,115 @@ fn resolve_package_uid(package: &str) -> Result<u32> {
         // format: "package:com.fedi.dev00 uid:10117"
         if let Some(rest) = line.strip_prefix("package:") {
             let parts: Vec<&str> = rest.splitn(2, ' ').collect();
-            if parts.first().map(|p| p.trim()) == Some(package) {
-                if let Some(uid_str) = parts.get(1).and_then(|s| s.strip_prefix("uid:")) {
-                    return uid_str.trim().parse::<u32>().context("failed to parse uid");
-                }
+            if parts.first().map(|p| p.trim()) == Some(package)
+                && let Some(uid_str) = parts.get(1).and_then(|s| s.strip_prefix("uid:"))
+            {
+                return uid_str.trim().parse::<u32>().context("failed to parse uid");
             }
         }
     }
     bail!("could not resolve uid for package {package}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_peek_skips_screen_creation() {
+        let parse = |args: &[&str]| Cli::from_args(&["andy"], args).unwrap().command;
+        assert!(peeks(&parse(&["a11y", "--peek"])));
+        assert!(!peeks(&parse(&["a11y"])));
+        assert!(!peeks(&parse(&["info"])));
+    }
+
+    #[test]
+    fn json_names_the_parsed_command() {
+        let parse = |args: &[&str]| Cli::from_args(&["andy"], args).unwrap().command;
+        assert_eq!(parse(&["--screen", "tap", "tap", "1,2"]).name(), "tap");
+        assert_eq!(
+            parse(&["--package", "a.b", "multi-tap", "1,2", "3,4"]).name(),
+            "multi-tap"
+        );
+        assert_eq!(parse(&["device", "wifi", "on"]).name(), "device");
+    }
+
+    #[test]
+    fn dry_run_json_lists_the_commands() {
+        let planned = vec!["adb shell pkill -9 -f andy-coordinator  # ignoring failure".into()];
+        assert_eq!(
+            dry_run_output(planned.clone()).into_json("kill").unwrap(),
+            serde_json::json!({ "command": "kill", "ok": true, "commands": planned })
+        );
+    }
+
+    #[test]
+    fn keycodes_by_name_letter_or_number() {
+        for (key, code) in [
+            ("ENTER", 66),
+            ("enter", 66),
+            ("KEYCODE_BACK", 4),
+            ("keycode_dpad_up", 19),
+            ("Move_End", 123),
+            ("a", 29),
+            ("Z", 54),
+            ("KEYCODE_C", 31),
+            ("4", 4),
+            ("287", 287),
+        ] {
+            assert_eq!(parse_keycode(key).unwrap(), code, "{key}");
+        }
+        for (name, code) in KEY_NAMES {
+            assert_eq!(parse_keycode(name).unwrap(), *code, "{name}");
+        }
+        let err = parse_keycode("FROBNICATE").unwrap_err().to_string();
+        assert!(err.contains("unknown key \"FROBNICATE\""), "{err}");
+        assert!(parse_keycode("AB").is_err());
+        assert!(parse_keycode("").is_err());
+    }
+
+    #[test]
+    fn image_space_scales_to_the_screen() {
+        let info = ScreenInfo {
+            name: "default".into(),
+            display_id: 2,
+            width: 1080,
+            height: 2400,
+            dpi: 420,
+            assigned_package: String::new(),
+            last_action: None,
+            timeout_secs: 0,
+            seconds_until_reap: None,
+        };
+        assert_eq!(
+            from_image_space((270.0, 600.0), "540x1200", &info).unwrap(),
+            (540.0, 1200.0)
+        );
+        // Rounded by a pixel when downscaled
+        assert!(from_image_space((0.0, 0.0), "487x1083", &info).is_ok());
+        assert!(from_image_space((10.0, 10.0), "1200x540", &info).is_err());
+        assert!(from_image_space((541.0, 10.0), "540x1200", &info).is_err());
+        assert!(from_image_space((10.0, -1.0), "540x1200", &info).is_err());
+        assert!(from_image_space((10.0, 10.0), "540", &info).is_err());
+    }
+
+    #[test]
+    fn stale_frame_warning_needs_a_later_interaction() {
+        let shot = |age_ms, interaction_age_ms| Screenshot {
+            data: Default::default(),
+            wait_ms: None,
+            scale: 1.0,
+            frozen: false,
+            age_ms: Some(age_ms),
+            interaction_age_ms,
+            width: None,
+            height: None,
+            pages: None,
+            pixel_format: None,
+        };
+        assert_eq!(stale_frame_ms(&shot(5000, Some(1000))), Some(5000));
+        assert_eq!(stale_frame_ms(&shot(5000, Some(9000))), None);
+        assert_eq!(stale_frame_ms(&shot(5000, None)), None);
+        assert_eq!(stale_frame_ms(&shot(500, Some(100))), None);
+    }
+}
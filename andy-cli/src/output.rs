@@ -0,0 +1,159 @@
+use anyhow::{Result, anyhow};
+use serde_json::{Map, Value, json};
+
+use crate::types::Interaction;
+
+/// What a command reports: prose on stdout and stderr, or with `--json` one object on
+/// stdout, `{"command": ..., "ok": true, ...}` with the result's fields merged in.
+pub enum Output {
+    /// Nothing to report beyond success.
+    Done,
+    /// Already structured; pretty-printed either way.
+    Json(Value),
+    /// `stdout` and `notes` (stderr) for people; `data` for `--json`.
+    Report {
+        stdout: Option<String>,
+        notes: Vec<String>,
+        data: Value,
+    },
+    /// Outcome of an interaction that auto-waited (tap, launch).
+    Interaction(Interaction),
+    /// A result that still counts as a failure, e.g. a batch where some entries failed.
+    Failed(Box<Output>, String),
+}
+
+impl Output {
+    /// Print for people; `Failed` prints its result, then returns the error.
+    pub fn print(self) -> Result<()> {
+        match self {
+            Output::Done => {}
+            Output::Json(value) => println!("{}", serde_json::to_string_pretty(&value)?),
+            Output::Report { stdout, notes, .. } => {
+                for note in notes {
+                    eprintln!("{note}");
+                }
+                if let Some(text) = stdout {
+                    println!("{text}");
+                }
+            }
+            Output::Interaction(result) => {
+                for note in interaction_notes(&result) {
+                    eprintln!("{note}");
+                }
+            }
+            Output::Failed(output, error) => {
+                output.print()?;
+                return Err(anyhow!(error));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `--json` object; `ok` is false for `Failed`.
+    pub fn into_json(self, command: &str) -> Result<Value> {
+        let (output, error) = match self {
+            Output::Failed(output, error) => (*output, Some(error)),
+            output => (output, None),
+        };
+        let data = match output {
+            Output::Done | Output::Failed(..) => Value::Null,
+            Output::Json(value) | Output::Report { data: value, .. } => value,
+            Output::Interaction(result) => serde_json::to_value(result)?,
+        };
+        let mut object = Map::new();
+        match data {
+            Value::Object(fields) => object.extend(fields),
+            Value::Null => {}
+            other => {
+                object.insert("result".into(), other);
+            }
+        }
+        object.insert("command".into(), json!(command));
+        object.insert("ok".into(), json!(error.is_none()));
+        if let Some(error) = error {
+            object.insert("error".into(), json!(error));
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+/// The `--json` object for a command that failed.
+pub fn error_json(command: &str, error: &anyhow::Error) -> Value {
+    json!({ "command": command, "ok": false, "error": format!("{error:#}") })
+}
+
+/// Notes and warnings about how an interaction settled, for stderr.
+pub fn interaction_notes(result: &Interaction) -> Vec<String> {
+    let mut notes = Vec::new();
+    if let Some(ms) = result.wait_ms
+        && ms > 0
+    {
+        notes.push(format!("note: waited {ms}ms for idle"));
+    }
+    if let Some(dialog) = &result.app_crashed {
+        notes.push(
+            [
+                "###########################################################",
+                "#  WARNING: app crashed or is not responding              #",
+                "###########################################################",
+                &format!("dialog: {dialog}"),
+            ]
+            .join("\n"),
+        );
+    }
+    if let Some(dismissed) = &result.auto_dismissed {
+        notes.push(format!("note: auto-dismissed system dialog: {dismissed}"));
+    }
+    if result.ui_changed == Some(false) {
+        notes.push("warning: tap may not have registered (UI unchanged)".into());
+    }
+    if result.cold_start == Some(false) {
+        notes.push(
+            "note: app was already running; its existing task was brought to the front".into(),
+        );
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_merges_object_fields() {
+        let report = Output::Report {
+            stdout: Some("com.example".into()),
+            notes: vec![],
+            data: json!({ "package": "com.example" }),
+        };
+        assert_eq!(
+            report.into_json("foreground").unwrap(),
+            json!({ "command": "foreground", "ok": true, "package": "com.example" })
+        );
+        assert_eq!(
+            Output::Done.into_json("stop").unwrap(),
+            json!({ "command": "stop", "ok": true })
+        );
+    }
+
+    #[test]
+    fn json_wraps_other_values_in_result() {
+        let output = Output::Json(json!([1, 2]));
+        assert_eq!(
+            output.into_json("screens").unwrap(),
+            json!({ "command": "screens", "ok": true, "result": [1, 2] })
+        );
+    }
+
+    #[test]
+    fn json_of_failed_is_not_ok() {
+        let output = Output::Failed(
+            Box::new(Output::Json(json!({ "failed": 1 }))),
+            "1 of 2 failed".into(),
+        );
+        assert_eq!(
+            output.into_json("fill").unwrap(),
+            json!({ "command": "fill", "ok": false, "failed": 1, "error": "1 of 2 failed" })
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Result, bail};
+
+/// Split a line into words the way a shell would for simple cases: whitespace
+/// separates words, single and double quotes group them, and a backslash escapes
+/// the next character outside single quotes.
+pub fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_default().push(c),
+            (_, '\\') => match chars.next() {
+                Some(next) => word.get_or_insert_default().push(next),
+                None => bail!("line ends with a backslash"),
+            },
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        bail!("unterminated {q} quote");
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_like_a_shell() {
+        let words = split_words(r#"tap --near 900,300 "Add item" 'it''s' a\ b """#).unwrap();
+        assert_eq!(
+            words,
+            ["tap", "--near", "900,300", "Add item", "its", "a b", ""]
+        );
+        assert!(split_words("type \"open").is_err());
+    }
+}
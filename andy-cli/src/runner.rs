@@ -1,4 +1,5 @@
 use anyhow::{Context, Result, bail};
+use serde::Serialize;
 use std::io::Write;
 use std::path::Path;
 use std::process::{Command, Stdio};
@@ -6,10 +7,18 @@ use std::process::{Command, Stdio};
 use crate::assets;
 
 const DEVICE_DIR: &str = "/data/local/tests/coordinator";
-const DEVICE_PORT: u16 = 21632;
+/// Where the coordinator listens on the device.
+pub const DEVICE_PORT: u16 = 21632;
 
-pub fn start(socket_path: &Path) -> Result<()> {
+/// Deploy and start the coordinator, forwarding it to `socket_path` and, when given, to
+/// `tcp_port` on this host for non-CLI HTTP clients. With `dry_run`, only read-only device
+/// queries run; every command that changes the device or host is returned instead.
+pub fn start(socket_path: &Path, tcp_port: Option<u16>, dry_run: bool) -> Result<Vec<String>> {
     let device_dir = DEVICE_DIR.to_string();
+    let mut deploy = Deploy {
+        dry_run,
+        planned: Vec::new(),
+    };
 
     // Check that we're talking to a virtual device
     let is_virtual =
@@ -24,7 +33,9 @@ pub fn start(socket_path: &Path) -> Result<()> {
 
     let so_bytes = select_so()?;
 
-    if let Some(parent) = socket_path.parent() {
+    if let Some(parent) = socket_path.parent()
+        && !dry_run
+    {
         std::fs::create_dir_all(parent)
             .with_context(|| format!("create socket dir {}", parent.display()))?;
     }
@@ -32,79 +43,259 @@ pub fn start(socket_path: &Path) -> Result<()> {
     let remote_spec = format!("tcp:{}", DEVICE_PORT);
 
     // Remove old forward so the socket file is recreated
-    let _ = Command::new("adb")
-        .args(["forward", "--remove", &local_spec])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
-
-    let _ = Command::new("adb")
-        .args(["shell", "pkill", "-9", "-f", "andy-coordinator"])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
+    deploy.run_quiet("adb", &["forward", "--remove", &local_spec]);
+
+    kill_with(&mut deploy);
 
     let setup = format!("rm -rf {device_dir} && mkdir -p {device_dir}");
-    run("adb", &["shell", &setup], "prepare device")?;
+    deploy.run("adb", &["shell", &setup], "prepare device")?;
 
-    push_bytes(
+    deploy.push_bytes(
         assets::JAR,
         &format!("{}/coordinator-server.jar", device_dir),
         "push jar",
     )?;
-    push_bytes(
+    deploy.push_bytes(
         so_bytes,
         &format!("{}/libcoordinator.so", device_dir),
         "push .so",
     )?;
 
-    run(
+    // Through a mode-600 file rather than the environment, where `ps` would show it
+    if let Ok(token) = std::env::var("ANDY_AUTH_TOKEN") {
+        let path = format!("{device_dir}/auth_token");
+        let mut write = format!("umask 077 && cat > {path}");
+        // Readable by the server when it runs as another user
+        if let Ok(user) = std::env::var("ANDY_RUN_AS") {
+            write = format!("su {user} sh -c '{write}'");
+        }
+        deploy.exec_in(token.as_bytes(), &write, "push auth token")?;
+    }
+
+    deploy.run(
         "adb",
         &["forward", &local_spec, &remote_spec],
         "configure adb forward",
     )?;
+    if let Some(port) = tcp_port {
+        deploy.run(
+            "adb",
+            &["forward", &format!("tcp:{port}"), &remote_spec],
+            "configure adb tcp forward",
+        )?;
+    }
 
     // Start coordinator — device side spawns daemon and exits.
     // The polling loop in ensure_server waits for it to become ready.
     let classpath = format!("{device_dir}/coordinator-server.jar");
     let lib_path = format!("{device_dir}/libcoordinator.so");
-    run(
-        "adb",
-        &[
-            "shell",
-            "env",
-            &format!("CLASSPATH={classpath}"),
-            &format!("ANDY_LIB={lib_path}"),
-            "app_process",
-            "/system/bin",
-            "com.coordinator.Main",
-        ],
-        "start coordinator",
-    )?;
+    let mut args = vec![
+        "shell".to_string(),
+        "env".to_string(),
+        format!("CLASSPATH={classpath}"),
+        format!("ANDY_LIB={lib_path}"),
+    ];
+    // Server settings, read once at server start
+    for var in [
+        "ANDY_OP_TIMEOUT_MS",
+        "ANDY_BIND_ADDR",
+        "ANDY_PERSIST",
+        "ANDY_RUN_AS",
+        "ANDY_PATH_PREFIX",
+        "ANDY_LOCK_TIMEOUT_MS",
+        "ANDY_PIXEL_FORMAT",
+        "ANDY_MAX_SCREENS",
+        "ANDY_NO_COMPRESS",
+    ] {
+        if let Ok(value) = std::env::var(var) {
+            args.push(format!("{var}={value}"));
+        }
+    }
+    args.extend(["app_process", "/system/bin", "com.coordinator.Main"].map(String::from));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    deploy.run("adb", &args, "start coordinator")?;
+
+    if !dry_run {
+        eprintln!("debug: andy server started");
+    }
+    Ok(deploy.planned)
+}
+
+/// Kill every coordinator process on the device. Best effort: nothing running is fine.
+/// With `dry_run`, returns the command instead of running it.
+pub fn kill(dry_run: bool) -> Vec<String> {
+    let mut deploy = Deploy {
+        dry_run,
+        planned: Vec::new(),
+    };
+    kill_with(&mut deploy);
+    deploy.planned
+}
+
+fn kill_with(deploy: &mut Deploy) {
+    // A daemon running as another uid can only be killed as that uid
+    let mut pkill = vec!["shell".to_string()];
+    if let Ok(user) = std::env::var("ANDY_RUN_AS") {
+        pkill.extend(["su".to_string(), user]);
+    }
+    pkill.extend(["pkill", "-9", "-f", "andy-coordinator"].map(String::from));
+    let pkill: Vec<&str> = pkill.iter().map(String::as_str).collect();
+    deploy.run_quiet("adb", &pkill);
+}
+
+/// A coordinator process on the device.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct DeviceProcess {
+    pub pid: u32,
+    pub user: String,
+    /// Time since it started, `[[DD-]HH:]MM:SS`.
+    pub elapsed: String,
+}
+
+/// Coordinator processes running on the device.
+pub fn processes() -> Result<Vec<DeviceProcess>> {
+    let output = Command::new("adb")
+        .args(["shell", "ps", "-A", "-o", "PID,USER,ETIME,ARGS"])
+        .output()
+        .context("failed to run adb shell ps")?;
+    if !output.status.success() {
+        bail!("adb shell ps failed (is a device connected?)");
+    }
+    Ok(parse_ps(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Coordinator processes in `ps -A -o PID,USER,ETIME,ARGS` output.
+fn parse_ps(output: &str) -> Vec<DeviceProcess> {
+    output
+        .lines()
+        .skip(1)
+        .filter(|line| line.contains("andy-coordinator"))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(DeviceProcess {
+                pid: fields.next()?.parse().ok()?,
+                user: fields.next()?.to_string(),
+                elapsed: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Local ends of the `adb forward` rules that reach the coordinator's port.
+pub fn forwards() -> Result<Vec<String>> {
+    let output = Command::new("adb")
+        .args(["forward", "--list"])
+        .output()
+        .context("failed to run adb forward --list")?;
+    if !output.status.success() {
+        bail!("adb forward --list failed");
+    }
+    Ok(parse_forwards(&String::from_utf8_lossy(&output.stdout)))
+}
 
-    eprintln!("debug: andy server started");
-    Ok(())
+/// Local ends in `adb forward --list` output whose remote end is the coordinator's port.
+fn parse_forwards(output: &str) -> Vec<String> {
+    let remote = format!("tcp:{DEVICE_PORT}");
+    // Each line is `<serial> <local> <remote>`
+    output
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, local, r] if r == remote => Some(local.to_string()),
+                _ => None,
+            },
+        )
+        .collect()
 }
 
-fn push_bytes(bytes: &[u8], device_path: &str, label: &str) -> Result<()> {
-    let mut child = Command::new("adb")
-        .args(["exec-in", &format!("cat > {device_path}")])
-        .stdin(Stdio::piped())
-        .spawn()
-        .with_context(|| format!("{label}: failed to spawn adb exec-in"))?;
-    child
-        .stdin
-        .take()
-        .unwrap()
-        .write_all(bytes)
-        .with_context(|| format!("{label}: failed to write bytes"))?;
-    let status = child
-        .wait()
-        .with_context(|| format!("{label}: failed to wait for adb exec-in"))?;
-    if !status.success() {
-        bail!("{label}: adb exec-in failed with status {status}");
+/// Tail of the daemon's raw output and its log, for a server that never became ready.
+pub fn startup_logs() -> String {
+    let tail = format!("tail -n 20 {DEVICE_DIR}/daemon.out {DEVICE_DIR}/andy.log 2>&1");
+    match Command::new("adb").args(["shell", &tail]).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        Err(e) => format!("failed to read device logs: {e}"),
+    }
+}
+
+/// Runs the commands that change the device or host, or with `dry_run` only collects
+/// them. Children's stdout goes to stderr, keeping stdout for the command's own result
+/// (one object with `--json`).
+struct Deploy {
+    dry_run: bool,
+    /// One line per command a dry run skipped, with what it is for.
+    planned: Vec<String>,
+}
+
+impl Deploy {
+    fn push_bytes(&mut self, bytes: &[u8], device_path: &str, label: &str) -> Result<()> {
+        self.exec_in(bytes, &format!("cat > {device_path}"), label)
+    }
+
+    /// Feed `bytes` to `command` run on the device, e.g. a `cat > path`.
+    fn exec_in(&mut self, bytes: &[u8], command: &str, label: &str) -> Result<()> {
+        if self.dry_run {
+            self.planned.push(format!(
+                "adb exec-in \"{command}\"  # {label}: {} bytes",
+                bytes.len()
+            ));
+            return Ok(());
+        }
+        let mut child = Command::new("adb")
+            .args(["exec-in", command])
+            .stdin(Stdio::piped())
+            .stdout(std::io::stderr())
+            .spawn()
+            .with_context(|| format!("{label}: failed to spawn adb exec-in"))?;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(bytes)
+            .with_context(|| format!("{label}: failed to write bytes"))?;
+        let status = child
+            .wait()
+            .with_context(|| format!("{label}: failed to wait for adb exec-in"))?;
+        if !status.success() {
+            bail!("{label}: adb exec-in failed with status {status}");
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, cmd: &str, args: &[&str], label: &str) -> Result<()> {
+        if self.dry_run {
+            self.planned
+                .push(format!("{}  # {label}", format_command(cmd, args)));
+            return Ok(());
+        }
+        let status = Command::new(cmd)
+            .args(args)
+            .stdout(std::io::stderr())
+            .status()
+            .with_context(|| format!("{label}: failed to spawn {}", format_command(cmd, args)))?;
+        if !status.success() {
+            bail!(
+                "{}: command failed with status {}: {}",
+                label,
+                status,
+                format_command(cmd, args)
+            );
+        }
+        Ok(())
+    }
+
+    /// Best-effort cleanup whose failure is expected, e.g. nothing to remove.
+    fn run_quiet(&mut self, cmd: &str, args: &[&str]) {
+        if self.dry_run {
+            self.planned
+                .push(format!("{}  # ignoring failure", format_command(cmd, args)));
+            return;
+        }
+        let _ = Command::new(cmd)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
     }
-    Ok(())
 }
 
 fn select_so() -> Result<&'static [u8]> {
@@ -116,22 +307,6 @@ fn select_so() -> Result<&'static [u8]> {
     }
 }
 
-fn run(cmd: &str, args: &[&str], label: &str) -> Result<()> {
-    let status = Command::new(cmd)
-        .args(args)
-        .status()
-        .with_context(|| format!("{label}: failed to spawn {}", format_command(cmd, args)))?;
-    if !status.success() {
-        bail!(
-            "{}: command failed with status {}: {}",
-            label,
-            status,
-            format_command(cmd, args)
-        );
-    }
-    Ok(())
-}
-
 fn adb_getprop(prop: &str) -> Result<String> {
     let output = Command::new("adb")
         .args(["shell", "getprop", prop])
@@ -160,3 +335,53 @@ fn format_command(cmd: &str, args: &[&str]) -> String {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_coordinator_processes() {
+        let ps = "\
+  PID USER         ELAPSED ARGS
+    1 root        02:14:07 init second_stage
+ 4242 shell          03:12 andy-coordinator
+ 4301 root     1-02:03:04 su 0 env ANDY_DAEMON=1 app_process /system/bin com.coordinator.Main andy-coordinator
+ 4400 shell          00:01 ps -A -o PID,USER,ETIME,ARGS
+";
+        assert_eq!(
+            parse_ps(ps),
+            [
+                DeviceProcess {
+                    pid: 4242,
+                    user: "shell".into(),
+                    elapsed: "03:12".into(),
+                },
+                DeviceProcess {
+                    pid: 4301,
+                    user: "root".into(),
+                    elapsed: "1-02:03:04".into(),
+                },
+            ]
+        );
+        assert!(parse_ps("  PID USER ELAPSED ARGS\n").is_empty());
+    }
+
+    #[test]
+    fn finds_forwards_to_the_coordinator() {
+        let list = "\
+emulator-5554 localfilesystem:/home/me/.local/state/andy.sock tcp:21632
+emulator-5554 tcp:21632 tcp:21632
+emulator-5554 tcp:8080 tcp:8080
+emulator-5556 tcp:9000 localabstract:chrome_devtools_remote
+";
+        assert_eq!(
+            parse_forwards(list),
+            [
+                "localfilesystem:/home/me/.local/state/andy.sock",
+                "tcp:21632"
+            ]
+        );
+        assert!(parse_forwards("").is_empty());
+    }
+}
@@ -6,8 +6,20 @@ pub struct CreateScreenRequest {
     pub width: i32,
     pub height: i32,
     pub dpi: i32,
-    pub timeout_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
     pub package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_dismiss: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dismiss_rules: Option<Vec<DismissRule>>,
+}
+
+/// Auto-dismiss table row: when a node's text contains `when`, tap the node labelled `tap`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DismissRule {
+    pub when: String,
+    pub tap: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -18,6 +30,85 @@ pub struct ScreenInfo {
     pub height: i32,
     pub dpi: i32,
     pub assigned_package: String,
+    #[serde(default)]
+    pub last_action: Option<LastAction>,
+    /// Heartbeat timeout; 0 means the screen is never reaped.
+    #[serde(default)]
+    pub timeout_secs: u64,
+    /// Left before the screen is reaped unless another command arrives.
+    #[serde(default)]
+    pub seconds_until_reap: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct AppOpsRequest {
+    pub op: String,
+    pub mode: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AppOpMode {
+    pub op: String,
+    pub mode: String,
+}
+
+/// Installed packages a package spec matches, or similar ones when it matches none.
+#[derive(Deserialize)]
+pub struct InstalledPackages {
+    pub matches: Vec<String>,
+    pub nearby: Vec<String>,
+}
+
+/// Outcome for one screen of a batch create; either `screen` or `error` is set.
+#[derive(Serialize, Deserialize)]
+pub struct BatchScreenResult {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen: Option<ScreenInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The server's record of the last injected input; `x`/`y` are device pixels.
+#[derive(Serialize, Deserialize)]
+pub struct LastAction {
+    pub kind: String,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub at_ms_ago: u64,
+}
+
+/// An action the server performed on a screen, from `/history`.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Milliseconds since the epoch.
+    pub timestamp_ms: u64,
+    pub action: String,
+    pub params: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ForegroundInfo {
+    pub package: String,
+    pub activity: String,
+}
+
+/// The soft keyboard on a screen.
+#[derive(Serialize, Deserialize)]
+pub struct ImeInfo {
+    pub shown: bool,
+    /// Pixels covered from the bottom of the screen.
+    pub height: i32,
+    pub bounds: Option<crate::a11y::Bounds>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AppProcessState {
+    pub package: String,
+    pub installed: bool,
+    pub running: bool,
+    pub foreground: bool,
+    pub pid: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -31,12 +122,107 @@ pub struct RawFrame {
     pub data: bytes::Bytes,
 }
 
+pub struct Screenshot {
+    pub data: bytes::Bytes,
+    pub wait_ms: Option<u64>,
+    /// Factor the image was downscaled by relative to device pixels.
+    pub scale: f32,
+    /// The picture has not changed across captures since a recent interaction.
+    pub frozen: bool,
+    /// How long ago the frame arrived from the display; 0 when captured for this request.
+    pub age_ms: Option<u64>,
+    /// How long ago the last interaction was, if there was one.
+    pub interaction_age_ms: Option<u64>,
+    /// Size of the encoded image, after any crop and scaling.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Viewports stitched together, for a full (scrolling) screenshot.
+    pub pages: Option<usize>,
+    /// Layout the surface delivered a raw frame in (`rgba` or `bgra`), before the server
+    /// converted it to RGBA.
+    pub pixel_format: Option<String>,
+}
+
+/// Outcome headers of an interaction that auto-waits (tap, launch).
+#[derive(Serialize)]
+pub struct Interaction {
+    pub wait_ms: Option<u64>,
+    /// Crash/ANR dialog text seen after the interaction settled.
+    pub app_crashed: Option<String>,
+    /// Whether the a11y tree changed across the interaction (tap only).
+    pub ui_changed: Option<bool>,
+    /// System dialog the server tapped away (screens created with `--auto-dismiss`).
+    pub auto_dismissed: Option<String>,
+    /// `Some(false)` when `launch` found the app already running (launch only).
+    pub cold_start: Option<bool>,
+}
+
+/// Body of `tap?snapshot=true`: the screen right after the tap settled.
+#[derive(Deserialize)]
+pub struct Snapshot {
+    pub wait_ms: u64,
+    pub app_crashed: Option<String>,
+    #[serde(default)]
+    pub auto_dismissed: Option<String>,
+    pub ui_changed: Option<bool>,
+    /// Base64-encoded JPEG.
+    pub screenshot: String,
+    pub a11y: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoordSpace {
+    Device,
+    Screenshot,
+}
+
+impl CoordSpace {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CoordSpace::Device => "device",
+            CoordSpace::Screenshot => "screenshot",
+        }
+    }
+}
+
+impl std::str::FromStr for CoordSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "device" => Ok(CoordSpace::Device),
+            "screenshot" => Ok(CoordSpace::Screenshot),
+            other => Err(format!(
+                "unknown coordinate space: {other} (device|screenshot)"
+            )),
+        }
+    }
+}
+
+/// Auto-wait before reads; see `ANDY_WAIT_STRATEGY`.
+#[derive(Debug, Clone, Copy)]
+pub enum WaitStrategy {
+    Idle,
+    Fixed(u64),
+    None,
+}
+
+#[derive(Serialize)]
+pub struct ReleasePackageRequest {
+    pub package: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct TapRequest {
     pub x: f32,
     pub y: f32,
 }
 
+#[derive(Serialize)]
+pub struct MultiTapRequest {
+    pub points: Vec<TapRequest>,
+}
+
 #[derive(Serialize)]
 pub struct SwipeRequest {
     pub x1: f32,
@@ -44,11 +230,18 @@ pub struct SwipeRequest {
     pub x2: f32,
     pub y2: f32,
     pub duration_ms: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub easing: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hold_ms: Option<i64>,
 }
 
 #[derive(Serialize)]
 pub struct TypeRequest {
     pub text: String,
+    pub paste: bool,
 }
 
 #[derive(Serialize)]
@@ -56,9 +249,99 @@ pub struct KeyRequest {
     pub keycode: i32,
 }
 
+#[derive(Serialize)]
+pub struct KeyComboRequest {
+    pub keycodes: Vec<i32>,
+}
+
+#[derive(Serialize)]
+pub struct SelectRequest {
+    pub text: String,
+    pub start: i32,
+    pub end: i32,
+}
+
+#[derive(Serialize, Default)]
+pub struct LaunchIntentRequest {
+    pub activity: Option<String>,
+    pub action: Option<String>,
+    pub data: Option<String>,
+    pub extras: Vec<IntentExtra>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IntentExtra {
+    String { key: String, value: String },
+    Int { key: String, value: i32 },
+    Bool { key: String, value: bool },
+    Long { key: String, value: i64 },
+}
+
+/// Requested and reported state of a device-wide toggle.
+#[derive(Serialize, Deserialize)]
+pub struct ToggleState {
+    pub on: bool,
+}
+
+#[derive(Serialize)]
+pub struct SetTimeRequest {
+    pub iso8601: String,
+    pub disable_auto_time: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceTime {
+    pub epoch_ms: i64,
+}
+
+#[derive(Serialize)]
+pub struct SetInputMethodRequest {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InputMethods {
+    pub current: Option<String>,
+    pub enabled: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FillRequest {
+    pub target: String,
+    pub text: String,
+    pub clear: bool,
+    pub submit: bool,
+    pub paste: bool,
+}
+
+#[derive(Serialize)]
+pub struct FocusRequest {
+    pub direction: String,
+}
+
 #[derive(Serialize)]
 pub struct OpenUrlRequest {
     pub url: String,
+    pub activity: Option<String>,
+    pub extras: Vec<IntentExtra>,
+}
+
+#[derive(Serialize)]
+pub struct Selector {
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct WaitForNodeRequest {
+    pub selector: Selector,
+    pub timeout_ms: u64,
+    pub gone: bool,
+}
+
+#[derive(Deserialize)]
+pub struct WaitForNodeResponse {
+    pub found: bool,
 }
 
 #[derive(Serialize)]
@@ -6,6 +6,7 @@ import android.content.Context;
 import android.content.ContextWrapper;
 import android.graphics.Point;
 import android.graphics.Rect;
+import android.os.Bundle;
 import android.os.HandlerThread;
 import android.os.Looper;
 import android.os.Process;
@@ -21,6 +22,7 @@ import java.lang.reflect.Constructor;
 import java.lang.reflect.Method;
 import java.util.ArrayList;
 import java.util.Collections;
+import java.util.Comparator;
 import java.util.List;
 
 final class AccessibilityBridge {
@@ -127,7 +129,12 @@ final class AccessibilityBridge {
     }
 
     String dumpDisplayJson(int displayId) throws Exception {
-        List<AccessibilityWindowInfo> windows = getWindowsForDisplay(displayId);
+        return dumpDisplayJson(displayId, false);
+    }
+
+    // System windows carry crash/ANR dialogs; they are left out of the normal dump.
+    String dumpDisplayJson(int displayId, boolean includeSystemWindows) throws Exception {
+        List<AccessibilityWindowInfo> windows = getWindowsTopFirst(displayId);
         Point displaySize = getDisplaySize(displayId);
 
         StringWriter out = new StringWriter(16 * 1024);
@@ -148,7 +155,10 @@ final class AccessibilityBridge {
         SnapshotState state = new SnapshotState(writer);
         for (int i = 0; i < windows.size(); i++) {
             AccessibilityWindowInfo w = windows.get(i);
-            if (w.getType() != AccessibilityWindowInfo.TYPE_APPLICATION) continue;
+            int type = w.getType();
+            boolean wanted = type == AccessibilityWindowInfo.TYPE_APPLICATION
+                    || (includeSystemWindows && type == AccessibilityWindowInfo.TYPE_SYSTEM);
+            if (!wanted) continue;
             writeWindow(w, i, state, displaySize);
         }
 
@@ -160,6 +170,30 @@ final class AccessibilityBridge {
         return out.toString();
     }
 
+    // Every window on the display, topmost first, without node trees.
+    String dumpWindowsJson(int displayId) throws Exception {
+        List<AccessibilityWindowInfo> windows = getWindowsTopFirst(displayId);
+
+        StringWriter out = new StringWriter(1024);
+        JsonWriter writer = new JsonWriter(out);
+        writer.beginArray();
+        for (int i = 0; i < windows.size(); i++) {
+            writer.beginObject();
+            writeWindowFields(writer, windows.get(i), i);
+            writer.endObject();
+        }
+        writer.endArray();
+        writer.close();
+        return out.toString();
+    }
+
+    // Descending layer, so the window drawn on top comes first.
+    private List<AccessibilityWindowInfo> getWindowsTopFirst(int displayId) throws Exception {
+        List<AccessibilityWindowInfo> windows = getWindowsForDisplay(displayId);
+        windows.sort(Comparator.comparingInt(AccessibilityWindowInfo::getLayer).reversed());
+        return windows;
+    }
+
     @SuppressWarnings("unchecked")
     private List<AccessibilityWindowInfo> getWindowsForDisplay(int displayId) throws Exception {
         if (getWindowsOnAllDisplaysMethod != null) {
@@ -206,6 +240,19 @@ final class AccessibilityBridge {
             throws Exception {
         JsonWriter writer = state.writer;
         writer.beginObject();
+        writeWindowFields(writer, window, index);
+
+        writer.name("nodes").beginArray();
+        AccessibilityNodeInfo root = window.getRoot();
+        if (root != null) {
+            writeNode(root, 0, 0, -1, state, window.getId(), displaySize);
+        }
+        writer.endArray();
+        writer.endObject();
+    }
+
+    private void writeWindowFields(JsonWriter writer, AccessibilityWindowInfo window, int index)
+            throws Exception {
         writer.name("index").value(index);
         writer.name("id").value(window.getId());
         writer.name("display_id").value(getWindowDisplayId(window));
@@ -220,14 +267,6 @@ final class AccessibilityBridge {
         window.getBoundsInScreen(bounds);
         writer.name("bounds");
         writeRect(writer, bounds);
-
-        writer.name("nodes").beginArray();
-        AccessibilityNodeInfo root = window.getRoot();
-        if (root != null) {
-            writeNode(root, 0, 0, -1, state, window.getId(), displaySize);
-        }
-        writer.endArray();
-        writer.endObject();
     }
 
     private void writeNode(
@@ -360,17 +399,21 @@ final class AccessibilityBridge {
         }
     }
 
-    boolean waitForIdle(long idleTimeoutMillis, long globalTimeoutMillis) throws Exception {
-        // Reset mLastEventTimeMillis to "now" so waitForIdle doesn't return
-        // immediately when the UI has been idle longer than idleTimeoutMillis
-        // before this call.
-        java.lang.reflect.Field lockField = uiAutomation.getClass().getDeclaredField("mLock");
-        lockField.setAccessible(true);
-        Object lock = lockField.get(uiAutomation);
-        java.lang.reflect.Field lastEventField = uiAutomation.getClass().getDeclaredField("mLastEventTimeMillis");
-        lastEventField.setAccessible(true);
-        synchronized (lock) {
-            lastEventField.setLong(uiAutomation, SystemClock.uptimeMillis());
+    // The caller waits in short slices so it can give up early; only the first slice
+    // resets the clock, the rest keep measuring quiet time from the last event.
+    boolean waitForIdle(long idleTimeoutMillis, long globalTimeoutMillis, boolean resetClock) throws Exception {
+        if (resetClock) {
+            // Reset mLastEventTimeMillis to "now" so waitForIdle doesn't return
+            // immediately when the UI has been idle longer than idleTimeoutMillis
+            // before this call.
+            java.lang.reflect.Field lockField = uiAutomation.getClass().getDeclaredField("mLock");
+            lockField.setAccessible(true);
+            Object lock = lockField.get(uiAutomation);
+            java.lang.reflect.Field lastEventField = uiAutomation.getClass().getDeclaredField("mLastEventTimeMillis");
+            lastEventField.setAccessible(true);
+            synchronized (lock) {
+                lastEventField.setLong(uiAutomation, SystemClock.uptimeMillis());
+            }
         }
         Method m = uiAutomation.getClass().getMethod("waitForIdle", long.class, long.class);
         try {
@@ -384,6 +427,124 @@ final class AccessibilityBridge {
         }
     }
 
+    // Bounds of the soft keyboard window on the display as {left, top, right, bottom},
+    // or null when no keyboard is shown there.
+    int[] imeBounds(int displayId) throws Exception {
+        for (AccessibilityWindowInfo w : getWindowsForDisplay(displayId)) {
+            if (w.getType() != AccessibilityWindowInfo.TYPE_INPUT_METHOD) continue;
+            Rect bounds = new Rect();
+            w.getBoundsInScreen(bounds);
+            if (bounds.isEmpty()) continue;
+            return new int[] {bounds.left, bounds.top, bounds.right, bounds.bottom};
+        }
+        return null;
+    }
+
+    // Returns null on success, otherwise why the selection could not be set.
+    String setSelection(int displayId, String label, int start, int end) throws Exception {
+        AccessibilityNodeInfo node = findNodeByLabel(displayId, label);
+        if (node == null) {
+            return "no node with text or content_desc: " + label;
+        }
+        if (!node.getActionList().contains(AccessibilityNodeInfo.AccessibilityAction.ACTION_SET_SELECTION)) {
+            return "node does not support text selection: " + label;
+        }
+        Bundle args = new Bundle();
+        args.putInt(AccessibilityNodeInfo.ACTION_ARGUMENT_SELECTION_START_INT, start);
+        args.putInt(AccessibilityNodeInfo.ACTION_ARGUMENT_SELECTION_END_INT, end);
+        if (!node.performAction(AccessibilityNodeInfo.ACTION_SET_SELECTION, args)) {
+            return "set selection rejected by node: " + label;
+        }
+        return null;
+    }
+
+    // Moves input focus like a D-pad press; direction is a View.FOCUS_* constant.
+    // Returns null on success, otherwise why focus could not move.
+    String focusSearch(int displayId, int direction) throws Exception {
+        AccessibilityNodeInfo focused = null;
+        AccessibilityNodeInfo firstRoot = null;
+        for (AccessibilityWindowInfo w : getWindowsForDisplay(displayId)) {
+            if (w.getType() != AccessibilityWindowInfo.TYPE_APPLICATION) continue;
+            AccessibilityNodeInfo root = w.getRoot();
+            if (root == null) continue;
+            if (firstRoot == null) firstRoot = root;
+            focused = root.findFocus(AccessibilityNodeInfo.FOCUS_INPUT);
+            if (focused != null) break;
+        }
+        AccessibilityNodeInfo target;
+        if (focused == null) {
+            // Nothing has focus yet: the first press lands on the first focusable node.
+            target = firstRoot == null ? null : findFirstFocusable(firstRoot, 0);
+            if (target == null) {
+                return "no focusable node on screen";
+            }
+        } else {
+            target = focused.focusSearch(direction);
+            if (target == null) {
+                return "no focusable node in that direction";
+            }
+        }
+        if (!target.performAction(AccessibilityNodeInfo.ACTION_FOCUS)) {
+            return "focus rejected by node";
+        }
+        return null;
+    }
+
+    private AccessibilityNodeInfo findFirstFocusable(AccessibilityNodeInfo node, int depth) {
+        if (depth > MAX_DEPTH) {
+            return null;
+        }
+        if (node.isFocusable() && node.isVisibleToUser()) {
+            return node;
+        }
+        int childCount = node.getChildCount();
+        for (int i = 0; i < childCount; i++) {
+            AccessibilityNodeInfo child = getChild(node, i);
+            if (child == null || !child.isVisibleToUser()) {
+                continue;
+            }
+            AccessibilityNodeInfo found = findFirstFocusable(child, depth + 1);
+            if (found != null) {
+                return found;
+            }
+        }
+        return null;
+    }
+
+    private AccessibilityNodeInfo findNodeByLabel(int displayId, String label) throws Exception {
+        for (AccessibilityWindowInfo w : getWindowsForDisplay(displayId)) {
+            if (w.getType() != AccessibilityWindowInfo.TYPE_APPLICATION) continue;
+            AccessibilityNodeInfo root = w.getRoot();
+            if (root == null) continue;
+            AccessibilityNodeInfo found = findNodeByLabel(root, label, 0);
+            if (found != null) return found;
+        }
+        return null;
+    }
+
+    // Same match as the a11y selector: exact text or content description.
+    private AccessibilityNodeInfo findNodeByLabel(AccessibilityNodeInfo node, String label, int depth) {
+        if (depth > MAX_DEPTH) {
+            return null;
+        }
+        if (label.equals(toNullableString(node.getText()))
+                || label.equals(toNullableString(node.getContentDescription()))) {
+            return node;
+        }
+        int childCount = node.getChildCount();
+        for (int i = 0; i < childCount; i++) {
+            AccessibilityNodeInfo child = getChild(node, i);
+            if (child == null || !child.isVisibleToUser()) {
+                continue;
+            }
+            AccessibilityNodeInfo found = findNodeByLabel(child, label, depth + 1);
+            if (found != null) {
+                return found;
+            }
+        }
+        return null;
+    }
+
     private static String toNullableString(CharSequence cs) {
         return cs == null ? null : cs.toString();
     }
@@ -1,14 +1,17 @@
 package com.coordinator;
 
 import android.content.AttributionSource;
+import android.content.ClipData;
 import android.content.Context;
 import android.content.ContextWrapper;
 import android.graphics.PixelFormat;
 import android.hardware.display.VirtualDisplay;
 import android.media.Image;
 import android.media.ImageReader;
+import android.os.IBinder;
 import android.os.Process;
 import android.os.SystemClock;
+import android.util.DisplayMetrics;
 import android.view.InputDevice;
 import android.view.InputEvent;
 import android.view.KeyCharacterMap;
@@ -50,12 +53,13 @@ public final class VirtualScreen {
     // --- Instance fields ---
 
     private int displayId;
-    private final int displayWidth;
-    private final int displayHeight;
-    private final int displayDpi;
-    private final ImageReader imageReader;
+    private int displayWidth;
+    private int displayHeight;
+    private int displayDpi;
+    private ImageReader imageReader;
     private final VirtualDisplay virtualDisplay;
     private byte[] rgbaBuffer;
+    private int pixelFormat = PixelFormat.RGBA_8888;
 
     // --- Constructor ---
 
@@ -70,6 +74,21 @@ public final class VirtualScreen {
         String name = "coordinator-" + System.currentTimeMillis();
         this.virtualDisplay = createVirtualDisplay(name, width, height, dpi, surface);
         this.displayId = virtualDisplay.getDisplay().getDisplayId();
+
+        // The framework may clamp the requested geometry: size frames to what the display
+        // really uses, so frame pixels and input coordinates agree
+        DisplayMetrics metrics = new DisplayMetrics();
+        virtualDisplay.getDisplay().getRealMetrics(metrics);
+        if (metrics.widthPixels != width || metrics.heightPixels != height) {
+            ImageReader resized = ImageReader.newInstance(
+                    metrics.widthPixels, metrics.heightPixels, PixelFormat.RGBA_8888, 2);
+            virtualDisplay.setSurface(resized.getSurface());
+            imageReader.close();
+            this.imageReader = resized;
+        }
+        this.displayWidth = metrics.widthPixels;
+        this.displayHeight = metrics.heightPixels;
+        this.displayDpi = metrics.densityDpi;
     }
 
     // --- Instance accessors ---
@@ -99,6 +118,12 @@ public final class VirtualScreen {
 
     // --- Screenshot ---
 
+    /** Newest frame, or the last one read when nothing new arrived; null before any frame. */
+    public byte[] takeScreenshotRGBAOrLast() {
+        byte[] frame = takeScreenshotRGBA();
+        return frame != null ? frame : rgbaBuffer;
+    }
+
     public byte[] takeScreenshotRGBA() {
         Image image = imageReader.acquireLatestImage();
         if (image == null) {
@@ -106,6 +131,7 @@ public final class VirtualScreen {
         }
 
         try {
+            pixelFormat = image.getFormat();
             Image.Plane plane = image.getPlanes()[0];
             ByteBuffer buffer = plane.getBuffer();
             int pixelStride = plane.getPixelStride();
@@ -131,6 +157,11 @@ public final class VirtualScreen {
         }
     }
 
+    /** Format of the last frame read; some surfaces deliver BGRA (HAL format 5) despite the request. */
+    public int getPixelFormat() {
+        return pixelFormat;
+    }
+
     // --- Input injection (instance methods, use this.displayId) ---
 
     public void injectTap(float x, float y) throws ReflectiveOperationException {
@@ -149,42 +180,76 @@ public final class VirtualScreen {
         up.recycle();
     }
 
-    public void injectSwipe(float x1, float y1, float x2, float y2, long durationMs) throws ReflectiveOperationException {
+    // All points touch down together and lift together; xy holds x0, y0, x1, y1, ...
+    public void injectMultiTap(float[] xy) throws ReflectiveOperationException {
+        int count = xy.length / 2;
         long now = SystemClock.uptimeMillis();
-        int steps = Math.max((int) (durationMs / 10), 2);
-
-        MotionEvent down = MotionEvent.obtain(now, now, MotionEvent.ACTION_DOWN, x1, y1, 0);
-        down.setSource(InputDevice.SOURCE_TOUCHSCREEN);
-        setDisplayId(down, displayId);
-        injectInputEvent(down);
-        down.recycle();
-
-        for (int i = 1; i < steps; i++) {
-            float t = (float) i / steps;
-            float x = x1 + (x2 - x1) * t;
-            float y = y1 + (y2 - y1) * t;
-            long eventTime = now + (durationMs * i / steps);
+        // Pointers go down one by one, then come up in reverse, as fingers would
+        for (int i = 0; i < count; i++) {
+            int action = i == 0
+                    ? MotionEvent.ACTION_DOWN
+                    : MotionEvent.ACTION_POINTER_DOWN | (i << MotionEvent.ACTION_POINTER_INDEX_SHIFT);
+            injectPointers(now, now, action, xy, i + 1);
+        }
+        for (int i = count - 1; i >= 0; i--) {
+            int action = i == 0
+                    ? MotionEvent.ACTION_UP
+                    : MotionEvent.ACTION_POINTER_UP | (i << MotionEvent.ACTION_POINTER_INDEX_SHIFT);
+            injectPointers(now, now + 10, action, xy, i + 1);
+        }
+    }
 
-            MotionEvent move = MotionEvent.obtain(now, eventTime, MotionEvent.ACTION_MOVE, x, y, 0);
-            move.setSource(InputDevice.SOURCE_TOUCHSCREEN);
-            setDisplayId(move, displayId);
-            injectInputEvent(move);
-            move.recycle();
+    // One touchscreen event carrying the first `count` points of xy as pointers 0..count-1.
+    // The building block for gestures with several fingers.
+    private void injectPointers(long downTime, long eventTime, int action, float[] xy, int count)
+            throws ReflectiveOperationException {
+        MotionEvent.PointerProperties[] properties = new MotionEvent.PointerProperties[count];
+        MotionEvent.PointerCoords[] coords = new MotionEvent.PointerCoords[count];
+        for (int i = 0; i < count; i++) {
+            properties[i] = new MotionEvent.PointerProperties();
+            properties[i].id = i;
+            properties[i].toolType = MotionEvent.TOOL_TYPE_FINGER;
+            coords[i] = new MotionEvent.PointerCoords();
+            coords[i].x = xy[2 * i];
+            coords[i].y = xy[2 * i + 1];
+            coords[i].pressure = 1;
+            coords[i].size = 1;
+        }
+        MotionEvent event = MotionEvent.obtain(downTime, eventTime, action, count, properties,
+                coords, 0, 0, 1, 1, 0, 0, InputDevice.SOURCE_TOUCHSCREEN, 0);
+        setDisplayId(event, displayId);
+        injectInputEvent(event);
+        event.recycle();
+    }
 
-            try {
-                Thread.sleep(durationMs / steps);
-            } catch (InterruptedException e) {
-                Thread.currentThread().interrupt();
-                break;
+    // Touch down on the first point, ACTION_MOVE through the middle ones and lift off on the
+    // last; xy holds x0, y0, x1, y1, ... and times each point's offset in ms from touch down.
+    public void injectSwipePath(float[] xy, long[] times) throws ReflectiveOperationException {
+        long now = SystemClock.uptimeMillis();
+        int last = times.length - 1;
+        for (int i = 0; i <= last; i++) {
+            long eventTime = now + times[i];
+            long wait = eventTime - SystemClock.uptimeMillis();
+            if (wait > 0) {
+                try {
+                    Thread.sleep(wait);
+                } catch (InterruptedException e) {
+                    // Keep going without sleeping so the finger still lifts
+                    Thread.currentThread().interrupt();
+                }
             }
-        }
 
-        long endTime = now + durationMs;
-        MotionEvent up = MotionEvent.obtain(now, endTime, MotionEvent.ACTION_UP, x2, y2, 0);
-        up.setSource(InputDevice.SOURCE_TOUCHSCREEN);
-        setDisplayId(up, displayId);
-        injectInputEvent(up);
-        up.recycle();
+            int action = i == 0
+                    ? MotionEvent.ACTION_DOWN
+                    : i == last ? MotionEvent.ACTION_UP : MotionEvent.ACTION_MOVE;
+            float x = xy[2 * i];
+            float y = xy[2 * i + 1];
+            MotionEvent event = MotionEvent.obtain(now, eventTime, action, x, y, 0);
+            event.setSource(InputDevice.SOURCE_TOUCHSCREEN);
+            setDisplayId(event, displayId);
+            injectInputEvent(event);
+            event.recycle();
+        }
     }
 
     public void injectKey(int keyCode) throws ReflectiveOperationException {
@@ -201,6 +266,52 @@ public final class VirtualScreen {
         injectInputEvent(up);
     }
 
+    // Keys go down in order and up in reverse; modifiers among them set the meta state
+    // of the keys that follow (e.g. CTRL_LEFT, A for select-all).
+    public void injectKeyCombo(int[] keyCodes) throws ReflectiveOperationException {
+        long now = SystemClock.uptimeMillis();
+        int metaState = 0;
+        for (int keyCode : keyCodes) {
+            metaState |= modifierMetaState(keyCode);
+            KeyEvent down = new KeyEvent(now, now, KeyEvent.ACTION_DOWN, keyCode, 0, metaState,
+                    KeyCharacterMap.VIRTUAL_KEYBOARD, 0, 0, InputDevice.SOURCE_KEYBOARD);
+            setDisplayId(down, displayId);
+            injectInputEvent(down);
+        }
+        for (int i = keyCodes.length - 1; i >= 0; i--) {
+            metaState &= ~modifierMetaState(keyCodes[i]);
+            KeyEvent up = new KeyEvent(now, now + 10, KeyEvent.ACTION_UP, keyCodes[i], 0, metaState,
+                    KeyCharacterMap.VIRTUAL_KEYBOARD, 0, 0, InputDevice.SOURCE_KEYBOARD);
+            setDisplayId(up, displayId);
+            injectInputEvent(up);
+        }
+    }
+
+    private static int modifierMetaState(int keyCode) {
+        switch (keyCode) {
+            case KeyEvent.KEYCODE_CTRL_LEFT:
+                return KeyEvent.META_CTRL_ON | KeyEvent.META_CTRL_LEFT_ON;
+            case KeyEvent.KEYCODE_CTRL_RIGHT:
+                return KeyEvent.META_CTRL_ON | KeyEvent.META_CTRL_RIGHT_ON;
+            case KeyEvent.KEYCODE_SHIFT_LEFT:
+                return KeyEvent.META_SHIFT_ON | KeyEvent.META_SHIFT_LEFT_ON;
+            case KeyEvent.KEYCODE_SHIFT_RIGHT:
+                return KeyEvent.META_SHIFT_ON | KeyEvent.META_SHIFT_RIGHT_ON;
+            case KeyEvent.KEYCODE_ALT_LEFT:
+                return KeyEvent.META_ALT_ON | KeyEvent.META_ALT_LEFT_ON;
+            case KeyEvent.KEYCODE_ALT_RIGHT:
+                return KeyEvent.META_ALT_ON | KeyEvent.META_ALT_RIGHT_ON;
+            case KeyEvent.KEYCODE_META_LEFT:
+                return KeyEvent.META_META_ON | KeyEvent.META_META_LEFT_ON;
+            case KeyEvent.KEYCODE_META_RIGHT:
+                return KeyEvent.META_META_ON | KeyEvent.META_META_RIGHT_ON;
+            default:
+                return 0;
+        }
+    }
+
+    // Key events cover what the virtual keyboard map can type (ASCII); anything else,
+    // such as accented letters or emoji, goes through the clipboard instead.
     public void injectText(String text) throws ReflectiveOperationException {
         KeyCharacterMap kcm = KeyCharacterMap.load(KeyCharacterMap.VIRTUAL_KEYBOARD);
         KeyEvent[] events = kcm.getEvents(text.toCharArray());
@@ -210,18 +321,50 @@ public final class VirtualScreen {
                 injectInputEvent(event);
             }
         } else {
-            try {
-                Runtime.getRuntime().exec(new String[]{
-                        "input", "-d", String.valueOf(displayId), "text", text
-                }).waitFor();
-            } catch (Exception e) {
-                throw new RuntimeException("Failed to inject text", e);
-            }
+            pasteText(text);
         }
     }
 
+    // Replaces the device clipboard with the text, then presses PASTE on this display.
+    public void pasteText(String text) throws ReflectiveOperationException {
+        setClipboardText(text);
+        injectKey(KeyEvent.KEYCODE_PASTE);
+    }
+
     // --- Static utilities ---
 
+    private static void setClipboardText(String text) throws ReflectiveOperationException {
+        Class<?> smClass = Class.forName("android.os.ServiceManager");
+        IBinder binder = (IBinder) smClass.getMethod("getService", String.class).invoke(null, "clipboard");
+        Class<?> stubClass = Class.forName("android.content.IClipboard$Stub");
+        Object clipboard = stubClass.getMethod("asInterface", IBinder.class).invoke(null, binder);
+        ClipData clip = ClipData.newPlainText(null, text);
+        String pkg = fakeContext.getPackageName();
+        // The signature grew over releases: userId, then attributionTag, then deviceId
+        for (Method method : clipboard.getClass().getMethods()) {
+            if (!method.getName().equals("setPrimaryClip")) {
+                continue;
+            }
+            switch (method.getParameterTypes().length) {
+                case 2:
+                    method.invoke(clipboard, clip, pkg);
+                    return;
+                case 3:
+                    method.invoke(clipboard, clip, pkg, 0);
+                    return;
+                case 4:
+                    method.invoke(clipboard, clip, pkg, null, 0);
+                    return;
+                case 5:
+                    method.invoke(clipboard, clip, pkg, null, 0, 0);
+                    return;
+                default:
+                    break;
+            }
+        }
+        throw new NoSuchMethodException("IClipboard.setPrimaryClip");
+    }
+
     private static synchronized Object getDisplayManagerGlobal() throws ReflectiveOperationException {
         if (displayManagerGlobal == null) {
             Class<?> dmgClass = Class.forName("android.hardware.display.DisplayManagerGlobal");
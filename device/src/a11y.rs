@@ -0,0 +1,509 @@
+//! Minimal model of the `dumpDisplayJson` tree for on-device node matching.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct Tree {
+    pub windows: Vec<Window>,
+}
+
+#[derive(Deserialize)]
+pub struct Window {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct Bounds {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct Node {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    /// Position among the parent's children.
+    #[serde(default)]
+    pub index: i32,
+    pub package: Option<String>,
+    #[serde(rename = "class")]
+    pub class_name: Option<String>,
+    pub resource_id: Option<String>,
+    pub text: Option<String>,
+    pub content_desc: Option<String>,
+    #[serde(default)]
+    pub hint: Option<String>,
+    #[serde(default)]
+    pub checkable: bool,
+    #[serde(default)]
+    pub checked: bool,
+    #[serde(default)]
+    pub clickable: bool,
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub focusable: bool,
+    #[serde(default)]
+    pub focused: bool,
+    #[serde(default)]
+    pub scrollable: bool,
+    #[serde(default)]
+    pub long_clickable: bool,
+    #[serde(default)]
+    pub password: bool,
+    #[serde(default)]
+    pub selected: bool,
+    pub bounds: Bounds,
+}
+
+/// Which node to look for; matches like the CLI's `tap "text"`.
+#[derive(Deserialize)]
+pub struct Selector {
+    pub text: String,
+}
+
+impl Selector {
+    pub fn matches(&self, node: &Node) -> bool {
+        node.text.as_deref() == Some(self.text.as_str())
+            || node.content_desc.as_deref() == Some(self.text.as_str())
+    }
+}
+
+impl Node {
+    pub fn is_text_field(&self) -> bool {
+        self.class_name
+            .as_deref()
+            .is_some_and(|c| c.ends_with("EditText") || c.ends_with("AutoCompleteTextView"))
+    }
+}
+
+const CRASH_PATTERNS: &[&str] = &[
+    "keeps stopping",
+    "has stopped",
+    "isn't responding",
+    "isn\u{2019}t responding",
+    "is not responding",
+];
+
+/// Packages whose windows auto-dismiss may tap in and crash detection reads: permission
+/// prompts, crash/ANR dialogs and Play's update prompt. Text in the app's own UI never
+/// triggers a rule or reports a crash.
+const SYSTEM_DIALOG_PACKAGES: &[&str] = &[
+    "android",
+    "com.android.systemui",
+    "com.android.permissioncontroller",
+    "com.google.android.permissioncontroller",
+    "com.android.vending",
+];
+
+fn in_system_dialog(node: &Node) -> bool {
+    node.package
+        .as_deref()
+        .is_some_and(|p| SYSTEM_DIALOG_PACKAGES.contains(&p))
+}
+
+/// Auto-dismiss table row: when some node's text in a system dialog contains `when`, tap
+/// the node in that dialog labelled `tap` (case-insensitive, since buttons often render
+/// in caps).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct DismissRule {
+    pub when: String,
+    pub tap: String,
+}
+
+/// Rules used when a screen enables auto-dismiss without its own table; first match wins.
+pub fn default_dismiss_rules() -> Vec<DismissRule> {
+    [
+        // Runtime permission prompts ("Allow X to access ...?"); "Don't allow" has no space
+        ("Allow ", "While using the app"),
+        ("Allow ", "Allow"),
+        ("isn't responding", "Wait"),
+        ("isn\u{2019}t responding", "Wait"),
+        ("is not responding", "Wait"),
+        ("has stopped", "OK"),
+        ("Update", "Not now"),
+    ]
+    .into_iter()
+    .map(|(when, tap)| DismissRule {
+        when: when.into(),
+        tap: tap.into(),
+    })
+    .collect()
+}
+
+/// The first rule whose dialog is showing, with the button to tap.
+pub fn find_dismissal<'a>(
+    tree: &'a Tree,
+    rules: &'a [DismissRule],
+) -> Option<(&'a DismissRule, &'a Node)> {
+    rules.iter().find_map(|rule| {
+        tree.windows.iter().find_map(|window| {
+            let system = || window.nodes.iter().filter(|n| in_system_dialog(n));
+            let showing =
+                system().any(|n| n.text.as_deref().is_some_and(|t| t.contains(&rule.when)));
+            if !showing {
+                return None;
+            }
+            let button = system().find(|n| {
+                n.text
+                    .as_deref()
+                    .or(n.content_desc.as_deref())
+                    .is_some_and(|label| label.eq_ignore_ascii_case(&rule.tap))
+            })?;
+            Some((rule, button))
+        })
+    })
+}
+
+/// Text of a system crash/ANR dialog ("X keeps stopping", "X isn't responding"); the
+/// same words in the app's own UI don't count.
+pub fn detect_crash_dialog(tree: &Tree) -> Option<String> {
+    tree.nodes()
+        .filter(|n| in_system_dialog(n))
+        .filter_map(|n| n.text.as_deref())
+        .find(|text| CRASH_PATTERNS.iter().any(|p| text.contains(p)))
+        .map(str::to_string)
+}
+
+/// A row in the expanded notification shade.
+#[derive(Serialize)]
+pub struct Notification {
+    pub app: Option<String>,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    /// On the default display, where the shade lives.
+    pub bounds: Bounds,
+}
+
+const NOTIFICATION_ROW_ID: &str = "com.android.systemui:id/expandableNotificationRow";
+
+impl Tree {
+    pub fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.windows.iter().flat_map(|w| &w.nodes)
+    }
+
+    /// Whether any node differs; window metadata and timestamps are ignored.
+    pub fn same_nodes(&self, other: &Tree) -> bool {
+        self.nodes().eq(other.nodes())
+    }
+
+    pub fn find(&self, selector: &Selector) -> Option<&Node> {
+        self.nodes().find(|n| selector.matches(n))
+    }
+
+    /// The node `fill` types into: the text field whose text, content_desc or hint is
+    /// `label`, or the one node matching it when no field does (e.g. a label the app
+    /// forwards focus from). `Err` carries the number of candidates when not exactly one.
+    pub fn field(&self, label: &str) -> Result<&Node, usize> {
+        let selector = Selector {
+            text: label.to_string(),
+        };
+        let matches: Vec<&Node> = self
+            .nodes()
+            .filter(|n| selector.matches(n) || n.hint.as_deref() == Some(label))
+            .collect();
+        let fields: Vec<&Node> = matches
+            .iter()
+            .copied()
+            .filter(|n| n.is_text_field())
+            .collect();
+        match (fields.as_slice(), matches.as_slice()) {
+            ([field], _) | ([], [field]) => Ok(field),
+            ([], _) => Err(matches.len()),
+            _ => Err(fields.len()),
+        }
+    }
+
+    /// The scrollable node to page through: the one matching `selector` or the smallest
+    /// scrollable node around it, or the largest scrollable node on screen.
+    pub fn scroll_container(&self, selector: Option<&Selector>) -> Option<&Node> {
+        let area = |n: &Node| {
+            let b = &n.bounds;
+            (b.right - b.left).max(0) as i64 * (b.bottom - b.top).max(0) as i64
+        };
+        let scrollable = || self.nodes().filter(|n| n.scrollable && area(n) > 0);
+        let Some(selector) = selector else {
+            return scrollable().max_by_key(|n| area(n));
+        };
+        let target = self.find(selector)?;
+        if target.scrollable {
+            return Some(target);
+        }
+        let t = &target.bounds;
+        scrollable()
+            .filter(|n| {
+                let b = &n.bounds;
+                b.left <= t.left && b.top <= t.top && b.right >= t.right && b.bottom >= t.bottom
+            })
+            .min_by_key(|n| area(n))
+    }
+
+    /// Notifications in the shade, top to bottom; the shade must be expanded to show them.
+    pub fn notifications(&self) -> Vec<Notification> {
+        let mut found = Vec::new();
+        for window in &self.windows {
+            let mut children: HashMap<Option<i32>, Vec<&Node>> = HashMap::new();
+            for node in &window.nodes {
+                children.entry(node.parent_id).or_default().push(node);
+            }
+            for row in &window.nodes {
+                if row.resource_id.as_deref() != Some(NOTIFICATION_ROW_ID) {
+                    continue;
+                }
+                let mut descendants = Vec::new();
+                let mut stack = vec![row.id];
+                while let Some(id) = stack.pop() {
+                    for child in children.get(&Some(id)).into_iter().flatten() {
+                        descendants.push(*child);
+                        stack.push(child.id);
+                    }
+                }
+                let text_of = |ids: &[&str]| {
+                    ids.iter().find_map(|id| {
+                        descendants
+                            .iter()
+                            .find(|n| n.resource_id.as_deref() == Some(id))
+                            .and_then(|n| n.text.clone())
+                    })
+                };
+                found.push(Notification {
+                    app: text_of(&["android:id/app_name_text"]),
+                    title: text_of(&["android:id/title"]),
+                    text: text_of(&["android:id/text", "android:id/big_text"]),
+                    bounds: row.bounds.clone(),
+                });
+            }
+        }
+        found
+    }
+
+    /// The tree in `uiautomator dump` format, nesting nodes by `parent_id`.
+    pub fn to_uiautomator_xml(&self) -> String {
+        let mut out = String::from(
+            "<?xml version='1.0' encoding='UTF-8' standalone='yes' ?><hierarchy rotation=\"0\">",
+        );
+        for window in &self.windows {
+            let mut children: HashMap<Option<i32>, Vec<&Node>> = HashMap::new();
+            for node in &window.nodes {
+                children.entry(node.parent_id).or_default().push(node);
+            }
+            write_xml_nodes(&mut out, &children, None);
+        }
+        out.push_str("</hierarchy>");
+        out
+    }
+}
+
+fn write_xml_nodes(
+    out: &mut String,
+    children: &HashMap<Option<i32>, Vec<&Node>>,
+    parent: Option<i32>,
+) {
+    for node in children.get(&parent).into_iter().flatten() {
+        let b = &node.bounds;
+        let _ = write!(
+            out,
+            "<node index=\"{}\" text=\"{}\" resource-id=\"{}\" class=\"{}\" package=\"{}\" \
+             content-desc=\"{}\" checkable=\"{}\" checked=\"{}\" clickable=\"{}\" enabled=\"{}\" \
+             focusable=\"{}\" focused=\"{}\" scrollable=\"{}\" long-clickable=\"{}\" \
+             password=\"{}\" selected=\"{}\" bounds=\"[{},{}][{},{}]\"",
+            node.index,
+            xml_escape(node.text.as_deref()),
+            xml_escape(node.resource_id.as_deref()),
+            xml_escape(node.class_name.as_deref()),
+            xml_escape(node.package.as_deref()),
+            xml_escape(node.content_desc.as_deref()),
+            node.checkable,
+            node.checked,
+            node.clickable,
+            node.enabled,
+            node.focusable,
+            node.focused,
+            node.scrollable,
+            node.long_clickable,
+            node.password,
+            node.selected,
+            b.left,
+            b.top,
+            b.right,
+            b.bottom,
+        );
+        if children.contains_key(&Some(node.id)) {
+            out.push('>');
+            write_xml_nodes(out, children, Some(node.id));
+            out.push_str("</node>");
+        } else {
+            out.push_str(" />");
+        }
+    }
+}
+
+fn xml_escape(value: Option<&str>) -> String {
+    let mut escaped = String::new();
+    for c in value.unwrap_or_default().chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uiautomator_xml_nests_children_and_escapes() {
+        let tree = Tree::parse(
+            r#"{"windows": [{"nodes": [
+                {"id": 1, "parent_id": null, "class": "android.widget.FrameLayout",
+                 "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 1920}},
+                {"id": 2, "parent_id": 1, "index": 0, "text": "Tom & \"Jerry\"", "clickable": true,
+                 "bounds": {"left": 10, "top": 20, "right": 30, "bottom": 40}}
+            ]}]}"#,
+        )
+        .unwrap();
+        let xml = tree.to_uiautomator_xml();
+        assert!(xml.contains("class=\"android.widget.FrameLayout\""));
+        assert!(xml.contains("text=\"Tom &amp; &quot;Jerry&quot;\""));
+        assert!(xml.contains("clickable=\"true\" enabled=\"false\""));
+        assert!(xml.contains("bounds=\"[10,20][30,40]\" /></node></hierarchy>"));
+    }
+
+    #[test]
+    fn notifications_read_row_fields() {
+        let tree = Tree::parse(
+            r#"{"windows": [{"nodes": [
+                {"id": 1, "parent_id": null,
+                 "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 2400}},
+                {"id": 2, "parent_id": 1,
+                 "resource_id": "com.android.systemui:id/expandableNotificationRow",
+                 "bounds": {"left": 0, "top": 300, "right": 1080, "bottom": 500}},
+                {"id": 3, "parent_id": 2,
+                 "bounds": {"left": 0, "top": 300, "right": 1080, "bottom": 500}},
+                {"id": 4, "parent_id": 3, "resource_id": "android:id/app_name_text",
+                 "text": "Messages", "bounds": {"left": 0, "top": 300, "right": 200, "bottom": 340}},
+                {"id": 5, "parent_id": 3, "resource_id": "android:id/title", "text": "Alice",
+                 "bounds": {"left": 0, "top": 350, "right": 500, "bottom": 400}},
+                {"id": 6, "parent_id": 3, "resource_id": "android:id/text", "text": "Hi there",
+                 "bounds": {"left": 0, "top": 400, "right": 500, "bottom": 450}}
+            ]}]}"#,
+        )
+        .unwrap();
+        let notifications = tree.notifications();
+        assert_eq!(notifications.len(), 1);
+        let n = &notifications[0];
+        assert_eq!(n.app.as_deref(), Some("Messages"));
+        assert_eq!(n.title.as_deref(), Some("Alice"));
+        assert_eq!(n.text.as_deref(), Some("Hi there"));
+        assert_eq!(n.bounds.top, 300);
+    }
+
+    #[test]
+    fn field_prefers_text_field_over_label() {
+        let tree = Tree::parse(
+            r#"{"windows": [{"nodes": [
+                {"id": 1, "parent_id": null, "text": "Email",
+                 "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 100}},
+                {"id": 2, "parent_id": null, "class": "android.widget.EditText", "hint": "Email",
+                 "bounds": {"left": 0, "top": 100, "right": 1080, "bottom": 200}},
+                {"id": 3, "parent_id": null, "class": "android.widget.EditText", "text": "hunter2",
+                 "bounds": {"left": 0, "top": 200, "right": 1080, "bottom": 300}},
+                {"id": 4, "parent_id": null, "text": "Next",
+                 "bounds": {"left": 0, "top": 300, "right": 1080, "bottom": 400}}
+            ]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(tree.field("Email").unwrap().id, 2);
+        assert_eq!(tree.field("hunter2").unwrap().id, 3);
+        assert_eq!(tree.field("Next").unwrap().id, 4);
+        assert_eq!(tree.field("Password").err(), Some(0));
+    }
+
+    #[test]
+    fn dismissal_prefers_first_matching_rule() {
+        let tree = Tree::parse(
+            r#"{"windows": [{"nodes": [
+                {"id": 1, "parent_id": null, "text": "Allow Maps to access this device's location?",
+                 "package": "com.google.android.permissioncontroller",
+                 "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 200}},
+                {"id": 2, "parent_id": 1, "text": "WHILE USING THE APP", "clickable": true,
+                 "package": "com.google.android.permissioncontroller",
+                 "bounds": {"left": 0, "top": 300, "right": 1080, "bottom": 400}},
+                {"id": 3, "parent_id": 1, "text": "Don't allow", "clickable": true,
+                 "package": "com.google.android.permissioncontroller",
+                 "bounds": {"left": 0, "top": 500, "right": 1080, "bottom": 600}}
+            ]}]}"#,
+        )
+        .unwrap();
+        let rules = default_dismiss_rules();
+        let (rule, button) = find_dismissal(&tree, &rules).unwrap();
+        assert_eq!(rule.tap, "While using the app");
+        assert_eq!(button.id, 2);
+
+        let only_deny = DismissRule {
+            when: "Don't allow".into(),
+            tap: "Allow".into(),
+        };
+        assert!(find_dismissal(&tree, &[only_deny]).is_none());
+    }
+
+    #[test]
+    fn dismissal_ignores_the_apps_own_ui() {
+        let tree = Tree::parse(
+            r#"{"windows": [{"nodes": [
+                {"id": 1, "parent_id": null, "text": "Allow notifications for new messages",
+                 "package": "com.example.chat",
+                 "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 200}},
+                {"id": 2, "parent_id": 1, "text": "Allow", "clickable": true,
+                 "package": "com.example.chat",
+                 "bounds": {"left": 0, "top": 300, "right": 1080, "bottom": 400}},
+                {"id": 3, "parent_id": 1, "text": "OK", "clickable": true,
+                 "package": "com.example.chat",
+                 "bounds": {"left": 0, "top": 500, "right": 1080, "bottom": 600}}
+            ]}, {"nodes": [
+                {"id": 4, "parent_id": null, "text": "Checking for Update",
+                 "package": "com.android.systemui",
+                 "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 100}}
+            ]}]}"#,
+        )
+        .unwrap();
+        // The app's "Allow" and the system window's "Update" don't pair up across windows
+        assert!(find_dismissal(&tree, &default_dismiss_rules()).is_none());
+    }
+
+    #[test]
+    fn crash_dialog_ignores_the_apps_own_ui() {
+        let app = r#"{"nodes": [
+            {"id": 1, "parent_id": null, "text": "Delivery has stopped",
+             "package": "com.example.shop",
+             "bounds": {"left": 0, "top": 0, "right": 1080, "bottom": 200}}
+        ]}"#;
+        let dialog = r#"{"nodes": [
+            {"id": 2, "parent_id": null, "text": "Shop keeps stopping", "package": "android",
+             "bounds": {"left": 0, "top": 800, "right": 1080, "bottom": 900}}
+        ]}"#;
+        let tree = Tree::parse(&format!(r#"{{"windows": [{app}, {dialog}]}}"#)).unwrap();
+        assert_eq!(
+            detect_crash_dialog(&tree).as_deref(),
+            Some("Shop keeps stopping")
+        );
+        let tree = Tree::parse(&format!(r#"{{"windows": [{app}]}}"#)).unwrap();
+        assert_eq!(detect_crash_dialog(&tree), None);
+    }
+}
@@ -0,0 +1,122 @@
+//! Special access (overlay, battery optimization, ...) granted through `cmd appops`
+//! rather than runtime permissions.
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+use crate::shell::run;
+
+const MODES: &[&str] = &["allow", "ignore", "deny", "default", "foreground"];
+
+#[derive(Deserialize)]
+pub struct SetRequest {
+    /// e.g. `SYSTEM_ALERT_WINDOW` or `android:system_alert_window`.
+    pub op: String,
+    pub mode: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetQuery {
+    pub op: Option<String>,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct OpMode {
+    pub op: String,
+    pub mode: String,
+}
+
+fn validate_op(op: &str) -> Result<(), AppError> {
+    let valid = !op.is_empty()
+        && op
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::bad_request(format!("invalid app op {op:?}")))
+    }
+}
+
+/// Set `op` for `package`, then report its mode as the system now sees it.
+pub fn set(package: &str, req: &SetRequest) -> Result<Vec<OpMode>, AppError> {
+    validate_op(&req.op)?;
+    if !MODES.contains(&req.mode.as_str()) {
+        return Err(AppError::bad_request(format!(
+            "invalid mode {:?}: expected one of {}",
+            req.mode,
+            MODES.join(", ")
+        )));
+    }
+    let out = run(&["cmd", "appops", "set", package, &req.op, &req.mode])?;
+    if out.trim_start().starts_with("Error") {
+        return Err(AppError::bad_request(format!(
+            "appops set {} {}: {}",
+            req.op,
+            req.mode,
+            out.trim()
+        )));
+    }
+    get(package, Some(&req.op))
+}
+
+/// Modes of every op `package` has a record for, or of just `op`.
+pub fn get(package: &str, op: Option<&str>) -> Result<Vec<OpMode>, AppError> {
+    let mut args = vec!["cmd", "appops", "get", package];
+    if let Some(op) = op {
+        validate_op(op)?;
+        args.push(op);
+    }
+    let out = run(&args)?;
+    if out.trim_start().starts_with("Error") {
+        return Err(AppError::bad_request(out.trim().to_string()));
+    }
+    Ok(parse_modes(&out))
+}
+
+/// Lines look like `SYSTEM_ALERT_WINDOW: allow; time=+5m ago`, or `Uid mode: OP: mode`
+/// for modes set on the whole uid.
+fn parse_modes(out: &str) -> Vec<OpMode> {
+    out.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let line = line.strip_prefix("Uid mode: ").unwrap_or(line);
+            let (op, rest) = line.split_once(": ")?;
+            let mode = rest.split(';').next()?.trim();
+            MODES.contains(&mode).then(|| OpMode {
+                op: op.to_string(),
+                mode: mode.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_appops_get_output() {
+        let out = "Uid mode: COARSE_LOCATION: ignore\n\
+                   SYSTEM_ALERT_WINDOW: allow; time=+5m2s ago\n\
+                   RUN_IN_BACKGROUND: default\n\
+                   No operations.\n";
+        assert_eq!(
+            parse_modes(out),
+            [
+                OpMode {
+                    op: "COARSE_LOCATION".into(),
+                    mode: "ignore".into()
+                },
+                OpMode {
+                    op: "SYSTEM_ALERT_WINDOW".into(),
+                    mode: "allow".into()
+                },
+                OpMode {
+                    op: "RUN_IN_BACKGROUND".into(),
+                    mode: "default".into()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Setting the device clock for time-dependent flows. Device-global like the
+//! connectivity toggles.
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+use crate::shell::run;
+
+#[derive(Deserialize)]
+pub struct SetTimeRequest {
+    /// `YYYY-MM-DDTHH:MM:SS[.fff](Z|±HH:MM)`.
+    pub iso8601: String,
+    /// Stop network time from undoing the change (`settings put global auto_time 0`).
+    #[serde(default = "default_true")]
+    pub disable_auto_time: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub struct DeviceTime {
+    /// Device clock after the change, in milliseconds since the epoch.
+    pub epoch_ms: i64,
+}
+
+/// Allowed gap between the requested and the read-back time.
+const TOLERANCE_MS: i64 = 5000;
+
+const PERMISSION_HINT: &str = "setting the clock needs the SET_TIME permission, e.g. run the server as root with ANDY_RUN_AS=0";
+
+pub fn set(req: &SetTimeRequest) -> Result<DeviceTime, AppError> {
+    let target = parse_iso8601(&req.iso8601).ok_or_else(|| {
+        AppError::bad_request(format!(
+            "invalid time {:?}: expected e.g. 2030-01-31T09:00:00Z",
+            req.iso8601
+        ))
+    })?;
+    let auto_time = if req.disable_auto_time {
+        let previous = run(&["settings", "get", "global", "auto_time"])?;
+        run(&["settings", "put", "global", "auto_time", "0"])?;
+        // Unset reads back as "null"; network time is on by default
+        Some(match previous.trim() {
+            "null" | "" => "1".to_string(),
+            value => value.to_string(),
+        })
+    } else {
+        None
+    };
+    let result = set_clock(target);
+    // A failed set-time (usually a missing SET_TIME) shouldn't leave network time off
+    if let (Err(_), Some(previous)) = (&result, auto_time)
+        && let Err(e) = run(&["settings", "put", "global", "auto_time", &previous])
+    {
+        tracing::warn!(error = %e.message, "restoring auto_time failed");
+    }
+    result
+}
+
+fn set_clock(target: i64) -> Result<DeviceTime, AppError> {
+    let out = run(&["cmd", "alarm", "set-time", &target.to_string()])
+        .map_err(|e| AppError::new(format!("{}; {PERMISSION_HINT}", e.message)))?;
+    let now = run(&["date", "+%s"])?
+        .trim()
+        .parse::<i64>()
+        .map_err(|e| AppError::new(format!("reading device time failed: {e}")))?
+        * 1000;
+    if (now - target).abs() > TOLERANCE_MS {
+        return Err(AppError::new(format!(
+            "device time was not changed ({}); {PERMISSION_HINT}",
+            out.trim()
+        )));
+    }
+    Ok(DeviceTime { epoch_ms: now })
+}
+
+/// Milliseconds since the epoch for an RFC 3339 timestamp.
+fn parse_iso8601(s: &str) -> Option<i64> {
+    let num = |range: std::ops::Range<usize>| -> Option<i64> {
+        let part = s.get(range)?;
+        part.bytes().all(|b| b.is_ascii_digit()).then_some(())?;
+        part.parse().ok()
+    };
+    let bytes = s.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+        millis = padded.parse::<i64>().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset_minutes = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (h, m) = rest[1..].split_once(':')?;
+            if h.len() != 2 || m.len() != 2 {
+                return None;
+            }
+            sign * (h.parse::<i64>().ok()? * 60 + m.parse::<i64>().ok()?)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+    Some(seconds * 1000 + millis)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc3339() {
+        assert_eq!(parse_iso8601("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_iso8601("2030-01-31T09:00:00Z"),
+            Some(1_896_080_400_000)
+        );
+        assert_eq!(
+            parse_iso8601("2030-01-31T10:30:00.25+01:30"),
+            Some(1_896_080_400_250)
+        );
+        assert_eq!(parse_iso8601("2030-01-31T09:00:00"), None);
+        assert_eq!(parse_iso8601("2030-13-01T00:00:00Z"), None);
+        assert_eq!(parse_iso8601("2030-02-31T09:00:00Z"), None);
+        assert_eq!(parse_iso8601("2030-04-31T09:00:00Z"), None);
+        assert_eq!(parse_iso8601("2030-02-29T09:00:00Z"), None);
+        assert!(parse_iso8601("2028-02-29T09:00:00Z").is_some());
+        assert_eq!(parse_iso8601("2100-02-29T09:00:00Z"), None);
+        assert!(parse_iso8601("2000-02-29T09:00:00Z").is_some());
+        assert_eq!(parse_iso8601("tomorrow"), None);
+    }
+}
@@ -0,0 +1,66 @@
+//! Device-global network toggles for failure testing. They affect every screen.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+use crate::shell::run;
+
+/// How long to wait for a toggle to show up in the reported state.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy)]
+pub enum Toggle {
+    AirplaneMode,
+    Wifi,
+    MobileData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ToggleState {
+    pub on: bool,
+}
+
+impl Toggle {
+    fn set_command(self, on: bool) -> Vec<&'static str> {
+        match (self, on) {
+            (Toggle::AirplaneMode, true) => vec!["cmd", "connectivity", "airplane-mode", "enable"],
+            (Toggle::AirplaneMode, false) => {
+                vec!["cmd", "connectivity", "airplane-mode", "disable"]
+            }
+            (Toggle::Wifi, true) => vec!["svc", "wifi", "enable"],
+            (Toggle::Wifi, false) => vec!["svc", "wifi", "disable"],
+            (Toggle::MobileData, true) => vec!["svc", "data", "enable"],
+            (Toggle::MobileData, false) => vec!["svc", "data", "disable"],
+        }
+    }
+
+    fn read(self) -> Result<bool, AppError> {
+        let (args, on): (&[&str], fn(&str) -> bool) = match self {
+            Toggle::AirplaneMode => (&["cmd", "connectivity", "airplane-mode"], |out| {
+                out.trim() == "enabled"
+            }),
+            Toggle::Wifi => (&["cmd", "wifi", "status"], |out| {
+                out.contains("Wifi is enabled")
+            }),
+            Toggle::MobileData => (&["settings", "get", "global", "mobile_data"], |out| {
+                out.trim() == "1"
+            }),
+        };
+        Ok(on(&run(args)?))
+    }
+
+    /// Switch and report the state once it settles, or whatever it is after the timeout.
+    pub fn set(self, on: bool) -> Result<ToggleState, AppError> {
+        run(&self.set_command(on))?;
+        let start = Instant::now();
+        loop {
+            let state = self.read()?;
+            if state == on || start.elapsed() >= SETTLE_TIMEOUT {
+                return Ok(ToggleState { on: state });
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Parsers for `dumpsys` / `am` text output.
+
+/// Top resumed activity on `display_id` from `dumpsys activity activities`,
+/// as `(package, fully-qualified activity)`.
+pub fn foreground_activity(output: &str, display_id: i32) -> Option<(String, String)> {
+    let header = format!("Display #{display_id} ");
+    let mut in_display = false;
+    let mut first_record = None;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("Display #") {
+            if in_display {
+                break;
+            }
+            in_display = trimmed.starts_with(&header);
+            continue;
+        }
+        if !in_display {
+            continue;
+        }
+        let Some(component) = activity_record_component(trimmed) else {
+            continue;
+        };
+        if trimmed.contains("ResumedActivity") {
+            return Some(component);
+        }
+        first_record.get_or_insert(component);
+    }
+    first_record
+}
+
+/// Display and task of the topmost activity of `package` from `dumpsys activity
+/// activities`, as `(display_id, task_id)`.
+pub fn package_task(output: &str, package: &str) -> Option<(i32, i32)> {
+    let mut display_id = None;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        if let Some(rest) = trimmed.strip_prefix("Display #") {
+            display_id = rest
+                .split_whitespace()
+                .next()
+                .and_then(|id| id.parse().ok());
+            continue;
+        }
+        let Some((record_package, _)) = activity_record_component(trimmed) else {
+            continue;
+        };
+        if record_package != package {
+            continue;
+        }
+        let record = &trimmed[trimmed.find("ActivityRecord{")?..];
+        let task_id = record[..record.find('}')?]
+            .split_whitespace()
+            .find_map(|t| t.strip_prefix('t')?.parse().ok());
+        if let (Some(display_id), Some(task_id)) = (display_id, task_id) {
+            return Some((display_id, task_id));
+        }
+    }
+    None
+}
+
+/// Component from an `ActivityRecord{<hash> u0 com.pkg/.Activity t12}` fragment.
+fn activity_record_component(line: &str) -> Option<(String, String)> {
+    let record = &line[line.find("ActivityRecord{")? + "ActivityRecord{".len()..];
+    let record = &record[..record.find('}')?];
+    let component = record.split_whitespace().find(|t| t.contains('/'))?;
+    let (package, activity) = component.split_once('/')?;
+    let activity = match activity.strip_prefix('.') {
+        Some(rest) => format!("{package}.{rest}"),
+        None => activity.to_string(),
+    };
+    Some((package.to_string(), activity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIVITIES: &str = "\
+ACTIVITY MANAGER ACTIVITIES (dumpsys activity activities)
+Display #0 (activities from top to bottom):
+  * Task{4a1 #1 type=home}
+      * Hist  #0: ActivityRecord{9f2 u0 com.android.launcher3/.uioverrides.QuickstepLauncher t1}
+Display #7 (activities from top to bottom):
+  * Task{5b2 #23 type=standard A=10117:com.fedi.dev00}
+    mResumedActivity: ActivityRecord{c31 u0 com.fedi.dev00/com.fedi.MainActivity t23}
+      * Hist  #1: ActivityRecord{c31 u0 com.fedi.dev00/com.fedi.MainActivity t23}
+      * Hist  #0: ActivityRecord{a07 u0 com.fedi.dev00/.SplashActivity t23}
+";
+
+    #[test]
+    fn picks_resumed_activity_on_display() {
+        assert_eq!(
+            foreground_activity(ACTIVITIES, 7),
+            Some(("com.fedi.dev00".into(), "com.fedi.MainActivity".into()))
+        );
+        assert_eq!(
+            foreground_activity(ACTIVITIES, 0),
+            Some((
+                "com.android.launcher3".into(),
+                "com.android.launcher3.uioverrides.QuickstepLauncher".into()
+            ))
+        );
+        assert_eq!(foreground_activity(ACTIVITIES, 3), None);
+    }
+
+    #[test]
+    fn finds_task_of_package() {
+        assert_eq!(package_task(ACTIVITIES, "com.fedi.dev00"), Some((7, 23)));
+        assert_eq!(
+            package_task(ACTIVITIES, "com.android.launcher3"),
+            Some((0, 1))
+        );
+        assert_eq!(package_task(ACTIVITIES, "com.fedi.dev01"), None);
+    }
+}
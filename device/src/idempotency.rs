@@ -0,0 +1,242 @@
+//! Replay of mutating responses keyed by `Idempotency-Key`, so a client retrying after
+//! a lost response gets the original result instead of tapping or launching twice. A
+//! retry that arrives while the first request is still running waits for its result.
+//! A key reused for another method or path is rejected with 422 rather than answered
+//! with an unrelated response.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+const HEADER: &str = "Idempotency-Key";
+/// How long a result stays replayable; client retries land well within this.
+const WINDOW: Duration = Duration::from_secs(60);
+/// Largest body kept for replay. Bigger ones (screenshots) pass through unbuffered and a
+/// retry gets 409 rather than a second run.
+const MAX_BODY: u64 = 1 << 20;
+
+struct Cached {
+    at: Instant,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+enum Entry {
+    /// The first request with this key is still running; its sender drops when it ends.
+    Running(watch::Receiver<()>),
+    Done(Cached),
+}
+
+/// A key's entry and the request it was first used for, e.g. `POST /screens/default/tap`.
+struct Slot {
+    request: String,
+    entry: Entry,
+}
+
+/// What a request with a key should do.
+enum Claim {
+    Replay(Response),
+    /// The key was first used for this other request.
+    Mismatch(String),
+    /// Wait for the request already running with this key, then claim again.
+    Wait(watch::Receiver<()>),
+    /// Run it; waiters are released when the sender drops.
+    Run(watch::Sender<()>),
+}
+
+#[derive(Clone, Default)]
+pub struct Cache(Arc<Mutex<HashMap<String, Slot>>>);
+
+impl Cache {
+    fn claim(&self, key: &str, request: &str) -> Claim {
+        let mut cache = self.0.lock().unwrap();
+        cache.retain(|_, slot| match &slot.entry {
+            Entry::Done(c) => c.at.elapsed() < WINDOW,
+            // A closed channel means the first request was dropped mid-way
+            Entry::Running(rx) => rx.has_changed().is_ok(),
+        });
+        let Some(slot) = cache.get(key) else {
+            let (tx, rx) = watch::channel(());
+            let slot = Slot {
+                request: request.to_owned(),
+                entry: Entry::Running(rx),
+            };
+            cache.insert(key.to_owned(), slot);
+            return Claim::Run(tx);
+        };
+        if slot.request != request {
+            return Claim::Mismatch(slot.request.clone());
+        }
+        match &slot.entry {
+            Entry::Done(cached) => {
+                let mut response = (cached.status, cached.body.clone()).into_response();
+                *response.headers_mut() = cached.headers.clone();
+                Claim::Replay(response)
+            }
+            Entry::Running(rx) => Claim::Wait(rx.clone()),
+        }
+    }
+
+    /// Record how the running request with `key` ended; `None` lets a retry run afresh.
+    fn finish(&self, key: String, done: Option<(StatusCode, HeaderMap, Bytes)>) {
+        let mut cache = self.0.lock().unwrap();
+        match done {
+            Some((status, headers, body)) => {
+                let cached = Cached {
+                    at: Instant::now(),
+                    status,
+                    headers,
+                    body,
+                };
+                if let Some(slot) = cache.get_mut(&key) {
+                    slot.entry = Entry::Done(cached);
+                }
+            }
+            None => {
+                cache.remove(&key);
+            }
+        }
+    }
+}
+
+/// Middleware: replay a cached response for a repeated key, wait for a running one,
+/// otherwise run and cache. Server errors aren't cached so a retry gets a fresh attempt.
+pub async fn replay(State(cache): State<Cache>, req: Request, next: Next) -> Response {
+    let key = match req.headers().get(HEADER).and_then(|v| v.to_str().ok()) {
+        Some(key) if req.method() == Method::POST => key.to_owned(),
+        _ => return next.run(req).await,
+    };
+    let request = format!("{} {}", req.method(), req.uri().path());
+    let running = loop {
+        match cache.claim(&key, &request) {
+            Claim::Replay(response) => {
+                tracing::info!(key = %key, "replaying idempotent response");
+                return response;
+            }
+            Claim::Mismatch(first) => {
+                let message = format!("{HEADER} {key} was already used for {first}");
+                return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+            }
+            Claim::Wait(mut rx) => {
+                tracing::info!(key = %key, "waiting for the running request with this key");
+                // Only ever errors: the sender never sends, it drops when the first ends
+                let _ = rx.changed().await;
+            }
+            Claim::Run(tx) => break tx,
+        }
+    };
+    // Spawned so a client that gives up and retries doesn't cancel the first run halfway,
+    // leaving the retry to run the action again
+    let task = tokio::spawn(async move {
+        let response = run_and_cache(&cache, key, req, next).await;
+        drop(running);
+        response
+    });
+    task.await.unwrap_or_else(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("request task failed: {e}"),
+        )
+            .into_response()
+    })
+}
+
+async fn run_and_cache(cache: &Cache, key: String, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    if response.status().is_server_error() {
+        cache.finish(key, None);
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    if body.size_hint().upper().is_none_or(|len| len > MAX_BODY) {
+        let message = format!("the response to {HEADER} {key} was too large to replay");
+        let headers = HeaderMap::new();
+        cache.finish(key, Some((StatusCode::CONFLICT, headers, message.into())));
+        return Response::from_parts(parts, body);
+    }
+    let body = match axum::body::to_bytes(body, MAX_BODY as usize).await {
+        Ok(body) => body,
+        Err(e) => {
+            cache.finish(key, None);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("buffering response failed: {e}"),
+            )
+                .into_response();
+        }
+    };
+    cache.finish(
+        key,
+        Some((parts.status, parts.headers.clone(), body.clone())),
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAP: &str = "POST /screens/default/tap";
+
+    #[test]
+    fn retry_waits_for_the_running_request() {
+        let cache = Cache::default();
+        let Claim::Run(tx) = cache.claim("k", TAP) else {
+            panic!("first request should run");
+        };
+        let Claim::Wait(rx) = cache.claim("k", TAP) else {
+            panic!("retry should wait");
+        };
+        cache.finish(
+            "k".into(),
+            Some((StatusCode::OK, HeaderMap::new(), "tapped".into())),
+        );
+        drop(tx);
+        assert!(rx.has_changed().is_err());
+        let Claim::Replay(response) = cache.claim("k", TAP) else {
+            panic!("retry should replay");
+        };
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn dropped_request_lets_a_retry_run() {
+        let cache = Cache::default();
+        let Claim::Run(tx) = cache.claim("k", TAP) else {
+            panic!("first request should run");
+        };
+        drop(tx);
+        assert!(matches!(cache.claim("k", TAP), Claim::Run(_)));
+    }
+
+    #[test]
+    fn key_reused_for_another_request_is_rejected() {
+        let cache = Cache::default();
+        let Claim::Run(tx) = cache.claim("k", TAP) else {
+            panic!("first request should run");
+        };
+        cache.finish(
+            "k".into(),
+            Some((StatusCode::OK, HeaderMap::new(), "tapped".into())),
+        );
+        drop(tx);
+        let Claim::Mismatch(first) = cache.claim("k", "POST /screens/default/launch") else {
+            panic!("another path should be rejected");
+        };
+        assert_eq!(first, TAP);
+        assert!(matches!(
+            cache.claim("k", "POST /screens/other/tap"),
+            Claim::Mismatch(_)
+        ));
+        assert!(matches!(cache.claim("k", TAP), Claim::Replay(_)));
+    }
+}
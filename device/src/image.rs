@@ -0,0 +1,270 @@
+//! Pixel operations on RGBA8888 frames before encoding.
+
+/// Box-filter downscale of an RGBA buffer. Returns the new buffer and its size.
+pub fn downscale(rgba: &[u8], width: u32, height: u32, scale: f32) -> (Vec<u8>, u32, u32) {
+    let new_width = ((width as f32 * scale).round() as u32).clamp(1, width);
+    let new_height = ((height as f32 * scale).round() as u32).clamp(1, height);
+    if new_width == width && new_height == height {
+        return (rgba.to_vec(), width, height);
+    }
+
+    let mut out = vec![0u8; (new_width * new_height * 4) as usize];
+    for dy in 0..new_height {
+        let sy0 = dy * height / new_height;
+        let sy1 = ((dy + 1) * height / new_height).max(sy0 + 1);
+        for dx in 0..new_width {
+            let sx0 = dx * width / new_width;
+            let sx1 = ((dx + 1) * width / new_width).max(sx0 + 1);
+            let mut sum = [0u32; 4];
+            for sy in sy0..sy1 {
+                let row = (sy * width) as usize * 4;
+                for sx in sx0..sx1 {
+                    let i = row + sx as usize * 4;
+                    for c in 0..4 {
+                        sum[c] += rgba[i + c] as u32;
+                    }
+                }
+            }
+            let count = (sy1 - sy0) * (sx1 - sx0);
+            let o = ((dy * new_width + dx) * 4) as usize;
+            for c in 0..4 {
+                out[o + c] = (sum[c] / count) as u8;
+            }
+        }
+    }
+    (out, new_width, new_height)
+}
+
+/// Cheap content fingerprint, to tell whether the screen changed between captures.
+pub fn frame_hash(rgba: &[u8]) -> u64 {
+    const K: u64 = 0x517c_c1b7_2722_0a95;
+    let mut hash = rgba.len() as u64;
+    let mut chunks = rgba.chunks_exact(8);
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(chunk.try_into().unwrap());
+        hash = (hash.rotate_left(5) ^ word).wrapping_mul(K);
+    }
+    for &byte in chunks.remainder() {
+        hash = (hash.rotate_left(5) ^ byte as u64).wrapping_mul(K);
+    }
+    hash
+}
+
+/// BT.601 luma of each RGBA pixel, one byte per pixel.
+pub fn luminance(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .map(|px| ((77 * px[0] as u32 + 150 * px[1] as u32 + 29 * px[2] as u32) >> 8) as u8)
+        .collect()
+}
+
+/// Width and height of an encoded screenshot, JPEG or AVIF.
+pub fn dimensions(image: &[u8]) -> Option<(u32, u32)> {
+    if image.starts_with(&[0xFF, 0xD8]) {
+        return jpeg_dimensions(image);
+    }
+    avif_dimensions(image)
+}
+
+/// Width and height from the `ispe` (image spatial extents) property of an AVIF, which
+/// follows the box type with 4 bytes of version and flags.
+fn avif_dimensions(avif: &[u8]) -> Option<(u32, u32)> {
+    let at = avif.windows(4).position(|w| w == b"ispe")? + 8;
+    let width = u32::from_be_bytes(avif.get(at..at + 4)?.try_into().ok()?);
+    let height = u32::from_be_bytes(avif.get(at + 4..at + 8)?.try_into().ok()?);
+    Some((width, height))
+}
+
+/// Width and height from a JPEG's start-of-frame header.
+pub fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    while pos + 9 <= jpeg.len() {
+        if jpeg[pos] != 0xFF {
+            return None;
+        }
+        let marker = jpeg[pos + 1];
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        // SOF0..SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let height = u16::from_be_bytes([jpeg[pos + 5], jpeg[pos + 6]]);
+            let width = u16::from_be_bytes([jpeg[pos + 7], jpeg[pos + 8]]);
+            return Some((width as u32, height as u32));
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// Turn BGRA pixels into RGBA in place.
+pub fn swap_red_blue(pixels: &mut [u8]) {
+    for px in pixels.chunks_exact_mut(4) {
+        px.swap(0, 2);
+    }
+}
+
+/// Smallest `(left, top, right, bottom)` box holding every pixel that differs between two
+/// same-sized frames, or `None` when they are identical.
+pub fn dirty_rect(prev: &[u8], cur: &[u8], width: u32) -> Option<(i32, i32, i32, i32)> {
+    let row_len = width as usize * 4;
+    let rows = || prev.chunks_exact(row_len).zip(cur.chunks_exact(row_len));
+    let top = rows().position(|(a, b)| a != b)?;
+    let bottom = rows().rposition(|(a, b)| a != b)?;
+    let (mut left, mut right) = (width as usize, 0);
+    for (a, b) in rows().skip(top).take(bottom + 1 - top) {
+        let pixels = || a.chunks_exact(4).zip(b.chunks_exact(4));
+        if let Some(x) = pixels().position(|(p, q)| p != q) {
+            left = left.min(x);
+            right = right.max(pixels().rposition(|(p, q)| p != q).unwrap() + 1);
+        }
+    }
+    Some((left as i32, top as i32, right as i32, bottom as i32 + 1))
+}
+
+/// Rows two viewports of a scrolled region must share to count as a match.
+const MIN_SCROLL_OVERLAP: u32 = 16;
+
+/// How many rows the content of `region` moved up between two same-sized frames: the
+/// shift `s` for which rows `top + s..bottom` of `prev` reappear as rows `top..bottom - s`
+/// of `cur`. Shifts nearest `expected` are tried first, and the rightmost columns are left
+/// out so a fading scrollbar doesn't break the match. `None` when nothing lines up.
+pub fn scroll_offset(
+    prev: &[u8],
+    cur: &[u8],
+    width: u32,
+    (left, top, right, bottom): (i32, i32, i32, i32),
+    expected: u32,
+) -> Option<u32> {
+    let row_len = width as usize * 4;
+    let left = left.clamp(0, width as i32) as usize;
+    let right = right.clamp(0, width as i32) as usize;
+    let right = right - right.saturating_sub(left) / 20;
+    let top = top.max(0) as u32;
+    let bottom = (bottom.max(0) as u32).min((prev.len() / row_len) as u32);
+    if right <= left || bottom < top + MIN_SCROLL_OVERLAP {
+        return None;
+    }
+    let span = |y: u32| {
+        let start = y as usize * row_len;
+        start + left * 4..start + right * 4
+    };
+    let aligned = |shift: u32| (top..bottom - shift).all(|y| prev[span(y + shift)] == cur[span(y)]);
+    if aligned(0) {
+        return Some(0);
+    }
+    let mut shifts: Vec<u32> = (1..=bottom - top - MIN_SCROLL_OVERLAP).collect();
+    shifts.sort_by_key(|&shift| shift.abs_diff(expected));
+    shifts.into_iter().find(|&shift| aligned(shift))
+}
+
+/// Copy out the `left..right` x `top..bottom` region, clamped to the frame.
+/// Returns `None` when nothing of the region is on screen.
+pub fn crop(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    (left, top, right, bottom): (i32, i32, i32, i32),
+) -> Option<(Vec<u8>, u32, u32)> {
+    let left = left.clamp(0, width as i32) as u32;
+    let right = right.clamp(0, width as i32) as u32;
+    let top = top.clamp(0, height as i32) as u32;
+    let bottom = bottom.clamp(0, height as i32) as u32;
+    if right <= left || bottom <= top {
+        return None;
+    }
+    let row_bytes = ((right - left) * 4) as usize;
+    let mut out = Vec::with_capacity(row_bytes * (bottom - top) as usize);
+    for y in top..bottom {
+        let start = ((y * width + left) * 4) as usize;
+        out.extend_from_slice(&rgba[start..start + row_bytes]);
+    }
+    Some((out, right - left, bottom - top))
+}
+
+/// Draw an opaque red crosshair centred on (x, y), clipped to the frame.
+pub fn draw_crosshair(rgba: &mut [u8], width: u32, height: u32, x: f32, y: f32) {
+    const COLOR: [u8; 4] = [255, 0, 0, 255];
+    let arm = (width.min(height) / 20).max(12) as i64;
+    let half_thickness = (width.min(height) / 400).max(1) as i64;
+    let (cx, cy) = (x.round() as i64, y.round() as i64);
+    let mut put = |px: i64, py: i64| {
+        if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+            return;
+        }
+        let i = ((py * width as i64 + px) * 4) as usize;
+        rgba[i..i + 4].copy_from_slice(&COLOR);
+    };
+    for d in -arm..=arm {
+        for t in -half_thickness..=half_thickness {
+            put(cx + d, cy + t);
+            put(cx + t, cy + d);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jpeg_dimensions_reads_frame_header() {
+        let mut jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg, 85)
+            .encode(&[0; 5 * 3 * 4], 5, 3, jpeg_encoder::ColorType::Rgba)
+            .unwrap();
+        assert_eq!(jpeg_dimensions(&jpeg), Some((5, 3)));
+        assert_eq!(dimensions(&jpeg), Some((5, 3)));
+        assert_eq!(jpeg_dimensions(b"not a jpeg"), None);
+    }
+
+    #[test]
+    fn avif_dimensions_read_spatial_extents() {
+        let mut ispe = vec![0, 0, 0, 20];
+        ispe.extend(b"ispe");
+        ispe.extend([0; 4]);
+        ispe.extend(1080u32.to_be_bytes());
+        ispe.extend(1920u32.to_be_bytes());
+        assert_eq!(dimensions(&ispe), Some((1080, 1920)));
+        assert_eq!(dimensions(&ispe[..ispe.len() - 1]), None);
+    }
+
+    #[test]
+    fn scroll_offset_finds_shifted_content() {
+        // Each row is one byte value repeated: a 4px wide page of numbered lines under a
+        // fixed header (rows 0..5) and above a fixed footer (rows 35..40).
+        let frame = |scrolled: u8| {
+            let mut rows = vec![200u8; 5];
+            rows.extend((0..30).map(|y| y + scrolled));
+            rows.extend([201u8; 5]);
+            rows.into_iter().flat_map(|v| [v; 16]).collect::<Vec<u8>>()
+        };
+        let region = (0, 5, 4, 35);
+        assert_eq!(
+            scroll_offset(&frame(0), &frame(12), 4, region, 15),
+            Some(12)
+        );
+        assert_eq!(
+            scroll_offset(&frame(12), &frame(12), 4, region, 15),
+            Some(0)
+        );
+        let unrelated = vec![7u8; 40 * 16];
+        assert_eq!(scroll_offset(&frame(0), &unrelated, 4, region, 15), None);
+    }
+
+    #[test]
+    fn swap_red_blue_keeps_green_and_alpha() {
+        let mut pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        swap_red_blue(&mut pixels);
+        assert_eq!(pixels, [3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn dirty_rect_bounds_changed_pixels() {
+        let (width, height) = (4, 3);
+        let prev = vec![0u8; width * height * 4];
+        assert_eq!(dirty_rect(&prev, &prev, width as u32), None);
+
+        let mut cur = prev.clone();
+        cur[(width + 1) * 4] = 255;
+        cur[(2 * width + 2) * 4 + 3] = 255;
+        assert_eq!(dirty_rect(&prev, &cur, width as u32), Some((1, 1, 3, 3)));
+    }
+}
@@ -0,0 +1,62 @@
+//! The device's input methods (keyboards). Device-global like the connectivity
+//! toggles: switching changes the keyboard on every screen.
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+use crate::shell::run;
+
+#[derive(Serialize)]
+pub struct InputMethods {
+    /// `default_input_method`; `None` before any keyboard was picked.
+    pub current: Option<String>,
+    /// Ids (`package/.Service`) the user can switch to.
+    pub enabled: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SetInputMethodRequest {
+    pub id: String,
+}
+
+pub fn list() -> Result<InputMethods, AppError> {
+    let current = run(&["settings", "get", "secure", "default_input_method"])?;
+    let current = current.trim();
+    Ok(InputMethods {
+        current: (!current.is_empty() && current != "null").then(|| current.to_string()),
+        enabled: ids(&run(&["ime", "list", "-s"])?),
+    })
+}
+
+/// Enable `id` if it is installed but off, make it the default, and report the result.
+pub fn set(req: &SetInputMethodRequest) -> Result<InputMethods, AppError> {
+    let installed = ids(&run(&["ime", "list", "-a", "-s"])?);
+    if !installed.contains(&req.id) {
+        return Err(AppError::bad_request(format!(
+            "input method {:?} is not installed; installed: {}",
+            req.id,
+            installed.join(", ")
+        )));
+    }
+    if !ids(&run(&["ime", "list", "-s"])?).contains(&req.id) {
+        run(&["ime", "enable", &req.id])?;
+    }
+    run(&["ime", "set", &req.id])?;
+    let methods = list()?;
+    if methods.current.as_deref() != Some(req.id.as_str()) {
+        return Err(AppError::new(format!(
+            "input method is still {} after switching to {}",
+            methods.current.as_deref().unwrap_or("unset"),
+            req.id
+        )));
+    }
+    Ok(methods)
+}
+
+fn ids(out: &str) -> Vec<String> {
+    out.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
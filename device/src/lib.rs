@@ -1,31 +1,455 @@
 use axum::extract::{Path, Query, State};
-use axum::http::{StatusCode, header};
+use axum::http::{Method, StatusCode, header};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{delete, get, post};
+use axum::routing::{MethodFilter, MethodRouter, on};
 use axum::{Json, Router};
+use base64::Engine;
 use jni::objects::{GlobalRef, JByteArray, JClass, JObject, JObjectArray, JString, JValue};
 use jni::{JNIEnv, JavaVM};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::os::unix::process::CommandExt;
 use std::process::{Command, Stdio};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
 use tokio::time::{self, Instant};
 
+mod a11y;
+mod appops;
+mod clock;
+mod connectivity;
+mod dumpsys;
+mod idempotency;
+mod image;
+mod input_method;
+mod metrics;
+mod openapi;
+mod persist;
+mod shell;
+
 const PORT: u16 = 21632;
 
+/// Where system UI such as the notification shade lives.
+const DEFAULT_DISPLAY: i32 = 0;
+
+/// How long opening the shade waits for notification rows to appear.
+const SHADE_SETTLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Deadline for one blocking device operation, `ANDY_OP_TIMEOUT_MS` or 20s.
+static OP_TIMEOUT: LazyLock<std::time::Duration> = LazyLock::new(|| {
+    std::env::var("ANDY_OP_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map_or(
+            std::time::Duration::from_secs(20),
+            std::time::Duration::from_millis,
+        )
+});
+
+/// Longest wait for the state lock before answering 503, `ANDY_LOCK_TIMEOUT_MS` or 10s.
+static LOCK_TIMEOUT: LazyLock<std::time::Duration> = LazyLock::new(|| {
+    std::env::var("ANDY_LOCK_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map_or(
+            std::time::Duration::from_secs(10),
+            std::time::Duration::from_millis,
+        )
+});
+
+/// Most screens at once, `ANDY_MAX_SCREENS` or 16; each holds a virtual display.
+static MAX_SCREENS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("ANDY_MAX_SCREENS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(16)
+});
+
+/// The request holding the state lock and since when, for reporting stalls.
+static LOCK_HOLDER: std::sync::Mutex<Option<(String, std::time::Instant)>> =
+    std::sync::Mutex::new(None);
+
+/// An op that ran past its deadline and still holds the state lock, and since when it
+/// started. Set by [`run_op_within`], cleared once the op returns.
+static WEDGED: std::sync::Mutex<Option<(String, std::time::Instant)>> = std::sync::Mutex::new(None);
+
+/// Per screen, bumped by `POST /screens/{name}/cancel`. A wait started under an older
+/// generation returns early; one whose screen is gone does too.
+static CANCEL_GENERATIONS: LazyLock<std::sync::Mutex<HashMap<String, u64>>> =
+    LazyLock::new(Default::default);
+
+/// How often a wait for idle checks whether it was cancelled.
+const WAIT_POLL_INTERVAL_MS: i64 = 200;
+
+/// The cancel generation a wait started under.
+#[derive(Clone)]
+struct WaitCancel {
+    screen: String,
+    generation: u64,
+}
+
+impl WaitCancel {
+    fn new(screen: &str) -> Self {
+        let generation = CANCEL_GENERATIONS
+            .lock()
+            .unwrap()
+            .get(screen)
+            .copied()
+            .unwrap_or_default();
+        WaitCancel {
+            screen: screen.to_string(),
+            generation,
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        CANCEL_GENERATIONS.lock().unwrap().get(&self.screen) != Some(&self.generation)
+    }
+}
+
+/// Make running waits on `screen` return early. Doesn't take the state lock, which an
+/// atomic interaction may hold through its wait.
+fn cancel_waits(screen: &str) -> Result<(), AppError> {
+    let mut generations = CANCEL_GENERATIONS.lock().unwrap();
+    let generation = generations
+        .get_mut(screen)
+        .ok_or_else(|| AppError::not_found(format!("screen {screen} not found")))?;
+    *generation += 1;
+    Ok(())
+}
+
+/// Names whose screen is being created, claimed by [`claim_screen_names`] before a
+/// create takes the state lock. Once creates stop sharing one lock, two for the same name
+/// would otherwise both miss the lookup, build two displays and leak one.
+static SCREEN_CREATIONS: LazyLock<ScreenCreations> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct ScreenCreations {
+    in_progress: std::sync::Mutex<std::collections::HashSet<String>>,
+    finished: tokio::sync::Notify,
+}
+
+impl ScreenCreations {
+    /// Claim every name, waiting while another create holds one so its screen is there to
+    /// reuse. Claimed in sorted order so two batches can't each wait on the other.
+    async fn claim(&self, mut names: Vec<String>) -> Vec<CreationClaim<'_>> {
+        names.sort();
+        names.dedup();
+        let mut claims = Vec::new();
+        for name in names {
+            loop {
+                // Created before the check so a release in between still wakes it
+                let finished = self.finished.notified();
+                if self.in_progress.lock().unwrap().insert(name.clone()) {
+                    break;
+                }
+                finished.await;
+            }
+            claims.push(CreationClaim {
+                creations: self,
+                name,
+            });
+        }
+        claims
+    }
+}
+
+/// Releases the name when the create returns, successfully or not.
+struct CreationClaim<'a> {
+    creations: &'a ScreenCreations,
+    name: String,
+}
+
+impl Drop for CreationClaim<'_> {
+    fn drop(&mut self) {
+        let mut in_progress = self
+            .creations
+            .in_progress
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        in_progress.remove(&self.name);
+        self.creations.finished.notify_waiters();
+    }
+}
+
+/// Largest create body the claim middleware reads; a batch of screen definitions is a
+/// few hundred bytes each.
+const MAX_CREATE_BODY: usize = 1 << 20;
+
+/// Middleware on `POST /screens` and `POST /screens/batch`: hold the claim on every screen
+/// name in the body until the create returns. A body that doesn't parse claims nothing
+/// and is left for the handler to reject.
+async fn claim_screen_names(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    #[derive(Deserialize)]
+    struct Named {
+        name: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Names {
+        One(Named),
+        Batch(Vec<Named>),
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_CREATE_BODY).await {
+        Ok(body) => body,
+        Err(e) => {
+            return AppError::bad_request(format!("reading body failed: {e}")).into_response();
+        }
+    };
+    let names = match serde_json::from_slice(&body) {
+        Ok(Names::One(named)) => vec![named.name],
+        Ok(Names::Batch(batch)) => batch.into_iter().map(|named| named.name).collect(),
+        Err(_) => Vec::new(),
+    };
+    let _claims = SCREEN_CREATIONS.claim(names).await;
+    let req = axum::extract::Request::from_parts(parts, axum::body::Body::from(body));
+    next.run(req).await
+}
+
+/// Requests running against each screen. Many release the state lock between steps
+/// (e.g. while waiting for idle), so a delete could otherwise release the display
+/// halfway through one of them.
+static SCREEN_OPS: LazyLock<ScreenOps> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct ScreenOps {
+    running: std::sync::Mutex<HashMap<String, usize>>,
+    finished: tokio::sync::Notify,
+}
+
+impl ScreenOps {
+    fn start(&self, name: &str) -> ScreenOp<'_> {
+        *self
+            .running
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += 1;
+        ScreenOp {
+            ops: self,
+            name: name.to_string(),
+        }
+    }
+
+    fn running(&self, name: &str) -> usize {
+        self.running.lock().unwrap().get(name).copied().unwrap_or(0)
+    }
+
+    /// Wait for every request on `name` to finish; 409 if some still run after `timeout`.
+    async fn wait_idle(&self, name: &str, timeout: std::time::Duration) -> Result<(), AppError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Created before the check so a finish in between still wakes it
+            let finished = self.finished.notified();
+            let running = self.running(name);
+            if running == 0 {
+                return Ok(());
+            }
+            if time::timeout_at(deadline, finished).await.is_err() {
+                return Err(screen_in_use(name, running));
+            }
+        }
+    }
+}
+
+fn screen_in_use(name: &str, running: usize) -> AppError {
+    AppError::conflict(format!(
+        "screen {name} is busy with {running} running request(s); retry the delete once they \
+         finish, or end their waits with POST /screens/{name}/cancel"
+    ))
+}
+
+/// Middleware on `DELETE /screens/{name}`: wait up to `timeout` for the screen's running
+/// requests before the delete goes ahead, 409 if some still run.
+async fn wait_for_screen_ops(
+    State(timeout): State<std::time::Duration>,
+    Path(name): Path<String>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    match SCREEN_OPS.wait_idle(&name, timeout).await {
+        Ok(()) => next.run(req).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// One request on a screen, counted until it returns.
+struct ScreenOp<'a> {
+    ops: &'a ScreenOps,
+    name: String,
+}
+
+impl Drop for ScreenOp<'_> {
+    fn drop(&mut self) {
+        let mut running = self.ops.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = running.get_mut(&self.name) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.name);
+            }
+        }
+        self.ops.finished.notify_waiters();
+    }
+}
+
+tokio::task_local! {
+    /// `METHOD /path` of the request being handled, set by [`track_op`].
+    static CURRENT_OP: String;
+}
+
 struct VirtualScreen {
     display_id: i32,
     instance: GlobalRef,
-    last_jpeg: Option<Vec<u8>>,
+    last_jpeg: Option<CachedJpeg>,
+    last_screenshot_scale: f32,
     width: i32,
     height: i32,
     dpi: i32,
     last_heartbeat: Instant,
     timeout_secs: u64,
-    last_interaction: Option<Instant>,
+    last_interaction: Option<(Instant, LastAction)>,
     assigned_package: String,
     last_raw_frame_seq: u64,
+    /// Pixels of frame `last_raw_frame_seq`, the base for the next dirty region.
+    last_raw_frame: Option<Vec<u8>>,
+    frames: FrameHistory,
+    /// System dialogs to dismiss after interactions; `None` leaves them to the client.
+    dismiss_rules: Option<Vec<a11y::DismissRule>>,
+    /// The last [`HISTORY_LEN`] actions performed on the screen, oldest first.
+    history: VecDeque<HistoryEntry>,
+    /// Tree dumped once a `?prefetch=true` launch settled, served to the next a11y read
+    /// while still current; any action drops it.
+    prefetched_tree: Option<PrefetchedTree>,
+}
+
+/// How long a tree prefetched after launch stands in for a fresh dump.
+const PREFETCH_TTL: std::time::Duration = std::time::Duration::from_secs(5);
+
+struct PrefetchedTree {
+    at: Instant,
+    /// [`FrameHistory::received`] when the tree was dumped.
+    frame_seq: u64,
+    json: String,
+}
+
+impl PrefetchedTree {
+    /// Within [`PREFETCH_TTL`] and no frame arrived since: the UI hasn't moved on.
+    fn current(&self, frames: &FrameHistory) -> bool {
+        self.at.elapsed() < PREFETCH_TTL && frames.received == self.frame_seq
+    }
+}
+
+/// Actions kept per screen for `GET /screens/{name}/history`.
+const HISTORY_LEN: usize = 200;
+
+#[derive(Serialize, Clone)]
+struct HistoryEntry {
+    /// Milliseconds since the epoch.
+    timestamp_ms: u64,
+    action: &'static str,
+    params: serde_json::Value,
+}
+
+/// Append to the history, dropping the oldest entry once it is full.
+fn push_history(
+    history: &mut VecDeque<HistoryEntry>,
+    action: &'static str,
+    params: serde_json::Value,
+) {
+    if history.len() == HISTORY_LEN {
+        history.pop_front();
+    }
+    let timestamp_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    history.push_back(HistoryEntry {
+        timestamp_ms,
+        action,
+        params,
+    });
+}
+
+/// History params for typed text: its length, never the text, which may be a password.
+fn text_params(text: &str, paste: bool) -> serde_json::Value {
+    serde_json::json!({ "chars": text.chars().count(), "paste": paste })
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Only the most recent this many actions.
+    limit: Option<usize>,
+}
+
+/// Identical frames delivered after an interaction before the surface counts as frozen.
+const FROZEN_AFTER_CAPTURES: u32 = 3;
+/// Only an interaction this recent makes an unchanged picture suspicious.
+const FROZEN_INTERACTION_WINDOW: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Whether captures keep returning the same picture.
+#[derive(Default)]
+struct FrameHistory {
+    hash: Option<u64>,
+    changed_at: Option<Instant>,
+    /// When a capture last returned a new frame, changed or not.
+    received_at: Option<Instant>,
+    /// New frames captured so far, changed or not.
+    received: u64,
+    /// Frames identical to the last one delivered since the last change or interaction,
+    /// whichever is later. Captures with no new frame don't count: an idle display only
+    /// gets one when something is drawn.
+    unchanged: u32,
+}
+
+impl FrameHistory {
+    /// `hash` is `None` when no new frame arrived.
+    fn observe(&mut self, hash: Option<u64>) {
+        if hash.is_some() {
+            self.received_at = Some(Instant::now());
+            self.received += 1;
+        }
+        match hash {
+            Some(hash) if self.hash != Some(hash) => {
+                self.hash = Some(hash);
+                self.changed_at = Some(Instant::now());
+                self.unchanged = 0;
+            }
+            Some(_) => self.unchanged = self.unchanged.saturating_add(1),
+            None => {}
+        }
+    }
+
+    /// Whether the display kept delivering the same picture since an interaction at `at`.
+    fn frozen_since(&self, at: Instant) -> bool {
+        self.unchanged >= FROZEN_AFTER_CAPTURES
+            && at.elapsed() < FROZEN_INTERACTION_WINDOW
+            && self.changed_at.is_none_or(|changed| changed < at)
+    }
+
+    /// How long ago the frame held from the last new capture arrived; zero when unknown.
+    fn age_ms(&self) -> u64 {
+        self.received_at
+            .map_or(0, |at| at.elapsed().as_millis() as u64)
+    }
+}
+
+/// Parameters a whole-screen image was encoded with.
+#[derive(Clone, Copy, PartialEq)]
+struct Encoding {
+    scale: f32,
+    max_dim: Option<u32>,
+    codec: Codec,
+    marker: Option<(f32, f32)>,
+}
+
+/// The last whole-screen image, served again while no new frame arrives.
+struct CachedJpeg {
+    encoding: Encoding,
+    jpeg: Vec<u8>,
+    applied_scale: f32,
+    /// When the frame it was encoded from arrived from the display.
+    captured_at: Instant,
 }
 
 struct ServerState {
@@ -33,6 +457,46 @@ struct ServerState {
     screen_class: GlobalRef,
     screens: HashMap<String, VirtualScreen>,
     a11y_bridge: GlobalRef,
+    soonest_expiry: ReapDeadline,
+    /// Screen definitions file, when `ANDY_PERSIST=1`.
+    persist_path: Option<std::path::PathBuf>,
+    metrics: Arc<metrics::Metrics>,
+}
+
+/// Earliest moment any screen could time out, readable by the reaper without the lock.
+/// Heartbeats only push expiry later, so it may fire early (and get recomputed) but never late.
+#[derive(Clone)]
+struct ReapDeadline {
+    epoch: Instant,
+    /// Milliseconds since `epoch`; `u64::MAX` when there are no screens.
+    at_ms: Arc<AtomicU64>,
+}
+
+impl ReapDeadline {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            at_ms: Arc::new(AtomicU64::new(u64::MAX)),
+        }
+    }
+
+    fn ms_since_epoch(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.epoch).as_millis() as u64
+    }
+
+    fn is_due(&self) -> bool {
+        self.ms_since_epoch(Instant::now()) >= self.at_ms.load(Ordering::Relaxed)
+    }
+
+    fn lower_to(&self, at: Instant) {
+        self.at_ms
+            .fetch_min(self.ms_since_epoch(at), Ordering::Relaxed);
+    }
+
+    fn set(&self, at: Option<Instant>) {
+        let ms = at.map_or(u64::MAX, |at| self.ms_since_epoch(at));
+        self.at_ms.store(ms, Ordering::Relaxed);
+    }
 }
 
 type AppState = Arc<tokio::sync::Mutex<ServerState>>;
@@ -41,6 +505,8 @@ type AppState = Arc<tokio::sync::Mutex<ServerState>>;
 struct AppError {
     message: String,
     status: StatusCode,
+    /// Class of the Java exception behind the failure, e.g. `java.lang.SecurityException`.
+    exception_class: Option<String>,
 }
 
 impl AppError {
@@ -48,6 +514,7 @@ impl AppError {
         Self {
             message: message.into(),
             status: StatusCode::INTERNAL_SERVER_ERROR,
+            exception_class: None,
         }
     }
 
@@ -55,13 +522,58 @@ impl AppError {
         Self {
             message: message.into(),
             status: StatusCode::NOT_FOUND,
+            exception_class: None,
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::BAD_REQUEST,
+            exception_class: None,
+        }
+    }
+
+    fn timeout(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::GATEWAY_TIMEOUT,
+            exception_class: None,
+        }
+    }
+
+    fn busy(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            exception_class: None,
+        }
+    }
+
+    fn conflict(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::CONFLICT,
+            exception_class: None,
+        }
+    }
+
+    fn too_many(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::TOO_MANY_REQUESTS,
+            exception_class: None,
         }
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (self.status, self.message).into_response()
+        let mut response = (self.status, self.message).into_response();
+        if let Some(class) = self.exception_class.and_then(|c| c.parse().ok()) {
+            response.headers_mut().insert("X-Exception-Class", class);
+        }
+        response
     }
 }
 
@@ -73,6 +585,90 @@ struct ScreenInfo {
     height: i32,
     dpi: i32,
     assigned_package: String,
+    last_action: Option<LastActionInfo>,
+    /// Heartbeat timeout; 0 never reaps.
+    #[serde(default)]
+    timeout_secs: u64,
+    /// Time left before the reaper removes the screen unless a request arrives.
+    #[serde(default)]
+    seconds_until_reap: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ActionKind {
+    Tap,
+    #[serde(rename = "multi_tap")]
+    MultiTap,
+    Swipe,
+    Text,
+    Key,
+    Select,
+    Focus,
+}
+
+impl ActionKind {
+    /// The serialized name, used for history entries.
+    fn name(self) -> &'static str {
+        match self {
+            ActionKind::Tap => "tap",
+            ActionKind::MultiTap => "multi_tap",
+            ActionKind::Swipe => "swipe",
+            ActionKind::Text => "text",
+            ActionKind::Key => "key",
+            ActionKind::Select => "select",
+            ActionKind::Focus => "focus",
+        }
+    }
+}
+
+/// What the server last injected. `point` is in device pixels (swipe start for swipes).
+#[derive(Clone, Copy)]
+struct LastAction {
+    kind: ActionKind,
+    point: Option<(f32, f32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LastActionInfo {
+    kind: ActionKind,
+    x: Option<f32>,
+    y: Option<f32>,
+    at_ms_ago: u64,
+}
+
+/// The soft keyboard on a screen's display.
+#[derive(Serialize)]
+struct ImeInfo {
+    shown: bool,
+    /// Pixels of the screen the keyboard covers from the bottom; 0 when hidden.
+    height: i32,
+    bounds: Option<a11y::Bounds>,
+}
+
+const KEYCODE_BACK: i32 = 4;
+const KEYCODE_ENTER: i32 = 66;
+const KEYCODE_DEL: i32 = 67;
+const KEYCODE_CTRL_LEFT: i32 = 113;
+const KEYCODE_A: i32 = 29;
+
+/// How long `hide-ime` waits for the keyboard window to go away after BACK.
+const IME_HIDE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Serialize)]
+struct ForegroundInfo {
+    package: String,
+    activity: String,
+}
+
+/// Whether the bound app is installed, running and in front on its screen.
+#[derive(Serialize)]
+struct AppProcessState {
+    package: String,
+    installed: bool,
+    running: bool,
+    foreground: bool,
+    pid: Option<u32>,
 }
 
 #[derive(Deserialize)]
@@ -81,16 +677,132 @@ struct CreateScreenRequest {
     width: i32,
     height: i32,
     dpi: i32,
-    timeout_secs: u64,
+    /// Seconds without a heartbeat before the screen is reaped; 0 keeps it forever. A new
+    /// screen defaults to [`DEFAULT_SCREEN_TIMEOUT_SECS`]; an existing one keeps its own.
+    timeout_secs: Option<u64>,
+    package: String,
+    /// Tap through known system dialogs (permissions, ANR, ...) after interactions. An
+    /// existing screen keeps its setting when this is left out.
+    auto_dismiss: Option<bool>,
+    /// Replaces the default dialog table when auto-dismiss is on.
+    dismiss_rules: Option<Vec<a11y::DismissRule>>,
+}
+
+const DEFAULT_SCREEN_TIMEOUT_SECS: u64 = 300;
+const MAX_SCREEN_DIMENSION: i32 = 4096;
+const SCREEN_DPI_RANGE: std::ops::RangeInclusive<i32> = 72..=960;
+
+impl CreateScreenRequest {
+    /// The timeout to leave on a screen that has `current`: a request that doesn't state
+    /// one keeps it, so `--no-timeout` survives later commands.
+    fn timeout_secs(&self, current: u64) -> u64 {
+        self.timeout_secs.unwrap_or(current)
+    }
+
+    /// The dismiss rules to leave on a screen that has `current`. A request that states
+    /// neither `auto_dismiss` nor `dismiss_rules` keeps them; rules alone only replace the
+    /// table of a screen that already auto-dismisses.
+    fn dismiss_rules(
+        &self,
+        current: Option<Vec<a11y::DismissRule>>,
+    ) -> Option<Vec<a11y::DismissRule>> {
+        match self.auto_dismiss {
+            Some(true) => Some(
+                self.dismiss_rules
+                    .clone()
+                    .unwrap_or_else(a11y::default_dismiss_rules),
+            ),
+            Some(false) => None,
+            None => current.map(|rules| self.dismiss_rules.clone().unwrap_or(rules)),
+        }
+    }
+
+    /// Reject geometry the `VirtualScreen` constructor would only fail on opaquely.
+    fn validate_geometry(&self) -> Result<(), AppError> {
+        for (label, value) in [("width", self.width), ("height", self.height)] {
+            if !(1..=MAX_SCREEN_DIMENSION).contains(&value) {
+                return Err(AppError::bad_request(format!(
+                    "{label} {value} out of range 1..={MAX_SCREEN_DIMENSION}"
+                )));
+            }
+        }
+        if !SCREEN_DPI_RANGE.contains(&self.dpi) {
+            return Err(AppError::bad_request(format!(
+                "dpi {} out of range {}..={}",
+                self.dpi,
+                SCREEN_DPI_RANGE.start(),
+                SCREEN_DPI_RANGE.end()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of one entry of `POST /screens/batch`; exactly one of `screen` and `error` is set.
+#[derive(Serialize)]
+struct BatchScreenResult {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    screen: Option<ScreenInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PackagesQuery {
     package: String,
 }
 
+#[derive(Serialize)]
+struct InstalledPackages {
+    /// What the spec could bind, sorted; bound or not.
+    matches: Vec<String>,
+    /// Similar installed packages, only when nothing matches.
+    nearby: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ReleasePackageRequest {
+    /// Package spec to bind after releasing; the screen stays unbound if absent.
+    package: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct TapRequest {
     x: f32,
     y: f32,
 }
 
+/// Points touched at the same instant, one finger each, in device pixels.
+#[derive(Deserialize)]
+struct MultiTapRequest {
+    points: Vec<TapRequest>,
+}
+
+impl MultiTapRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        if !(2..=MAX_TOUCH_POINTERS).contains(&self.points.len()) {
+            return Err(AppError::bad_request(format!(
+                "multi-tap takes 2 to {MAX_TOUCH_POINTERS} points, got {}",
+                self.points.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct MultiTapQuery {
+    #[serde(default)]
+    no_wait: bool,
+    #[serde(default)]
+    wait: WaitStrategy,
+    wait_ms: Option<u64>,
+}
+
+/// Fingers Android tracks at once on a touchscreen.
+const MAX_TOUCH_POINTERS: usize = 10;
+
 #[derive(Deserialize)]
 struct SwipeRequest {
     x1: f32,
@@ -98,11 +810,76 @@ struct SwipeRequest {
     x2: f32,
     y2: f32,
     duration_ms: i64,
+    /// `ACTION_MOVE` events between down and up; one per 10ms of duration by default.
+    steps: Option<u32>,
+    #[serde(default)]
+    easing: Easing,
+    /// How long the finger rests on the end point before lifting. Android measures
+    /// lift-off velocity over the last 100ms of moves, so a longer rest means no fling.
+    #[serde(default)]
+    hold_ms: i64,
+}
+
+const MAX_SWIPE_STEPS: u32 = 1000;
+const MAX_SWIPE_HOLD_MS: i64 = 1000;
+/// The swipe holds the state lock while Java sleeps through it.
+const MAX_SWIPE_DURATION_MS: i64 = 10_000;
+/// Spacing of the moves that keep the finger on the end point while it rests.
+const SWIPE_HOLD_INTERVAL_MS: i64 = 10;
+
+/// How the finger's position advances over a swipe's duration.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Easing {
+    #[default]
+    Linear,
+    /// Fast start, slowing toward the end, like a finger lifting off gently.
+    Decelerate,
+    /// Slow start, speeding up toward the end.
+    Accelerate,
+}
+
+impl Easing {
+    /// Fraction of the way travelled after fraction `t` of the duration.
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::Decelerate => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::Accelerate => t * t,
+        }
+    }
+}
+
+/// Every touch point of a swipe with its time in ms after touch down: down on the start,
+/// moves along the eased path, moves resting on the end for `hold_ms`, then up.
+fn swipe_path(req: &SwipeRequest) -> Vec<(f32, f32, i64)> {
+    let steps = match req.steps {
+        Some(steps) => steps as i64 + 1,
+        None => (req.duration_ms / 10).clamp(2, MAX_SWIPE_STEPS as i64),
+    };
+    let mut path = vec![(req.x1, req.y1, 0)];
+    for i in 1..steps {
+        let t = req.easing.apply(i as f32 / steps as f32);
+        let x = req.x1 + (req.x2 - req.x1) * t;
+        let y = req.y1 + (req.y2 - req.y1) * t;
+        path.push((x, y, req.duration_ms * i / steps));
+    }
+    let up_at = req.duration_ms + req.hold_ms;
+    path.extend(
+        (req.duration_ms..up_at)
+            .step_by(SWIPE_HOLD_INTERVAL_MS as usize)
+            .map(|at| (req.x2, req.y2, at)),
+    );
+    path.push((req.x2, req.y2, up_at));
+    path
 }
 
 #[derive(Deserialize)]
 struct TypeRequest {
     text: String,
+    /// Insert through the clipboard even when the text could be typed key by key.
+    #[serde(default)]
+    paste: bool,
 }
 
 #[derive(Deserialize)]
@@ -110,9 +887,70 @@ struct KeyRequest {
     keycode: i32,
 }
 
+/// Keycodes pressed together, modifiers first (e.g. `[113, 29]` for Ctrl+A).
+#[derive(Deserialize)]
+struct KeyComboRequest {
+    keycodes: Vec<i32>,
+}
+
+/// Select `start..end` in the node whose text or content_desc is `text`; equal ends place the cursor.
+#[derive(Deserialize)]
+struct SelectRequest {
+    text: String,
+    start: i32,
+    end: i32,
+}
+
+/// Tap the text field labelled `target`, then type into it, under one lock.
+#[derive(Deserialize)]
+struct FillRequest {
+    target: String,
+    text: String,
+    /// Select all and delete what the field held before typing.
+    #[serde(default)]
+    clear: bool,
+    /// Press ENTER after typing.
+    #[serde(default)]
+    submit: bool,
+    #[serde(default)]
+    paste: bool,
+}
+
+/// Where `focus` moves input focus, as a D-pad would.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum FocusDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl FocusDirection {
+    /// The matching `View.FOCUS_*` constant.
+    fn view_constant(self) -> i32 {
+        match self {
+            FocusDirection::Up => 33,
+            FocusDirection::Down => 130,
+            FocusDirection::Left => 17,
+            FocusDirection::Right => 66,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FocusRequest {
+    direction: FocusDirection,
+}
+
 #[derive(Deserialize)]
 struct OpenUrlRequest {
     url: String,
+    /// Activity in the bound package; by default Android picks the one handling the URL.
+    activity: Option<String>,
+    /// Extras the handling activity understands, e.g. a browser's referrer extra.
+    #[serde(default)]
+    extras: Vec<IntentExtra>,
 }
 
 #[derive(Deserialize)]
@@ -121,36 +959,583 @@ struct WaitForIdleRequest {
     global_timeout_ms: i64,
 }
 
-#[derive(Deserialize)]
-struct NoWaitQuery {
-    #[serde(default)]
-    no_wait: bool,
+#[derive(Deserialize)]
+struct WaitForNodeRequest {
+    selector: a11y::Selector,
+    timeout_ms: u64,
+    /// Wait for the node to disappear instead of appear.
+    #[serde(default)]
+    gone: bool,
+}
+
+#[derive(Serialize)]
+struct WaitForNodeResponse {
+    found: bool,
+    node: Option<a11y::Node>,
+}
+
+/// How reads settle after the last interaction. `no_wait=true` is shorthand for `wait=none`.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum WaitStrategy {
+    /// Wait for a11y idle, at most 2500ms after the last interaction.
+    #[default]
+    Idle,
+    /// Settle for `wait_ms` after the last interaction, whatever the UI is doing.
+    Fixed,
+    None,
+}
+
+const DEFAULT_FIXED_WAIT_MS: u64 = 500;
+
+/// How long launch waits for the app to go idle, and at most overall, unless the request
+/// says otherwise.
+const LAUNCH_IDLE_TIMEOUT_MS: i64 = 5000;
+const LAUNCH_GLOBAL_TIMEOUT_MS: i64 = 30000;
+
+fn wait_strategy(no_wait: bool, wait: WaitStrategy) -> WaitStrategy {
+    if no_wait { WaitStrategy::None } else { wait }
+}
+
+/// Launch the bound package with an explicit intent instead of its launcher activity.
+#[derive(Deserialize, Default)]
+struct LaunchIntentRequest {
+    /// Activity in the bound package, e.g. `.settings.SettingsActivity`.
+    activity: Option<String>,
+    action: Option<String>,
+    data: Option<String>,
+    #[serde(default)]
+    extras: Vec<IntentExtra>,
+}
+
+/// One `am start` extra; the type tag picks the flag.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum IntentExtra {
+    String { key: String, value: String },
+    Int { key: String, value: i32 },
+    Bool { key: String, value: bool },
+    Long { key: String, value: i64 },
+}
+
+impl IntentExtra {
+    fn am_args(&self) -> Result<[String; 3], AppError> {
+        let (flag, key, value) = match self {
+            IntentExtra::String { key, value } => ("--es", key, value.clone()),
+            IntentExtra::Int { key, value } => ("--ei", key, value.to_string()),
+            IntentExtra::Bool { key, value } => ("--ez", key, value.to_string()),
+            IntentExtra::Long { key, value } => ("--el", key, value.to_string()),
+        };
+        if key.is_empty() {
+            return Err(AppError::bad_request("intent extra with empty key"));
+        }
+        Ok([flag.to_string(), key.clone(), value])
+    }
+}
+
+impl LaunchIntentRequest {
+    fn am_args(&self, package: &str) -> Result<Vec<String>, AppError> {
+        let mut args = Vec::new();
+        if let Some(action) = &self.action {
+            args.extend(["-a".to_string(), action.clone()]);
+        }
+        if let Some(data) = &self.data {
+            args.extend(["-d".to_string(), data.clone()]);
+        }
+        if let Some(activity) = &self.activity {
+            args.extend(["-n".to_string(), activity_component(package, activity)?]);
+        }
+        for extra in &self.extras {
+            args.extend(extra.am_args()?);
+        }
+        Ok(args)
+    }
+}
+
+/// `package/activity` for an activity in the bound package, given as `.Main`,
+/// `com.example.Main` or a full `com.example/.Main` component.
+fn activity_component(package: &str, activity: &str) -> Result<String, AppError> {
+    let class = match activity.split_once('/') {
+        Some((owner, class)) if owner == package => class,
+        Some((owner, _)) => {
+            return Err(AppError::bad_request(format!(
+                "activity {activity} belongs to {owner}, not the bound package {package}"
+            )));
+        }
+        None => activity,
+    };
+    let valid_segment = |segment: &str| {
+        segment
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+            && segment
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    };
+    if !class
+        .strip_prefix('.')
+        .unwrap_or(class)
+        .split('.')
+        .all(valid_segment)
+    {
+        return Err(AppError::bad_request(format!(
+            "invalid activity {activity:?}: expected a class name like .MainActivity"
+        )));
+    }
+    Ok(format!("{package}/{class}"))
+}
+
+#[derive(Deserialize)]
+struct LaunchQuery {
+    #[serde(default)]
+    no_wait: bool,
+    #[serde(default)]
+    wait: WaitStrategy,
+    wait_ms: Option<u64>,
+    /// Overrides [`LAUNCH_IDLE_TIMEOUT_MS`] for `wait=idle`.
+    idle_timeout_ms: Option<i64>,
+    /// Overrides [`LAUNCH_GLOBAL_TIMEOUT_MS`] for `wait=idle`.
+    global_timeout_ms: Option<i64>,
+    /// Once settled, cache the a11y tree and a screenshot for the next read.
+    #[serde(default)]
+    prefetch: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum A11yFormat {
+    #[default]
+    Json,
+    /// `uiautomator dump` compatible `<hierarchy>` document.
+    Xml,
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ScreenshotFormat {
+    #[default]
+    Jpeg,
+    /// AV1 in HEIF: smaller than JPEG at the same quality but far slower to encode, so for
+    /// one-off captures over slow links. Needs the `avif` cargo feature.
+    Avif,
+    /// Unencoded RGBA, 4 bytes per pixel, rows top to bottom without padding.
+    Raw,
+}
+
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+/// About 30% smaller than a default JPEG, at ~1.5s to encode instead of ~20ms (the
+/// README's `screenshot --avif` numbers).
+const DEFAULT_AVIF_QUALITY: u8 = 50;
+/// rav1e's fastest speed; slower ones only shrink the file by a few percent.
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 10;
+
+/// Chroma subsampling of screenshot JPEGs.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+enum Chroma {
+    /// 4:2:0, what the encoder picks at our quality; the smallest files.
+    #[default]
+    #[serde(rename = "420")]
+    Half,
+    /// 4:4:4, so thin colored text stays sharp.
+    #[serde(rename = "444")]
+    Full,
+}
+
+/// How a screenshot is encoded: container, quality and how it stores color.
+#[derive(Clone, Copy, PartialEq)]
+struct Codec {
+    avif: bool,
+    /// 1 to 100.
+    quality: u8,
+    /// Luminance only, for OCR and smaller payloads.
+    grayscale: bool,
+    /// JPEG only; AVIF picks its own.
+    chroma: Chroma,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self {
+            avif: false,
+            quality: DEFAULT_JPEG_QUALITY,
+            grayscale: false,
+            chroma: Chroma::default(),
+        }
+    }
+}
+
+impl Codec {
+    fn content_type(self) -> &'static str {
+        if self.avif {
+            "image/avif"
+        } else {
+            "image/jpeg"
+        }
+    }
+
+    fn format_name(self) -> &'static str {
+        if self.avif { "avif" } else { "jpeg" }
+    }
+}
+
+#[derive(Deserialize)]
+struct A11yQuery {
+    #[serde(default)]
+    no_wait: bool,
+    #[serde(default)]
+    wait: WaitStrategy,
+    wait_ms: Option<u64>,
+    #[serde(default)]
+    format: A11yFormat,
+    /// Read without counting as activity: no heartbeat, so the screen is still reaped
+    /// when idle. Skips the auto-wait.
+    #[serde(default)]
+    peek: bool,
+}
+
+#[derive(Deserialize)]
+struct ScreenshotQuery {
+    #[serde(default)]
+    no_wait: bool,
+    #[serde(default)]
+    wait: WaitStrategy,
+    wait_ms: Option<u64>,
+    /// Downscale factor in (0, 1]; tap with `coord_space=screenshot` to map back.
+    scale: Option<f32>,
+    /// Mark the last tap/swipe point on the image.
+    #[serde(default)]
+    debug: bool,
+    /// Crop to the bounds of the node whose text or content_desc matches.
+    node: Option<String>,
+    /// Encode luminance only, for OCR and smaller payloads.
+    #[serde(default)]
+    grayscale: bool,
+    /// Further downscale so the longer side is at most this many pixels.
+    max_dim: Option<u32>,
+    /// Scroll through a scrollable node and stitch its whole content into one image.
+    #[serde(default)]
+    full: bool,
+    /// With `full`, the scrollable node (or a node inside it) by text or content_desc;
+    /// the largest scrollable node by default.
+    scroll_node: Option<String>,
+    /// Frames to capture and throw away first, for animations still running after idle.
+    #[serde(default)]
+    settle_frames: u32,
+    #[serde(default)]
+    format: ScreenshotFormat,
+    /// 1 to 100; 85 for JPEG and 50 for AVIF by default.
+    quality: Option<u8>,
+    #[serde(default)]
+    chroma: Chroma,
+}
+
+impl ScreenshotQuery {
+    fn codec(&self) -> Result<Codec, AppError> {
+        let avif = self.format == ScreenshotFormat::Avif;
+        if avif && !cfg!(feature = "avif") {
+            return Err(AppError::bad_request(
+                "this server was built without AVIF support (cargo feature avif)",
+            ));
+        }
+        let default_quality = if avif {
+            DEFAULT_AVIF_QUALITY
+        } else {
+            DEFAULT_JPEG_QUALITY
+        };
+        let quality = self.quality.unwrap_or(default_quality);
+        if !(1..=100).contains(&quality) {
+            return Err(AppError::bad_request(format!(
+                "quality must be 1 to 100, got {quality}"
+            )));
+        }
+        Ok(Codec {
+            avif,
+            quality,
+            grayscale: self.grayscale,
+            chroma: self.chroma,
+        })
+    }
+}
+
+/// Cap on `settle_frames`, which hold the screen for one interval each.
+const MAX_SETTLE_FRAMES: u32 = 30;
+/// Pause between discarded frames, a few display refreshes.
+const SETTLE_FRAME_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+/// Cap on viewports stitched into one long screenshot.
+const MAX_SCROLL_PAGES: usize = 30;
+/// JPEG stores each dimension in 16 bits.
+const MAX_JPEG_DIM: u32 = 65_535;
+const LONG_SCREENSHOT_SWIPE_MS: i64 = 1000;
+/// Rest on the end of each page drag, past Android's 100ms velocity window.
+const LONG_SCREENSHOT_HOLD_MS: i64 = 150;
+/// Pause after each page scroll for the content to come to rest.
+const LONG_SCREENSHOT_SETTLE: std::time::Duration = std::time::Duration::from_millis(400);
+
+/// Coordinate space of tap input. A11y bounds are always in device pixels.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CoordSpace {
+    #[default]
+    Device,
+    /// Pixels of the last screenshot; scaled back by the scale it was taken at.
+    Screenshot,
+}
+
+#[derive(Deserialize)]
+struct TapQuery {
+    #[serde(default)]
+    no_wait: bool,
+    #[serde(default)]
+    wait: WaitStrategy,
+    wait_ms: Option<u64>,
+    #[serde(default)]
+    coord_space: CoordSpace,
+    /// Respond with a `Snapshot` taken after the tap settles.
+    #[serde(default)]
+    snapshot: bool,
+}
+
+/// Screen state after an interaction, so agents need no follow-up reads.
+#[derive(Serialize)]
+struct Snapshot {
+    wait_ms: u64,
+    app_crashed: Option<String>,
+    /// System dialog tapped away by auto-dismiss.
+    auto_dismissed: Option<String>,
+    /// Whether the a11y tree changed across the tap; absent when not waiting.
+    ui_changed: Option<bool>,
+    /// Base64-encoded JPEG at full scale.
+    screenshot: String,
+    a11y: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct RawFrameInfo {
+    width: i32,
+    height: i32,
+    stride: i32,
+    bytes_per_pixel: i32,
+    seq: u64,
+    timestamp_ms: u64,
+    /// `(x, y, w, h)` when the pixels are only this region, changed since the base frame.
+    region: Option<(i32, i32, i32, i32)>,
+}
+
+/// Send a dirty region instead of the full frame only while it stays this small.
+const MAX_REGION_FRACTION: f32 = 0.5;
+
+#[derive(Deserialize)]
+struct RawFrameQuery {
+    #[serde(default)]
+    no_wait: bool,
+    #[serde(default)]
+    wait: WaitStrategy,
+    wait_ms: Option<u64>,
+    /// Seq of the frame the client holds; a small change comes back as a region of it.
+    base: Option<u64>,
+}
+
+/// Byte order of the frames a screen delivers.
+#[derive(Clone, Copy, PartialEq)]
+enum PixelFormat {
+    Rgba,
+    Bgra,
+}
+
+impl PixelFormat {
+    fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgba => "rgba",
+            PixelFormat::Bgra => "bgra",
+        }
+    }
+}
+
+/// `HAL_PIXEL_FORMAT_BGRA_8888`, reported by surfaces that ignore the RGBA request.
+const HAL_BGRA_8888: i32 = 5;
+
+/// `ANDY_PIXEL_FORMAT=rgba|bgra`, for devices that mislabel their frames.
+static PIXEL_FORMAT_OVERRIDE: LazyLock<Option<PixelFormat>> =
+    LazyLock::new(|| match std::env::var("ANDY_PIXEL_FORMAT").as_deref() {
+        Ok("rgba") => Some(PixelFormat::Rgba),
+        Ok("bgra") => Some(PixelFormat::Bgra),
+        Ok(other) => {
+            tracing::warn!(value = other, "ignoring unknown ANDY_PIXEL_FORMAT");
+            None
+        }
+        Err(_) => None,
+    });
+
+/// Call one of the `takeScreenshotRGBA*` methods; the array is null when it has no frame.
+fn take_frame<'local>(
+    env: &mut JNIEnv<'local>,
+    screen: &JObject,
+    take: &str,
+) -> Result<JByteArray<'local>, AppError> {
+    Ok(env
+        .call_method(screen, take, "()[B", &[])
+        .map_err(|e| jni_error(env, &format!("{take} call failed"), e))?
+        .l()
+        .map_err(|e| AppError::new(format!("{take} result failed: {e}")))?
+        .into())
+}
+
+/// Format of the frame `screen` last returned, unless overridden.
+fn frame_pixel_format(env: &mut JNIEnv, screen: &JObject) -> Result<PixelFormat, AppError> {
+    if let Some(format) = *PIXEL_FORMAT_OVERRIDE {
+        return Ok(format);
+    }
+    let format = env
+        .call_method(screen, "getPixelFormat", "()I", &[])
+        .map_err(|e| AppError::new(format!("getPixelFormat failed: {e}")))?
+        .i()
+        .map_err(|e| AppError::new(format!("getPixelFormat result failed: {e}")))?;
+    Ok(if format == HAL_BGRA_8888 {
+        PixelFormat::Bgra
+    } else {
+        PixelFormat::Rgba
+    })
+}
+
+/// The scale to apply, once `scale` and `max_dim` check out.
+fn check_encoding(scale: Option<f32>, max_dim: Option<u32>) -> Result<f32, AppError> {
+    let scale = scale.unwrap_or(1.0);
+    if !(scale > 0.0 && scale <= 1.0) {
+        return Err(AppError::bad_request(format!(
+            "scale must be in (0, 1], got {scale}"
+        )));
+    }
+    if max_dim == Some(0) {
+        return Err(AppError::bad_request("max_dim must be at least 1"));
+    }
+    Ok(scale)
 }
 
-#[derive(Serialize)]
-struct RawFrameInfo {
-    width: i32,
-    height: i32,
-    stride: i32,
-    bytes_per_pixel: i32,
-    seq: u64,
-    timestamp_ms: u64,
+/// Encode RGBA pixels as JPEG or AVIF, optionally reduced to luminance only.
+fn encode_image(rgba: &[u8], width: u32, height: u32, codec: Codec) -> Result<Vec<u8>, AppError> {
+    if codec.avif {
+        return encode_avif(rgba, width, height, codec);
+    }
+    encode_jpeg(rgba, width, height, codec)
 }
 
-fn encode_jpeg(rgba: &[u8], width: u32, height: u32) -> Result<Vec<u8>, AppError> {
+fn encode_jpeg(rgba: &[u8], width: u32, height: u32, codec: Codec) -> Result<Vec<u8>, AppError> {
+    let luma;
+    let (pixels, color_type) = if codec.grayscale {
+        luma = image::luminance(rgba);
+        (&luma[..], jpeg_encoder::ColorType::Luma)
+    } else {
+        (rgba, jpeg_encoder::ColorType::Rgba)
+    };
     let mut buf = Vec::new();
-    let encoder = jpeg_encoder::Encoder::new(&mut buf, 85);
+    let mut encoder = jpeg_encoder::Encoder::new(&mut buf, codec.quality);
+    if codec.chroma == Chroma::Full {
+        encoder.set_sampling_factor(jpeg_encoder::SamplingFactor::R_4_4_4);
+    }
     encoder
-        .encode(
-            rgba,
-            width as u16,
-            height as u16,
-            jpeg_encoder::ColorType::Rgba,
-        )
+        .encode(pixels, width as u16, height as u16, color_type)
         .map_err(|e| AppError::new(format!("jpeg encode failed: {e}")))?;
     Ok(buf)
 }
 
+#[cfg(feature = "avif")]
+fn encode_avif(rgba: &[u8], width: u32, height: u32, codec: Codec) -> Result<Vec<u8>, AppError> {
+    // The display is opaque, so the alpha channel is dropped rather than encoded
+    let pixels: Vec<ravif::RGB8> = if codec.grayscale {
+        let luma = image::luminance(rgba);
+        luma.into_iter()
+            .map(|y| ravif::RGB8::new(y, y, y))
+            .collect()
+    } else {
+        let rgb = rgba
+            .chunks_exact(4)
+            .map(|px| ravif::RGB8::new(px[0], px[1], px[2]));
+        rgb.collect()
+    };
+    let encoded = ravif::Encoder::new()
+        .with_quality(codec.quality as f32)
+        .with_speed(AVIF_SPEED)
+        .encode_rgb(ravif::Img::new(
+            &pixels[..],
+            width as usize,
+            height as usize,
+        ))
+        .map_err(|e| AppError::new(format!("avif encode failed: {e}")))?;
+    Ok(encoded.avif_file)
+}
+
+#[cfg(not(feature = "avif"))]
+fn encode_avif(_: &[u8], _: u32, _: u32, _: Codec) -> Result<Vec<u8>, AppError> {
+    Err(AppError::bad_request(
+        "this server was built without AVIF support (cargo feature avif)",
+    ))
+}
+
+impl VirtualScreen {
+    fn info(&self, name: &str) -> ScreenInfo {
+        ScreenInfo {
+            name: name.to_string(),
+            display_id: self.display_id,
+            width: self.width,
+            height: self.height,
+            dpi: self.dpi,
+            assigned_package: self.assigned_package.clone(),
+            last_action: self.last_interaction.map(|(at, action)| LastActionInfo {
+                kind: action.kind,
+                x: action.point.map(|(x, _)| x),
+                y: action.point.map(|(_, y)| y),
+                at_ms_ago: at.elapsed().as_millis() as u64,
+            }),
+            timeout_secs: self.timeout_secs,
+            seconds_until_reap: self
+                .expiry()
+                .map(|at| at.saturating_duration_since(Instant::now()).as_secs()),
+        }
+    }
+
+    fn record(&mut self, kind: ActionKind, point: Option<(f32, f32)>, params: serde_json::Value) {
+        self.last_interaction = Some((Instant::now(), LastAction { kind, point }));
+        self.frames.unchanged = 0;
+        self.log(kind.name(), params);
+    }
+
+    fn log(&mut self, action: &'static str, params: serde_json::Value) {
+        self.prefetched_tree = None;
+        push_history(&mut self.history, action, params);
+    }
+
+    /// Milliseconds since the last interaction, if there was one.
+    fn interaction_age_ms(&self) -> Option<u64> {
+        let (at, _) = self.last_interaction?;
+        Some(at.elapsed().as_millis() as u64)
+    }
+
+    /// Several frames delivered since a recent interaction all showed the same picture,
+    /// which looks like a surface that stopped rendering rather than an app ignoring input.
+    fn frame_frozen(&self) -> bool {
+        self.last_interaction
+            .is_some_and(|(at, _)| self.frames.frozen_since(at))
+    }
+
+    /// When the screen is reaped without a heartbeat; never for `timeout_secs: 0`.
+    fn expiry(&self) -> Option<Instant> {
+        (self.timeout_secs != 0)
+            .then(|| self.last_heartbeat + std::time::Duration::from_secs(self.timeout_secs))
+    }
+
+    /// The bound package; empty after `release-package` until rebound.
+    fn package(&self) -> Result<&str, AppError> {
+        if self.assigned_package.is_empty() {
+            return Err(AppError::bad_request(
+                "no package bound to this screen; rebind with release-package",
+            ));
+        }
+        Ok(&self.assigned_package)
+    }
+}
+
 impl ServerState {
     fn get_screen_mut(&mut self, name: &str) -> Result<&mut VirtualScreen, AppError> {
         let screen = self
@@ -161,6 +1546,14 @@ impl ServerState {
         Ok(screen)
     }
 
+    /// Like [`Self::get_screen_mut`] without the heartbeat, for reads that shouldn't keep
+    /// a screen alive.
+    fn get_screen(&self, name: &str) -> Result<&VirtualScreen, AppError> {
+        self.screens
+            .get(name)
+            .ok_or_else(|| AppError::not_found(format!("screen {name} not found")))
+    }
+
     fn with_env<T>(
         &self,
         f: impl FnOnce(&mut JNIEnv) -> Result<T, AppError>,
@@ -169,23 +1562,49 @@ impl ServerState {
             .jvm
             .attach_current_thread()
             .map_err(|e| AppError::new(format!("attach_current_thread failed: {e}")))?;
-        f(&mut env)
+        let start = Instant::now();
+        let result = f(&mut env);
+        self.metrics.jni_call.observe(start.elapsed());
+        result
     }
 
     fn create_screen(&mut self, req: &CreateScreenRequest) -> Result<ScreenInfo, AppError> {
+        req.validate_geometry()?;
         // Get-or-create: if screen with this name exists, reset heartbeat and return it
         if let Some(screen) = self.screens.get_mut(&req.name) {
             screen.last_heartbeat = Instant::now();
-            return Ok(ScreenInfo {
-                name: req.name.clone(),
-                display_id: screen.display_id,
-                width: screen.width,
-                height: screen.height,
-                dpi: screen.dpi,
-                assigned_package: screen.assigned_package.clone(),
-            });
+            let dismiss_rules = req.dismiss_rules(screen.dismiss_rules.clone());
+            let timeout_secs = req.timeout_secs(screen.timeout_secs);
+            // Most calls are heartbeats; only a changed definition is worth a write
+            let mut changed =
+                screen.timeout_secs != timeout_secs || screen.dismiss_rules != dismiss_rules;
+            screen.timeout_secs = timeout_secs;
+            screen.dismiss_rules = dismiss_rules;
+            if let Some(expiry) = screen.expiry() {
+                self.soonest_expiry.lower_to(expiry);
+            }
+            // A screen whose package was released binds again on next use
+            if screen.assigned_package.is_empty() {
+                let package = self.resolve_package(&req.package)?;
+                self.screens.get_mut(&req.name).unwrap().assigned_package = package;
+                changed = true;
+            }
+            if changed {
+                self.screens_changed();
+            }
+            return Ok(self.screens[&req.name].info(&req.name));
+        }
+        if self.screens.len() >= *MAX_SCREENS {
+            return Err(AppError::too_many(format!(
+                "{} of {} screens in use (ANDY_MAX_SCREENS); delete one before creating {}",
+                self.screens.len(),
+                *MAX_SCREENS,
+                req.name
+            )));
         }
 
+        // Before the display exists, so a missing app doesn't leave one behind
+        let assigned_package = self.resolve_package(&req.package)?;
         let instance = self.with_env(|env| {
             let class: &JClass = self.screen_class.as_obj().into();
             let obj = env
@@ -198,58 +1617,96 @@ impl ServerState {
                         JValue::Int(req.dpi),
                     ],
                 )
-                .map_err(|e| {
-                    if let Some(exc_msg) = get_exception_message(env) {
-                        AppError::new(format!("VirtualScreen constructor failed: {exc_msg}"))
-                    } else {
-                        AppError::new(format!("VirtualScreen constructor failed: {e}"))
-                    }
-                })?;
-            let display_id = env
-                .call_method(&obj, "getDisplayId", "()I", &[])
-                .map_err(|e| AppError::new(format!("getDisplayId failed: {e}")))?
-                .i()
-                .map_err(|e| AppError::new(format!("getDisplayId result failed: {e}")))?;
+                .map_err(|e| jni_error(env, "VirtualScreen constructor failed", e))?;
+            let mut int_of = |method: &str| {
+                env.call_method(&obj, method, "()I", &[])
+                    .map_err(|e| AppError::new(format!("{method} failed: {e}")))?
+                    .i()
+                    .map_err(|e| AppError::new(format!("{method} result failed: {e}")))
+            };
+            let display_id = int_of("getDisplayId")?;
+            let geometry = (int_of("getWidth")?, int_of("getHeight")?, int_of("getDpi")?);
             let global = env
                 .new_global_ref(&obj)
                 .map_err(|e| AppError::new(format!("new_global_ref failed: {e}")))?;
-            Ok((global, display_id))
+            Ok((global, display_id, geometry))
         })?;
 
-        let (global, display_id) = instance;
-        let assigned_package = {
-            let p = &req.package;
-            let installed = self.list_installed_packages(p)?;
-            if installed.contains(p) {
-                p.clone()
-            } else {
-                self.allocate_from_prefix(p, &installed)?
-            }
-        };
+        let (global, display_id, (width, height, dpi)) = instance;
+        if (width, height, dpi) != (req.width, req.height, req.dpi) {
+            tracing::warn!(
+                name = %req.name,
+                requested = %format!("{}x{}@{}", req.width, req.height, req.dpi),
+                actual = %format!("{width}x{height}@{dpi}"),
+                "display geometry was adjusted; reporting the actual geometry"
+            );
+        }
 
         let screen = VirtualScreen {
             display_id,
             instance: global,
             last_jpeg: None,
-            width: req.width,
-            height: req.height,
-            dpi: req.dpi,
+            last_screenshot_scale: 1.0,
+            width,
+            height,
+            dpi,
             last_heartbeat: Instant::now(),
-            timeout_secs: req.timeout_secs,
+            timeout_secs: req.timeout_secs(DEFAULT_SCREEN_TIMEOUT_SECS),
             last_interaction: None,
-            assigned_package: assigned_package.clone(),
+            assigned_package,
             last_raw_frame_seq: 0,
+            last_raw_frame: None,
+            frames: FrameHistory::default(),
+            dismiss_rules: req.dismiss_rules(None),
+            history: VecDeque::new(),
+            prefetched_tree: None,
         };
+        let info = screen.info(&req.name);
+        if let Some(expiry) = screen.expiry() {
+            self.soonest_expiry.lower_to(expiry);
+        }
         self.screens.insert(req.name.clone(), screen);
+        CANCEL_GENERATIONS
+            .lock()
+            .unwrap()
+            .entry(req.name.clone())
+            .or_default();
+        self.screens_changed();
+        Ok(info)
+    }
 
-        Ok(ScreenInfo {
-            name: req.name.clone(),
-            display_id,
-            width: req.width,
-            height: req.height,
-            dpi: req.dpi,
-            assigned_package,
-        })
+    /// Get-or-create every screen. Malformed requests reject the whole batch before anything
+    /// is created; failures after that are reported per screen.
+    fn create_screens(
+        &mut self,
+        reqs: &[CreateScreenRequest],
+    ) -> Result<Vec<BatchScreenResult>, AppError> {
+        let mut names = std::collections::HashSet::new();
+        for req in reqs {
+            if !names.insert(req.name.as_str()) {
+                return Err(AppError::bad_request(format!(
+                    "screen {} listed twice",
+                    req.name
+                )));
+            }
+            req.validate_geometry().map_err(|e| {
+                AppError::bad_request(format!("screen {}: {}", req.name, e.message))
+            })?;
+        }
+        Ok(reqs
+            .iter()
+            .map(|req| {
+                let (screen, error) = match self.create_screen(req) {
+                    Ok(info) => (Some(info), None),
+                    Err(e) => (None, Some(e.message)),
+                };
+                BatchScreenResult {
+                    name: req.name.clone(),
+                    screen,
+                    error,
+                }
+            })
+            .collect())
     }
 
     fn destroy_screen(&mut self, name: &str) -> Result<(), AppError> {
@@ -257,66 +1714,80 @@ impl ServerState {
             .screens
             .remove(name)
             .ok_or_else(|| AppError::not_found(format!("screen {name} not found")))?;
+        CANCEL_GENERATIONS.lock().unwrap().remove(name);
+        self.screens_changed();
 
         self.with_env(|env| {
             let obj: &JObject = screen.instance.as_obj();
-            env.call_method(obj, "release", "()V", &[]).map_err(|e| {
-                if let Some(exc_msg) = get_exception_message(env) {
-                    AppError::new(format!("release failed: {exc_msg}"))
-                } else {
-                    AppError::new(format!("release failed: {e}"))
-                }
-            })?;
+            env.call_method(obj, "release", "()V", &[])
+                .map_err(|e| jni_error(env, "release failed", e))?;
             Ok(())
         })
     }
 
     fn list_screens(&self) -> Vec<ScreenInfo> {
-        self.screens
-            .iter()
-            .map(|(name, s)| ScreenInfo {
-                name: name.clone(),
-                display_id: s.display_id,
-                width: s.width,
-                height: s.height,
-                dpi: s.dpi,
-                assigned_package: s.assigned_package.clone(),
-            })
-            .collect()
+        self.screens.iter().map(|(name, s)| s.info(name)).collect()
     }
 
     fn screen_info(&mut self, name: &str) -> Result<ScreenInfo, AppError> {
-        let s = self.get_screen_mut(name)?;
-        Ok(ScreenInfo {
-            name: name.to_string(),
-            display_id: s.display_id,
-            width: s.width,
-            height: s.height,
-            dpi: s.dpi,
-            assigned_package: s.assigned_package.clone(),
-        })
+        Ok(self.get_screen_mut(name)?.info(name))
     }
 
-    fn screenshot(&mut self, name: &str) -> Result<Vec<u8>, AppError> {
+    fn screenshot(
+        &mut self,
+        name: &str,
+        scale: Option<f32>,
+        debug: bool,
+        crop_to: Option<&str>,
+        codec: Codec,
+        max_dim: Option<u32>,
+    ) -> Result<(Vec<u8>, f32, u64), AppError> {
+        let scale = check_encoding(scale, max_dim)?;
+        let crop = match crop_to {
+            Some(text) => {
+                let tree = a11y::Tree::parse(&self.accessibility_tree(name)?)
+                    .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+                let selector = a11y::Selector {
+                    text: text.to_string(),
+                };
+                let node = tree
+                    .find(&selector)
+                    .ok_or_else(|| AppError::not_found(format!("no node matching: {text}")))?;
+                let b = &node.bounds;
+                Some((b.left, b.top, b.right, b.bottom))
+            }
+            None => None,
+        };
         let screen = self.get_screen_mut(name)?;
         let width = screen.width as u32;
         let height = screen.height as u32;
         let instance = screen.instance.clone();
+        let marker = match screen.last_interaction {
+            Some((_, action)) if debug => action.point,
+            _ => None,
+        };
 
+        // On a static screen the cache only answers for the same encoding; anything else
+        // re-encodes the last frame
+        let encoding = Encoding {
+            scale,
+            max_dim,
+            codec,
+            marker,
+        };
+        let cache_hit = crop.is_none()
+            && screen
+                .last_jpeg
+                .as_ref()
+                .is_some_and(|cached| cached.encoding == encoding);
+        let mut frame_hash = None;
         let new_jpeg = self.with_env(|env| {
             let obj: &JObject = instance.as_obj();
-            let rgba_array: JByteArray = env
-                .call_method(obj, "takeScreenshotRGBA", "()[B", &[])
-                .map_err(|e| {
-                    if let Some(exc_msg) = get_exception_message(env) {
-                        AppError::new(format!("takeScreenshotRGBA call failed: {exc_msg}"))
-                    } else {
-                        AppError::new(format!("takeScreenshotRGBA call failed: {e}"))
-                    }
-                })?
-                .l()
-                .map_err(|e| AppError::new(format!("takeScreenshotRGBA result failed: {e}")))?
-                .into();
+            let mut rgba_array = take_frame(env, obj, "takeScreenshotRGBA")?;
+            let fresh = !rgba_array.is_null();
+            if !fresh && !cache_hit {
+                rgba_array = take_frame(env, obj, "takeScreenshotRGBAOrLast")?;
+            }
 
             if rgba_array.is_null() {
                 return Ok(None);
@@ -327,85 +1798,390 @@ impl ServerState {
                     .map_err(|e| AppError::new(format!("get array elements failed: {e}")))?
             };
 
-            let rgba: &[u8] = unsafe {
+            let mut rgba: &[u8] = unsafe {
                 std::slice::from_raw_parts(elements.as_ptr() as *const u8, elements.len())
             };
+            if fresh {
+                frame_hash = Some(image::frame_hash(rgba));
+            }
+
+            let swapped;
+            if frame_pixel_format(env, obj)? == PixelFormat::Bgra {
+                let mut copy = rgba.to_vec();
+                image::swap_red_blue(&mut copy);
+                swapped = copy;
+                rgba = &swapped;
+            }
+
+            let annotated;
+            if let Some((x, y)) = marker {
+                let mut copy = rgba.to_vec();
+                image::draw_crosshair(&mut copy, width, height, x, y);
+                annotated = copy;
+                rgba = &annotated;
+            }
+
+            let (mut frame_width, mut frame_height) = (width, height);
+            let cropped;
+            if let Some(bounds) = crop {
+                let (region, w, h) = image::crop(rgba, width, height, bounds)
+                    .ok_or_else(|| AppError::bad_request("node has no visible area"))?;
+                cropped = region;
+                rgba = &cropped;
+                (frame_width, frame_height) = (w, h);
+            }
 
-            let jpeg = encode_jpeg(rgba, width, height)?;
-            drop(elements);
+            let scale = match max_dim {
+                Some(max) => scale.min(max as f32 / frame_width.max(frame_height) as f32),
+                None => scale,
+            };
+            let encode_start = Instant::now();
+            let jpeg = if scale < 1.0 {
+                let (scaled, w, h) = image::downscale(rgba, frame_width, frame_height, scale);
+                drop(elements);
+                // Report the scale actually applied after rounding to whole pixels
+                (
+                    encode_image(&scaled, w, h, codec)?,
+                    w as f32 / frame_width as f32,
+                )
+            } else {
+                let jpeg = encode_image(rgba, frame_width, frame_height, codec)?;
+                drop(elements);
+                (jpeg, 1.0)
+            };
+            self.metrics
+                .screenshot_encode
+                .observe(encode_start.elapsed());
 
             Ok(Some(jpeg))
         })?;
 
+        self.get_screen_mut(name)?.frames.observe(frame_hash);
+
+        // A crop is not a view of the whole screen: keep it out of the cache and leave the
+        // scale used by `coord_space=screenshot` alone. It may still have consumed a new
+        // frame, after which the cached JPEG no longer shows the latest.
         let screen = self.get_screen_mut(name)?;
-        match new_jpeg {
-            Some(jpeg) => {
-                screen.last_jpeg = Some(jpeg);
+        let fresh = frame_hash.is_some();
+        if crop.is_some() {
+            if fresh {
+                screen.last_jpeg = None;
             }
-            None if screen.last_jpeg.is_none() => {
-                return Err(AppError::new("no frame available"));
+            let (jpeg, applied_scale) =
+                new_jpeg.ok_or_else(|| AppError::new("no frame available"))?;
+            let age_ms = if fresh { 0 } else { screen.frames.age_ms() };
+            return Ok((jpeg, applied_scale, age_ms));
+        }
+        if let Some((jpeg, applied_scale)) = new_jpeg {
+            // Re-encoding the held frame doesn't make it any newer
+            let captured_at = match screen.frames.received_at {
+                Some(at) if !fresh => at,
+                _ => Instant::now(),
+            };
+            screen.last_jpeg = Some(CachedJpeg {
+                encoding,
+                jpeg,
+                applied_scale,
+                captured_at,
+            });
+        }
+        let cached = screen
+            .last_jpeg
+            .as_ref()
+            .filter(|cached| cached.encoding == encoding)
+            .ok_or_else(|| AppError::new("no frame available"))?;
+        let age_ms = if fresh {
+            0
+        } else {
+            cached.captured_at.elapsed().as_millis() as u64
+        };
+        let result = (cached.jpeg.clone(), cached.applied_scale, age_ms);
+        screen.last_screenshot_scale = cached.applied_scale;
+        Ok(result)
+    }
+
+    /// Capture and drop `count` frames, pausing between them, so the next screenshot is
+    /// taken after a transition had time to play out.
+    fn settle_frames(&mut self, name: &str, count: u32) -> Result<(), AppError> {
+        if count > MAX_SETTLE_FRAMES {
+            return Err(AppError::bad_request(format!(
+                "settle_frames must be at most {MAX_SETTLE_FRAMES}"
+            )));
+        }
+        for _ in 0..count {
+            std::thread::sleep(SETTLE_FRAME_INTERVAL);
+            if let Some(rgba) = self.capture_rgba(name, "takeScreenshotRGBA")? {
+                let frames = &mut self.get_screen_mut(name)?.frames;
+                frames.observe(Some(image::frame_hash(&rgba)));
             }
-            None => {}
         }
-        Ok(screen.last_jpeg.clone().unwrap())
+        Ok(())
     }
 
-    fn raw_frame(&mut self, name: &str) -> Result<Option<(RawFrameInfo, Vec<u8>)>, AppError> {
+    /// The latest whole frame as unencoded RGBA, with its width, height, age in milliseconds
+    /// (0 when it just arrived) and the pixel layout the surface delivered it in.
+    fn screenshot_raw(
+        &mut self,
+        name: &str,
+    ) -> Result<(Vec<u8>, u32, u32, u64, PixelFormat), AppError> {
         let screen = self.get_screen_mut(name)?;
-        let width = screen.width;
-        let height = screen.height;
+        let (width, height) = (screen.width as u32, screen.height as u32);
         let instance = screen.instance.clone();
+        let format = self.with_env(|env| frame_pixel_format(env, instance.as_obj()))?;
+        let fresh = self.capture_rgba(name, "takeScreenshotRGBA")?;
+        let frames = &mut self.get_screen_mut(name)?.frames;
+        frames.observe(fresh.as_deref().map(image::frame_hash));
+        let (rgba, age_ms) = match fresh {
+            Some(rgba) => (Some(rgba), 0),
+            None => {
+                let age_ms = frames.age_ms();
+                (self.capture_rgba(name, "takeScreenshotRGBAOrLast")?, age_ms)
+            }
+        };
+        let rgba = rgba.ok_or_else(|| AppError::new("no frame available"))?;
+        Ok((rgba, width, height, age_ms, format))
+    }
 
+    /// A copy of the frame as RGBA, or `None` when `take` has no frame to give. Any frame
+    /// handed back may be newer than the cached JPEG, so that cache is dropped.
+    fn capture_rgba(&mut self, name: &str, take: &str) -> Result<Option<Vec<u8>>, AppError> {
+        let instance = self.get_screen_mut(name)?.instance.clone();
         let rgba = self.with_env(|env| {
             let obj: &JObject = instance.as_obj();
             let rgba_array: JByteArray = env
-                .call_method(obj, "takeScreenshotRGBA", "()[B", &[])
-                .map_err(|e| {
-                    if let Some(exc_msg) = get_exception_message(env) {
-                        AppError::new(format!("takeScreenshotRGBA call failed: {exc_msg}"))
-                    } else {
-                        AppError::new(format!("takeScreenshotRGBA call failed: {e}"))
-                    }
-                })?
+                .call_method(obj, take, "()[B", &[])
+                .map_err(|e| jni_error(env, &format!("{take} call failed"), e))?
                 .l()
-                .map_err(|e| AppError::new(format!("takeScreenshotRGBA result failed: {e}")))?
+                .map_err(|e| AppError::new(format!("{take} result failed: {e}")))?
                 .into();
 
             if rgba_array.is_null() {
                 return Ok(None);
             }
 
-            let bytes = env
+            let mut bytes = env
                 .convert_byte_array(&rgba_array)
-                .map_err(|e| AppError::new(format!("convert raw frame failed: {e}")))?;
+                .map_err(|e| AppError::new(format!("convert frame failed: {e}")))?;
+            if frame_pixel_format(env, obj)? == PixelFormat::Bgra {
+                image::swap_red_blue(&mut bytes);
+            }
             Ok(Some(bytes))
         })?;
+        if rgba.is_some() {
+            self.get_screen_mut(name)?.last_jpeg = None;
+        }
+        Ok(rgba)
+    }
+
+    /// Page through a scrollable node and stitch the viewports into one tall image.
+    /// Returns the image, the scale applied and how many viewports went into it.
+    fn long_screenshot(
+        &mut self,
+        name: &str,
+        scroll_node: Option<&str>,
+        scale: Option<f32>,
+        codec: Codec,
+        max_dim: Option<u32>,
+    ) -> Result<(Vec<u8>, f32, usize), AppError> {
+        let scale = check_encoding(scale, max_dim)?;
+        let tree = a11y::Tree::parse(&self.accessibility_tree(name)?)
+            .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+        let selector = scroll_node.map(|text| a11y::Selector {
+            text: text.to_string(),
+        });
+        let container = tree.scroll_container(selector.as_ref()).ok_or_else(|| {
+            AppError::not_found(match scroll_node {
+                Some(text) => format!("no scrollable node matching: {text}"),
+                None => "no scrollable node on screen".to_string(),
+            })
+        })?;
+        let screen = self.get_screen_mut(name)?;
+        let (width, height) = (screen.width as u32, screen.height as u32);
+        let b = &container.bounds;
+        let region = (
+            b.left.max(0),
+            b.top.max(0),
+            b.right.min(width as i32),
+            b.bottom.min(height as i32),
+        );
+        let (left, top, right, bottom) = region;
+        if right <= left || bottom <= top {
+            return Err(AppError::bad_request("scrollable node has no visible area"));
+        }
+
+        let capture = |s: &mut Self| {
+            s.capture_rgba(name, "takeScreenshotRGBAOrLast")?
+                .ok_or_else(|| AppError::new("no frame available"))
+        };
+        let row_len = width as usize * 4;
+        let rows = |frame: &[u8], from: u32, to: u32| {
+            frame[from as usize * row_len..to as usize * row_len].to_vec()
+        };
+        let mut frame = capture(self)?;
+        let mut stitched = rows(&frame, 0, bottom as u32);
+        let mut pages = 1;
+
+        // Drag from 80% to 20% of the node, slowly and resting at the end so it doesn't fling
+        let x = (left + right) as f32 / 2.0;
+        let span = (bottom - top) as f32;
+        let swipe = SwipeRequest {
+            x1: x,
+            y1: top as f32 + span * 0.8,
+            x2: x,
+            y2: top as f32 + span * 0.2,
+            duration_ms: LONG_SCREENSHOT_SWIPE_MS,
+            steps: None,
+            easing: Easing::Decelerate,
+            hold_ms: LONG_SCREENSHOT_HOLD_MS,
+        };
+        let tail = height - bottom as u32;
+        while pages < MAX_SCROLL_PAGES {
+            // Not recorded one by one: the client asked for a screenshot, not these swipes
+            self.inject_swipe(name, &swipe)?;
+            std::thread::sleep(LONG_SCREENSHOT_SETTLE);
+            let next = capture(self)?;
+            let shift =
+                match image::scroll_offset(&frame, &next, width, region, (span * 0.6) as u32) {
+                    Some(0) => break,
+                    Some(shift) => shift,
+                    None => {
+                        tracing::warn!(name, "scrolled content no longer lines up, stopping");
+                        break;
+                    }
+                };
+            let room = MAX_JPEG_DIM - tail - (stitched.len() / row_len) as u32;
+            let shift = shift.min(room);
+            stitched.extend(rows(&next, bottom as u32 - shift, bottom as u32));
+            frame = next;
+            pages += 1;
+            if shift == room {
+                break;
+            }
+        }
+        stitched.extend(rows(&frame, bottom as u32, height));
+        let params = serde_json::json!({ "scroll_node": scroll_node, "pages": pages });
+        self.get_screen_mut(name)?.log("screenshot_full", params);
+        let total_height = (stitched.len() / row_len) as u32;
+
+        let scale = match max_dim {
+            Some(max) => scale.min(max as f32 / width.max(total_height) as f32),
+            None => scale,
+        };
+        let encode_start = Instant::now();
+        let (jpeg, applied_scale) = if scale < 1.0 {
+            let (scaled, w, h) = image::downscale(&stitched, width, total_height, scale);
+            (encode_image(&scaled, w, h, codec)?, w as f32 / width as f32)
+        } else {
+            (encode_image(&stitched, width, total_height, codec)?, 1.0)
+        };
+        self.metrics
+            .screenshot_encode
+            .observe(encode_start.elapsed());
+        Ok((jpeg, applied_scale, pages))
+    }
+
+    fn raw_frame(
+        &mut self,
+        name: &str,
+        base: Option<u64>,
+    ) -> Result<Option<(RawFrameInfo, Vec<u8>)>, AppError> {
+        let screen = self.get_screen_mut(name)?;
+        let width = screen.width;
+        let height = screen.height;
 
+        let rgba = self.capture_rgba(name, "takeScreenshotRGBA")?;
+
+        let screen = self.get_screen_mut(name)?;
+        screen
+            .frames
+            .observe(rgba.as_deref().map(image::frame_hash));
         let Some(rgba) = rgba else {
             return Ok(None);
         };
 
-        let screen = self.get_screen_mut(name)?;
+        let prev = screen.last_raw_frame.replace(rgba.clone());
+        let base_pixels = prev.filter(|_| base == Some(screen.last_raw_frame_seq));
         screen.last_raw_frame_seq += 1;
+        let mut region = None;
+        let mut pixels = rgba;
+        if let Some(prev) = base_pixels {
+            let dirty = image::dirty_rect(&prev, &pixels, width as u32).unwrap_or((0, 0, 0, 0));
+            let (left, top, right, bottom) = dirty;
+            let area = ((right - left) * (bottom - top)) as f32;
+            if area <= (width * height) as f32 * MAX_REGION_FRACTION {
+                pixels = image::crop(&pixels, width as u32, height as u32, dirty)
+                    .map_or_else(Vec::new, |(region, _, _)| region);
+                region = Some((left, top, right - left, bottom - top));
+            }
+        }
         Ok(Some((
             RawFrameInfo {
                 width,
                 height,
-                stride: width * 4,
+                stride: region.map_or(width, |(_, _, w, _)| w) * 4,
                 bytes_per_pixel: 4,
                 seq: screen.last_raw_frame_seq,
                 timestamp_ms: std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .map_err(|e| AppError::new(format!("system time failed: {e}")))?
                     .as_millis() as u64,
+                region,
             },
-            rgba,
+            pixels,
         )))
     }
 
-    fn tap(&mut self, name: &str, x: f32, y: f32) -> Result<(), AppError> {
+    fn tap(&mut self, name: &str, x: f32, y: f32, space: CoordSpace) -> Result<(), AppError> {
         let screen = self.get_screen_mut(name)?;
-        let instance = screen.instance.clone();
+        let (x, y) = match space {
+            CoordSpace::Device => (x, y),
+            CoordSpace::Screenshot => (
+                x / screen.last_screenshot_scale,
+                y / screen.last_screenshot_scale,
+            ),
+        };
+        self.inject_tap(name, x, y)?;
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Tap,
+            Some((x, y)),
+            serde_json::json!({ "x": x, "y": y }),
+        );
+        Ok(())
+    }
+
+    /// Touch all `points` down together, then lift them, as a multi-finger tap.
+    fn multi_tap(&mut self, name: &str, points: &[TapRequest]) -> Result<(), AppError> {
+        let xy: Vec<f32> = points.iter().flat_map(|p| [p.x, p.y]).collect();
+        let instance = self.get_screen_mut(name)?.instance.clone();
+        self.with_env(|env| {
+            let obj: &JObject = instance.as_obj();
+            let array = env
+                .new_float_array(xy.len() as i32)
+                .map_err(|e| AppError::new(format!("new_float_array failed: {e}")))?;
+            env.set_float_array_region(&array, 0, &xy)
+                .map_err(|e| AppError::new(format!("set_float_array_region failed: {e}")))?;
+            call_instance_void(
+                env,
+                obj,
+                "injectMultiTap",
+                "([F)V",
+                &[JValue::Object(&array)],
+            )
+        })?;
+        let params: Vec<serde_json::Value> = points
+            .iter()
+            .map(|p| serde_json::json!({ "x": p.x, "y": p.y }))
+            .collect();
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::MultiTap,
+            Some((points[0].x, points[0].y)),
+            serde_json::json!({ "points": params }),
+        );
+        Ok(())
+    }
+
+    fn inject_tap(&mut self, name: &str, x: f32, y: f32) -> Result<(), AppError> {
+        let instance = self.get_screen_mut(name)?.instance.clone();
         self.with_env(|env| {
             let obj: &JObject = instance.as_obj();
             call_instance_void(
@@ -415,68 +2191,426 @@ impl ServerState {
                 "(FF)V",
                 &[JValue::Float(x), JValue::Float(y)],
             )
+        })
+    }
+
+    /// Tap through a known system dialog in `tree` if the screen opted in. Returns what
+    /// was dismissed. Not recorded as the last action, which stays the client's own.
+    fn auto_dismiss(&mut self, name: &str, tree: &a11y::Tree) -> Result<Option<String>, AppError> {
+        let Some(rules) = &self.get_screen_mut(name)?.dismiss_rules else {
+            return Ok(None);
+        };
+        let Some((rule, button)) = a11y::find_dismissal(tree, rules) else {
+            return Ok(None);
+        };
+        let b = &button.bounds;
+        let (x, y) = (
+            (b.left + b.right) as f32 / 2.0,
+            (b.top + b.bottom) as f32 / 2.0,
+        );
+        let dismissed = format!("tapped \"{}\" on \"{}\" dialog", rule.tap, rule.when.trim());
+        self.inject_tap(name, x, y)?;
+        self.get_screen_mut(name)?.log(
+            "auto_dismiss",
+            serde_json::json!({ "x": x, "y": y, "dismissed": dismissed }),
+        );
+        tracing::info!(screen = name, action = %dismissed, "auto-dismissed system dialog");
+        Ok(Some(dismissed))
+    }
+
+    fn swipe(&mut self, name: &str, req: &SwipeRequest) -> Result<(), AppError> {
+        self.inject_swipe(name, req)?;
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Swipe,
+            Some((req.x1, req.y1)),
+            serde_json::json!({
+                "x1": req.x1,
+                "y1": req.y1,
+                "x2": req.x2,
+                "y2": req.y2,
+                "duration_ms": req.duration_ms,
+                "hold_ms": req.hold_ms,
+            }),
+        );
+        Ok(())
+    }
+
+    /// The swipe without recording it, for scrolls the server makes on its own behalf.
+    fn inject_swipe(&mut self, name: &str, req: &SwipeRequest) -> Result<(), AppError> {
+        if req.steps.is_some_and(|steps| steps > MAX_SWIPE_STEPS) {
+            return Err(AppError::bad_request(format!(
+                "steps must be at most {MAX_SWIPE_STEPS}"
+            )));
+        }
+        if !(0..=MAX_SWIPE_HOLD_MS).contains(&req.hold_ms) {
+            return Err(AppError::bad_request(format!(
+                "hold_ms must be 0 to {MAX_SWIPE_HOLD_MS}"
+            )));
+        }
+        if !(0..=MAX_SWIPE_DURATION_MS).contains(&req.duration_ms) {
+            return Err(AppError::bad_request(format!(
+                "duration_ms must be 0 to {MAX_SWIPE_DURATION_MS}"
+            )));
+        }
+        let path = swipe_path(req);
+        let xy: Vec<f32> = path.iter().flat_map(|&(x, y, _)| [x, y]).collect();
+        let times: Vec<i64> = path.iter().map(|&(_, _, at)| at).collect();
+        let instance = self.get_screen_mut(name)?.instance.clone();
+        self.with_env(|env| {
+            let obj: &JObject = instance.as_obj();
+            let xy_array = env
+                .new_float_array(jsize(xy.len())?)
+                .map_err(|e| AppError::new(format!("new_float_array failed: {e}")))?;
+            env.set_float_array_region(&xy_array, 0, &xy)
+                .map_err(|e| AppError::new(format!("set_float_array_region failed: {e}")))?;
+            let times_array = env
+                .new_long_array(jsize(times.len())?)
+                .map_err(|e| AppError::new(format!("new_long_array failed: {e}")))?;
+            env.set_long_array_region(&times_array, 0, &times)
+                .map_err(|e| AppError::new(format!("set_long_array_region failed: {e}")))?;
+            call_instance_void(
+                env,
+                obj,
+                "injectSwipePath",
+                "([F[J)V",
+                &[JValue::Object(&xy_array), JValue::Object(&times_array)],
+            )
+        })
+    }
+
+    fn input_text(&mut self, name: &str, text: &str, paste: bool) -> Result<(), AppError> {
+        let screen = self.get_screen_mut(name)?;
+        let instance = screen.instance.clone();
+        self.with_env(|env| {
+            let obj: &JObject = instance.as_obj();
+            let jtext = env
+                .new_string(text)
+                .map_err(|e| AppError::new(format!("new_string failed: {e}")))?;
+            call_instance_void(
+                env,
+                obj,
+                if paste { "pasteText" } else { "injectText" },
+                "(Ljava/lang/String;)V",
+                &[JValue::Object(&jtext)],
+            )
+        })?;
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Text,
+            None,
+            text_params(text, paste),
+        );
+        Ok(())
+    }
+
+    fn key(&mut self, name: &str, keycode: i32) -> Result<(), AppError> {
+        let screen = self.get_screen_mut(name)?;
+        let instance = screen.instance.clone();
+        self.with_env(|env| {
+            let obj: &JObject = instance.as_obj();
+            call_instance_void(env, obj, "injectKey", "(I)V", &[JValue::Int(keycode)])
+        })?;
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Key,
+            None,
+            serde_json::json!({ "keycode": keycode }),
+        );
+        Ok(())
+    }
+
+    fn key_combo(&mut self, name: &str, keycodes: &[i32]) -> Result<(), AppError> {
+        if keycodes.is_empty() {
+            return Err(AppError::bad_request(
+                "key combo needs at least one keycode",
+            ));
+        }
+        let screen = self.get_screen_mut(name)?;
+        let instance = screen.instance.clone();
+        self.with_env(|env| {
+            let obj: &JObject = instance.as_obj();
+            let array = env
+                .new_int_array(keycodes.len() as i32)
+                .map_err(|e| AppError::new(format!("new_int_array failed: {e}")))?;
+            env.set_int_array_region(&array, 0, keycodes)
+                .map_err(|e| AppError::new(format!("set_int_array_region failed: {e}")))?;
+            call_instance_void(
+                env,
+                obj,
+                "injectKeyCombo",
+                "([I)V",
+                &[JValue::Object(&array)],
+            )
         })?;
-        self.screens.get_mut(name).unwrap().last_interaction = Some(Instant::now());
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Key,
+            None,
+            serde_json::json!({ "keycodes": keycodes }),
+        );
         Ok(())
     }
 
-    fn swipe(&mut self, name: &str, req: &SwipeRequest) -> Result<(), AppError> {
-        let screen = self.get_screen_mut(name)?;
-        let instance = screen.instance.clone();
-        self.with_env(|env| {
-            let obj: &JObject = instance.as_obj();
-            call_instance_void(
-                env,
-                obj,
-                "injectSwipe",
-                "(FFFFJ)V",
-                &[
-                    JValue::Float(req.x1),
-                    JValue::Float(req.y1),
-                    JValue::Float(req.x2),
-                    JValue::Float(req.y2),
-                    JValue::Long(req.duration_ms),
-                ],
-            )
-        })?;
-        self.screens.get_mut(name).unwrap().last_interaction = Some(Instant::now());
-        Ok(())
+    /// Focus a field by tapping it, then clear, type and submit with nothing in between.
+    /// Returns the node that was tapped.
+    fn fill(&mut self, name: &str, req: &FillRequest) -> Result<a11y::Node, AppError> {
+        let tree = a11y::Tree::parse(&self.accessibility_tree(name)?)
+            .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+        let node = match tree.field(&req.target) {
+            Ok(node) => node.clone(),
+            Err(0) => {
+                return Err(AppError::not_found(format!(
+                    "no node matching: {}",
+                    req.target
+                )));
+            }
+            Err(count) => {
+                return Err(AppError::bad_request(format!(
+                    "{count} nodes match {:?}; use a more specific label",
+                    req.target
+                )));
+            }
+        };
+        let b = &node.bounds;
+        let x = (b.left + b.right) as f32 / 2.0;
+        let y = (b.top + b.bottom) as f32 / 2.0;
+        self.tap(name, x, y, CoordSpace::Device)?;
+        if req.clear {
+            self.key_combo(name, &[KEYCODE_CTRL_LEFT, KEYCODE_A])?;
+            self.key(name, KEYCODE_DEL)?;
+        }
+        if !req.text.is_empty() {
+            self.input_text(name, &req.text, req.paste)?;
+        }
+        if req.submit {
+            self.key(name, KEYCODE_ENTER)?;
+        }
+        Ok(node)
+    }
+
+    fn ime(&mut self, name: &str) -> Result<ImeInfo, AppError> {
+        let screen = self.get_screen_mut(name)?;
+        let (display_id, height) = (screen.display_id, screen.height);
+        let bridge = self.a11y_bridge.clone();
+        let bounds = self.with_env(|env| {
+            let result = env
+                .call_method(
+                    bridge.as_obj(),
+                    "imeBounds",
+                    "(I)[I",
+                    &[JValue::Int(display_id)],
+                )
+                .map_err(|e| jni_error(env, "imeBounds call failed", e))?
+                .l()
+                .map_err(|e| AppError::new(format!("imeBounds result failed: {e}")))?;
+            if result.is_null() {
+                return Ok(None);
+            }
+            let mut ltrb = [0; 4];
+            env.get_int_array_region(jni::objects::JIntArray::from(result), 0, &mut ltrb)
+                .map_err(|e| AppError::new(format!("imeBounds decode failed: {e}")))?;
+            let [left, top, right, bottom] = ltrb;
+            Ok(Some(a11y::Bounds {
+                left,
+                top,
+                right,
+                bottom,
+            }))
+        })?;
+        Ok(ImeInfo {
+            shown: bounds.is_some(),
+            height: bounds.as_ref().map_or(0, |b| (height - b.top).max(0)),
+            bounds,
+        })
+    }
+
+    /// Press BACK if the keyboard is shown, which closes it without leaving the screen,
+    /// and report the keyboard once it is gone or after [`IME_HIDE_TIMEOUT`].
+    fn hide_ime(&mut self, name: &str) -> Result<ImeInfo, AppError> {
+        let ime = self.ime(name)?;
+        if !ime.shown {
+            return Ok(ime);
+        }
+        self.key(name, KEYCODE_BACK)?;
+        let start = Instant::now();
+        loop {
+            let ime = self.ime(name)?;
+            if !ime.shown || start.elapsed() >= IME_HIDE_TIMEOUT {
+                return Ok(ime);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
     }
 
-    fn input_text(&mut self, name: &str, text: &str) -> Result<(), AppError> {
-        let screen = self.get_screen_mut(name)?;
-        let instance = screen.instance.clone();
-        self.with_env(|env| {
-            let obj: &JObject = instance.as_obj();
+    fn select(&mut self, name: &str, req: &SelectRequest) -> Result<(), AppError> {
+        let display_id = self.get_screen_mut(name)?.display_id;
+        let bridge = self.a11y_bridge.clone();
+        let failure = self.with_env(|env| {
+            let obj: &JObject = bridge.as_obj();
             let jtext = env
-                .new_string(text)
+                .new_string(&req.text)
                 .map_err(|e| AppError::new(format!("new_string failed: {e}")))?;
-            call_instance_void(
-                env,
-                obj,
-                "injectText",
-                "(Ljava/lang/String;)V",
-                &[JValue::Object(&jtext)],
-            )
+            let result = env
+                .call_method(
+                    obj,
+                    "setSelection",
+                    "(ILjava/lang/String;II)Ljava/lang/String;",
+                    &[
+                        JValue::Int(display_id),
+                        JValue::Object(&jtext),
+                        JValue::Int(req.start),
+                        JValue::Int(req.end),
+                    ],
+                )
+                .map_err(|e| jni_error(env, "setSelection call failed", e))?
+                .l()
+                .map_err(|e| AppError::new(format!("setSelection result failed: {e}")))?;
+            if result.is_null() {
+                return Ok(None);
+            }
+            let message: String = env
+                .get_string(&JString::from(result))
+                .map_err(|e| AppError::new(format!("setSelection decode failed: {e}")))?
+                .into();
+            Ok(Some(message))
         })?;
-        self.screens.get_mut(name).unwrap().last_interaction = Some(Instant::now());
+        if let Some(message) = failure {
+            return Err(AppError::bad_request(message));
+        }
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Select,
+            None,
+            serde_json::json!({ "text": req.text, "start": req.start, "end": req.end }),
+        );
         Ok(())
     }
 
-    fn key(&mut self, name: &str, keycode: i32) -> Result<(), AppError> {
-        let screen = self.get_screen_mut(name)?;
-        let instance = screen.instance.clone();
-        self.with_env(|env| {
-            let obj: &JObject = instance.as_obj();
-            call_instance_void(env, obj, "injectKey", "(I)V", &[JValue::Int(keycode)])
+    /// Move input focus and return the node that now holds it.
+    fn focus(&mut self, name: &str, direction: FocusDirection) -> Result<a11y::Node, AppError> {
+        let display_id = self.get_screen_mut(name)?.display_id;
+        let bridge = self.a11y_bridge.clone();
+        let failure = self.with_env(|env| {
+            let obj: &JObject = bridge.as_obj();
+            let result = env
+                .call_method(
+                    obj,
+                    "focusSearch",
+                    "(II)Ljava/lang/String;",
+                    &[
+                        JValue::Int(display_id),
+                        JValue::Int(direction.view_constant()),
+                    ],
+                )
+                .map_err(|e| jni_error(env, "focusSearch call failed", e))?
+                .l()
+                .map_err(|e| AppError::new(format!("focusSearch result failed: {e}")))?;
+            if result.is_null() {
+                return Ok(None);
+            }
+            let message: String = env
+                .get_string(&JString::from(result))
+                .map_err(|e| AppError::new(format!("focusSearch decode failed: {e}")))?
+                .into();
+            Ok(Some(message))
         })?;
-        self.screens.get_mut(name).unwrap().last_interaction = Some(Instant::now());
-        Ok(())
+        if let Some(message) = failure {
+            return Err(AppError::bad_request(message));
+        }
+        self.screens.get_mut(name).unwrap().record(
+            ActionKind::Focus,
+            None,
+            serde_json::json!({ "direction": direction }),
+        );
+        let tree = self.ui_tree(name)?;
+        tree.nodes()
+            .find(|node| node.focused)
+            .cloned()
+            .ok_or_else(|| AppError::new("focus moved but no focused node in the tree"))
     }
 
     fn accessibility_tree(&mut self, name: &str) -> Result<String, AppError> {
+        if self.get_screen_mut(name)?.prefetched_tree.is_some() {
+            // A frame since the prefetch means the UI may have moved on without an action
+            if let Some(rgba) = self.capture_rgba(name, "takeScreenshotRGBA")? {
+                let frames = &mut self.get_screen_mut(name)?.frames;
+                frames.observe(Some(image::frame_hash(&rgba)));
+            }
+        }
         let screen = self.get_screen_mut(name)?;
-        let display_id = screen.display_id;
+        if let Some(tree) = screen.prefetched_tree.take()
+            && tree.current(&screen.frames)
+        {
+            return Ok(tree.json);
+        }
+        self.dump_tree(name, false)
+    }
+
+    /// Capture a default screenshot and dump the tree right after a launch settled, so
+    /// the inspection that usually follows doesn't pay for them. The screenshot goes
+    /// first: a frame arriving during the dump then counts against the tree.
+    fn prefetch(&mut self, name: &str) -> Result<(), AppError> {
+        self.screenshot(name, None, false, None, Codec::default(), None)?;
+        let frame_seq = self.get_screen_mut(name)?.frames.received;
+        let json = self.dump_tree(name, false)?;
+        self.get_screen_mut(name)?.prefetched_tree = Some(PrefetchedTree {
+            at: Instant::now(),
+            frame_seq,
+            json,
+        });
+        Ok(())
+    }
+
+    /// Notifications in the shade. The shade is system UI on the default display, so this
+    /// is the same for every screen.
+    fn notifications(&mut self, name: &str) -> Result<Vec<a11y::Notification>, AppError> {
+        self.get_screen_mut(name)?;
+        let json = self.dump_display(DEFAULT_DISPLAY, true)?;
+        let tree = a11y::Tree::parse(&json)
+            .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+        Ok(tree.notifications())
+    }
+
+    /// Expand the shade and list notifications once rows show up, or after the timeout.
+    fn open_notifications(&mut self, name: &str) -> Result<Vec<a11y::Notification>, AppError> {
+        self.get_screen_mut(name)?;
+        shell::run(&["cmd", "statusbar", "expand-notifications"])?;
+        let start = std::time::Instant::now();
+        loop {
+            let notifications = self.notifications(name)?;
+            if !notifications.is_empty() || start.elapsed() >= SHADE_SETTLE_TIMEOUT {
+                return Ok(notifications);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    /// Inspect the screen once an interaction settled: note any crash/ANR dialog, then tap
+    /// through known system dialogs if the screen opted in.
+    fn settled(&mut self, name: &str) -> Result<Settled, AppError> {
+        let tree = self.ui_tree(name)?;
+        let crash = a11y::detect_crash_dialog(&tree);
+        let dismissed = self.auto_dismiss(name, &tree)?;
+        Ok(Settled {
+            tree,
+            crash,
+            dismissed,
+        })
+    }
+
+    /// Parsed tree including system windows, for crash detection and before/after diffs.
+    fn ui_tree(&mut self, name: &str) -> Result<a11y::Tree, AppError> {
+        let json = self.dump_tree(name, true)?;
+        a11y::Tree::parse(&json).map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))
+    }
+
+    /// The tree without a heartbeat, for `?peek=true`.
+    fn peek_tree(&self, name: &str) -> Result<String, AppError> {
+        self.dump_display(self.get_screen(name)?.display_id, false)
+    }
+
+    fn dump_tree(&mut self, name: &str, include_system: bool) -> Result<String, AppError> {
+        let display_id = self.get_screen_mut(name)?.display_id;
+        self.dump_display(display_id, include_system)
+    }
+
+    fn dump_display(&self, display_id: i32, include_system: bool) -> Result<String, AppError> {
         let bridge = self.a11y_bridge.clone();
         self.with_env(|env| {
             let obj: &JObject = bridge.as_obj();
@@ -484,16 +2618,10 @@ impl ServerState {
                 .call_method(
                     obj,
                     "dumpDisplayJson",
-                    "(I)Ljava/lang/String;",
-                    &[JValue::Int(display_id)],
+                    "(IZ)Ljava/lang/String;",
+                    &[JValue::Int(display_id), JValue::Bool(include_system.into())],
                 )
-                .map_err(|e| {
-                    if let Some(exc_msg) = get_exception_message(env) {
-                        AppError::new(format!("dumpDisplayJson call failed: {exc_msg}"))
-                    } else {
-                        AppError::new(format!("dumpDisplayJson call failed: {e}"))
-                    }
-                })?
+                .map_err(|e| jni_error(env, "dumpDisplayJson call failed", e))?
                 .l()
                 .map_err(|e| AppError::new(format!("dumpDisplayJson result failed: {e}")))?;
             if json_obj.is_null() {
@@ -508,23 +2636,48 @@ impl ServerState {
         })
     }
 
-    fn launch(&mut self, name: &str) -> Result<(), AppError> {
+    /// Every window on the screen's display, topmost first, as JSON without node trees.
+    fn windows(&mut self, name: &str) -> Result<String, AppError> {
+        let display_id = self.get_screen_mut(name)?.display_id;
+        let bridge = self.a11y_bridge.clone();
+        self.with_env(|env| {
+            let json_obj = env
+                .call_method(
+                    bridge.as_obj(),
+                    "dumpWindowsJson",
+                    "(I)Ljava/lang/String;",
+                    &[JValue::Int(display_id)],
+                )
+                .map_err(|e| jni_error(env, "dumpWindowsJson call failed", e))?
+                .l()
+                .map_err(|e| AppError::new(format!("dumpWindowsJson result failed: {e}")))?;
+            if json_obj.is_null() {
+                return Err(AppError::new("dumpWindowsJson returned null"));
+            }
+            let json: String = env
+                .get_string(&JString::from(json_obj))
+                .map_err(|e| AppError::new(format!("dumpWindowsJson decode failed: {e}")))?
+                .into();
+            Ok(json)
+        })
+    }
+
+    fn launch(
+        &mut self,
+        name: &str,
+        intent: Option<&LaunchIntentRequest>,
+    ) -> Result<AmStart, AppError> {
         let screen = self.get_screen_mut(name)?;
         let display_id = screen.display_id;
-        let package = &screen.assigned_package;
-
-        let resolve = Command::new("cmd")
-            .args(["package", "resolve-activity", "--brief", package])
-            .output()
-            .map_err(|e| AppError::new(format!("resolve-activity failed: {e}")))?;
+        let package = screen.package()?;
 
-        let resolve_out = String::from_utf8_lossy(&resolve.stdout);
-        let component = resolve_out
-            .lines()
-            .filter(|line| line.contains('/'))
-            .last()
-            .map(|line| line.trim().to_string())
-            .ok_or_else(|| AppError::new(format!("no activity found for {package}")))?;
+        let mut intent_args = match intent {
+            Some(intent) => intent.am_args(package)?,
+            None => Vec::new(),
+        };
+        if intent.and_then(|i| i.activity.as_ref()).is_none() {
+            intent_args.extend(["-n".to_string(), launcher_component(package)?]);
+        }
 
         let start = Command::new("am")
             .args([
@@ -532,66 +2685,167 @@ impl ServerState {
                 "--activity-clear-task",
                 "--display",
                 &display_id.to_string(),
-                "-n",
-                &component,
             ])
+            .args(&intent_args)
             .output()
             .map_err(|e| AppError::new(format!("am start failed: {e}")))?;
+        let params = serde_json::json!({
+            "package": package,
+            "activity": intent.and_then(|i| i.activity.as_ref()),
+            "action": intent.and_then(|i| i.action.as_ref()),
+            "data": intent.and_then(|i| i.data.as_ref()),
+        });
+        let outcome = am_start_outcome(
+            start.status.success(),
+            &String::from_utf8_lossy(&start.stdout),
+            &String::from_utf8_lossy(&start.stderr),
+        )?;
+        self.get_screen_mut(name)?.log("launch", params);
+        Ok(outcome)
+    }
 
-        if !start.status.success() {
-            let stdout = String::from_utf8_lossy(&start.stdout);
-            let stderr = String::from_utf8_lossy(&start.stderr);
-            return Err(AppError::new(format!(
-                "am start failed: {} {}",
-                stdout.trim(),
-                stderr.trim()
-            )));
-        }
-
-        let start_stdout = String::from_utf8_lossy(&start.stdout);
-        if start_stdout.contains("Activity not started") {
-            return Err(AppError::new(format!(
-                "am start did not launch a new task: {}",
-                start_stdout.trim()
-            )));
+    fn open_url(&mut self, name: &str, req: OpenUrlRequest) -> Result<(), AppError> {
+        let screen = self.get_screen_mut(name)?;
+        let display_id = screen.display_id;
+        let package = screen.package()?;
+        let scoped_to_package = req.activity.is_none();
+        let intent = LaunchIntentRequest {
+            activity: req.activity,
+            action: Some("android.intent.action.VIEW".into()),
+            data: Some(req.url),
+            extras: req.extras,
+        };
+        let params = serde_json::json!({ "url": intent.data, "activity": intent.activity });
+        let mut intent_args = intent.am_args(package)?;
+        if scoped_to_package {
+            intent_args.extend(["-p".to_string(), package.to_string()]);
         }
-
+        let start = Command::new("am")
+            .args(["start", "--display", &display_id.to_string()])
+            .args(&intent_args)
+            .output()
+            .map_err(|e| AppError::new(format!("am start failed: {e}")))?;
+        am_start_outcome(
+            start.status.success(),
+            &String::from_utf8_lossy(&start.stdout),
+            &String::from_utf8_lossy(&start.stderr),
+        )?;
+        self.get_screen_mut(name)?.log("open_url", params);
         Ok(())
     }
 
-    fn open_url(&mut self, name: &str, url: &str) -> Result<(), AppError> {
+    fn foreground(&mut self, name: &str) -> Result<ForegroundInfo, AppError> {
+        let display_id = self.get_screen_mut(name)?.display_id;
+        let output = Command::new("dumpsys")
+            .args(["activity", "activities"])
+            .output()
+            .map_err(|e| AppError::new(format!("dumpsys activity failed: {e}")))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (package, activity) = dumpsys::foreground_activity(&stdout, display_id)
+            .ok_or_else(|| AppError::not_found(format!("no activity on display {display_id}")))?;
+        Ok(ForegroundInfo { package, activity })
+    }
+
+    /// Move the bound app's running task onto this screen's display, for an app started
+    /// out-of-band (e.g. on the default display) instead of through `launch`.
+    fn adopt(&mut self, name: &str) -> Result<ForegroundInfo, AppError> {
         let screen = self.get_screen_mut(name)?;
         let display_id = screen.display_id;
-        let package = &screen.assigned_package;
-        let start = Command::new("am")
-            .args([
-                "start",
-                "--display",
+        let package = screen.package()?.to_string();
+        let activities = shell::run(&["dumpsys", "activity", "activities"])?;
+        let (from, task_id) = dumpsys::package_task(&activities, &package).ok_or_else(|| {
+            AppError::not_found(format!("{package} has no running task; launch it instead"))
+        })?;
+        if from != display_id {
+            shell::run(&[
+                "am",
+                "display",
+                "move-stack",
+                &task_id.to_string(),
                 &display_id.to_string(),
-                "-a",
-                "android.intent.action.VIEW",
-                "-d",
-                url,
-                "-n",
-                &format!("{package}/.MainActivity"),
-            ])
-            .output()
-            .map_err(|e| AppError::new(format!("am start failed: {e}")))?;
-        if !start.status.success() {
-            let stdout = String::from_utf8_lossy(&start.stdout);
-            let stderr = String::from_utf8_lossy(&start.stderr);
-            return Err(AppError::new(format!(
-                "am start failed: {} {}",
-                stdout.trim(),
-                stderr.trim()
+            ])?;
+        }
+        self.get_screen_mut(name)?.log(
+            "adopt",
+            serde_json::json!({ "package": package, "task_id": task_id, "from_display": from }),
+        );
+        let foreground = self.foreground(name).ok();
+        match foreground {
+            Some(info) if info.package == package => Ok(info),
+            _ => Err(AppError::new(format!(
+                "task {task_id} of {package} did not move to display {display_id}; \
+                 activities with resizeableActivity=false can't leave their display"
+            ))),
+        }
+    }
+
+    fn app_state(&mut self, name: &str) -> Result<AppProcessState, AppError> {
+        let package = self.get_screen_mut(name)?.assigned_package.clone();
+        if package.is_empty() {
+            return Err(AppError::not_found(format!(
+                "screen {name} has no bound package"
             )));
         }
-        Ok(())
+        let installed = self.list_installed_packages(&package)?.contains(&package);
+        // `pidof` exits non-zero with no output when nothing runs under that name
+        let output = Command::new("pidof")
+            .arg(&package)
+            .output()
+            .map_err(|e| AppError::new(format!("pidof failed: {e}")))?;
+        let pid = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .next()
+            .and_then(|pid| pid.parse().ok());
+        let foreground = match self.foreground(name) {
+            Ok(fg) => fg.package == package,
+            Err(e) if e.status == StatusCode::NOT_FOUND => false,
+            Err(e) => return Err(e),
+        };
+        Ok(AppProcessState {
+            package,
+            installed,
+            running: pid.is_some(),
+            foreground,
+            pid,
+        })
     }
 
-    fn wait_for_idle_args(&mut self, name: &str) -> Result<(Arc<JavaVM>, GlobalRef), AppError> {
+    fn wait_plan(
+        &mut self,
+        name: &str,
+        strategy: WaitStrategy,
+        fixed_ms: Option<u64>,
+    ) -> Result<WaitPlan, AppError> {
+        let budget_ms = match strategy {
+            WaitStrategy::None => return Ok(WaitPlan::Skip),
+            WaitStrategy::Idle => 2500,
+            WaitStrategy::Fixed => fixed_ms.unwrap_or(DEFAULT_FIXED_WAIT_MS),
+        };
+        let Some((last_interaction, _)) = self.get_screen_mut(name)?.last_interaction else {
+            return Ok(WaitPlan::Skip);
+        };
+        let global_timeout =
+            std::time::Duration::from_millis(budget_ms).saturating_sub(last_interaction.elapsed());
+        Ok(if global_timeout.is_zero() {
+            WaitPlan::Skip
+        } else if strategy == WaitStrategy::Fixed {
+            WaitPlan::Sleep(global_timeout)
+        } else {
+            let (jvm, bridge, cancel) = self.wait_for_idle_args(name)?;
+            WaitPlan::Idle(jvm, bridge, cancel, global_timeout)
+        })
+    }
+
+    fn wait_for_idle_args(
+        &mut self,
+        name: &str,
+    ) -> Result<(Arc<JavaVM>, GlobalRef, WaitCancel), AppError> {
         self.get_screen_mut(name)?;
-        Ok((self.jvm.clone(), self.a11y_bridge.clone()))
+        Ok((
+            self.jvm.clone(),
+            self.a11y_bridge.clone(),
+            WaitCancel::new(name),
+        ))
     }
 
     fn heartbeat(&mut self, name: &str) -> Result<(), AppError> {
@@ -601,18 +2855,28 @@ impl ServerState {
     }
 
     fn reap_dead_screens(&mut self) {
-        let dead: Vec<String> = self
-            .screens
-            .iter()
-            .filter(|(_, s)| {
-                s.last_heartbeat.elapsed() > std::time::Duration::from_secs(s.timeout_secs)
-            })
-            .map(|(name, _)| name.clone())
-            .collect();
+        let now = Instant::now();
+        let mut dead = Vec::new();
+        for (name, screen) in &mut self.screens {
+            if screen.expiry().is_none_or(|expiry| now <= expiry) {
+                continue;
+            }
+            // A request still running on the screen (a long recording or wait-for) is
+            // activity too; releasing the display would pull it out from under that request
+            if SCREEN_OPS.running(name) > 0 {
+                tracing::debug!(name = %name, "postponing reap of a screen with a running request");
+                screen.last_heartbeat = now;
+                continue;
+            }
+            dead.push(name.clone());
+        }
+        let reaped_any = !dead.is_empty();
 
         for name in dead {
             if let Some(screen) = self.screens.remove(&name) {
+                CANCEL_GENERATIONS.lock().unwrap().remove(&name);
                 tracing::info!(name = %name, display_id = screen.display_id, "reaping dead screen (timeout {}s)", screen.timeout_secs);
+                self.metrics.screens_reaped.fetch_add(1, Ordering::Relaxed);
                 let _ = self.with_env(|env| {
                     let obj: &JObject = screen.instance.as_obj();
                     let _ = env.call_method(obj, "release", "()V", &[]);
@@ -623,43 +2887,207 @@ impl ServerState {
                 });
             }
         }
+
+        let soonest = self
+            .screens
+            .values()
+            .filter_map(VirtualScreen::expiry)
+            .min();
+        self.soonest_expiry.set(soonest);
+        if reaped_any {
+            self.screens_changed();
+        }
     }
 
-    fn stop(&mut self, name: &str) -> Result<(), AppError> {
-        let screen = self.get_screen_mut(name)?;
-        let package = &screen.assigned_package;
+    fn screens_changed(&self) {
+        self.metrics
+            .screens
+            .store(self.screens.len() as u64, Ordering::Relaxed);
+        self.persist();
+    }
 
-        let status = Command::new("am")
-            .args(["force-stop", package])
-            .status()
-            .map_err(|e| AppError::new(format!("am force-stop failed: {e}")))?;
+    /// Save screen definitions if persistence is on. Unbound screens are left out: they
+    /// bind from the client's `--package` when next used anyway.
+    fn persist(&self) {
+        let Some(path) = &self.persist_path else {
+            return;
+        };
+        let screens: Vec<persist::PersistedScreen> = self
+            .screens
+            .iter()
+            .filter(|(_, s)| !s.assigned_package.is_empty())
+            .map(|(name, s)| persist::PersistedScreen {
+                name: name.clone(),
+                width: s.width,
+                height: s.height,
+                dpi: s.dpi,
+                timeout_secs: s.timeout_secs,
+                package: s.assigned_package.clone(),
+                dismiss_rules: s.dismiss_rules.clone(),
+            })
+            .collect();
+        if let Err(e) = persist::save(path, &screens) {
+            tracing::warn!(error = %e, "persisting screens failed");
+        }
+    }
 
-        if !status.success() {
-            return Err(AppError::new(format!("am force-stop failed for {package}")));
+    /// Recreate screens saved by a previous daemon. Displays are new, so app state is lost.
+    fn restore_screens(&mut self) {
+        let Some(path) = self.persist_path.clone() else {
+            return;
+        };
+        for saved in persist::load(&path) {
+            let req = CreateScreenRequest {
+                name: saved.name,
+                width: saved.width,
+                height: saved.height,
+                dpi: saved.dpi,
+                timeout_secs: Some(saved.timeout_secs),
+                package: saved.package,
+                auto_dismiss: Some(saved.dismiss_rules.is_some()),
+                dismiss_rules: saved.dismiss_rules,
+            };
+            match self.create_screen(&req) {
+                Ok(info) => {
+                    tracing::info!(name = %req.name, package = %info.assigned_package, "restored screen")
+                }
+                Err(e) => {
+                    tracing::warn!(name = %req.name, error = %e.message, "restoring screen failed")
+                }
+            }
         }
+    }
 
+    fn stop(&mut self, name: &str) -> Result<(), AppError> {
+        let package = self.get_screen_mut(name)?.package()?.to_string();
+        force_stop(&package)?;
+        let params = serde_json::json!({ "package": package });
+        self.get_screen_mut(name)?.log("stop", params);
         Ok(())
     }
 
     fn reset(&mut self, name: &str) -> Result<(), AppError> {
-        let screen = self.get_screen_mut(name)?;
-        let package = &screen.assigned_package;
+        let package = self.get_screen_mut(name)?.package()?.to_string();
+        clear_package(&package)?;
+        let params = serde_json::json!({ "package": package });
+        self.get_screen_mut(name)?.log("reset", params);
+        Ok(())
+    }
 
-        let output = Command::new("pm")
-            .args(["clear", package])
-            .output()
-            .map_err(|e| AppError::new(format!("pm clear failed: {e}")))?;
+    /// The most recent `limit` history entries (all kept ones by default), oldest first.
+    fn history(&mut self, name: &str, limit: Option<usize>) -> Result<Vec<HistoryEntry>, AppError> {
+        let history = &self.get_screen_mut(name)?.history;
+        let skip = history.len().saturating_sub(limit.unwrap_or(HISTORY_LEN));
+        Ok(history.iter().skip(skip).cloned().collect())
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(AppError::new(format!(
-                "pm clear failed for {package}: {}",
-                stderr.trim()
-            )));
+    /// Force-stops and clears the bound app, freeing it for other screens,
+    /// then optionally binds a new package spec to this screen.
+    fn release_package(
+        &mut self,
+        name: &str,
+        package: Option<&str>,
+    ) -> Result<ScreenInfo, AppError> {
+        let old = self.get_screen_mut(name)?.assigned_package.clone();
+        if !old.is_empty() {
+            tracing::info!(name = %name, package = %old, "releasing package");
+            force_stop(&old)?;
+            clear_package(&old)?;
+            // Only now: a failed stop or clear leaves the screen bound to the app it still holds
+            self.screens.get_mut(name).unwrap().assigned_package.clear();
+        }
+        if let Some(spec) = package {
+            let new = self.resolve_package(spec)?;
+            self.screens.get_mut(name).unwrap().assigned_package = new;
         }
+        self.screens_changed();
+        Ok(self.screens[name].info(name))
+    }
+}
 
-        Ok(())
+/// What `am start` did with an intent it accepted.
+#[derive(Debug, PartialEq)]
+enum AmStart {
+    Started,
+    /// The app was already running: its task came to the front, or the intent went to the
+    /// running top activity.
+    AlreadyRunning,
+}
+
+/// Reads `am start` output, which reports most failures on stdout with a zero exit status.
+fn am_start_outcome(success: bool, stdout: &str, stderr: &str) -> Result<AmStart, AppError> {
+    let failed = || {
+        AppError::new(format!(
+            "am start failed: {} {}",
+            stdout.trim(),
+            stderr.trim()
+        ))
+    };
+    if !success {
+        return Err(failed());
+    }
+    let mut outcome = AmStart::Started;
+    for line in stdout.lines().map(str::trim) {
+        if line.starts_with("Error") || line.contains("Exception") {
+            return Err(failed());
+        }
+        if line.starts_with("Warning: Activity not started, its current task has been brought")
+            || line.starts_with("Warning: Activity not started, intent has been delivered")
+        {
+            outcome = AmStart::AlreadyRunning;
+        } else if line.contains("Activity not started") {
+            return Err(failed());
+        }
+    }
+    Ok(outcome)
+}
+
+/// `package/activity` that the launcher would start.
+fn launcher_component(package: &str) -> Result<String, AppError> {
+    let resolve = Command::new("cmd")
+        .args(["package", "resolve-activity", "--brief", package])
+        .output()
+        .map_err(|e| AppError::new(format!("resolve-activity failed: {e}")))?;
+
+    let resolve_out = String::from_utf8_lossy(&resolve.stdout);
+    resolve_out
+        .lines()
+        .rfind(|line| line.contains('/'))
+        .map(|line| line.trim().to_string())
+        .ok_or_else(|| AppError::new(format!("no activity found for {package}")))
+}
+
+fn force_stop(package: &str) -> Result<(), AppError> {
+    let status = Command::new("am")
+        .args(["force-stop", package])
+        .status()
+        .map_err(|e| AppError::new(format!("am force-stop failed: {e}")))?;
+
+    if !status.success() {
+        return Err(AppError::new(format!("am force-stop failed for {package}")));
     }
+
+    Ok(())
+}
+
+fn clear_package(package: &str) -> Result<(), AppError> {
+    let output = Command::new("pm")
+        .args(["clear", package])
+        .output()
+        .map_err(|e| AppError::new(format!("pm clear failed: {e}")))?;
+
+    // `pm clear` reports some failures on stdout with a zero exit status
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || stdout.trim_start().starts_with("Failed") {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::new(format!(
+            "pm clear failed for {package}: {} {}",
+            stdout.trim(),
+            stderr.trim()
+        )));
+    }
+
+    Ok(())
 }
 
 impl ServerState {
@@ -680,29 +3108,151 @@ impl ServerState {
             .collect())
     }
 
+    /// Resolves a package spec to a concrete package. Precedence:
+    /// 1. a comma-separated list is an explicit pool: the first installed
+    ///    entry not bound to another screen, in the given order;
+    /// 2. an exact installed package name;
+    /// 3. a prefix: the first (sorted) installed match not bound to another screen.
+    fn resolve_package(&self, spec: &str) -> Result<String, AppError> {
+        if spec.contains(',') {
+            let pool: Vec<&str> = spec
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .collect();
+            let installed = self.list_installed_packages("")?;
+            return self.allocate_from_pool(&pool, &installed);
+        }
+        let installed = self.list_installed_packages(spec)?;
+        if installed.contains(spec) {
+            Ok(spec.to_string())
+        } else {
+            self.allocate_from_prefix(spec, &installed)
+        }
+    }
+
+    fn assigned_packages(&self) -> std::collections::HashSet<String> {
+        self.screens
+            .values()
+            .map(|s| s.assigned_package.clone())
+            .collect()
+    }
+
+    fn allocate_from_pool(
+        &self,
+        pool: &[&str],
+        installed: &std::collections::HashSet<String>,
+    ) -> Result<String, AppError> {
+        let assigned = self.assigned_packages();
+        let mut pool_installed = pool.iter().filter(|p| installed.contains(**p)).peekable();
+        if pool_installed.peek().is_none() {
+            return Err(AppError::not_found(format!(
+                "no installed package matches '{}'",
+                pool.join(",")
+            )));
+        }
+        pool_installed
+            .find(|p| !assigned.contains(**p))
+            .map(|p| p.to_string())
+            .ok_or_else(|| {
+                AppError::new(format!(
+                    "every installed package in '{}' is bound to another screen",
+                    pool.join(",")
+                ))
+            })
+    }
+
     fn allocate_from_prefix(
         &self,
         prefix: &str,
         installed_input: &std::collections::HashSet<String>,
     ) -> Result<String, AppError> {
-        let assigned: std::collections::HashSet<String> = self
-            .screens
-            .values()
-            .map(|s| s.assigned_package.clone())
-            .collect();
+        let assigned = self.assigned_packages();
         let mut candidates: Vec<String> = installed_input
             .iter()
-            .filter(|pkg| pkg.starts_with(prefix))
+            .filter(|pkg| pkg.starts_with(prefix))
+            .cloned()
+            .collect();
+        candidates.sort();
+        if candidates.is_empty() {
+            return Err(AppError::not_found(format!(
+                "no installed package matches '{prefix}'"
+            )));
+        }
+        for candidate in &candidates {
+            if !assigned.contains(candidate) {
+                return Ok(candidate.clone());
+            }
+        }
+        Err(AppError::new(format!(
+            "every installed package matching '{prefix}' is bound to another screen: {}",
+            candidates.join(", ")
+        )))
+    }
+
+    /// Installed packages a spec can bind, and similar ones when it matches none.
+    fn installed_packages(&self, spec: &str) -> Result<InstalledPackages, AppError> {
+        let installed = self.list_installed_packages("")?;
+        let mut matches: Vec<String> = if spec.contains(',') {
+            spec.split(',')
+                .map(str::trim)
+                .filter(|p| installed.contains(*p))
+                .map(str::to_string)
+                .collect()
+        } else {
+            installed
+                .iter()
+                .filter(|p| p.starts_with(spec))
+                .cloned()
+                .collect()
+        };
+        matches.sort();
+        let nearby = if matches.is_empty() {
+            nearby_packages(spec, &installed)
+        } else {
+            Vec::new()
+        };
+        Ok(InstalledPackages { matches, nearby })
+    }
+}
+
+const MAX_NEARBY_PACKAGES: usize = 10;
+
+/// Installed packages resembling a spec that matched nothing: those under the longest
+/// parent namespace that has any (at least two segments, so not all of `com.`), else
+/// those containing its last segment.
+fn nearby_packages(spec: &str, installed: &std::collections::HashSet<String>) -> Vec<String> {
+    let spec = spec.split(',').next().unwrap_or_default().trim();
+    let mut found: Vec<String> = Vec::new();
+    let mut namespace = spec;
+    while let Some((parent, _)) = namespace.rsplit_once('.') {
+        namespace = parent;
+        if !namespace.contains('.') {
+            break;
+        }
+        let prefix = format!("{namespace}.");
+        found = installed
+            .iter()
+            .filter(|p| p.starts_with(&prefix))
             .cloned()
             .collect();
-        candidates.sort();
-        for candidate in candidates {
-            if !assigned.contains(&candidate) {
-                return Ok(candidate);
-            }
+        if !found.is_empty() {
+            break;
+        }
+    }
+    if found.is_empty() {
+        let last = spec.rsplit('.').next().unwrap_or_default();
+        if last.len() >= 3 {
+            found = installed
+                .iter()
+                .filter(|p| p.contains(last))
+                .cloned()
+                .collect();
         }
-        Err(AppError::new("no free package available with given prefix"))
     }
+    found.sort();
+    found.truncate(MAX_NEARBY_PACKAGES);
+    found
 }
 
 fn format_exception(env: &mut JNIEnv, exc: &JObject) -> String {
@@ -732,13 +3282,46 @@ fn format_exception(env: &mut JNIEnv, exc: &JObject) -> String {
     try_format().unwrap_or_else(|| "<failed to format exception>".into())
 }
 
-fn get_exception_message(env: &mut JNIEnv) -> Option<String> {
+/// Clear the pending Java exception, if any, returning its class name and stack trace.
+fn take_exception(env: &mut JNIEnv) -> Option<(Option<String>, String)> {
     if !env.exception_check().unwrap_or(false) {
         return None;
     }
     let exc = env.exception_occurred().ok()?;
     env.exception_clear().ok();
-    Some(format_exception(env, &exc))
+    let class = exception_class(env, &exc);
+    Some((class, format_exception(env, &exc)))
+}
+
+fn exception_class(env: &mut JNIEnv, exc: &JObject) -> Option<String> {
+    let class = env
+        .call_method(exc, "getClass", "()Ljava/lang/Class;", &[])
+        .ok()?
+        .l()
+        .ok()?;
+    let name: JString = env
+        .call_method(&class, "getName", "()Ljava/lang/String;", &[])
+        .ok()?
+        .l()
+        .ok()?
+        .into();
+    env.get_string(&name).ok().map(|s| s.into())
+}
+
+/// Error for a failed JNI call, carrying the Java exception behind it when there is one.
+fn jni_error(env: &mut JNIEnv, what: &str, e: jni::errors::Error) -> AppError {
+    match take_exception(env) {
+        Some((class, trace)) => AppError {
+            exception_class: class,
+            ..AppError::new(format!("{what}: {trace}"))
+        },
+        None => AppError::new(format!("{what}: {e}")),
+    }
+}
+
+/// A Rust length as a JNI array length, which is an `i32`.
+fn jsize(len: usize) -> Result<i32, AppError> {
+    i32::try_from(len).map_err(|_| AppError::new(format!("{len} elements is too many for jni")))
 }
 
 fn call_instance_void(
@@ -748,19 +3331,17 @@ fn call_instance_void(
     sig: &str,
     args: &[JValue],
 ) -> Result<(), AppError> {
-    env.call_method(obj, method, sig, args).map_err(|e| {
-        if let Some(exc_msg) = get_exception_message(env) {
-            AppError::new(format!("{method} call failed: {exc_msg}"))
-        } else {
-            AppError::new(format!("{method} call failed: {e}"))
-        }
-    })?;
+    env.call_method(obj, method, sig, args)
+        .map_err(|e| jni_error(env, &format!("{method} call failed"), e))?;
     Ok(())
 }
 
+/// Wait in slices of [`WAIT_POLL_INTERVAL_MS`] so `cancel` can end it early. Returns
+/// whether the UI went idle; false after a timeout or cancel.
 fn jni_wait_for_idle(
     jvm: &JavaVM,
     bridge: &GlobalRef,
+    cancel: &WaitCancel,
     idle_timeout_ms: i64,
     global_timeout_ms: i64,
 ) -> Result<bool, AppError> {
@@ -768,123 +3349,536 @@ fn jni_wait_for_idle(
         .attach_current_thread()
         .map_err(|e| AppError::new(format!("attach_current_thread failed: {e}")))?;
     let obj: &JObject = bridge.as_obj();
-    let result = env
-        .call_method(
-            obj,
-            "waitForIdle",
-            "(JJ)Z",
-            &[
-                JValue::Long(idle_timeout_ms),
-                JValue::Long(global_timeout_ms),
-            ],
-        )
-        .map_err(|e| {
-            if let Some(exc_msg) = get_exception_message(&mut env) {
-                AppError::new(format!("waitForIdle call failed: {exc_msg}"))
-            } else {
-                AppError::new(format!("waitForIdle call failed: {e}"))
-            }
-        })?;
-    Ok(result.z().unwrap_or(false))
+    let start = std::time::Instant::now();
+    let mut first = true;
+    loop {
+        let remaining = global_timeout_ms - start.elapsed().as_millis() as i64;
+        let slice = remaining.clamp(0, WAIT_POLL_INTERVAL_MS);
+        let idle = env
+            .call_method(
+                obj,
+                "waitForIdle",
+                "(JJZ)Z",
+                &[
+                    JValue::Long(idle_timeout_ms),
+                    JValue::Long(slice),
+                    JValue::Bool(first.into()),
+                ],
+            )
+            .map_err(|e| jni_error(&mut env, "waitForIdle call failed", e))?
+            .z()
+            .unwrap_or(false);
+        if idle {
+            return Ok(true);
+        }
+        if remaining <= slice {
+            return Ok(false);
+        }
+        if cancel.cancelled() {
+            tracing::info!(screen = %cancel.screen, "wait for idle cancelled");
+            return Ok(false);
+        }
+        first = false;
+    }
 }
 
 async fn spawn_wait_for_idle(
     jvm: Arc<JavaVM>,
     bridge: GlobalRef,
+    cancel: WaitCancel,
     idle_timeout_ms: i64,
     global_timeout_ms: i64,
 ) -> Result<bool, AppError> {
-    tokio::task::spawn_blocking(move || {
-        jni_wait_for_idle(&jvm, &bridge, idle_timeout_ms, global_timeout_ms)
-    })
-    .await
-    .map_err(|e| AppError::new(format!("spawn_blocking failed: {e}")))?
-}
-
-async fn auto_wait_for_idle(state: &AppState, name: &str) -> Result<u64, AppError> {
-    let args = {
-        let mut guard = state.lock().await;
-        let last_interaction = guard.get_screen_mut(name)?.last_interaction;
-        if let Some(last_interaction) = last_interaction {
-            let elapsed = last_interaction.elapsed();
-            let global_timeout = std::time::Duration::from_millis(2500).saturating_sub(elapsed);
-            if !global_timeout.is_zero() {
-                Some((guard.wait_for_idle_args(name)?, global_timeout))
-            } else {
-                None
+    let deadline = *OP_TIMEOUT + std::time::Duration::from_millis(global_timeout_ms.max(0) as u64);
+    let task = tokio::task::spawn_blocking(move || {
+        jni_wait_for_idle(&jvm, &bridge, &cancel, idle_timeout_ms, global_timeout_ms)
+    });
+    time::timeout(deadline, task)
+        .await
+        .map_err(|_| op_timed_out(deadline))?
+        .map_err(|e| AppError::new(format!("spawn_blocking failed: {e}")))?
+}
+
+fn op_timed_out(deadline: std::time::Duration) -> AppError {
+    AppError::timeout(format!(
+        "device operation exceeded {}ms",
+        deadline.as_millis()
+    ))
+}
+
+/// 503 for a request that gave up waiting for the lock, naming the current holder.
+fn server_busy(waited: std::time::Duration) -> AppError {
+    let holder = LOCK_HOLDER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|(op, since)| (op.clone(), since.elapsed().as_millis()));
+    let waiting = CURRENT_OP.try_with(Clone::clone).unwrap_or_default();
+    let (op, held_ms) = holder.unwrap_or_else(|| ("unknown".into(), 0));
+    tracing::warn!(waiting = %waiting, holder = %op, held_ms, "state lock busy");
+    AppError::busy(format!(
+        "server busy: waited {}ms for {op}, running for {held_ms}ms",
+        waited.as_millis()
+    ))
+}
+
+/// Run `op` on the locked state from a blocking thread, failing with 503 if the lock
+/// isn't free within [`LOCK_TIMEOUT`] and with 504 if `op` runs past [`OP_TIMEOUT`]. A
+/// hung JNI call keeps the lock until it returns, so an op that overran marks the
+/// server [`WEDGED`] and later ones are refused up front rather than queueing on it.
+async fn run_op<T: Send + 'static>(
+    state: &AppState,
+    op: impl FnOnce(&mut ServerState) -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    run_op_within(state, *OP_TIMEOUT, op).await
+}
+
+async fn run_op_within<T: Send + 'static>(
+    state: &AppState,
+    deadline: std::time::Duration,
+    op: impl FnOnce(&mut ServerState) -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    if let Some((op, since)) = WEDGED.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return Err(AppError::busy(format!(
+            "server wedged: {op} overran its deadline and has held the lock for {}ms",
+            since.elapsed().as_millis()
+        )));
+    }
+    let lock_wait = *LOCK_TIMEOUT;
+    let mut guard = time::timeout(lock_wait, state.clone().lock_owned())
+        .await
+        .map_err(|_| server_busy(lock_wait))?;
+    let op_name = CURRENT_OP
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| "background task".into());
+    let started = std::time::Instant::now();
+    *LOCK_HOLDER.lock().unwrap_or_else(|e| e.into_inner()) = Some((op_name.clone(), started));
+    // Set once `op` returns; checked under the same lock before marking the server wedged
+    // so an op finishing right at the deadline can't leave the mark behind
+    let finished = Arc::new(std::sync::Mutex::new(false));
+    let mut task = tokio::task::spawn_blocking({
+        let finished = finished.clone();
+        move || {
+            let result = op(&mut guard);
+            *LOCK_HOLDER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            *finished.lock().unwrap_or_else(|e| e.into_inner()) = true;
+            if let Some((op, since)) = WEDGED.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                let held_ms = since.elapsed().as_millis();
+                tracing::warn!(op = %op, held_ms, "wedged operation returned");
             }
-        } else {
-            None
+            result
         }
-    };
-    if let Some(((jvm, bridge), global_timeout)) = args {
+    });
+    if time::timeout(deadline, &mut task).await.is_err() {
+        let finished = finished.lock().unwrap_or_else(|e| e.into_inner());
+        if !*finished {
+            let deadline_ms = deadline.as_millis();
+            tracing::error!(op = %op_name, deadline_ms, "operation overran, server wedged");
+            *WEDGED.lock().unwrap_or_else(|e| e.into_inner()) = Some((op_name, started));
+            return Err(AppError::timeout(format!(
+                "device operation exceeded {}ms and is still running; new operations are \
+                 refused until it returns",
+                deadline.as_millis()
+            )));
+        }
+    }
+    task.await
+        .map_err(|e| AppError::new(format!("spawn_blocking failed: {e}")))?
+}
+
+/// Run `op` on a blocking thread without the state lock, failing with 504 past
+/// [`OP_TIMEOUT`]. For device-global shell work that never touches [`ServerState`], so a
+/// slow `settings`/`cmd` call doesn't stall taps and screenshots on every screen.
+async fn run_unlocked<T: Send + 'static>(
+    op: impl FnOnce() -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    let deadline = *OP_TIMEOUT;
+    time::timeout(deadline, tokio::task::spawn_blocking(op))
+        .await
+        .map_err(|_| op_timed_out(deadline))?
+        .map_err(|e| AppError::new(format!("spawn_blocking failed: {e}")))?
+}
+
+/// Sets `X-App-Crashed` when a crash/ANR dialog is up after an interaction.
+fn insert_crash_header(response: &mut Response, crash: Option<String>) {
+    if let Some(text) = crash {
+        tracing::warn!(dialog = %text, "app crash dialog detected");
+        response
+            .headers_mut()
+            .insert("X-App-Crashed", header_text(&text));
+    }
+}
+
+/// Dialog text as a header value. Header values are visible ASCII, so other bytes (and
+/// `%`) are percent-encoded as UTF-8.
+fn header_text(text: &str) -> header::HeaderValue {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded.parse().unwrap()
+}
+
+/// What the screen showed once an interaction settled.
+struct Settled {
+    tree: a11y::Tree,
+    crash: Option<String>,
+    /// System dialog tapped away by auto-dismiss.
+    dismissed: Option<String>,
+}
+
+/// Settling step decided under the lock; `run` it after releasing the lock unless the
+/// caller needs the whole interaction to be atomic.
+enum WaitPlan {
+    Skip,
+    Idle(Arc<JavaVM>, GlobalRef, WaitCancel, std::time::Duration),
+    Sleep(std::time::Duration),
+}
+
+impl WaitPlan {
+    async fn run(self) -> Result<u64, AppError> {
         let wait_start = Instant::now();
-        spawn_wait_for_idle(jvm, bridge, 750, global_timeout.as_millis() as i64).await?;
+        match self {
+            WaitPlan::Skip => return Ok(0),
+            WaitPlan::Idle(jvm, bridge, cancel, global_timeout) => {
+                let global_ms = global_timeout.as_millis() as i64;
+                spawn_wait_for_idle(jvm, bridge, cancel, 750, global_ms).await?;
+            }
+            WaitPlan::Sleep(global_timeout) => time::sleep(global_timeout).await,
+        }
+        Ok(wait_start.elapsed().as_millis() as u64)
+    }
+
+    /// `run` for callers already on a blocking thread.
+    fn run_blocking(self) -> Result<u64, AppError> {
+        let wait_start = Instant::now();
+        match self {
+            WaitPlan::Skip => return Ok(0),
+            WaitPlan::Idle(jvm, bridge, cancel, global_timeout) => {
+                let global_ms = global_timeout.as_millis() as i64;
+                jni_wait_for_idle(&jvm, &bridge, &cancel, 750, global_ms)?;
+            }
+            WaitPlan::Sleep(global_timeout) => std::thread::sleep(global_timeout),
+        }
         Ok(wait_start.elapsed().as_millis() as u64)
-    } else {
-        Ok(0)
     }
 }
 
+async fn auto_wait(
+    state: &AppState,
+    name: &str,
+    strategy: WaitStrategy,
+    fixed_ms: Option<u64>,
+) -> Result<u64, AppError> {
+    let name = name.to_owned();
+    let plan = run_op(state, move |s| s.wait_plan(&name, strategy, fixed_ms)).await?;
+    plan.run().await
+}
+
 // --- Route handlers ---
 
+/// 201 when this request bound the screen's package (a new screen, or one whose package
+/// was released), so the caller can report which package a prefix picked.
 async fn create_screen(
     State(state): State<AppState>,
     Json(req): Json<CreateScreenRequest>,
-) -> Result<Json<ScreenInfo>, AppError> {
-    let info = state.lock().await.create_screen(&req)?;
-    Ok(Json(info))
+) -> Result<(StatusCode, Json<ScreenInfo>), AppError> {
+    let (info, bound) = run_op(&state, move |s| {
+        let bound = s
+            .screens
+            .get(&req.name)
+            .is_none_or(|screen| screen.assigned_package.is_empty());
+        Ok((s.create_screen(&req)?, bound))
+    })
+    .await?;
+    let status = if bound {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(info)))
+}
+
+async fn create_screens(
+    State(state): State<AppState>,
+    Json(reqs): Json<Vec<CreateScreenRequest>>,
+) -> Result<Json<Vec<BatchScreenResult>>, AppError> {
+    let results = run_op(&state, move |s| s.create_screens(&reqs)).await?;
+    Ok(Json(results))
 }
 
+async fn installed_packages(
+    State(state): State<AppState>,
+    Query(query): Query<PackagesQuery>,
+) -> Result<Json<InstalledPackages>, AppError> {
+    let packages = run_op(&state, move |s| s.installed_packages(&query.package)).await?;
+    Ok(Json(packages))
+}
+
+/// Runs behind [`wait_for_screen_ops`].
 async fn delete_screen(
     State(state): State<AppState>,
     Path(name): Path<String>,
 ) -> Result<StatusCode, AppError> {
-    state.lock().await.destroy_screen(&name)?;
+    run_op(&state, move |s| {
+        // Under the lock, so nothing can start a step on the screen past this check
+        match SCREEN_OPS.running(&name) {
+            0 => s.destroy_screen(&name),
+            running => Err(screen_in_use(&name, running)),
+        }
+    })
+    .await?;
     Ok(StatusCode::OK)
 }
 
-async fn list_screens(State(state): State<AppState>) -> Json<Vec<ScreenInfo>> {
-    Json(state.lock().await.list_screens())
+async fn list_screens(State(state): State<AppState>) -> Result<Json<Vec<ScreenInfo>>, AppError> {
+    let screens = run_op(&state, |s| Ok(s.list_screens())).await?;
+    Ok(Json(screens))
 }
 
 async fn screen_info(
     State(state): State<AppState>,
     Path(name): Path<String>,
 ) -> Result<Json<ScreenInfo>, AppError> {
-    let info = state.lock().await.screen_info(&name)?;
+    let info = run_op(&state, move |s| s.screen_info(&name)).await?;
+    Ok(Json(info))
+}
+
+async fn history(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<HistoryEntry>>, AppError> {
+    let entries = run_op(&state, move |s| s.history(&name, query.limit)).await?;
+    Ok(Json(entries))
+}
+
+async fn foreground(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ForegroundInfo>, AppError> {
+    let info = run_op(&state, move |s| s.foreground(&name)).await?;
+    Ok(Json(info))
+}
+
+async fn adopt(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ForegroundInfo>, AppError> {
+    let info = run_op(&state, move |s| s.adopt(&name)).await?;
     Ok(Json(info))
 }
 
+async fn get_appops(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<appops::GetQuery>,
+) -> Result<Json<Vec<appops::OpMode>>, AppError> {
+    let package = run_op(&state, move |s| {
+        Ok(s.get_screen_mut(&name)?.package()?.to_string())
+    })
+    .await?;
+    let modes = run_unlocked(move || appops::get(&package, query.op.as_deref())).await?;
+    Ok(Json(modes))
+}
+
+async fn set_appops(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<appops::SetRequest>,
+) -> Result<Json<Vec<appops::OpMode>>, AppError> {
+    let package = run_op(&state, move |s| {
+        Ok(s.get_screen_mut(&name)?.package()?.to_string())
+    })
+    .await?;
+    let modes = run_unlocked(move || appops::set(&package, &req)).await?;
+    Ok(Json(modes))
+}
+
+async fn app_state(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<AppProcessState>, AppError> {
+    let app = run_op(&state, move |s| s.app_state(&name)).await?;
+    Ok(Json(app))
+}
+
 async fn screenshot(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Query(query): Query<NoWaitQuery>,
+    Query(query): Query<ScreenshotQuery>,
 ) -> Result<Response, AppError> {
-    let waited_ms = if query.no_wait {
-        0
-    } else {
-        auto_wait_for_idle(&state, &name).await?
-    };
-    let jpeg = state.lock().await.screenshot(&name)?;
-    let mut response = ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response();
-    response
-        .headers_mut()
-        .insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
+    let waited_ms = auto_wait(
+        &state,
+        &name,
+        wait_strategy(query.no_wait, query.wait),
+        query.wait_ms,
+    )
+    .await?;
+    if query.full {
+        return long_screenshot(state, name, query, waited_ms).await;
+    }
+    if query.format == ScreenshotFormat::Raw {
+        return raw_screenshot(state, name, query, waited_ms).await;
+    }
+    let codec = query.codec()?;
+    let (jpeg, scale, age_ms, screen_state) = run_op(&state, move |s| {
+        s.settle_frames(&name, query.settle_frames)?;
+        let (jpeg, scale, age_ms) = s.screenshot(
+            &name,
+            query.scale,
+            query.debug,
+            query.node.as_deref(),
+            codec,
+            query.max_dim,
+        )?;
+        Ok((
+            jpeg,
+            scale,
+            age_ms,
+            FrameState::of(s.get_screen_mut(&name)?),
+        ))
+    })
+    .await?;
+    let dimensions = image::dimensions(&jpeg);
+    let mut response = ([(header::CONTENT_TYPE, codec.content_type())], jpeg).into_response();
+    let headers = response.headers_mut();
+    headers.insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
+    headers.insert("X-Scale", scale.to_string().parse().unwrap());
+    // The encoded image itself, which may differ from `/info` after a crop or resize
+    if let Some((width, height)) = dimensions {
+        headers.insert("X-Width", width.to_string().parse().unwrap());
+        headers.insert("X-Height", height.to_string().parse().unwrap());
+    }
+    headers.insert("X-Format", codec.format_name().parse().unwrap());
+    headers.insert("X-Frame-Age-Ms", age_ms.to_string().parse().unwrap());
+    screen_state.insert_headers(headers);
+    Ok(response)
+}
+
+/// What a screenshot response says about the display besides the frame's own age.
+struct FrameState {
+    frozen: bool,
+    interaction_age_ms: Option<u64>,
+}
+
+impl FrameState {
+    fn of(screen: &VirtualScreen) -> Self {
+        Self {
+            frozen: screen.frame_frozen(),
+            interaction_age_ms: screen.interaction_age_ms(),
+        }
+    }
+
+    fn insert_headers(&self, headers: &mut axum::http::HeaderMap) {
+        if self.frozen {
+            headers.insert("X-Frame-Frozen", "true".parse().unwrap());
+        }
+        // Lets the client tell a frame from before the last interaction from a static screen
+        if let Some(ms) = self.interaction_age_ms {
+            headers.insert("X-Interaction-Age-Ms", ms.to_string().parse().unwrap());
+        }
+    }
+}
+
+async fn raw_screenshot(
+    state: AppState,
+    name: String,
+    query: ScreenshotQuery,
+    waited_ms: u64,
+) -> Result<Response, AppError> {
+    if query.scale.is_some()
+        || query.max_dim.is_some()
+        || query.debug
+        || query.node.is_some()
+        || query.quality.is_some()
+        || query.codec()? != Codec::default()
+    {
+        return Err(AppError::bad_request(
+            "raw screenshots are whole unscaled frames: scale, max_dim, debug, node, quality, grayscale and chroma don't apply",
+        ));
+    }
+    let (rgba, width, height, age_ms, format, screen_state) = run_op(&state, move |s| {
+        s.settle_frames(&name, query.settle_frames)?;
+        let (rgba, width, height, age_ms, format) = s.screenshot_raw(&name)?;
+        let screen_state = FrameState::of(s.get_screen_mut(&name)?);
+        Ok((rgba, width, height, age_ms, format, screen_state))
+    })
+    .await?;
+    let mut response = ([(header::CONTENT_TYPE, "application/octet-stream")], rgba).into_response();
+    let headers = response.headers_mut();
+    headers.insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
+    headers.insert("X-Scale", "1".parse().unwrap());
+    headers.insert("X-Width", width.to_string().parse().unwrap());
+    headers.insert("X-Height", height.to_string().parse().unwrap());
+    headers.insert("X-Format", "raw".parse().unwrap());
+    headers.insert("X-Pixel-Format", format.name().parse().unwrap());
+    headers.insert("X-Frame-Age-Ms", age_ms.to_string().parse().unwrap());
+    screen_state.insert_headers(headers);
+    Ok(response)
+}
+
+async fn long_screenshot(
+    state: AppState,
+    name: String,
+    query: ScreenshotQuery,
+    waited_ms: u64,
+) -> Result<Response, AppError> {
+    if query.node.is_some()
+        || query.debug
+        || query.settle_frames > 0
+        || query.format == ScreenshotFormat::Raw
+    {
+        return Err(AppError::bad_request(
+            "full screenshots don't support node, debug, settle_frames or format=raw",
+        ));
+    }
+    // Every page is a slow swipe plus a settle pause
+    let deadline = *OP_TIMEOUT
+        + (std::time::Duration::from_millis(LONG_SCREENSHOT_SWIPE_MS as u64)
+            + LONG_SCREENSHOT_SETTLE)
+            * MAX_SCROLL_PAGES as u32;
+    let codec = query.codec()?;
+    let (jpeg, scale, pages) = run_op_within(&state, deadline, move |s| {
+        s.long_screenshot(
+            &name,
+            query.scroll_node.as_deref(),
+            query.scale,
+            codec,
+            query.max_dim,
+        )
+    })
+    .await?;
+    let dimensions = image::dimensions(&jpeg);
+    let mut response = ([(header::CONTENT_TYPE, codec.content_type())], jpeg).into_response();
+    let headers = response.headers_mut();
+    headers.insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
+    headers.insert("X-Scale", scale.to_string().parse().unwrap());
+    if let Some((width, height)) = dimensions {
+        headers.insert("X-Width", width.to_string().parse().unwrap());
+        headers.insert("X-Height", height.to_string().parse().unwrap());
+    }
+    headers.insert("X-Format", codec.format_name().parse().unwrap());
+    headers.insert("X-Pages", pages.to_string().parse().unwrap());
     Ok(response)
 }
 
 async fn raw_frame(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Query(query): Query<NoWaitQuery>,
+    Query(query): Query<RawFrameQuery>,
 ) -> Result<Response, AppError> {
-    let waited_ms = if query.no_wait {
-        0
-    } else {
-        auto_wait_for_idle(&state, &name).await?
-    };
-    let frame = state.lock().await.raw_frame(&name)?;
+    let waited_ms = auto_wait(
+        &state,
+        &name,
+        wait_strategy(query.no_wait, query.wait),
+        query.wait_ms,
+    )
+    .await?;
+    let frame = run_op(&state, move |s| s.raw_frame(&name, query.base)).await?;
     let Some((info, rgba)) = frame else {
         let mut response = StatusCode::NO_CONTENT.into_response();
         response
@@ -909,98 +3903,371 @@ async fn raw_frame(
         "X-Frame-Timestamp-Ms",
         info.timestamp_ms.to_string().parse().unwrap(),
     );
+    if let Some((x, y, w, h)) = info.region {
+        headers.insert("X-Region", format!("{x},{y},{w},{h}").parse().unwrap());
+    }
     Ok(response)
 }
 
 async fn a11y(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Query(query): Query<NoWaitQuery>,
+    Query(query): Query<A11yQuery>,
 ) -> Result<Response, AppError> {
-    let waited_ms = if query.no_wait {
-        0
+    let strategy = if query.peek {
+        WaitStrategy::None
     } else {
-        auto_wait_for_idle(&state, &name).await?
+        wait_strategy(query.no_wait, query.wait)
+    };
+    let waited_ms = auto_wait(&state, &name, strategy, query.wait_ms).await?;
+    let json = run_op(&state, move |s| {
+        if query.peek {
+            s.peek_tree(&name)
+        } else {
+            s.accessibility_tree(&name)
+        }
+    })
+    .await?;
+    let mut response = match query.format {
+        A11yFormat::Json => ([(header::CONTENT_TYPE, "application/json")], json).into_response(),
+        A11yFormat::Xml => {
+            let tree = a11y::Tree::parse(&json)
+                .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+            (
+                [(header::CONTENT_TYPE, "application/xml")],
+                tree.to_uiautomator_xml(),
+            )
+                .into_response()
+        }
     };
-    let json = state.lock().await.accessibility_tree(&name)?;
-    let mut response = ([(header::CONTENT_TYPE, "application/json")], json).into_response();
     response
         .headers_mut()
         .insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
     Ok(response)
 }
 
+async fn windows(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let json = run_op(&state, move |s| s.windows(&name)).await?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], json).into_response())
+}
+
 async fn tap(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Query(query): Query<NoWaitQuery>,
+    Query(query): Query<TapQuery>,
     Json(req): Json<TapRequest>,
 ) -> Result<Response, AppError> {
-    state.lock().await.tap(&name, req.x, req.y)?;
-    let waited_ms = if query.no_wait {
-        0
-    } else {
-        auto_wait_for_idle(&state, &name).await?
+    if query.snapshot {
+        return tap_snapshot(&state, name, query, req).await;
+    }
+    let strategy = wait_strategy(query.no_wait, query.wait);
+    let coord_space = query.coord_space;
+    interact(&state, name, strategy, query.wait_ms, move |s, name| {
+        s.tap(name, req.x, req.y, coord_space)
+    })
+    .await
+}
+
+/// Run a touch, then wait as asked and report what followed in `X-Wait-Ms`,
+/// `X-Ui-Changed`, `X-App-Crashed` and `X-Auto-Dismissed`.
+async fn interact(
+    state: &AppState,
+    name: String,
+    strategy: WaitStrategy,
+    wait_ms: Option<u64>,
+    touch: impl FnOnce(&mut ServerState, &str) -> Result<(), AppError> + Send + 'static,
+) -> Result<Response, AppError> {
+    let before = {
+        let name = name.clone();
+        run_op(state, move |s| {
+            // Injection can't tell whether a view took the event; a UI diff can
+            let before = match strategy {
+                WaitStrategy::None => None,
+                _ => Some(s.ui_tree(&name)?),
+            };
+            touch(s, &name)?;
+            Ok(before)
+        })
+        .await?
+    };
+    let (waited_ms, after, changed) = match before {
+        None => (0, None, None),
+        Some(before) => {
+            let waited_ms = auto_wait(state, &name, strategy, wait_ms).await?;
+            let after = run_op(state, move |s| s.settled(&name)).await?;
+            let changed = !after.tree.same_nodes(&before);
+            (waited_ms, Some(after), Some(changed))
+        }
     };
     let mut response = StatusCode::OK.into_response();
     response
         .headers_mut()
         .insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
+    if let Some(after) = after {
+        insert_crash_header(&mut response, after.crash);
+        insert_dismissed_header(&mut response, after.dismissed);
+    }
+    insert_changed_header(&mut response, changed);
+    Ok(response)
+}
+
+/// Sets `X-Auto-Dismissed` when auto-dismiss tapped through a system dialog.
+fn insert_dismissed_header(response: &mut Response, dismissed: Option<String>) {
+    if let Some(text) = dismissed {
+        response
+            .headers_mut()
+            .insert("X-Auto-Dismissed", header_text(&text));
+    }
+}
+
+/// Sets `X-Ui-Changed` when the a11y tree was compared across a waited interaction.
+fn insert_changed_header(response: &mut Response, changed: Option<bool>) {
+    if let Some(changed) = changed {
+        response
+            .headers_mut()
+            .insert("X-Ui-Changed", changed.to_string().parse().unwrap());
+    }
+}
+
+/// Tap, settle, and capture under a single lock so nothing else interleaves.
+async fn tap_snapshot(
+    state: &AppState,
+    name: String,
+    query: TapQuery,
+    req: TapRequest,
+) -> Result<Response, AppError> {
+    let strategy = wait_strategy(query.no_wait, query.wait);
+    let deadline = *OP_TIMEOUT + std::time::Duration::from_millis(query.wait_ms.unwrap_or(0));
+    let (wait_ms, settled, ui_changed, jpeg, a11y) = run_op_within(state, deadline, move |s| {
+        let before = match strategy {
+            WaitStrategy::None => None,
+            _ => Some(s.ui_tree(&name)?),
+        };
+        s.tap(&name, req.x, req.y, query.coord_space)?;
+        let wait_ms = s
+            .wait_plan(&name, strategy, query.wait_ms)?
+            .run_blocking()?;
+        let (settled, ui_changed) = match before {
+            None => (None, None),
+            Some(before) => {
+                let after = s.settled(&name)?;
+                let changed = !after.tree.same_nodes(&before);
+                (Some(after), Some(changed))
+            }
+        };
+        let (jpeg, ..) = s.screenshot(&name, None, false, None, Codec::default(), None)?;
+        let a11y: serde_json::Value = serde_json::from_str(&s.accessibility_tree(&name)?)
+            .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+        Ok((wait_ms, settled, ui_changed, jpeg, a11y))
+    })
+    .await?;
+
+    let (app_crashed, auto_dismissed) = settled.map_or((None, None), |s| (s.crash, s.dismissed));
+    let mut response = Json(Snapshot {
+        wait_ms,
+        app_crashed: app_crashed.clone(),
+        auto_dismissed: auto_dismissed.clone(),
+        ui_changed,
+        screenshot: base64::engine::general_purpose::STANDARD.encode(jpeg),
+        a11y,
+    })
+    .into_response();
+    response
+        .headers_mut()
+        .insert("X-Wait-Ms", wait_ms.to_string().parse().unwrap());
+    insert_crash_header(&mut response, app_crashed);
+    insert_dismissed_header(&mut response, auto_dismissed);
+    insert_changed_header(&mut response, ui_changed);
     Ok(response)
 }
 
-async fn swipe(
+async fn multi_tap(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<MultiTapQuery>,
+    Json(req): Json<MultiTapRequest>,
+) -> Result<Response, AppError> {
+    // Rejected before taking the lock
+    req.validate()?;
+    let strategy = wait_strategy(query.no_wait, query.wait);
+    interact(&state, name, strategy, query.wait_ms, move |s, name| {
+        s.multi_tap(name, &req.points)
+    })
+    .await
+}
+
+async fn swipe(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<SwipeRequest>,
+) -> Result<StatusCode, AppError> {
+    // Java sleeps through the gesture with the lock held; out-of-range values are
+    // rejected inside, so clamping only keeps the deadline sane
+    let gesture_ms =
+        req.duration_ms.clamp(0, MAX_SWIPE_DURATION_MS) + req.hold_ms.clamp(0, MAX_SWIPE_HOLD_MS);
+    let deadline = *OP_TIMEOUT + std::time::Duration::from_millis(gesture_ms as u64);
+    run_op_within(&state, deadline, move |s| s.swipe(&name, &req)).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn type_text(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<TypeRequest>,
+) -> Result<StatusCode, AppError> {
+    run_op(&state, move |s| s.input_text(&name, &req.text, req.paste)).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn key(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<KeyRequest>,
+) -> Result<StatusCode, AppError> {
+    run_op(&state, move |s| s.key(&name, req.keycode)).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn key_combo(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<KeyComboRequest>,
+) -> Result<StatusCode, AppError> {
+    run_op(&state, move |s| s.key_combo(&name, &req.keycodes)).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn fill(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<FillRequest>,
+) -> Result<Json<a11y::Node>, AppError> {
+    let node = run_op(&state, move |s| s.fill(&name, &req)).await?;
+    Ok(Json(node))
+}
+
+async fn ime(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ImeInfo>, AppError> {
+    let ime = run_op(&state, move |s| s.ime(&name)).await?;
+    Ok(Json(ime))
+}
+
+async fn hide_ime(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Json(req): Json<SwipeRequest>,
-) -> Result<StatusCode, AppError> {
-    state.lock().await.swipe(&name, &req)?;
-    Ok(StatusCode::OK)
+) -> Result<Json<ImeInfo>, AppError> {
+    let ime = run_op(&state, move |s| s.hide_ime(&name)).await?;
+    Ok(Json(ime))
 }
 
-async fn type_text(
+async fn select(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Json(req): Json<TypeRequest>,
+    Json(req): Json<SelectRequest>,
 ) -> Result<StatusCode, AppError> {
-    state.lock().await.input_text(&name, &req.text)?;
+    run_op(&state, move |s| s.select(&name, &req)).await?;
     Ok(StatusCode::OK)
 }
 
-async fn key(
+async fn focus(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Json(req): Json<KeyRequest>,
-) -> Result<StatusCode, AppError> {
-    state.lock().await.key(&name, req.keycode)?;
-    Ok(StatusCode::OK)
+    Json(req): Json<FocusRequest>,
+) -> Result<Json<a11y::Node>, AppError> {
+    let node = run_op(&state, move |s| s.focus(&name, req.direction)).await?;
+    Ok(Json(node))
 }
 
 async fn launch(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Query(query): Query<NoWaitQuery>,
+    Query(query): Query<LaunchQuery>,
 ) -> Result<Response, AppError> {
-    let wait_args = {
-        let mut guard = state.lock().await;
-        guard.launch(&name)?;
-        if query.no_wait {
-            None
-        } else {
-            Some(guard.wait_for_idle_args(&name)?)
-        }
+    launch_with(state, name, query, None).await
+}
+
+async fn launch_intent(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<LaunchQuery>,
+    Json(req): Json<LaunchIntentRequest>,
+) -> Result<Response, AppError> {
+    launch_with(state, name, query, Some(req)).await
+}
+
+async fn launch_with(
+    state: AppState,
+    name: String,
+    query: LaunchQuery,
+    intent: Option<LaunchIntentRequest>,
+) -> Result<Response, AppError> {
+    let strategy = wait_strategy(query.no_wait, query.wait);
+    let (outcome, wait_args) = {
+        let name = name.clone();
+        run_op(&state, move |s| {
+            let outcome = s.launch(&name, intent.as_ref())?;
+            let wait_args = match strategy {
+                WaitStrategy::Idle => Some(s.wait_for_idle_args(&name)?),
+                _ => None,
+            };
+            Ok((outcome, wait_args))
+        })
+        .await?
     };
-    let waited_ms = if let Some((jvm, bridge)) = wait_args {
-        let wait_start = Instant::now();
-        spawn_wait_for_idle(jvm, bridge, 5000, 30000).await?;
-        wait_start.elapsed().as_millis() as u64
-    } else {
-        0
+    let wait_start = Instant::now();
+    match (strategy, wait_args) {
+        (_, Some((jvm, bridge, cancel))) => {
+            spawn_wait_for_idle(
+                jvm,
+                bridge,
+                cancel,
+                query.idle_timeout_ms.unwrap_or(LAUNCH_IDLE_TIMEOUT_MS),
+                query.global_timeout_ms.unwrap_or(LAUNCH_GLOBAL_TIMEOUT_MS),
+            )
+            .await?;
+        }
+        (WaitStrategy::Fixed, None) => {
+            let ms = query.wait_ms.unwrap_or(DEFAULT_FIXED_WAIT_MS);
+            time::sleep(std::time::Duration::from_millis(ms)).await;
+        }
+        _ => {}
+    }
+    let (waited_ms, settled) = match strategy {
+        WaitStrategy::None => (0, None),
+        _ => {
+            let waited_ms = wait_start.elapsed().as_millis() as u64;
+            let settled = run_op(&state, move |s| {
+                let settled = s.settled(&name)?;
+                // Only a speedup; the launch itself succeeded
+                if query.prefetch
+                    && let Err(e) = s.prefetch(&name)
+                {
+                    tracing::warn!(name = %name, error = %e.message, "prefetch after launch failed");
+                }
+                Ok(settled)
+            })
+            .await?;
+            (waited_ms, Some(settled))
+        }
     };
     let mut response = StatusCode::OK.into_response();
     response
         .headers_mut()
         .insert("X-Wait-Ms", waited_ms.to_string().parse().unwrap());
+    if outcome == AmStart::AlreadyRunning {
+        response
+            .headers_mut()
+            .insert("X-Cold-Start", "false".parse().unwrap());
+    }
+    if let Some(settled) = settled {
+        insert_crash_header(&mut response, settled.crash);
+        insert_dismissed_header(&mut response, settled.dismissed);
+    }
     Ok(response)
 }
 
@@ -1008,7 +4275,7 @@ async fn stop(
     State(state): State<AppState>,
     Path(name): Path<String>,
 ) -> Result<StatusCode, AppError> {
-    state.lock().await.stop(&name)?;
+    run_op(&state, move |s| s.stop(&name)).await?;
     Ok(StatusCode::OK)
 }
 
@@ -1016,15 +4283,88 @@ async fn reset(
     State(state): State<AppState>,
     Path(name): Path<String>,
 ) -> Result<StatusCode, AppError> {
-    state.lock().await.reset(&name)?;
+    run_op(&state, move |s| s.reset(&name)).await?;
     Ok(StatusCode::OK)
 }
 
+async fn release_package(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<ReleasePackageRequest>,
+) -> Result<Json<ScreenInfo>, AppError> {
+    let info = run_op(&state, move |s| {
+        s.release_package(&name, req.package.as_deref())
+    })
+    .await?;
+    Ok(Json(info))
+}
+
+async fn set_toggle(
+    toggle: connectivity::Toggle,
+    req: connectivity::ToggleState,
+) -> Result<Json<connectivity::ToggleState>, AppError> {
+    let state = run_unlocked(move || toggle.set(req.on)).await?;
+    Ok(Json(state))
+}
+
+async fn airplane_mode(
+    Json(req): Json<connectivity::ToggleState>,
+) -> Result<Json<connectivity::ToggleState>, AppError> {
+    set_toggle(connectivity::Toggle::AirplaneMode, req).await
+}
+
+async fn wifi(
+    Json(req): Json<connectivity::ToggleState>,
+) -> Result<Json<connectivity::ToggleState>, AppError> {
+    set_toggle(connectivity::Toggle::Wifi, req).await
+}
+
+async fn mobile_data(
+    Json(req): Json<connectivity::ToggleState>,
+) -> Result<Json<connectivity::ToggleState>, AppError> {
+    set_toggle(connectivity::Toggle::MobileData, req).await
+}
+
+async fn notifications(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<a11y::Notification>>, AppError> {
+    let notifications = run_op(&state, move |s| s.notifications(&name)).await?;
+    Ok(Json(notifications))
+}
+
+async fn open_notifications(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<a11y::Notification>>, AppError> {
+    let notifications = run_op(&state, move |s| s.open_notifications(&name)).await?;
+    Ok(Json(notifications))
+}
+
+async fn set_time(
+    Json(req): Json<clock::SetTimeRequest>,
+) -> Result<Json<clock::DeviceTime>, AppError> {
+    let time = run_unlocked(move || clock::set(&req)).await?;
+    Ok(Json(time))
+}
+
+async fn input_methods() -> Result<Json<input_method::InputMethods>, AppError> {
+    let methods = run_unlocked(input_method::list).await?;
+    Ok(Json(methods))
+}
+
+async fn set_input_method(
+    Json(req): Json<input_method::SetInputMethodRequest>,
+) -> Result<Json<input_method::InputMethods>, AppError> {
+    let methods = run_unlocked(move || input_method::set(&req)).await?;
+    Ok(Json(methods))
+}
+
 async fn heartbeat(
     State(state): State<AppState>,
     Path(name): Path<String>,
 ) -> Result<StatusCode, AppError> {
-    state.lock().await.heartbeat(&name)?;
+    run_op(&state, move |s| s.heartbeat(&name)).await?;
     Ok(StatusCode::OK)
 }
 
@@ -1033,7 +4373,7 @@ async fn open_url(
     Path(name): Path<String>,
     Json(req): Json<OpenUrlRequest>,
 ) -> Result<StatusCode, AppError> {
-    state.lock().await.open_url(&name, &req.url)?;
+    run_op(&state, move |s| s.open_url(&name, req)).await?;
     Ok(StatusCode::OK)
 }
 
@@ -1042,11 +4382,345 @@ async fn wait_for_idle(
     Path(name): Path<String>,
     Json(req): Json<WaitForIdleRequest>,
 ) -> Result<StatusCode, AppError> {
-    let (jvm, bridge) = state.lock().await.wait_for_idle_args(&name)?;
-    spawn_wait_for_idle(jvm, bridge, req.idle_timeout_ms, req.global_timeout_ms).await?;
+    let (jvm, bridge, cancel) = {
+        let name = name.clone();
+        run_op(&state, move |s| s.wait_for_idle_args(&name)).await?
+    };
+    spawn_wait_for_idle(
+        jvm,
+        bridge,
+        cancel,
+        req.idle_timeout_ms,
+        req.global_timeout_ms,
+    )
+    .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn cancel(Path(name): Path<String>) -> Result<StatusCode, AppError> {
+    cancel_waits(&name)?;
     Ok(StatusCode::OK)
 }
 
+async fn wait_for_node(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<WaitForNodeRequest>,
+) -> Result<Json<WaitForNodeResponse>, AppError> {
+    let timeout = std::time::Duration::from_millis(req.timeout_ms);
+    let start = Instant::now();
+    let cancel = WaitCancel::new(&name);
+    loop {
+        let json = {
+            let name = name.clone();
+            run_op(&state, move |s| s.accessibility_tree(&name)).await?
+        };
+        let tree = a11y::Tree::parse(&json)
+            .map_err(|e| AppError::new(format!("a11y tree parse failed: {e}")))?;
+        let node = tree.find(&req.selector).cloned();
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if node.is_some() != req.gone || remaining.is_zero() || cancel.cancelled() {
+            return Ok(Json(WaitForNodeResponse {
+                found: node.is_some(),
+                node,
+            }));
+        }
+        // Poll again once the UI settles, without holding the lock meanwhile
+        let (jvm, bridge, _) = {
+            let name = name.clone();
+            run_op(&state, move |s| s.wait_for_idle_args(&name)).await?
+        };
+        let global_ms = remaining.min(std::time::Duration::from_millis(5000));
+        spawn_wait_for_idle(
+            jvm,
+            bridge,
+            cancel.clone(),
+            500,
+            global_ms.as_millis() as i64,
+        )
+        .await?;
+    }
+}
+
+/// Middleware: count each request by matched route and final status.
+async fn count_requests(
+    State(metrics): State<Arc<metrics::Metrics>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |p| p.as_str().to_string());
+    let response = next.run(req).await;
+    metrics.count_request(&route, response.status().as_u16());
+    response
+}
+
+/// Middleware: remember which request is running so lock stalls can name it, and
+/// count it against its screen so a delete waits for it.
+async fn track_op(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    use axum::RequestPartsExt;
+
+    let op = format!("{} {}", req.method(), req.uri().path());
+    let on_screen = req
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .is_some_and(|path| path.as_str().contains("/screens/{name}/"));
+    let (mut parts, body) = req.into_parts();
+    let name = if on_screen {
+        parts
+            .extract::<Path<HashMap<String, String>>>()
+            .await
+            .ok()
+            .and_then(|Path(mut params)| params.remove("name"))
+    } else {
+        None
+    };
+    let _op = name.as_deref().map(|name| SCREEN_OPS.start(name));
+    let req = axum::extract::Request::from_parts(parts, body);
+    CURRENT_OP.scope(op, next.run(req)).await
+}
+
+/// Where `andy start` leaves `ANDY_AUTH_TOKEN`, mode 600, so the secret isn't in the argv
+/// of `adb shell env ...` or of `su` for anyone running `ps` to read.
+static AUTH_TOKEN_FILE: LazyLock<String> = LazyLock::new(|| format!("{DEVICE_DIR}/auth_token"));
+
+/// `ANDY_AUTH_TOKEN`, or else the contents of [`AUTH_TOKEN_FILE`]. A token file the server
+/// can't read stops it rather than leaving the API open.
+static AUTH_TOKEN: LazyLock<Option<Arc<str>>> = LazyLock::new(|| {
+    if let Ok(token) = std::env::var("ANDY_AUTH_TOKEN") {
+        return Some(token.into());
+    }
+    match std::fs::read_to_string(&*AUTH_TOKEN_FILE) {
+        Ok(token) => Some(token.trim_end_matches('\n').into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => panic!("read {}: {e}", *AUTH_TOKEN_FILE),
+    }
+});
+
+/// Middleware: with `ANDY_AUTH_TOKEN` set, every request needs `Authorization: Bearer <token>`.
+async fn require_token(
+    State(token): State<Option<Arc<str>>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let Some(token) = token else {
+        return next.run(req).await;
+    };
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    // Compare every byte so timing doesn't reveal the matching prefix
+    let authorized = presented.is_some_and(|presented| {
+        presented.len() == token.len()
+            && presented
+                .bytes()
+                .zip(token.bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    });
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "missing or invalid bearer token").into_response();
+    }
+    next.run(req).await
+}
+
+/// `ANDY_BIND_ADDR`, defaulting to loopback where only the adb forward reaches it.
+fn bind_ip() -> std::net::IpAddr {
+    let loopback = std::net::IpAddr::from([127, 0, 0, 1]);
+    let Ok(addr) = std::env::var("ANDY_BIND_ADDR") else {
+        return loopback;
+    };
+    let ip = match addr.parse::<std::net::IpAddr>() {
+        Ok(ip) => ip,
+        Err(e) => {
+            tracing::error!(addr = %addr, error = %e, "invalid ANDY_BIND_ADDR, using loopback");
+            return loopback;
+        }
+    };
+    if !ip.is_loopback() && AUTH_TOKEN.is_none() {
+        tracing::warn!(
+            %ip,
+            "binding to a non-loopback address without ANDY_AUTH_TOKEN: anyone who can reach \
+             this port controls the device"
+        );
+    }
+    ip
+}
+
+/// One method on one path of the api. The method is kept next to the handler so the
+/// OpenAPI document can be checked against the same list the router is built from.
+struct ApiRoute {
+    method: Method,
+    path: &'static str,
+    handler: MethodRouter<AppState>,
+}
+
+impl ApiRoute {
+    fn new<H, T>(method: Method, path: &'static str, handler: H) -> Self
+    where
+        H: axum::handler::Handler<T, AppState>,
+        T: 'static,
+    {
+        let filter =
+            MethodFilter::try_from(method.clone()).expect("api methods are all filterable");
+        ApiRoute {
+            method,
+            path,
+            handler: on(filter, handler),
+        }
+    }
+
+    fn get<H: axum::handler::Handler<T, AppState>, T: 'static>(
+        path: &'static str,
+        handler: H,
+    ) -> Self {
+        Self::new(Method::GET, path, handler)
+    }
+
+    fn post<H: axum::handler::Handler<T, AppState>, T: 'static>(
+        path: &'static str,
+        handler: H,
+    ) -> Self {
+        Self::new(Method::POST, path, handler)
+    }
+
+    fn delete<H: axum::handler::Handler<T, AppState>, T: 'static>(
+        path: &'static str,
+        handler: H,
+    ) -> Self {
+        Self::new(Method::DELETE, path, handler)
+    }
+
+    fn map(mut self, f: impl FnOnce(MethodRouter<AppState>) -> MethodRouter<AppState>) -> Self {
+        self.handler = f(self.handler);
+        self
+    }
+}
+
+/// Every route `nativeRun` serves. A path listed once per method is merged by the router.
+fn api_routes(metrics: &Arc<metrics::Metrics>) -> Vec<ApiRoute> {
+    // Served from its own handle so a wedged state lock can't hide the numbers
+    let metrics = metrics.clone();
+    vec![
+        ApiRoute::post("/screens", create_screen)
+            .map(|h| h.route_layer(axum::middleware::from_fn(claim_screen_names))),
+        ApiRoute::post("/screens/batch", create_screens)
+            .map(|h| h.route_layer(axum::middleware::from_fn(claim_screen_names))),
+        ApiRoute::delete("/screens/{name}", delete_screen).map(|h| {
+            h.route_layer(axum::middleware::from_fn_with_state(
+                *LOCK_TIMEOUT,
+                wait_for_screen_ops,
+            ))
+        }),
+        ApiRoute::get("/debug/screens", list_screens),
+        ApiRoute::get("/packages/installed", installed_packages),
+        ApiRoute::get("/screens/{name}/info", screen_info),
+        ApiRoute::get("/screens/{name}/foreground", foreground),
+        ApiRoute::get("/screens/{name}/history", history),
+        ApiRoute::get("/screens/{name}/app-state", app_state),
+        ApiRoute::get("/screens/{name}/appops", get_appops),
+        ApiRoute::post("/screens/{name}/appops", set_appops),
+        ApiRoute::get("/screens/{name}/screenshot", screenshot),
+        ApiRoute::get("/screens/{name}/frame/raw", raw_frame),
+        ApiRoute::get("/screens/{name}/a11y", a11y),
+        ApiRoute::get("/screens/{name}/windows", windows),
+        ApiRoute::post("/screens/{name}/tap", tap),
+        ApiRoute::post("/screens/{name}/multi-tap", multi_tap),
+        ApiRoute::post("/screens/{name}/swipe", swipe),
+        ApiRoute::post("/screens/{name}/type", type_text),
+        ApiRoute::post("/screens/{name}/key", key),
+        ApiRoute::post("/screens/{name}/key-combo", key_combo),
+        ApiRoute::post("/screens/{name}/select", select),
+        ApiRoute::post("/screens/{name}/fill", fill),
+        ApiRoute::get("/screens/{name}/ime", ime),
+        ApiRoute::post("/screens/{name}/hide-ime", hide_ime),
+        ApiRoute::post("/screens/{name}/focus", focus),
+        ApiRoute::post("/screens/{name}/launch", launch),
+        ApiRoute::post("/screens/{name}/launch-intent", launch_intent),
+        ApiRoute::post("/screens/{name}/adopt", adopt),
+        ApiRoute::post("/screens/{name}/stop", stop),
+        ApiRoute::post("/screens/{name}/reset", reset),
+        ApiRoute::post("/screens/{name}/release-package", release_package),
+        ApiRoute::post("/screens/{name}/heartbeat", heartbeat),
+        ApiRoute::post("/screens/{name}/open-url", open_url),
+        ApiRoute::post("/screens/{name}/wait-for-idle", wait_for_idle),
+        ApiRoute::post("/screens/{name}/cancel", cancel),
+        ApiRoute::post("/screens/{name}/wait-for-node", wait_for_node),
+        ApiRoute::get("/screens/{name}/notifications", notifications),
+        ApiRoute::post("/screens/{name}/notifications/open", open_notifications),
+        ApiRoute::post("/device/airplane-mode", airplane_mode),
+        ApiRoute::post("/device/wifi", wifi),
+        ApiRoute::post("/device/mobile-data", mobile_data),
+        ApiRoute::post("/device/time", set_time),
+        ApiRoute::get("/device/ime", input_methods),
+        ApiRoute::post("/device/ime", set_input_method),
+        ApiRoute::get("/openapi.json", || async { Json(openapi::document()) }),
+        ApiRoute::get("/metrics", move || async move {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                metrics.render(),
+            )
+        }),
+    ]
+}
+
+/// zstd response compression unless `ANDY_NO_COMPRESS=1`. Negotiated per request: clients
+/// that don't send `Accept-Encoding: zstd` (curl without --compressed, or `identity`) get
+/// plain bodies either way.
+fn compression() -> tower_http::compression::CompressionLayer {
+    tower_http::compression::CompressionLayer::new()
+        .zstd(std::env::var("ANDY_NO_COMPRESS").as_deref() != Ok("1"))
+        .no_br()
+        .no_gzip()
+        .no_deflate()
+}
+
+/// `ANDY_PATH_PREFIX` as `/segment[/segment...]`, for serving behind a reverse proxy that
+/// forwards e.g. `/android/...` unchanged.
+fn path_prefix() -> Option<String> {
+    let prefix = std::env::var("ANDY_PATH_PREFIX").ok()?;
+    let prefix = prefix.trim().trim_matches('/');
+    (!prefix.is_empty()).then(|| format!("/{prefix}"))
+}
+
+/// Where the CLI pushes the server, and where it looks for the server's logs.
+const DEVICE_DIR: &str = "/data/local/tests/coordinator";
+
+/// The daemon's stdout and stderr, for output from before logging is up.
+static DAEMON_OUT: LazyLock<String> = LazyLock::new(|| format!("{DEVICE_DIR}/daemon.out"));
+
+/// The daemon's `app_process`, run through `su <ANDY_RUN_AS>` when set so input injection
+/// and `am`/`pm` get that uid's privileges.
+fn daemon_command() -> Command {
+    let Ok(user) = std::env::var("ANDY_RUN_AS") else {
+        let mut cmd = Command::new("app_process");
+        cmd.arg0("andy-coordinator")
+            .args(["/system/bin", "com.coordinator.Main"])
+            .env("ANDY_DAEMON", "1");
+        return cmd;
+    };
+    // su may scrub the environment, so hand the settings over explicitly. argv[0] belongs
+    // to su here; the trailing marker keeps `pkill -f andy-coordinator` working.
+    let mut cmd = Command::new("su");
+    cmd.arg(&user).arg("env").arg("ANDY_DAEMON=1");
+    for (key, value) in std::env::vars() {
+        if key == "CLASSPATH" || (key.starts_with("ANDY_") && key != "ANDY_RUN_AS") {
+            cmd.arg(format!("{key}={value}"));
+        }
+    }
+    cmd.args([
+        "app_process",
+        "/system/bin",
+        "com.coordinator.Main",
+        "andy-coordinator",
+    ]);
+    cmd
+}
+
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_coordinator_Main_nativeRun(
     mut env: JNIEnv,
@@ -1056,26 +4730,28 @@ pub extern "system" fn Java_com_coordinator_Main_nativeRun(
     let is_daemon = std::env::var("ANDY_DAEMON").is_ok();
 
     if !is_daemon {
-        // Parent: spawn daemon child, print "ready", exit
-        let mut cmd = Command::new("app_process");
-        cmd.arg0("andy-coordinator")
-            .args(["/system/bin", "com.coordinator.Main"])
-            .env("ANDY_DAEMON", "1")
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
+        // Parent: spawn daemon child, print "ready", exit. The daemon's own output (JVM
+        // crashes, panics before logging is up) goes to a file the CLI shows on startup failure.
+        let out = std::fs::File::create(&*DAEMON_OUT).ok();
+        let err = out.as_ref().and_then(|f| f.try_clone().ok());
+        let mut cmd = daemon_command();
+        cmd.stdin(Stdio::null())
+            .stdout(out.map_or_else(Stdio::null, Stdio::from))
+            .stderr(err.map_or_else(Stdio::null, Stdio::from));
         unsafe {
             cmd.pre_exec(|| {
                 libc::setsid();
                 Ok(())
             });
         }
+        // The daemon outlives us in its own session; nothing to reap here.
+        #[allow(clippy::zombie_processes)]
         cmd.spawn().expect("spawn daemon");
         return;
     }
 
     let log_file =
-        std::fs::File::create("/data/local/tests/coordinator/andy.log").expect("create log file");
+        std::fs::File::create(format!("{DEVICE_DIR}/andy.log")).expect("create log file");
     tracing_subscriber::fmt()
         .with_writer(log_file)
         .with_ansi(false)
@@ -1120,39 +4796,48 @@ pub extern "system" fn Java_com_coordinator_Main_nativeRun(
         .expect("create global ref for AccessibilityBridge");
 
     let jvm = Arc::new(env.get_java_vm().expect("get JavaVM"));
-    let state: AppState = Arc::new(tokio::sync::Mutex::new(ServerState {
+    let soonest_expiry = ReapDeadline::new();
+    let metrics = Arc::new(metrics::Metrics::default());
+    let mut server = ServerState {
         jvm,
         screen_class: screen_class_global,
         screens: HashMap::new(),
         a11y_bridge,
-    }));
-
-    let app = Router::new()
-        .route("/screens", post(create_screen))
-        .route("/screens/{name}", delete(delete_screen))
-        .route("/debug/screens", get(list_screens))
-        .route("/screens/{name}/info", get(screen_info))
-        .route("/screens/{name}/screenshot", get(screenshot))
-        .route("/screens/{name}/frame/raw", get(raw_frame))
-        .route("/screens/{name}/a11y", get(a11y))
-        .route("/screens/{name}/tap", post(tap))
-        .route("/screens/{name}/swipe", post(swipe))
-        .route("/screens/{name}/type", post(type_text))
-        .route("/screens/{name}/key", post(key))
-        .route("/screens/{name}/launch", post(launch))
-        .route("/screens/{name}/stop", post(stop))
-        .route("/screens/{name}/reset", post(reset))
-        .route("/screens/{name}/heartbeat", post(heartbeat))
-        .route("/screens/{name}/open-url", post(open_url))
-        .route("/screens/{name}/wait-for-idle", post(wait_for_idle))
-        .layer(
-            tower_http::compression::CompressionLayer::new()
-                .zstd(true)
-                .no_br()
-                .no_gzip()
-                .no_deflate(),
-        )
+        soonest_expiry: soonest_expiry.clone(),
+        persist_path: persist::path_from_env(),
+        metrics: metrics.clone(),
+    };
+    server.restore_screens();
+    let state: AppState = Arc::new(tokio::sync::Mutex::new(server));
+
+    let app = api_routes(&metrics)
+        .into_iter()
+        .fold(Router::new(), |app, route| {
+            tracing::debug!(method = %route.method, path = route.path, "route");
+            app.route(route.path, route.handler)
+        })
+        .layer(axum::middleware::from_fn(track_op))
+        .layer(axum::middleware::from_fn_with_state(
+            idempotency::Cache::default(),
+            idempotency::replay,
+        ))
+        .layer(compression())
+        .layer(axum::middleware::from_fn_with_state(
+            metrics.clone(),
+            count_requests,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            AUTH_TOKEN.clone(),
+            require_token,
+        ))
         .with_state(state.clone());
+    let app = match path_prefix() {
+        Some(prefix) => {
+            tracing::info!(%prefix, "serving the api under a path prefix");
+            Router::new().nest(&prefix, app)
+        }
+        None => app,
+    };
 
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -1160,21 +4845,601 @@ pub extern "system" fn Java_com_coordinator_Main_nativeRun(
         .expect("build tokio runtime");
 
     runtime.block_on(async move {
-        // Spawn reaper task that checks for dead screens every 2s
+        // Spawn reaper task that checks for dead screens every 2s, taking the lock only
+        // once some screen may have expired
         let reaper_state = state.clone();
         tokio::spawn(async move {
             let mut interval = time::interval(std::time::Duration::from_secs(2));
             loop {
                 interval.tick().await;
-                reaper_state.lock().await.reap_dead_screens();
+                if soonest_expiry.is_due() {
+                    let reaped = run_op(&reaper_state, |s| {
+                        s.reap_dead_screens();
+                        Ok(())
+                    })
+                    .await;
+                    if let Err(e) = reaped {
+                        tracing::warn!(error = %e.message, "reaper skipped");
+                    }
+                }
             }
         });
 
-        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], PORT));
+        let addr = std::net::SocketAddr::new(bind_ip(), PORT);
         let listener = tokio::net::TcpListener::bind(addr)
             .await
             .expect("bind tcp listener");
-        tracing::info!(port = PORT, "http api ready");
+        tracing::info!(%addr, "http api ready");
         axum::serve(listener, app).await.expect("tcp server failed");
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::{delete, get, post};
+
+    #[test]
+    fn cancel_ends_waits_started_before_it() {
+        CANCEL_GENERATIONS
+            .lock()
+            .unwrap()
+            .insert("cancel-test".into(), 0);
+        let running = WaitCancel::new("cancel-test");
+        assert!(!running.cancelled());
+        cancel_waits("cancel-test").unwrap();
+        assert!(running.cancelled());
+        assert!(!WaitCancel::new("cancel-test").cancelled());
+
+        CANCEL_GENERATIONS.lock().unwrap().remove("cancel-test");
+        assert!(running.cancelled());
+        assert_eq!(
+            cancel_waits("cancel-test").unwrap_err().status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn concurrent_creates_share_one_display() {
+        use tower::ServiceExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // Stands in for create_screen's get-or-create without the state lock, which is
+            // what would let two creates race
+            let displays = Arc::new(std::sync::Mutex::new(HashMap::new()));
+            let created = Arc::new(AtomicU64::new(0));
+            let create = {
+                let (displays, created) = (displays.clone(), created.clone());
+                move |Json(req): Json<serde_json::Value>| async move {
+                    let name = req["name"].as_str().unwrap().to_string();
+                    if let Some(&id) = displays.lock().unwrap().get(&name) {
+                        return Json(id);
+                    }
+                    // Long enough for the other create to reach the claim
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    let id = created.fetch_add(1, Ordering::Relaxed);
+                    displays.lock().unwrap().insert(name, id);
+                    Json(id)
+                }
+            };
+            let app = Router::new().route(
+                "/screens",
+                post(create).route_layer(axum::middleware::from_fn(claim_screen_names)),
+            );
+            let request = || {
+                axum::extract::Request::builder()
+                    .method("POST")
+                    .uri("/screens")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(r#"{"name": "create-test"}"#))
+                    .unwrap()
+            };
+            let display = |response: Result<Response, _>| async {
+                let body = response.unwrap().into_body();
+                axum::body::to_bytes(body, usize::MAX).await.unwrap()
+            };
+
+            let (a, b) = tokio::join!(
+                app.clone().oneshot(request()),
+                app.clone().oneshot(request())
+            );
+            assert_eq!(display(a).await, display(b).await);
+            assert_eq!(created.load(Ordering::Relaxed), 1);
+            assert!(SCREEN_CREATIONS.in_progress.lock().unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn delete_waits_for_running_ops() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let slow = SCREEN_OPS.start("delete-test");
+            let short = std::time::Duration::from_millis(20);
+            let busy = SCREEN_OPS.wait_idle("delete-test", short).await;
+            assert_eq!(busy.unwrap_err().status, StatusCode::CONFLICT);
+
+            tokio::spawn(async move {
+                time::sleep(std::time::Duration::from_millis(50)).await;
+                drop(slow);
+            });
+            let long = std::time::Duration::from_secs(5);
+            SCREEN_OPS.wait_idle("delete-test", long).await.unwrap();
+            assert_eq!(SCREEN_OPS.running("delete-test"), 0);
+        });
+    }
+
+    #[test]
+    fn held_swipe_lifts_off_without_velocity() {
+        let mut req = SwipeRequest {
+            x1: 540.0,
+            y1: 1344.0,
+            x2: 540.0,
+            y2: 576.0,
+            duration_ms: 800,
+            steps: None,
+            easing: Easing::Linear,
+            hold_ms: 150,
+        };
+        // Android's velocity tracker looks at the last 100ms of moves before lift-off
+        let end_speed = |path: &[(f32, f32, i64)]| {
+            let &(_, up_y, up_at) = path.last().unwrap();
+            let &(_, y, at) = path.iter().find(|p| p.2 >= up_at - 100).unwrap();
+            (up_y - y).abs() / (up_at - at) as f32 * 1000.0
+        };
+        let path = swipe_path(&req);
+        assert_eq!(path.first(), Some(&(540.0, 1344.0, 0)));
+        assert_eq!(path.last(), Some(&(540.0, 576.0, 950)));
+        assert!(path.windows(2).all(|w| w[0].2 <= w[1].2));
+        assert_eq!(end_speed(&path), 0.0);
+
+        req.hold_ms = 0;
+        assert!(end_speed(&swipe_path(&req)) > 900.0);
+    }
+
+    #[test]
+    fn long_default_step_swipe_stays_bounded() {
+        let req = SwipeRequest {
+            x1: 0.0,
+            y1: 0.0,
+            x2: 100.0,
+            y2: 100.0,
+            duration_ms: 100_000_000_000_000,
+            steps: None,
+            easing: Easing::Linear,
+            hold_ms: 0,
+        };
+        let path = swipe_path(&req);
+        assert_eq!(path.len(), MAX_SWIPE_STEPS as usize + 1);
+        assert_eq!(path.last(), Some(&(100.0, 100.0, req.duration_ms)));
+        assert!(path.windows(2).all(|w| w[0].2 <= w[1].2));
+    }
+
+    #[test]
+    fn multi_tap_takes_two_to_ten_points() {
+        let points = |n: usize| MultiTapRequest {
+            points: (0..n)
+                .map(|i| TapRequest {
+                    x: i as f32,
+                    y: 0.0,
+                })
+                .collect(),
+        };
+        assert!(points(1).validate().is_err());
+        assert!(points(2).validate().is_ok());
+        assert!(points(MAX_TOUCH_POINTERS).validate().is_ok());
+        let err = points(MAX_TOUCH_POINTERS + 1).validate().unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn header_text_percent_encodes_non_ascii() {
+        assert_eq!(header_text("Maps keeps stopping"), "Maps keeps stopping");
+        assert_eq!(
+            header_text("«Karten» wurde beendet 100%"),
+            "%C2%ABKarten%C2%BB wurde beendet 100%25"
+        );
+    }
+
+    #[test]
+    fn delete_answers_409_while_a_screen_request_runs() {
+        use tower::ServiceExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let release = Arc::new(tokio::sync::Notify::new());
+            let slow_op = {
+                let release = release.clone();
+                move || async move {
+                    release.notified().await;
+                    StatusCode::OK
+                }
+            };
+            let short = std::time::Duration::from_millis(50);
+            let app = Router::new()
+                .route(
+                    "/screens/{name}",
+                    delete(|| async { StatusCode::OK }).route_layer(
+                        axum::middleware::from_fn_with_state(short, wait_for_screen_ops),
+                    ),
+                )
+                .route("/screens/{name}/wait-for-idle", post(slow_op))
+                .layer(axum::middleware::from_fn(track_op));
+            let request = |method: &str, uri: &str| {
+                axum::extract::Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap()
+            };
+            let status = |response: Result<Response, _>| response.unwrap().status();
+
+            let slow = tokio::spawn(
+                app.clone()
+                    .oneshot(request("POST", "/screens/route-test/wait-for-idle")),
+            );
+            while SCREEN_OPS.running("route-test") == 0 {
+                tokio::task::yield_now().await;
+            }
+            let busy = app
+                .clone()
+                .oneshot(request("DELETE", "/screens/route-test"));
+            assert_eq!(status(busy.await), StatusCode::CONFLICT);
+            // The delete's own route isn't counted, or it would always wait on itself
+            let other = app
+                .clone()
+                .oneshot(request("DELETE", "/screens/route-idle"));
+            assert_eq!(status(other.await), StatusCode::OK);
+
+            release.notify_one();
+            assert_eq!(status(slow.await.unwrap()), StatusCode::OK);
+            let done = app.oneshot(request("DELETE", "/screens/route-test"));
+            assert_eq!(status(done.await), StatusCode::OK);
+        });
+    }
+
+    #[test]
+    fn compression_follows_accept_encoding() {
+        use tower::ServiceExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let app = Router::new()
+                .route("/openapi.json", get(|| async { Json(openapi::document()) }))
+                .layer(compression());
+            let fetch = |accept: &'static str| {
+                let request = axum::extract::Request::builder()
+                    .uri("/openapi.json")
+                    .header(header::ACCEPT_ENCODING, accept)
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                app.clone().oneshot(request)
+            };
+
+            let plain = fetch("identity").await.unwrap();
+            assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+            let body = axum::body::to_bytes(plain.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(serde_json::from_slice::<serde_json::Value>(&body).is_ok());
+
+            let zstd = fetch("zstd").await.unwrap();
+            assert_eq!(zstd.headers()[header::CONTENT_ENCODING], "zstd");
+        });
+    }
+
+    #[test]
+    fn openapi_documents_every_route() {
+        use std::collections::BTreeSet;
+
+        let served: BTreeSet<(String, String)> = api_routes(&Arc::default())
+            .into_iter()
+            .map(|route| (route.path.to_owned(), route.method.as_str().to_lowercase()))
+            .collect();
+        let doc = openapi::document();
+        let documented: BTreeSet<(String, String)> = doc["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .filter(|key| *key != "parameters")
+                    .map(move |method| (path.clone(), method.clone()))
+            })
+            .collect();
+        assert_eq!(served, documented);
+    }
+
+    #[test]
+    fn chroma_sets_luma_sampling_factor() {
+        // The first component's sampling byte in the SOF0 segment: 0x22 is 4:2:0, 0x11 4:4:4
+        let luma_sampling = |chroma| {
+            let codec = Codec {
+                chroma,
+                ..Default::default()
+            };
+            let jpeg = encode_jpeg(&[128; 16 * 16 * 4], 16, 16, codec).unwrap();
+            let sof = jpeg.windows(2).position(|m| m == [0xFF, 0xC0]).unwrap();
+            jpeg[sof + 11]
+        };
+        assert_eq!(luma_sampling(Chroma::Half), 0x22);
+        assert_eq!(luma_sampling(Chroma::Full), 0x11);
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn avif_reports_its_size() {
+        let codec = Codec {
+            avif: true,
+            quality: DEFAULT_AVIF_QUALITY,
+            ..Default::default()
+        };
+        let avif = encode_image(&[200; 24 * 16 * 4], 24, 16, codec).unwrap();
+        assert_eq!(image::dimensions(&avif), Some((24, 16)));
+    }
+
+    fn request(width: i32, height: i32, dpi: i32) -> CreateScreenRequest {
+        CreateScreenRequest {
+            name: "default".into(),
+            width,
+            height,
+            dpi,
+            timeout_secs: None,
+            package: "com.example".into(),
+            auto_dismiss: None,
+            dismiss_rules: None,
+        }
+    }
+
+    #[test]
+    fn nearby_packages_walk_up_the_namespace() {
+        let installed: std::collections::HashSet<String> = [
+            "com.example.shop",
+            "com.example.shop.debug",
+            "com.android.settings",
+            "org.other.maps",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            nearby_packages("com.example.shopp", &installed),
+            ["com.example.shop", "com.example.shop.debug"]
+        );
+        assert_eq!(
+            nearby_packages("net.acme.maps", &installed),
+            ["org.other.maps"]
+        );
+        assert!(nearby_packages("com.nothing.here", &installed).is_empty());
+    }
+
+    #[test]
+    fn history_drops_the_oldest_entry_when_full() {
+        let mut history = VecDeque::new();
+        for keycode in 0..HISTORY_LEN + 5 {
+            push_history(
+                &mut history,
+                "key",
+                serde_json::json!({ "keycode": keycode }),
+            );
+        }
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history.front().unwrap().params["keycode"], 5);
+        assert_eq!(history.back().unwrap().params["keycode"], HISTORY_LEN + 4);
+    }
+
+    #[test]
+    fn history_keeps_only_the_length_of_typed_text() {
+        let params = text_params("hunter2 ü", true);
+        assert_eq!(params, serde_json::json!({ "chars": 9, "paste": true }));
+        assert!(!params.to_string().contains("hunter2"));
+    }
+
+    #[test]
+    fn prefetched_tree_goes_stale_on_a_new_frame() {
+        let mut frames = FrameHistory::default();
+        frames.observe(Some(1));
+        let tree = PrefetchedTree {
+            at: Instant::now(),
+            frame_seq: frames.received,
+            json: "{}".into(),
+        };
+        frames.observe(None);
+        assert!(tree.current(&frames));
+        // Even a frame with the same picture: the capture can't tell what it missed
+        frames.observe(Some(1));
+        assert!(!tree.current(&frames));
+    }
+
+    #[test]
+    fn frame_history_counts_unchanged_captures() {
+        let mut frames = FrameHistory::default();
+        frames.observe(None);
+        assert!(frames.received_at.is_none());
+        frames.observe(Some(1));
+        frames.observe(None);
+        frames.observe(Some(1));
+        assert_eq!(frames.unchanged, 1);
+        assert!(frames.received_at.is_some());
+        frames.observe(Some(2));
+        assert_eq!(frames.unchanged, 0);
+        assert!(frames.changed_at.is_some());
+    }
+
+    #[test]
+    fn no_new_frame_after_an_interaction_is_not_frozen() {
+        let mut frames = FrameHistory::default();
+        frames.observe(Some(1));
+        let interaction = Instant::now();
+        frames.unchanged = 0; // as `record` does
+        for _ in 0..FROZEN_AFTER_CAPTURES + 2 {
+            frames.observe(None);
+        }
+        assert!(!frames.frozen_since(interaction));
+        for _ in 0..FROZEN_AFTER_CAPTURES {
+            frames.observe(Some(1));
+        }
+        assert!(frames.frozen_since(interaction));
+    }
+
+    #[test]
+    fn launch_intent_am_args() {
+        let req: LaunchIntentRequest = serde_json::from_value(serde_json::json!({
+            "activity": ".DeepLinkActivity",
+            "extras": [
+                {"type": "string", "key": "user", "value": "alice"},
+                {"type": "int", "key": "tab", "value": 2},
+                {"type": "bool", "key": "debug", "value": true},
+                {"type": "long", "key": "since", "value": 1700000000000i64},
+            ],
+        }))
+        .unwrap();
+        assert_eq!(
+            req.am_args("com.example").unwrap(),
+            [
+                "-n",
+                "com.example/.DeepLinkActivity",
+                "--es",
+                "user",
+                "alice",
+                "--ei",
+                "tab",
+                "2",
+                "--ez",
+                "debug",
+                "true",
+                "--el",
+                "since",
+                "1700000000000",
+            ]
+        );
+
+        let bad_type =
+            serde_json::json!({"extras": [{"type": "int", "key": "tab", "value": "two"}]});
+        assert!(serde_json::from_value::<LaunchIntentRequest>(bad_type).is_err());
+        let other_package = LaunchIntentRequest {
+            activity: Some("com.other/.Main".into()),
+            ..Default::default()
+        };
+        let err = other_package.am_args("com.example").unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn am_start_output() {
+        let outcome = |stdout: &str| am_start_outcome(true, stdout, "");
+        let started = "Starting: Intent { cmp=com.example/.Main }";
+        assert_eq!(outcome(started).unwrap(), AmStart::Started);
+        for already_running in [
+            "Starting: Intent { cmp=com.example/.Main }\nWarning: Activity not started, its \
+             current task has been brought to the front",
+            "Starting: Intent { cmp=com.example/.Main }\nWarning: Activity not started, intent \
+             has been delivered to currently running top-most instance.",
+        ] {
+            assert_eq!(outcome(already_running).unwrap(), AmStart::AlreadyRunning);
+        }
+        for failure in [
+            "Starting: Intent { act=android.intent.action.VIEW }\nError: Activity not started, \
+             unable to resolve Intent { act=android.intent.action.VIEW }",
+            "Starting: Intent { cmp=com.example/.Missing }\nError type 3\nError: Activity class \
+             {com.example/com.example.Missing} does not exist.",
+            "Starting: Intent { cmp=com.example/.Private }\njava.lang.SecurityException: \
+             Permission Denial: starting Intent",
+            "Starting: Intent { cmp=com.example/.Main }\nActivity not started, unknown error code -96",
+        ] {
+            assert!(outcome(failure).is_err(), "{failure}");
+        }
+        assert!(am_start_outcome(false, started, "boom").is_err());
+    }
+
+    #[test]
+    fn activity_component_formats() {
+        for activity in [".Main", "com.example.ui.Main", "com.example/.Main"] {
+            let component = activity_component("com.example", activity).unwrap();
+            assert!(component.starts_with("com.example/"), "{component}");
+        }
+        assert_eq!(
+            activity_component("com.example", "com.example/.Main").unwrap(),
+            "com.example/.Main"
+        );
+        for activity in ["", ".", "Main Activity", ".1Main", "com..Main", "a/b/c"] {
+            assert!(
+                activity_component("com.example", activity).is_err(),
+                "{activity}"
+            );
+        }
+    }
+
+    #[test]
+    fn get_or_create_keeps_an_unstated_timeout() {
+        // What a plain command sends after `--no-timeout` created the screen
+        let mut req: CreateScreenRequest = serde_json::from_value(serde_json::json!({
+            "name": "default", "width": 1080, "height": 1920, "dpi": 240, "package": "com.example",
+        }))
+        .unwrap();
+        assert_eq!(req.timeout_secs(0), 0);
+        assert_eq!(req.timeout_secs(DEFAULT_SCREEN_TIMEOUT_SECS), 300);
+        req.timeout_secs = Some(0);
+        assert_eq!(req.timeout_secs(300), 0);
+        req.timeout_secs = Some(60);
+        assert_eq!(req.timeout_secs(0), 60);
+    }
+
+    #[test]
+    fn get_or_create_keeps_unstated_dismiss_rules() {
+        let rule = |tap: &str| a11y::DismissRule {
+            when: "Allow ".into(),
+            tap: tap.into(),
+        };
+        let on = Some(vec![rule("Deny")]);
+        let mut req = request(1080, 1920, 240);
+        assert_eq!(req.dismiss_rules(on.clone()), on);
+        assert_eq!(req.dismiss_rules(None), None);
+        req.dismiss_rules = Some(vec![rule("Allow")]);
+        assert_eq!(req.dismiss_rules(on.clone()), req.dismiss_rules);
+        assert_eq!(req.dismiss_rules(None), None);
+        req.auto_dismiss = Some(false);
+        assert_eq!(req.dismiss_rules(on.clone()), None);
+        req.auto_dismiss = Some(true);
+        req.dismiss_rules = None;
+        assert_eq!(req.dismiss_rules(None), Some(a11y::default_dismiss_rules()));
+    }
+
+    #[test]
+    fn screen_geometry_bounds() {
+        assert!(request(1, 1, 72).validate_geometry().is_ok());
+        assert!(request(4096, 4096, 960).validate_geometry().is_ok());
+        for (width, height, dpi) in [(0, 100, 160), (100, 4097, 160), (-1, 100, 160)] {
+            let err = request(width, height, dpi).validate_geometry().unwrap_err();
+            assert_eq!(err.status, StatusCode::BAD_REQUEST);
+            assert!(
+                err.message.contains("out of range 1..=4096"),
+                "{}",
+                err.message
+            );
+        }
+        for dpi in [71, 961, 0] {
+            let err = request(1080, 1920, dpi).validate_geometry().unwrap_err();
+            assert_eq!(err.status, StatusCode::BAD_REQUEST);
+            assert!(
+                err.message.starts_with(&format!("dpi {dpi} ")),
+                "{}",
+                err.message
+            );
+        }
+    }
+}
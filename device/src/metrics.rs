@@ -0,0 +1,124 @@
+//! Counters and histograms served at `GET /metrics` in the Prometheus text format.
+//! Hand-rolled on atomics: the handful of series here doesn't justify a metrics stack.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds in seconds, from a fast JNI call to a stuck one.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&le| secs <= le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (le, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Keyed by (matched route, status code).
+    requests: Mutex<BTreeMap<(String, u16), u64>>,
+    pub jni_call: Histogram,
+    pub screenshot_encode: Histogram,
+    pub screens: AtomicU64,
+    pub screens_reaped: AtomicU64,
+}
+
+impl Metrics {
+    pub fn count_request(&self, route: &str, status: u16) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((route.to_string(), status))
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP andy_requests_total HTTP requests by route and status.\n");
+        out.push_str("# TYPE andy_requests_total counter\n");
+        for ((route, status), n) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "andy_requests_total{{route=\"{route}\",status=\"{status}\"}} {n}"
+            );
+        }
+        self.jni_call.render(
+            &mut out,
+            "andy_jni_call_seconds",
+            "Time inside JNI sections, including screenshot encoding done there.",
+        );
+        self.screenshot_encode.render(
+            &mut out,
+            "andy_screenshot_encode_seconds",
+            "Time spent encoding screenshot JPEGs.",
+        );
+        out.push_str("# HELP andy_screens Screens currently alive.\n");
+        out.push_str("# TYPE andy_screens gauge\n");
+        let _ = writeln!(out, "andy_screens {}", self.screens.load(Ordering::Relaxed));
+        out.push_str("# HELP andy_screens_reaped_total Screens removed for missing heartbeats.\n");
+        out.push_str("# TYPE andy_screens_reaped_total counter\n");
+        let _ = writeln!(
+            out,
+            "andy_screens_reaped_total {}",
+            self.screens_reaped.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        metrics.jni_call.observe(Duration::from_millis(3));
+        metrics.jni_call.observe(Duration::from_millis(40));
+        metrics.jni_call.observe(Duration::from_secs(30));
+        metrics.count_request("/screens/{name}/tap", 200);
+        let text = metrics.render();
+        assert!(text.contains("andy_jni_call_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("andy_jni_call_seconds_bucket{le=\"0.05\"} 2\n"));
+        assert!(text.contains("andy_jni_call_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(text.contains("andy_jni_call_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("andy_jni_call_seconds_count 3\n"));
+        assert!(
+            text.contains("andy_requests_total{route=\"/screens/{name}/tap\",status=\"200\"} 1\n")
+        );
+    }
+}
@@ -0,0 +1,1015 @@
+//! Hand-written OpenAPI 3.1 description of the HTTP API, served at `GET /openapi.json`.
+//! Keep it in step with `api_routes` in `lib.rs` (checked by a test there) and the serde
+//! types named here.
+
+use serde_json::{Value, json};
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn array_of(item: Value) -> Value {
+    json!({ "type": "array", "items": item })
+}
+
+fn body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+fn empty(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn query(name: &str, schema: Value, description: &str) -> Value {
+    json!({ "name": name, "in": "query", "schema": schema, "description": description })
+}
+
+/// `no_wait`, `wait` and `wait_ms`, accepted by every read that auto-waits.
+fn wait_params() -> Vec<Value> {
+    vec![
+        query(
+            "no_wait",
+            json!({ "type": "boolean" }),
+            "Shorthand for wait=none.",
+        ),
+        query(
+            "wait",
+            json!({ "enum": ["idle", "fixed", "none"] }),
+            "How to settle after the last interaction; idle by default.",
+        ),
+        query(
+            "wait_ms",
+            json!({ "type": "integer", "minimum": 0 }),
+            "Sleep for wait=fixed, in milliseconds.",
+        ),
+    ]
+}
+
+/// [`wait_params`] plus the idle wait bounds launch accepts.
+fn launch_params() -> Vec<Value> {
+    let mut params = wait_params();
+    params.extend([
+        query(
+            "idle_timeout_ms",
+            json!({ "type": "integer", "minimum": 0 }),
+            "How long the app must stay idle for wait=idle; 5000 by default.",
+        ),
+        query(
+            "global_timeout_ms",
+            json!({ "type": "integer", "minimum": 0 }),
+            "Longest wait=idle waits overall; 30000 by default.",
+        ),
+        query(
+            "prefetch",
+            json!({ "type": "boolean" }),
+            "Once settled, dump the a11y tree and take a screenshot so the next a11y read (within 5s, before any other action) and a default screenshot of an unchanged frame are served from cache. Ignored with no_wait.",
+        ),
+    ]);
+    params
+}
+
+fn op(summary: &str, params: Vec<Value>, request: Option<Value>, response: Value) -> Value {
+    let mut op = json!({
+        "summary": summary,
+        "parameters": params,
+        "responses": {
+            "200": response,
+            "default": { "$ref": "#/components/responses/Error" },
+        },
+    });
+    if let Some(request) = request {
+        op["requestBody"] = request;
+    }
+    op
+}
+
+/// A path under `/screens/{name}`, which every operation on it shares.
+fn screen_path(ops: Value) -> Value {
+    let mut item = ops;
+    item["parameters"] = json!([{
+        "name": "name",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+        "description": "Screen name, e.g. default.",
+    }]);
+    item
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({ "type": "object", "required": required, "properties": properties })
+}
+
+/// A JSON object built entry by entry; one `json!` for the whole table would exceed the
+/// macro recursion limit.
+fn keyed<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn paths() -> Value {
+    let entries = [
+        (
+            "/screens",
+            json!({
+                "post": op(
+                    "Get or create a screen bound to a package",
+                    vec![],
+                    Some(body(schema("CreateScreenRequest"))),
+                    json_response(
+                        "The screen. 201 instead when this request bound its package (new screen, or one whose package was released). 429 when a new name would exceed ANDY_MAX_SCREENS.",
+                        schema("ScreenInfo"),
+                    ),
+                ),
+            }),
+        ),
+        (
+            "/screens/batch",
+            json!({
+                "post": op(
+                    "Get or create several screens, reporting each",
+                    vec![],
+                    Some(body(array_of(schema("CreateScreenRequest")))),
+                    json_response("One result per request, in order", array_of(schema("BatchScreenResult"))),
+                ),
+            }),
+        ),
+        (
+            "/screens/{name}",
+            screen_path(json!({
+                "delete": op("Release a screen and its display once requests running on it finish; 409 if they still run after the lock timeout (end their waits with `cancel` first)", vec![], None, empty("Deleted")),
+            })),
+        ),
+        (
+            "/debug/screens",
+            json!({
+                "get": op("List all screens", vec![], None, json_response("Screens", array_of(schema("ScreenInfo")))),
+            }),
+        ),
+        (
+            "/packages/installed",
+            json!({
+                "get": op(
+                    "Installed packages a package spec could bind",
+                    vec![query("package", json!({ "type": "string" }), "Package, prefix, or comma-separated pool.")],
+                    None,
+                    json_response("Matches, or similar packages when nothing matches", schema("InstalledPackages")),
+                ),
+            }),
+        ),
+        (
+            "/screens/{name}/info",
+            screen_path(json!({
+                "get": op("Screen geometry, binding and lifetime", vec![], None, json_response("The screen", schema("ScreenInfo"))),
+            })),
+        ),
+        (
+            "/screens/{name}/foreground",
+            screen_path(json!({
+                "get": op("Foreground package and activity", vec![], None, json_response("Foreground", schema("ForegroundInfo"))),
+            })),
+        ),
+        (
+            "/screens/{name}/history",
+            screen_path(json!({
+                "get": op(
+                    "Recent actions performed on the screen, oldest first",
+                    vec![query("limit", json!({ "type": "integer", "minimum": 0 }), "Only the most recent this many (at most 200 are kept).")],
+                    None,
+                    json_response("History", array_of(schema("HistoryEntry"))),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/app-state",
+            screen_path(json!({
+                "get": op("Whether the bound app is installed, running and in front", vec![], None, json_response("State", schema("AppProcessState"))),
+            })),
+        ),
+        (
+            "/screens/{name}/appops",
+            screen_path(json!({
+                "get": op(
+                    "App op modes of the bound app",
+                    vec![query("op", json!({ "type": "string" }), "Only this op.")],
+                    None,
+                    json_response("Modes", array_of(schema("OpMode"))),
+                ),
+                "post": op(
+                    "Set an app op mode for the bound app",
+                    vec![],
+                    Some(body(schema("AppOpsSetRequest"))),
+                    json_response("Modes after the change", array_of(schema("OpMode"))),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/screenshot",
+            screen_path(json!({
+                "get": op(
+                    "JPEG, AVIF or raw screenshot",
+                    [
+                        wait_params(),
+                        vec![
+                            query("scale", json!({ "type": "number", "exclusiveMinimum": 0, "maximum": 1 }), "Downscale factor."),
+                            query("max_dim", json!({ "type": "integer", "minimum": 1 }), "Longest side at most this many pixels."),
+                            query("debug", json!({ "type": "boolean" }), "Mark the last tap or swipe."),
+                            query("node", json!({ "type": "string" }), "Crop to the node with this text or content_desc."),
+                            query("grayscale", json!({ "type": "boolean" }), "Encode luminance only."),
+                            query("chroma", json!({ "enum": ["420", "444"] }), "JPEG chroma subsampling; 444 keeps thin colored text sharp at a larger size. 420 by default."),
+                            query("full", json!({ "type": "boolean" }), "Scroll through a scrollable and stitch it into one image."),
+                            query("scroll_node", json!({ "type": "string" }), "With full, the scrollable to page through."),
+                            query("settle_frames", json!({ "type": "integer", "minimum": 0, "maximum": 30 }), "Frames to capture and discard first, 50ms apart, for animations still running after idle."),
+                            query("format", json!({ "enum": ["jpeg", "avif", "raw"] }), "`avif` is smaller at the same quality but encodes about 100x slower, so suits one-off captures over slow links; servers built without the `avif` feature answer 400. `raw` returns the unencoded frame: width * height * 4 bytes of RGBA, rows top to bottom without padding. `X-Pixel-Format` (`rgba` or `bgra`) is the layout the surface delivered, already converted to RGBA. No scale, max_dim, debug, node, quality, grayscale or chroma."),
+                            query("quality", json!({ "type": "integer", "minimum": 1, "maximum": 100 }), "Encoder quality; 85 for JPEG and 50 for AVIF by default."),
+                        ],
+                    ]
+                    .concat(),
+                    None,
+                    json!({
+                        "description": "The image. `X-Width`, `X-Height`, `X-Format`, `X-Scale` and `X-Wait-Ms` describe it; `X-Frame-Frozen` warns of a stalled display, `X-Frame-Age-Ms` is how long ago the frame arrived (0 when captured for this request; not sent for full screenshots), `X-Interaction-Age-Ms` how long ago the last interaction was (absent before the first) and `X-Pages` counts stitched viewports.",
+                        "content": {
+                            "image/jpeg": { "schema": { "type": "string", "contentMediaType": "image/jpeg" } },
+                            "image/avif": { "schema": { "type": "string", "contentMediaType": "image/avif" } },
+                            "application/octet-stream": { "schema": { "type": "string", "contentMediaType": "application/octet-stream" } },
+                        },
+                    }),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/frame/raw",
+            screen_path(json!({
+                "get": op(
+                    "Next changed RGBA frame",
+                    [
+                        wait_params(),
+                        vec![query("base", json!({ "type": "integer", "minimum": 0 }), "X-Frame-Seq of the frame held; allows a region-only reply.")],
+                    ]
+                    .concat(),
+                    None,
+                    json!({
+                        "description": "Raw RGBA pixels, described by the `X-Frame-*` headers; `X-Region: x,y,w,h` when only a region is sent. 204 when nothing changed.",
+                        "content": { "application/octet-stream": { "schema": { "type": "string", "contentMediaType": "application/octet-stream" } } },
+                    }),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/a11y",
+            screen_path(json!({
+                "get": op(
+                    "Accessibility tree",
+                    [
+                        wait_params(),
+                        vec![
+                            query("format", json!({ "enum": ["json", "xml"] }), "xml is `uiautomator dump` format."),
+                            query(
+                                "peek",
+                                json!({ "type": "boolean" }),
+                                "Read without refreshing the screen's heartbeat, so polling doesn't keep an idle screen alive. Implies no_wait.",
+                            ),
+                        ],
+                    ]
+                    .concat(),
+                    None,
+                    json!({
+                        "description": "The tree",
+                        "content": {
+                            "application/json": { "schema": schema("A11yTree") },
+                            "application/xml": { "schema": { "type": "string" } },
+                        },
+                    }),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/windows",
+            screen_path(json!({
+                "get": op(
+                    "Windows on the screen, topmost first, without their nodes",
+                    vec![],
+                    None,
+                    json_response("The windows", array_of(schema("Window"))),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/tap",
+            screen_path(json!({
+                "post": op(
+                    "Tap a point",
+                    [
+                        wait_params(),
+                        vec![
+                            query("coord_space", json!({ "enum": ["device", "screenshot"] }), "Space of x and y."),
+                            query("snapshot", json!({ "type": "boolean" }), "Answer with a screenshot and tree taken after the tap."),
+                        ],
+                    ]
+                    .concat(),
+                    Some(body(schema("TapRequest"))),
+                    json!({
+                        "description": "Done: `X-Wait-Ms`, and after a wait `X-Ui-Changed`, `X-App-Crashed` and `X-Auto-Dismissed`, describe what followed. `X-App-Crashed` and `X-Auto-Dismissed` hold dialog text, percent-encoded as UTF-8. With snapshot=true the body holds the capture.",
+                        "content": { "application/json": { "schema": schema("Snapshot") } },
+                    }),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/multi-tap",
+            screen_path(json!({
+                "post": op(
+                    "Tap 2 to 10 points at once, one finger each (not one after another)",
+                    wait_params(),
+                    Some(body(schema("MultiTapRequest"))),
+                    empty("Done: `X-Wait-Ms`, and after a wait `X-Ui-Changed`, `X-App-Crashed` and `X-Auto-Dismissed`, describe what followed. `X-App-Crashed` and `X-Auto-Dismissed` hold dialog text, percent-encoded as UTF-8."),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/swipe",
+            screen_path(json!({
+                "post": op("Swipe between two points", vec![], Some(body(schema("SwipeRequest"))), empty("Done")),
+            })),
+        ),
+        (
+            "/screens/{name}/type",
+            screen_path(json!({
+                "post": op("Type text into the focused field", vec![], Some(body(schema("TypeRequest"))), empty("Done")),
+            })),
+        ),
+        (
+            "/screens/{name}/key",
+            screen_path(json!({
+                "post": op("Press a key", vec![], Some(body(schema("KeyRequest"))), empty("Done")),
+            })),
+        ),
+        (
+            "/screens/{name}/key-combo",
+            screen_path(json!({
+                "post": op("Press keys together", vec![], Some(body(schema("KeyComboRequest"))), empty("Done")),
+            })),
+        ),
+        (
+            "/screens/{name}/select",
+            screen_path(json!({
+                "post": op("Select a range in a text field", vec![], Some(body(schema("SelectRequest"))), empty("Done")),
+            })),
+        ),
+        (
+            "/screens/{name}/fill",
+            screen_path(json!({
+                "post": op(
+                    "Tap a text field and type into it under one lock",
+                    vec![],
+                    Some(body(schema("FillRequest"))),
+                    json_response("The node that was tapped", schema("Node")),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/focus",
+            screen_path(json!({
+                "post": op(
+                    "Move input focus like a D-pad",
+                    vec![],
+                    Some(body(schema("FocusRequest"))),
+                    json_response("The newly focused node", schema("Node")),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/launch",
+            screen_path(json!({
+                "post": op(
+                    "Launch the bound app",
+                    launch_params(),
+                    None,
+                    json!({ "description": "Launched. `X-Cold-Start: false` when the app was already running." }),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/launch-intent",
+            screen_path(json!({
+                "post": op(
+                    "Launch the bound app with an explicit intent",
+                    launch_params(),
+                    Some(body(schema("LaunchIntentRequest"))),
+                    json!({ "description": "Launched. `X-Cold-Start: false` when the app was already running." }),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/cancel",
+            screen_path(json!({
+                "post": op("End waits in progress on the screen (wait-for-idle, wait-for-node, auto-waits); they return as if timed out", vec![], None, empty("Cancelled")),
+            })),
+        ),
+        (
+            "/screens/{name}/ime",
+            screen_path(json!({
+                "get": op("Whether the soft keyboard is shown on the screen and how much it covers", vec![], None, json_response("Keyboard", schema("ImeInfo"))),
+            })),
+        ),
+        (
+            "/screens/{name}/hide-ime",
+            screen_path(json!({
+                "post": op("Close the soft keyboard with BACK if it is shown", vec![], None, json_response("Keyboard afterwards", schema("ImeInfo"))),
+            })),
+        ),
+        (
+            "/screens/{name}/adopt",
+            screen_path(json!({
+                "post": op(
+                    "Move the bound app's running task (e.g. started on the default display) onto this screen",
+                    vec![],
+                    None,
+                    json_response("Foreground of the screen afterwards", schema("ForegroundInfo")),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/stop",
+            screen_path(json!({
+                "post": op("Force-stop the bound app", vec![], None, empty("Stopped")),
+            })),
+        ),
+        (
+            "/screens/{name}/reset",
+            screen_path(json!({
+                "post": op("Clear the bound app's data", vec![], None, empty("Cleared")),
+            })),
+        ),
+        (
+            "/screens/{name}/release-package",
+            screen_path(json!({
+                "post": op(
+                    "Stop, clear and unbind the app, optionally binding another",
+                    vec![],
+                    Some(body(schema("ReleasePackageRequest"))),
+                    json_response("The screen", schema("ScreenInfo")),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/heartbeat",
+            screen_path(json!({
+                "post": op("Keep the screen from being reaped", vec![], None, empty("Done")),
+            })),
+        ),
+        (
+            "/screens/{name}/open-url",
+            screen_path(json!({
+                "post": op("Open a URL in the bound app", vec![], Some(body(schema("OpenUrlRequest"))), empty("Opened")),
+            })),
+        ),
+        (
+            "/screens/{name}/wait-for-idle",
+            screen_path(json!({
+                "post": op("Wait for the UI to go idle", vec![], Some(body(schema("WaitForIdleRequest"))), empty("Idle or timed out")),
+            })),
+        ),
+        (
+            "/screens/{name}/wait-for-node",
+            screen_path(json!({
+                "post": op(
+                    "Wait for a node to appear or disappear",
+                    vec![],
+                    Some(body(schema("WaitForNodeRequest"))),
+                    json_response("Outcome", schema("WaitForNodeResponse")),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/notifications",
+            screen_path(json!({
+                "get": op(
+                    "Notifications in the open shade",
+                    vec![],
+                    None,
+                    json_response("Notifications, top to bottom", array_of(schema("Notification"))),
+                ),
+            })),
+        ),
+        (
+            "/screens/{name}/notifications/open",
+            screen_path(json!({
+                "post": op(
+                    "Expand the notification shade",
+                    vec![],
+                    None,
+                    json_response("Notifications, top to bottom", array_of(schema("Notification"))),
+                ),
+            })),
+        ),
+        (
+            "/device/airplane-mode",
+            json!({
+                "post": op("Turn airplane mode on or off", vec![], Some(body(schema("ToggleState"))), json_response("State after settling", schema("ToggleState"))),
+            }),
+        ),
+        (
+            "/device/wifi",
+            json!({
+                "post": op("Turn wifi on or off", vec![], Some(body(schema("ToggleState"))), json_response("State after settling", schema("ToggleState"))),
+            }),
+        ),
+        (
+            "/device/mobile-data",
+            json!({
+                "post": op("Turn mobile data on or off", vec![], Some(body(schema("ToggleState"))), json_response("State after settling", schema("ToggleState"))),
+            }),
+        ),
+        (
+            "/device/time",
+            json!({
+                "post": op("Set the device clock", vec![], Some(body(schema("SetTimeRequest"))), json_response("Clock after the change", schema("DeviceTime"))),
+            }),
+        ),
+        (
+            "/device/ime",
+            json!({
+                "get": op("Enabled input methods and the current one", vec![], None, json_response("Input methods", schema("InputMethods"))),
+                "post": op("Switch the input method, enabling it first if needed", vec![], Some(body(schema("SetInputMethodRequest"))), json_response("Input methods after the switch", schema("InputMethods"))),
+            }),
+        ),
+        (
+            "/metrics",
+            json!({
+                "get": op(
+                    "Prometheus metrics",
+                    vec![],
+                    None,
+                    json!({ "description": "Metrics", "content": { "text/plain": { "schema": { "type": "string" } } } }),
+                ),
+            }),
+        ),
+        (
+            "/openapi.json",
+            json!({
+                "get": op("This document", vec![], None, json_response("OpenAPI document", json!({ "type": "object" }))),
+            }),
+        ),
+    ];
+    keyed(entries)
+}
+
+fn schemas() -> Value {
+    let string = || json!({ "type": "string" });
+    let nullable_string = || json!({ "type": ["string", "null"] });
+    let boolean = || json!({ "type": "boolean" });
+    let integer = || json!({ "type": "integer" });
+    let number = || json!({ "type": "number" });
+    let entries = [
+        (
+            "ScreenInfo",
+            object(
+                &[
+                    "name",
+                    "display_id",
+                    "width",
+                    "height",
+                    "dpi",
+                    "assigned_package",
+                    "timeout_secs",
+                ],
+                json!({
+                    "name": string(),
+                    "display_id": integer(),
+                    "width": integer(),
+                    "height": integer(),
+                    "dpi": integer(),
+                    "assigned_package": { "type": "string", "description": "Empty after release-package." },
+                    "last_action": { "oneOf": [schema("LastAction"), { "type": "null" }] },
+                    "timeout_secs": { "type": "integer", "description": "Heartbeat timeout; 0 never reaps." },
+                    "seconds_until_reap": { "type": ["integer", "null"] },
+                }),
+            ),
+        ),
+        (
+            "LastAction",
+            object(
+                &["kind", "at_ms_ago"],
+                json!({
+                    "kind": { "enum": ["tap", "swipe", "text", "key", "select", "focus"] },
+                    "x": { "type": ["number", "null"] },
+                    "y": { "type": ["number", "null"] },
+                    "at_ms_ago": integer(),
+                }),
+            ),
+        ),
+        (
+            "CreateScreenRequest",
+            object(
+                &["name", "width", "height", "dpi", "package"],
+                json!({
+                    "name": string(),
+                    "width": { "type": "integer", "minimum": 1, "maximum": 4096 },
+                    "height": { "type": "integer", "minimum": 1, "maximum": 4096 },
+                    "dpi": integer(),
+                    "timeout_secs": { "type": "integer", "description": "0 never reaps; 300 for a new screen and unchanged for an existing one when omitted." },
+                    "package": { "type": "string", "description": "Package, prefix, or comma-separated pool." },
+                    "auto_dismiss": { "type": "boolean", "description": "Unchanged for an existing screen when omitted." },
+                    "dismiss_rules": { "type": ["array", "null"], "items": schema("DismissRule"), "description": "Replaces the default table; alone, only on a screen that already auto-dismisses." },
+                }),
+            ),
+        ),
+        (
+            "DismissRule",
+            object(
+                &["when", "tap"],
+                json!({ "when": string(), "tap": string() }),
+            ),
+        ),
+        (
+            "BatchScreenResult",
+            object(
+                &["name"],
+                json!({ "name": string(), "screen": schema("ScreenInfo"), "error": string() }),
+            ),
+        ),
+        (
+            "InstalledPackages",
+            object(
+                &["matches", "nearby"],
+                json!({ "matches": array_of(string()), "nearby": array_of(string()) }),
+            ),
+        ),
+        (
+            "ForegroundInfo",
+            object(
+                &["package", "activity"],
+                json!({ "package": string(), "activity": string() }),
+            ),
+        ),
+        (
+            "AppProcessState",
+            object(
+                &["package", "installed", "running", "foreground"],
+                json!({
+                    "package": string(),
+                    "installed": boolean(),
+                    "running": boolean(),
+                    "foreground": boolean(),
+                    "pid": { "type": ["integer", "null"] },
+                }),
+            ),
+        ),
+        (
+            "HistoryEntry",
+            object(
+                &["timestamp_ms", "action", "params"],
+                json!({
+                    "timestamp_ms": { "type": "integer", "description": "Milliseconds since the epoch." },
+                    "action": { "enum": ["tap", "multi_tap", "swipe", "text", "key", "select", "focus", "launch", "open_url", "stop", "reset", "auto_dismiss", "screenshot_full"] },
+                    "params": { "type": "object", "description": "The action's arguments, e.g. x and y of a tap. Typed text is logged as its length (`chars`), not its content." },
+                }),
+            ),
+        ),
+        (
+            "ImeInfo",
+            object(
+                &["shown", "height"],
+                json!({
+                    "shown": boolean(),
+                    "height": { "type": "integer", "description": "Pixels covered from the bottom of the screen; 0 when hidden." },
+                    "bounds": { "oneOf": [schema("Bounds"), { "type": "null" }] },
+                }),
+            ),
+        ),
+        (
+            "OpMode",
+            object(&["op", "mode"], json!({ "op": string(), "mode": string() })),
+        ),
+        (
+            "AppOpsSetRequest",
+            object(
+                &["op", "mode"],
+                json!({
+                    "op": { "type": "string", "description": "e.g. SYSTEM_ALERT_WINDOW." },
+                    "mode": { "enum": ["allow", "ignore", "deny", "default", "foreground"] },
+                }),
+            ),
+        ),
+        (
+            "Bounds",
+            object(
+                &["left", "top", "right", "bottom"],
+                json!({ "left": integer(), "top": integer(), "right": integer(), "bottom": integer() }),
+            ),
+        ),
+        (
+            "Node",
+            object(
+                &["id", "bounds"],
+                json!({
+                    "id": integer(),
+                    "parent_id": { "type": ["integer", "null"] },
+                    "index": integer(),
+                    "package": nullable_string(),
+                    "class": nullable_string(),
+                    "resource_id": nullable_string(),
+                    "text": nullable_string(),
+                    "content_desc": nullable_string(),
+                    "hint": nullable_string(),
+                    "checkable": boolean(),
+                    "checked": boolean(),
+                    "clickable": boolean(),
+                    "enabled": boolean(),
+                    "focusable": boolean(),
+                    "focused": boolean(),
+                    "scrollable": boolean(),
+                    "long_clickable": boolean(),
+                    "password": boolean(),
+                    "selected": boolean(),
+                    "bounds": schema("Bounds"),
+                }),
+            ),
+        ),
+        (
+            "A11yTree",
+            object(
+                &["windows"],
+                json!({
+                    "windows": array_of(json!({
+                        "allOf": [schema("Window"), object(&["nodes"], json!({ "nodes": array_of(schema("Node")) }))],
+                    })),
+                }),
+            ),
+        ),
+        (
+            "Window",
+            object(
+                &["index", "id", "type", "layer", "bounds"],
+                json!({
+                    "index": { "type": "integer", "description": "Position in stacking order; 0 is the topmost window." },
+                    "id": integer(),
+                    "display_id": integer(),
+                    "type": { "type": "integer", "description": "AccessibilityWindowInfo.TYPE_* (1 application, 2 input method, 3 system)." },
+                    "layer": { "type": "integer", "description": "Z-order; windows come sorted by it, highest first." },
+                    "active": boolean(),
+                    "focused": boolean(),
+                    "accessibility_focused": boolean(),
+                    "title": nullable_string(),
+                    "bounds": schema("Bounds"),
+                }),
+            ),
+        ),
+        (
+            "Notification",
+            object(
+                &["bounds"],
+                json!({
+                    "app": nullable_string(),
+                    "title": nullable_string(),
+                    "text": nullable_string(),
+                    "bounds": schema("Bounds"),
+                }),
+            ),
+        ),
+        (
+            "TapRequest",
+            object(&["x", "y"], json!({ "x": number(), "y": number() })),
+        ),
+        (
+            "MultiTapRequest",
+            object(
+                &["points"],
+                json!({ "points": { "type": "array", "items": schema("TapRequest"), "minItems": 2, "maxItems": 10 } }),
+            ),
+        ),
+        (
+            "SwipeRequest",
+            object(
+                &["x1", "y1", "x2", "y2", "duration_ms"],
+                json!({
+                    "x1": number(),
+                    "y1": number(),
+                    "x2": number(),
+                    "y2": number(),
+                    "duration_ms": { "type": "integer", "minimum": 0, "maximum": 10000 },
+                    "steps": { "type": ["integer", "null"], "maximum": 1000 },
+                    "easing": { "enum": ["linear", "decelerate", "accelerate"] },
+                    "hold_ms": { "type": "integer", "minimum": 0, "maximum": 1000, "description": "Rest on the end point before lifting; 100ms or more means no fling." },
+                }),
+            ),
+        ),
+        (
+            "TypeRequest",
+            object(
+                &["text"],
+                json!({
+                    "text": string(),
+                    "paste": { "type": "boolean", "description": "Insert through the clipboard." },
+                }),
+            ),
+        ),
+        (
+            "KeyRequest",
+            object(&["keycode"], json!({ "keycode": integer() })),
+        ),
+        (
+            "KeyComboRequest",
+            object(&["keycodes"], json!({ "keycodes": array_of(integer()) })),
+        ),
+        (
+            "SelectRequest",
+            object(
+                &["text", "start", "end"],
+                json!({ "text": string(), "start": integer(), "end": integer() }),
+            ),
+        ),
+        (
+            "FillRequest",
+            object(
+                &["target", "text"],
+                json!({
+                    "target": { "type": "string", "description": "Text, content_desc or hint of the field; a text field wins over a plain label." },
+                    "text": string(),
+                    "clear": { "type": "boolean", "description": "Select all and delete first." },
+                    "submit": { "type": "boolean", "description": "Press ENTER after typing." },
+                    "paste": { "type": "boolean", "description": "Insert through the clipboard." },
+                }),
+            ),
+        ),
+        (
+            "FocusRequest",
+            object(
+                &["direction"],
+                json!({ "direction": { "enum": ["up", "down", "left", "right"] } }),
+            ),
+        ),
+        (
+            "IntentExtra",
+            json!({
+                "type": "object",
+                "required": ["type", "key", "value"],
+                "properties": {
+                    "type": { "enum": ["string", "int", "bool", "long"] },
+                    "key": string(),
+                    "value": { "type": ["string", "integer", "boolean"] },
+                },
+            }),
+        ),
+        (
+            "LaunchIntentRequest",
+            object(
+                &[],
+                json!({
+                    "activity": nullable_string(),
+                    "action": nullable_string(),
+                    "data": nullable_string(),
+                    "extras": array_of(schema("IntentExtra")),
+                }),
+            ),
+        ),
+        (
+            "OpenUrlRequest",
+            object(
+                &["url"],
+                json!({
+                    "url": string(),
+                    "activity": nullable_string(),
+                    "extras": array_of(schema("IntentExtra")),
+                }),
+            ),
+        ),
+        (
+            "ReleasePackageRequest",
+            object(&[], json!({ "package": nullable_string() })),
+        ),
+        (
+            "WaitForIdleRequest",
+            object(
+                &["idle_timeout_ms", "global_timeout_ms"],
+                json!({ "idle_timeout_ms": integer(), "global_timeout_ms": integer() }),
+            ),
+        ),
+        ("Selector", object(&["text"], json!({ "text": string() }))),
+        (
+            "WaitForNodeRequest",
+            object(
+                &["selector", "timeout_ms"],
+                json!({ "selector": schema("Selector"), "timeout_ms": integer(), "gone": boolean() }),
+            ),
+        ),
+        (
+            "WaitForNodeResponse",
+            object(
+                &["found"],
+                json!({ "found": boolean(), "node": { "oneOf": [schema("Node"), { "type": "null" }] } }),
+            ),
+        ),
+        (
+            "Snapshot",
+            object(
+                &["wait_ms", "screenshot", "a11y"],
+                json!({
+                    "wait_ms": integer(),
+                    "app_crashed": nullable_string(),
+                    "auto_dismissed": nullable_string(),
+                    "ui_changed": { "type": ["boolean", "null"] },
+                    "screenshot": { "type": "string", "contentEncoding": "base64", "contentMediaType": "image/jpeg" },
+                    "a11y": schema("A11yTree"),
+                }),
+            ),
+        ),
+        ("ToggleState", object(&["on"], json!({ "on": boolean() }))),
+        (
+            "SetTimeRequest",
+            object(
+                &["iso8601"],
+                json!({
+                    "iso8601": { "type": "string", "format": "date-time" },
+                    "disable_auto_time": boolean(),
+                }),
+            ),
+        ),
+        (
+            "DeviceTime",
+            object(&["epoch_ms"], json!({ "epoch_ms": integer() })),
+        ),
+        (
+            "InputMethods",
+            object(
+                &["current", "enabled"],
+                json!({ "current": nullable_string(), "enabled": array_of(string()) }),
+            ),
+        ),
+        (
+            "SetInputMethodRequest",
+            object(&["id"], json!({ "id": string() })),
+        ),
+    ];
+    keyed(entries)
+}
+
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "andy coordinator",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "responses": {
+                "Error": {
+                    "description": "Plain-text message. 503 when the server is busy with another request, 504 when the device operation timed out.",
+                    "headers": {
+                        "X-Exception-Class": {
+                            "description": "Class of the Java exception behind the failure.",
+                            "schema": { "type": "string" },
+                        },
+                    },
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+        // Only enforced when the server runs with ANDY_AUTH_TOKEN
+        "security": [{ "bearer": [] }, {}],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refs<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    out.push(target);
+                }
+                map.values().for_each(|v| refs(v, out));
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, out)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn every_ref_resolves() {
+        let doc = document();
+        let mut found = Vec::new();
+        refs(&doc, &mut found);
+        assert!(!found.is_empty());
+        for target in found {
+            let pointer = target.strip_prefix('#').unwrap();
+            assert!(doc.pointer(pointer).is_some(), "dangling {target}");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Screen definitions saved across daemon restarts when `ANDY_PERSIST=1`.
+//!
+//! Only names, geometry and bindings survive: displays come back fresh, so whatever the
+//! app had on screen is gone.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::a11y::DismissRule;
+
+/// Outside the deploy dir, which `andy start` wipes.
+const PATH: &str = "/data/local/tmp/andy-screens.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct PersistedScreen {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub dpi: i32,
+    pub timeout_secs: u64,
+    pub package: String,
+    pub dismiss_rules: Option<Vec<DismissRule>>,
+}
+
+/// Where to persist, if enabled.
+pub fn path_from_env() -> Option<PathBuf> {
+    (std::env::var("ANDY_PERSIST").as_deref() == Ok("1")).then(|| PathBuf::from(PATH))
+}
+
+pub fn load(path: &Path) -> Vec<PersistedScreen> {
+    let Ok(json) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        tracing::warn!(error = %e, path = %path.display(), "ignoring unreadable screen file");
+        Vec::new()
+    })
+}
+
+/// Write via a temp file so a crash mid-write leaves the previous definitions.
+pub fn save(path: &Path, screens: &[PersistedScreen]) -> std::io::Result<()> {
+    let json = serde_json::to_vec(screens).map_err(std::io::Error::other)?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(tmp, path)
+}
@@ -0,0 +1,22 @@
+//! Running device shell commands (`cmd`, `svc`, `settings`, `dumpsys`) for their output.
+
+use std::process::Command;
+
+use crate::AppError;
+
+/// Stdout of `args`; an error carrying stderr when the command fails.
+pub(crate) fn run(args: &[&str]) -> Result<String, AppError> {
+    let command = args.join(" ");
+    let output = Command::new(args[0])
+        .args(&args[1..])
+        .output()
+        .map_err(|e| AppError::new(format!("{command} failed: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::new(format!(
+            "{command} failed: {}",
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}